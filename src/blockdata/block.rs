@@ -28,6 +28,7 @@ use util::hash::bitcoin_merkle_root;
 use hashes::{Hash, HashEngine};
 use hash_types::{Wtxid, BlockHash, TxMerkleNode, WitnessMerkleNode, WitnessCommitment};
 use util::uint::Uint256;
+use util::amount::{VSize, Weight};
 use consensus::encode::Encodable;
 use network::constants::Network;
 use blockdata::transaction::Transaction;
@@ -246,6 +247,16 @@ impl Block {
         base_weight + txs_weight
     }
 
+    /// The BIP141 weight of the block
+    pub fn weight(&self) -> Weight {
+        Weight::from_wu(self.get_weight() as u64)
+    }
+
+    /// The virtual size of the block: its weight divided by 4, rounded up
+    pub fn vsize(&self) -> VSize {
+        self.weight().to_vsize()
+    }
+
     /// Get the coinbase transaction, if one is present.
     pub fn coinbase(&self) -> Option<&Transaction> {
         self.txdata.first()
@@ -317,6 +328,7 @@ mod tests {
     use blockdata::block::{Block, BlockHeader};
     use consensus::encode::{deserialize, serialize};
     use util::uint::Uint256;
+    use util::amount::{VSize, Weight};
     use util::Error::{BlockBadTarget, BlockBadProofOfWork};
     use network::constants::Network;
 
@@ -404,6 +416,8 @@ mod tests {
 
         assert_eq!(real_decode.get_size(), segwit_block.len());
         assert_eq!(real_decode.get_weight(), 17168);
+        assert_eq!(real_decode.weight(), Weight::from_wu(17168));
+        assert_eq!(real_decode.vsize(), VSize::from_vb(4292));
 
         assert!(real_decode.check_witness_commitment());
 
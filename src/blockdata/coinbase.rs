@@ -0,0 +1,283 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Coinbase builder
+//!
+//! Builds coinbase transactions the way Bitcoin Core's miner does: the
+//! scriptSig starts with the block height as required by BIP34, followed by
+//! an extra nonce. When building a whole block, the coinbase commits to the
+//! witness data of the other transactions as per BIP141, and the merkle root
+//! of the header is filled in. The reward is paid to the outputs given, see
+//! [`Params::block_subsidy`](::consensus::Params::block_subsidy) for the
+//! amount a block may claim besides fees.
+//!
+
+use std::{error, fmt};
+
+use blockdata::block::{Block, BlockHeader};
+use blockdata::opcodes;
+use blockdata::script::{Builder, Script};
+use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+
+/// Maximum length of a coinbase scriptSig (consensus rule)
+const MAX_COINBASE_SCRIPT_SIG_LEN: usize = 100;
+/// Header of the output holding a witness commitment: `OP_RETURN`, a 36 byte
+/// push and the commitment tag
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// A coinbase building error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// No output was added
+    NoOutputs,
+    /// The scriptSig holding the height and extra nonce exceeds 100 bytes
+    ScriptSigTooLong(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoOutputs => f.write_str("coinbase has no outputs"),
+            Error::ScriptSigTooLong(len) => write!(f, "coinbase scriptSig of {} bytes exceeds 100 bytes", len),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Builds the coinbase transaction of a block at a given height
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CoinbaseBuilder {
+    height: u32,
+    extra_nonce: Vec<u8>,
+    outputs: Vec<TxOut>,
+    witness_reserved_value: [u8; 32],
+    force_witness_commitment: bool,
+    version: i32,
+    lock_time: u32,
+}
+
+impl CoinbaseBuilder {
+    /// Creates a builder for the coinbase of the block at `height`, with an
+    /// empty extra nonce and an all-zero witness reserved value
+    pub fn new(height: u32) -> CoinbaseBuilder {
+        CoinbaseBuilder {
+            height: height,
+            extra_nonce: vec![],
+            outputs: vec![],
+            witness_reserved_value: [0; 32],
+            force_witness_commitment: false,
+            version: 2,
+            lock_time: 0,
+        }
+    }
+
+    /// Sets the data pushed after the height in the scriptSig, which miners
+    /// vary to extend the search space beyond the header nonce
+    pub fn extra_nonce(mut self, extra_nonce: &[u8]) -> CoinbaseBuilder {
+        self.extra_nonce = extra_nonce.to_vec();
+        self
+    }
+
+    /// Adds an output paying `value` satoshis to `script_pubkey`
+    pub fn add_output(mut self, script_pubkey: Script, value: u64) -> CoinbaseBuilder {
+        self.outputs.push(TxOut { value: value, script_pubkey: script_pubkey });
+        self
+    }
+
+    /// Sets the witness reserved value, the coinbase witness the witness
+    /// commitment is computed with
+    pub fn witness_reserved_value(mut self, value: [u8; 32]) -> CoinbaseBuilder {
+        self.witness_reserved_value = value;
+        self
+    }
+
+    /// Adds the witness commitment even when no transaction has witness
+    /// data, as Bitcoin Core's miner does. Signet blocks need it to hold
+    /// their solution.
+    pub fn force_witness_commitment(mut self) -> CoinbaseBuilder {
+        self.force_witness_commitment = true;
+        self
+    }
+
+    /// Sets the transaction version, 2 by default
+    pub fn version(mut self, version: i32) -> CoinbaseBuilder {
+        self.version = version;
+        self
+    }
+
+    /// Sets the lock time, 0 by default
+    pub fn lock_time(mut self, lock_time: u32) -> CoinbaseBuilder {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// The scriptSig: the BIP34 height, then the extra nonce. An empty extra
+    /// nonce is pushed as `OP_0`, which keeps the script at least two bytes
+    /// long.
+    pub fn script_sig(&self) -> Script {
+        Builder::new()
+            .push_int(self.height as i64)
+            .push_slice(&self.extra_nonce)
+            .into_script()
+    }
+
+    /// Builds the coinbase transaction without witness commitment, for blocks
+    /// without witness data
+    pub fn build_tx(&self) -> Result<Transaction, Error> {
+        if self.outputs.is_empty() {
+            return Err(Error::NoOutputs);
+        }
+        let script_sig = self.script_sig();
+        if script_sig.len() > MAX_COINBASE_SCRIPT_SIG_LEN {
+            return Err(Error::ScriptSigTooLong(script_sig.len()));
+        }
+        Ok(Transaction {
+            version: self.version,
+            lock_time: self.lock_time,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: script_sig,
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: self.outputs.clone(),
+        })
+    }
+
+    /// Builds a block made of the coinbase followed by `txdata`, with the
+    /// header `header` whose merkle root is replaced. If any transaction
+    /// has witness data, or the commitment is forced, the coinbase gets the
+    /// witness reserved value as its witness, and an output committing to
+    /// the witnesses.
+    ///
+    /// The proof of work is left to the caller.
+    pub fn build_block(&self, header: BlockHeader, txdata: Vec<Transaction>) -> Result<Block, Error> {
+        let coinbase = self.build_tx()?;
+        let mut block = Block {
+            header: header,
+            txdata: Some(coinbase).into_iter().chain(txdata).collect(),
+        };
+
+        if self.force_witness_commitment
+            || block.txdata[1..].iter().any(|tx| tx.input.iter().any(|i| !i.witness.is_empty())) {
+            // The coinbase witness is not part of the witness root
+            let witness_root = block.witness_root();
+            let commitment = Block::compute_witness_commitment(&witness_root, &self.witness_reserved_value);
+            let mut data = WITNESS_COMMITMENT_HEADER[2..].to_vec();
+            data.extend_from_slice(&commitment[..]);
+            let script_pubkey = Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .push_slice(&data)
+                .into_script();
+            debug_assert!(script_pubkey.as_bytes().starts_with(&WITNESS_COMMITMENT_HEADER));
+
+            let coinbase = &mut block.txdata[0];
+            coinbase.input[0].witness = vec![self.witness_reserved_value.to_vec()];
+            coinbase.output.push(TxOut { value: 0, script_pubkey: script_pubkey });
+        }
+
+        block.header.merkle_root = block.merkle_root();
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blockdata::constants::COIN_VALUE;
+    use consensus::validation::{check_block, check_block_contextual, Utxo};
+    use consensus::Params;
+    use hash_types::Txid;
+    use hashes::Hash;
+    use network::constants::Network;
+    use test_utils::mine;
+
+    fn header(params: &Params) -> BlockHeader {
+        BlockHeader {
+            version: 4,
+            prev_blockhash: Default::default(),
+            merkle_root: Default::default(),
+            time: 1_600_000_000,
+            bits: BlockHeader::compact_target_from_u256(&params.pow_limit),
+            nonce: 0,
+        }
+    }
+
+    #[test]
+    fn script_sig() {
+        let builder = CoinbaseBuilder::new(100_000).extra_nonce(&[0xab, 0xcd]);
+        assert_eq!(builder.script_sig(), hex_script!("03a0860102abcd"));
+        assert_eq!(CoinbaseBuilder::new(1).script_sig(), hex_script!("5100"));
+        assert_eq!(CoinbaseBuilder::new(0).script_sig(), hex_script!("0000"));
+
+        assert_eq!(CoinbaseBuilder::new(1).build_tx(), Err(Error::NoOutputs));
+        let long = CoinbaseBuilder::new(100_000).extra_nonce(&[0; 95]).add_output(Script::new(), 0);
+        assert_eq!(long.build_tx(), Err(Error::ScriptSigTooLong(101)));
+        let max = long.extra_nonce(&[0; 94]);
+        assert!(max.build_tx().unwrap().is_coin_base());
+    }
+
+    #[test]
+    fn build_block() {
+        let params = Params::new(Network::Regtest);
+        let height = 1000;
+        let reward = params.block_subsidy(height);
+        let builder = CoinbaseBuilder::new(height)
+            .extra_nonce(&[1, 2, 3, 4])
+            .add_output(Script::new_op_return(&[]), reward);
+
+        let block = mine(builder.build_block(header(&params), vec![]).unwrap());
+        assert_eq!(block.txdata.len(), 1);
+        assert_eq!(block.txdata[0].output.len(), 1);
+        assert_eq!(block.bip34_block_height(), Ok(height as u64));
+        assert_eq!(check_block(&block, &params), Ok(()));
+        assert_eq!(check_block_contextual(&block, height, 0, &params, |_| None), Ok(0));
+
+        let forced = mine(builder.clone().force_witness_commitment().build_block(header(&params), vec![]).unwrap());
+        assert_eq!(forced.txdata[0].output.len(), 2);
+        assert!(forced.check_witness_commitment());
+        assert_eq!(check_block(&forced, &params), Ok(()));
+
+        // A segwit spend makes the coinbase commit to the witnesses
+        let utxo = Utxo {
+            txout: TxOut { value: COIN_VALUE, script_pubkey: Script::new() },
+            height: 1,
+            is_coinbase: false,
+        };
+        let spend = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(&[1]), 0),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![vec![1; 72]],
+            }],
+            output: vec![TxOut { value: COIN_VALUE - 1000, script_pubkey: Script::new() }],
+        };
+        let builder = CoinbaseBuilder::new(height)
+            .add_output(Script::new_op_return(&[]), reward + 1000)
+            .witness_reserved_value([7; 32]);
+        let block = mine(builder.build_block(header(&params), vec![spend]).unwrap());
+        let coinbase = &block.txdata[0];
+        assert_eq!(coinbase.input[0].witness, vec![vec![7; 32]]);
+        assert_eq!(coinbase.output.len(), 2);
+        assert!(coinbase.output[1].script_pubkey.as_bytes().starts_with(&WITNESS_COMMITMENT_HEADER));
+        assert!(block.check_witness_commitment());
+        assert_eq!(check_block(&block, &params), Ok(()));
+        assert_eq!(check_block_contextual(&block, height, 0, &params, |_| Some(utxo.clone())), Ok(1000));
+    }
+}
@@ -46,6 +46,11 @@ pub const MAX_BLOCK_WEIGHT: u32 = 4_000_000;
 pub const MIN_TRANSACTION_WEIGHT: u32 = 4 * 60;
 /// The factor that non-witness serialization data is multiplied by during weight calculation
 pub const WITNESS_SCALE_FACTOR: usize = 4;
+/// The maximum allowed number of signature check operations in a block, scaled by the witness
+/// scale factor as per BIP 141 (network rule)
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+/// How many blocks must be mined on top of a coinbase transaction before its outputs can be spent
+pub const COINBASE_MATURITY: u32 = 100;
 
 
 /// In Bitcoind this is insanely described as ~((u256)0 >> 32)
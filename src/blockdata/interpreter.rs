@@ -0,0 +1,2031 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Script interpreter
+//!
+//! A native implementation of the script evaluation rules of Bitcoin Core,
+//! usable without linking against `libbitcoinconsensus`. Scripts are run by
+//! [verify_script], which evaluates a scriptSig (and witness, if any) against
+//! the scriptPubKey it spends under a set of [VerifyFlags]. Signature, lock
+//! time and sequence checks are delegated to a [SignatureChecker], which for
+//! real transactions is a [TransactionSignatureChecker].
+//!
+
+use std::cell::RefCell;
+use std::{fmt, ops};
+
+use hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use secp256k1::{self, Secp256k1, Verification};
+
+use blockdata::opcodes;
+pub use blockdata::locktime::{
+    LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+use blockdata::locktime::{LockTime, Sequence};
+use blockdata::script::{build_scriptint, read_scriptbool, read_uint, Builder, Error, Script};
+use blockdata::transaction::Transaction;
+use util::bip143::SigHashCache;
+
+/// Maximum number of bytes in a script
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// Maximum number of bytes pushable to the stack
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// Maximum number of non-push operations per script
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+
+/// Maximum number of public keys per multisig
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// Maximum combined number of elements on the main and alt stacks
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// Script verification flags, selecting which rules beyond the base consensus
+/// rules are enforced. Values match the `SCRIPT_VERIFY_*` constants of
+/// Bitcoin Core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerifyFlags(u32);
+
+impl VerifyFlags {
+    /// No additional rules.
+    pub const NONE: VerifyFlags = VerifyFlags(0);
+
+    /// Evaluate P2SH subscripts (BIP16).
+    pub const P2SH: VerifyFlags = VerifyFlags(1 << 0);
+
+    /// Passing a non-strict-DER signature or one with an undefined hashtype to a checksig
+    /// operation, or a public key which is neither compressed nor uncompressed, causes script
+    /// failure.
+    pub const STRICTENC: VerifyFlags = VerifyFlags(1 << 1);
+
+    /// Passing a non-strict-DER signature to a checksig operation causes script failure (BIP66).
+    pub const DERSIG: VerifyFlags = VerifyFlags(1 << 2);
+
+    /// Passing a signature with a high S value to a checksig operation causes script failure
+    /// (BIP62 rule 5).
+    pub const LOW_S: VerifyFlags = VerifyFlags(1 << 3);
+
+    /// The dummy element consumed by `CHECKMULTISIG` must be empty (BIP147).
+    pub const NULLDUMMY: VerifyFlags = VerifyFlags(1 << 4);
+
+    /// The scriptSig may only contain push operations (BIP62 rule 2).
+    pub const SIGPUSHONLY: VerifyFlags = VerifyFlags(1 << 5);
+
+    /// Pushes and numbers must use their minimal encoding (BIP62 rules 3 and 4).
+    pub const MINIMALDATA: VerifyFlags = VerifyFlags(1 << 6);
+
+    /// Executing one of the upgradable NOPs (`NOP1`, `NOP4`-`NOP10`) causes script failure.
+    pub const DISCOURAGE_UPGRADABLE_NOPS: VerifyFlags = VerifyFlags(1 << 7);
+
+    /// Exactly one stack element must remain after evaluation (BIP62 rule 6). Only meaningful
+    /// together with `P2SH` and `WITNESS`.
+    pub const CLEANSTACK: VerifyFlags = VerifyFlags(1 << 8);
+
+    /// Verify `OP_CHECKLOCKTIMEVERIFY` (BIP65).
+    pub const CHECKLOCKTIMEVERIFY: VerifyFlags = VerifyFlags(1 << 9);
+
+    /// Verify `OP_CHECKSEQUENCEVERIFY` (BIP112).
+    pub const CHECKSEQUENCEVERIFY: VerifyFlags = VerifyFlags(1 << 10);
+
+    /// Evaluate segregated witness programs (BIP141, BIP143).
+    pub const WITNESS: VerifyFlags = VerifyFlags(1 << 11);
+
+    /// Spending a witness program of an unknown version causes script failure.
+    pub const DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: VerifyFlags = VerifyFlags(1 << 12);
+
+    /// The argument of `OP_IF`/`OP_NOTIF` in witness scripts must be empty or exactly `0x01`.
+    pub const MINIMALIF: VerifyFlags = VerifyFlags(1 << 13);
+
+    /// Failed signature checks must have been passed an empty signature (BIP146).
+    pub const NULLFAIL: VerifyFlags = VerifyFlags(1 << 14);
+
+    /// Public keys in witness scripts must be compressed.
+    pub const WITNESS_PUBKEYTYPE: VerifyFlags = VerifyFlags(1 << 15);
+
+    /// `OP_CODESEPARATOR` and signatures found by `FindAndDelete` cause failure in non-segwit
+    /// scripts.
+    pub const CONST_SCRIPTCODE: VerifyFlags = VerifyFlags(1 << 16);
+
+    // NOTE: When adding new flags, remember to update the Display impl accordingly.
+
+    /// The flags enforced by Bitcoin Core's mempool policy.
+    pub const STANDARD: VerifyFlags = VerifyFlags(
+        (1 << 0) | (1 << 1) | (1 << 2) | (1 << 3) | (1 << 4) | (1 << 6) | (1 << 7) | (1 << 8) |
+        (1 << 9) | (1 << 10) | (1 << 11) | (1 << 12) | (1 << 13) | (1 << 14) | (1 << 15) | (1 << 16)
+    );
+
+    /// Add [VerifyFlags] together.
+    ///
+    /// Returns itself.
+    pub fn add(&mut self, other: VerifyFlags) -> VerifyFlags {
+        self.0 |= other.0;
+        *self
+    }
+
+    /// Remove [VerifyFlags] from this.
+    ///
+    /// Returns itself.
+    pub fn remove(&mut self, other: VerifyFlags) -> VerifyFlags {
+        self.0 &= !other.0;
+        *self
+    }
+
+    /// Check whether [VerifyFlags] are included in this one.
+    pub fn has(self, flags: VerifyFlags) -> bool {
+        (self.0 | flags.0) == self.0
+    }
+
+    /// Get the integer representation of this [VerifyFlags].
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::LowerHex for VerifyFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::UpperHex for VerifyFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for VerifyFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut flags = *self;
+        if flags == VerifyFlags::NONE {
+            return write!(f, "VerifyFlags(NONE)");
+        }
+        let mut first = true;
+        macro_rules! write_flag {
+            ($f:ident) => {
+                if flags.has(VerifyFlags::$f) {
+                    if !first {
+                        write!(f, "|")?;
+                    }
+                    first = false;
+                    write!(f, stringify!($f))?;
+                    flags.remove(VerifyFlags::$f);
+                }
+            }
+        }
+        write!(f, "VerifyFlags(")?;
+        write_flag!(P2SH);
+        write_flag!(STRICTENC);
+        write_flag!(DERSIG);
+        write_flag!(LOW_S);
+        write_flag!(NULLDUMMY);
+        write_flag!(SIGPUSHONLY);
+        write_flag!(MINIMALDATA);
+        write_flag!(DISCOURAGE_UPGRADABLE_NOPS);
+        write_flag!(CLEANSTACK);
+        write_flag!(CHECKLOCKTIMEVERIFY);
+        write_flag!(CHECKSEQUENCEVERIFY);
+        write_flag!(WITNESS);
+        write_flag!(DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM);
+        write_flag!(MINIMALIF);
+        write_flag!(NULLFAIL);
+        write_flag!(WITNESS_PUBKEYTYPE);
+        write_flag!(CONST_SCRIPTCODE);
+        // If there are unknown flags left, we append them in hex.
+        if flags != VerifyFlags::NONE {
+            if !first {
+                write!(f, "|")?;
+            }
+            write!(f, "0x{:x}", flags)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl From<u32> for VerifyFlags {
+    fn from(f: u32) -> Self {
+        VerifyFlags(f)
+    }
+}
+
+impl From<VerifyFlags> for u32 {
+    fn from(f: VerifyFlags) -> Self {
+        f.0
+    }
+}
+
+impl ops::BitOr for VerifyFlags {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: Self) -> Self {
+        self.add(rhs)
+    }
+}
+
+impl ops::BitOrAssign for VerifyFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.add(rhs);
+    }
+}
+
+/// The context a script is evaluated in, which determines how signature
+/// hashes are computed and which rules apply.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum SigVersion {
+    /// Legacy scripts: scriptSig, scriptPubKey and P2SH redeem scripts
+    Base,
+    /// Version 0 witness scripts (BIP141/BIP143)
+    WitnessV0,
+}
+
+/// Interface for the transaction-dependent checks performed by script
+/// evaluation. The default implementations fail every check, which is what
+/// evaluating a script outside of any transaction amounts to.
+pub trait SignatureChecker {
+    /// Checks an encoded signature (including its trailing sighash byte)
+    /// against an encoded public key and the given script code.
+    fn check_sig(&self, _sig: &[u8], _pubkey: &[u8], _script_code: &Script, _sig_version: SigVersion) -> bool {
+        false
+    }
+
+    /// Checks the lock time argument of an `OP_CHECKLOCKTIMEVERIFY`.
+    fn check_lock_time(&self, _lock_time: i64) -> bool {
+        false
+    }
+
+    /// Checks the sequence argument of an `OP_CHECKSEQUENCEVERIFY`.
+    fn check_sequence(&self, _sequence: i64) -> bool {
+        false
+    }
+}
+
+/// A [SignatureChecker] which fails every check.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct NullSignatureChecker;
+
+impl SignatureChecker for NullSignatureChecker {}
+
+/// A [SignatureChecker] verifying signatures, lock times and sequences
+/// against an input of a transaction.
+pub struct TransactionSignatureChecker<'a, C: Verification + 'a> {
+    secp: &'a Secp256k1<C>,
+    tx: &'a Transaction,
+    input_index: usize,
+    amount: u64,
+    cache: RefCell<SigHashCache<&'a Transaction>>,
+}
+
+impl<'a, C: Verification> TransactionSignatureChecker<'a, C> {
+    /// Creates a checker for the input `input_index` of `tx`, spending an
+    /// output of value `amount` (only used by segwit signature hashes).
+    ///
+    /// # Panics
+    /// Panics if `input_index` is greater than or equal to `tx.input.len()`
+    pub fn new(secp: &'a Secp256k1<C>, tx: &'a Transaction, input_index: usize, amount: u64) -> Self {
+        assert!(input_index < tx.input.len());  // Panic on OOB
+        TransactionSignatureChecker {
+            secp: secp,
+            tx: tx,
+            input_index: input_index,
+            amount: amount,
+            cache: RefCell::new(SigHashCache::new(tx)),
+        }
+    }
+}
+
+impl<'a, C: Verification> SignatureChecker for TransactionSignatureChecker<'a, C> {
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], script_code: &Script, sig_version: SigVersion) -> bool {
+        let pubkey = match secp256k1::PublicKey::from_slice(pubkey) {
+            Ok(pk) => pk,
+            Err(_) => return false,
+        };
+        if sig.is_empty() {
+            return false;
+        }
+        let (sig, hash_type) = (&sig[..sig.len() - 1], sig[sig.len() - 1] as u32);
+
+        let sighash = match sig_version {
+            SigVersion::Base => {
+                // The legacy algorithm signs the number one when SIGHASH_SINGLE
+                // is used without a matching output.
+                if hash_type & 0x1f == 0x03 && self.input_index >= self.tx.output.len() {
+                    let mut one = [0; 32];
+                    one[0] = 1;
+                    one
+                } else {
+                    let script_code = strip_codeseparators(script_code);
+                    self.tx.signature_hash(self.input_index, &script_code, hash_type).into_inner()
+                }
+            }
+            SigVersion::WitnessV0 => {
+                self.cache.borrow_mut()
+                    .signature_hash_u32(self.input_index, script_code, self.amount, hash_type)
+                    .into_inner()
+            }
+        };
+
+        let mut sig = match secp256k1::Signature::from_der_lax(sig) {
+            Ok(sig) => sig,
+            Err(_) => return false,
+        };
+        sig.normalize_s();
+        let msg = secp256k1::Message::from_slice(&sighash[..]).expect("32 bytes");
+        self.secp.verify(&msg, &sig, &pubkey).is_ok()
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        // No transaction lock time can satisfy a lock time beyond 32 bits
+        if lock_time > u32::max_value() as i64 {
+            return false;
+        }
+        let tx_lock_time = LockTime::from_consensus(self.tx.lock_time);
+        if !LockTime::from_consensus(lock_time as u32).is_implied_by(tx_lock_time) {
+            return false;
+        }
+        // A final input makes the transaction lock time ineffective
+        !Sequence(self.tx.input[self.input_index].sequence).is_final()
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        // Relative lock times are only enforced from version 2 onwards
+        if (self.tx.version as u32) < 2 {
+            return false;
+        }
+        let tx_lock_time = match Sequence(self.tx.input[self.input_index].sequence).to_relative_lock_time() {
+            Some(lock_time) => lock_time,
+            None => return false,
+        };
+        // Only the lock time bits matter, the disable flag was checked by the caller
+        match Sequence(sequence as u32).to_relative_lock_time() {
+            Some(lock_time) => lock_time.is_implied_by(tx_lock_time),
+            None => false,
+        }
+    }
+}
+
+/// Verifies that `script_sig` and `witness` satisfy `script_pubkey` under
+/// the given verification flags.
+pub fn verify_script<S: SignatureChecker + ?Sized>(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    flags: VerifyFlags,
+    checker: &S,
+) -> Result<(), Error> {
+    if flags.has(VerifyFlags::SIGPUSHONLY) && !is_push_only(script_sig) {
+        return Err(Error::SigPushOnly);
+    }
+
+    let mut stack = vec![];
+    eval_script(&mut stack, script_sig, flags, checker, SigVersion::Base)?;
+    let stack_copy = if flags.has(VerifyFlags::P2SH) { stack.clone() } else { vec![] };
+    eval_script(&mut stack, script_pubkey, flags, checker, SigVersion::Base)?;
+    if stack.is_empty() || !read_scriptbool(&stack[stack.len() - 1]) {
+        return Err(Error::EvalFalse);
+    }
+
+    let mut had_witness = false;
+    if flags.has(VerifyFlags::WITNESS) && script_pubkey.is_witness_program() {
+        had_witness = true;
+        if !script_sig.is_empty() {
+            return Err(Error::WitnessMalleated);
+        }
+        verify_witness_program(witness, script_pubkey, flags, checker)?;
+        // Bypass the cleanstack check at the end
+        stack.truncate(1);
+    }
+
+    if flags.has(VerifyFlags::P2SH) && script_pubkey.is_p2sh() {
+        if !is_push_only(script_sig) {
+            return Err(Error::SigPushOnly);
+        }
+        stack = stack_copy;
+        // The scriptSig is push-only and the scriptPubKey succeeded, so the
+        // redeem script must have been pushed.
+        let redeem_script = Script::from(stack.pop().expect("non-empty stack"));
+        eval_script(&mut stack, &redeem_script, flags, checker, SigVersion::Base)?;
+        if stack.is_empty() || !read_scriptbool(&stack[stack.len() - 1]) {
+            return Err(Error::EvalFalse);
+        }
+
+        if flags.has(VerifyFlags::WITNESS) && redeem_script.is_witness_program() {
+            had_witness = true;
+            let expected_sig = Builder::new().push_slice(redeem_script.as_bytes()).into_script();
+            if *script_sig != expected_sig {
+                return Err(Error::WitnessMalleatedP2sh);
+            }
+            verify_witness_program(witness, &redeem_script, flags, checker)?;
+            stack.truncate(1);
+        }
+    }
+
+    if flags.has(VerifyFlags::CLEANSTACK) && stack.len() != 1 {
+        return Err(Error::CleanStack);
+    }
+
+    if flags.has(VerifyFlags::WITNESS) && !had_witness && !witness.is_empty() {
+        return Err(Error::WitnessUnexpected);
+    }
+
+    Ok(())
+}
+
+/// Verifies a witness program (the scriptPubKey or P2SH redeem script
+/// `program_script`) against its witness.
+fn verify_witness_program<S: SignatureChecker + ?Sized>(
+    witness: &[Vec<u8>],
+    program_script: &Script,
+    flags: VerifyFlags,
+    checker: &S,
+) -> Result<(), Error> {
+    let version = match program_script[0] {
+        0 => 0,
+        op => op - opcodes::all::OP_PUSHNUM_1.into_u8() + 1,
+    };
+    let program = &program_script[2..];
+
+    if version != 0 {
+        if flags.has(VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM) {
+            return Err(Error::DiscourageUpgradableWitnessProgram);
+        }
+        // Unknown versions are anyone-can-spend, reserved for future soft forks
+        return Ok(());
+    }
+
+    let (mut stack, script) = if program.len() == 32 {
+        if witness.is_empty() {
+            return Err(Error::WitnessProgramWitnessEmpty);
+        }
+        let script = Script::from(witness[witness.len() - 1].clone());
+        if sha256::Hash::hash(script.as_bytes())[..] != *program {
+            return Err(Error::WitnessProgramMismatch);
+        }
+        (witness[..witness.len() - 1].to_vec(), script)
+    } else if program.len() == 20 {
+        if witness.len() != 2 {
+            return Err(Error::WitnessProgramMismatch);
+        }
+        let script = Builder::new()
+            .push_opcode(opcodes::all::OP_DUP)
+            .push_opcode(opcodes::all::OP_HASH160)
+            .push_slice(program)
+            .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        (witness.to_vec(), script)
+    } else {
+        return Err(Error::WitnessProgramWrongLength);
+    };
+
+    if stack.iter().any(|elem| elem.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+        return Err(Error::PushSize);
+    }
+    eval_script(&mut stack, &script, flags, checker, SigVersion::WitnessV0)?;
+    // Witness scripts implicitly require a clean stack
+    if stack.len() != 1 {
+        return Err(Error::CleanStack);
+    }
+    if !read_scriptbool(&stack[0]) {
+        return Err(Error::EvalFalse);
+    }
+    Ok(())
+}
+
+/// Evaluates `script` on top of `stack`, leaving the resulting stack in place.
+pub fn eval_script<S: SignatureChecker + ?Sized>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &Script,
+    flags: VerifyFlags,
+    checker: &S,
+    sig_version: SigVersion,
+) -> Result<(), Error> {
+    use blockdata::opcodes::all::*;
+
+    if script.len() > MAX_SCRIPT_SIZE {
+        return Err(Error::ScriptSize);
+    }
+
+    let require_minimal = flags.has(VerifyFlags::MINIMALDATA);
+    let bytes = script.as_bytes();
+    let mut pc = 0;
+    let mut code_start = 0;
+    let mut altstack: Vec<Vec<u8>> = vec![];
+    let mut exec_stack: Vec<bool> = vec![];
+    let mut op_count = 0;
+
+    while pc < bytes.len() {
+        let executing = exec_stack.iter().all(|b| *b);
+        let (opcode, push) = next_op(bytes, &mut pc).map_err(|_| Error::BadOpcode)?;
+
+        if let Some(data) = push {
+            if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                return Err(Error::PushSize);
+            }
+        }
+
+        // Note how OP_RESERVED does not count towards the opcode limit.
+        if opcode.into_u8() > OP_PUSHNUM_16.into_u8() {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(Error::OpCount);
+            }
+        }
+
+        // Disabled opcodes fail the script even in an unexecuted branch.
+        if opcode.classify() == opcodes::Class::IllegalOp &&
+           opcode != OP_VERIF && opcode != OP_VERNOTIF {
+            return Err(Error::DisabledOpcode);
+        }
+
+        if opcode == OP_CODESEPARATOR && sig_version == SigVersion::Base &&
+           flags.has(VerifyFlags::CONST_SCRIPTCODE) {
+            return Err(Error::OpCodeSeparator);
+        }
+
+        if let Some(data) = push {
+            if executing {
+                if require_minimal && !is_minimal_push(opcode, data) {
+                    return Err(Error::MinimalData);
+                }
+                stack.push(data.to_vec());
+            }
+        } else if executing || (OP_IF.into_u8() <= opcode.into_u8() && opcode.into_u8() <= OP_ENDIF.into_u8()) {
+            match opcode {
+                // Push value
+                OP_PUSHNUM_NEG1 | OP_PUSHNUM_1 | OP_PUSHNUM_2 | OP_PUSHNUM_3 | OP_PUSHNUM_4 |
+                OP_PUSHNUM_5 | OP_PUSHNUM_6 | OP_PUSHNUM_7 | OP_PUSHNUM_8 | OP_PUSHNUM_9 |
+                OP_PUSHNUM_10 | OP_PUSHNUM_11 | OP_PUSHNUM_12 | OP_PUSHNUM_13 | OP_PUSHNUM_14 |
+                OP_PUSHNUM_15 | OP_PUSHNUM_16 => {
+                    let n = opcode.into_u8() as i64 - (OP_PUSHNUM_1.into_u8() as i64 - 1);
+                    stack.push(build_scriptint(n));
+                }
+
+                // Control
+                OP_NOP => {}
+
+                OP_CLTV => {
+                    if !flags.has(VerifyFlags::CHECKLOCKTIMEVERIFY) {
+                        // Treat as a NOP2 when not enabled
+                        if flags.has(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                            return Err(Error::DiscourageUpgradableNops);
+                        }
+                    } else {
+                        // Lock times are 5-byte numbers: 4-byte numbers would
+                        // overflow in 2038.
+                        let lock_time = read_scriptnum(top(stack, 1)?, require_minimal, 5)?;
+                        if lock_time < 0 {
+                            return Err(Error::NegativeLocktime);
+                        }
+                        if !checker.check_lock_time(lock_time) {
+                            return Err(Error::UnsatisfiedLocktime);
+                        }
+                    }
+                }
+
+                OP_CSV => {
+                    if !flags.has(VerifyFlags::CHECKSEQUENCEVERIFY) {
+                        // Treat as a NOP3 when not enabled
+                        if flags.has(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                            return Err(Error::DiscourageUpgradableNops);
+                        }
+                    } else {
+                        let sequence = read_scriptnum(top(stack, 1)?, require_minimal, 5)?;
+                        if sequence < 0 {
+                            return Err(Error::NegativeLocktime);
+                        }
+                        // A disabled relative lock time makes the opcode a NOP
+                        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0 &&
+                           !checker.check_sequence(sequence) {
+                            return Err(Error::UnsatisfiedLocktime);
+                        }
+                    }
+                }
+
+                OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9 | OP_NOP10 => {
+                    if flags.has(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) {
+                        return Err(Error::DiscourageUpgradableNops);
+                    }
+                }
+
+                OP_IF | OP_NOTIF => {
+                    let mut value = false;
+                    if executing {
+                        let cond = match stack.pop() {
+                            Some(cond) => cond,
+                            None => return Err(Error::UnbalancedConditional),
+                        };
+                        if sig_version == SigVersion::WitnessV0 && flags.has(VerifyFlags::MINIMALIF) &&
+                           (cond.len() > 1 || (cond.len() == 1 && cond[0] != 1)) {
+                            return Err(Error::MinimalIf);
+                        }
+                        value = read_scriptbool(&cond);
+                        if opcode == OP_NOTIF {
+                            value = !value;
+                        }
+                    }
+                    exec_stack.push(value);
+                }
+
+                OP_ELSE => {
+                    match exec_stack.last_mut() {
+                        Some(value) => *value = !*value,
+                        None => return Err(Error::UnbalancedConditional),
+                    }
+                }
+
+                OP_ENDIF => {
+                    if exec_stack.pop().is_none() {
+                        return Err(Error::UnbalancedConditional);
+                    }
+                }
+
+                OP_VERIFY => {
+                    if !read_scriptbool(top(stack, 1)?) {
+                        return Err(Error::Verify);
+                    }
+                    stack.pop();
+                }
+
+                OP_RETURN => return Err(Error::OpReturn),
+
+                // Stack ops
+                OP_TOALTSTACK => {
+                    let elem = pop(stack)?;
+                    altstack.push(elem);
+                }
+
+                OP_FROMALTSTACK => {
+                    match altstack.pop() {
+                        Some(elem) => stack.push(elem),
+                        None => return Err(Error::InvalidAltstackOperation),
+                    }
+                }
+
+                OP_2DROP => {
+                    require(stack, 2)?;
+                    stack.pop();
+                    stack.pop();
+                }
+
+                OP_2DUP => {
+                    let (a, b) = (top(stack, 2)?.to_vec(), top(stack, 1)?.to_vec());
+                    stack.push(a);
+                    stack.push(b);
+                }
+
+                OP_3DUP => {
+                    let (a, b, c) = (top(stack, 3)?.to_vec(), top(stack, 2)?.to_vec(), top(stack, 1)?.to_vec());
+                    stack.push(a);
+                    stack.push(b);
+                    stack.push(c);
+                }
+
+                OP_2OVER => {
+                    let (a, b) = (top(stack, 4)?.to_vec(), top(stack, 3)?.to_vec());
+                    stack.push(a);
+                    stack.push(b);
+                }
+
+                OP_2ROT => {
+                    require(stack, 6)?;
+                    let len = stack.len();
+                    let a = stack.remove(len - 6);
+                    let b = stack.remove(len - 6);
+                    stack.push(a);
+                    stack.push(b);
+                }
+
+                OP_2SWAP => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack.swap(len - 4, len - 2);
+                    stack.swap(len - 3, len - 1);
+                }
+
+                OP_IFDUP => {
+                    let elem = top(stack, 1)?.to_vec();
+                    if read_scriptbool(&elem) {
+                        stack.push(elem);
+                    }
+                }
+
+                OP_DEPTH => {
+                    let depth = build_scriptint(stack.len() as i64);
+                    stack.push(depth);
+                }
+
+                OP_DROP => {
+                    pop(stack)?;
+                }
+
+                OP_DUP => {
+                    let elem = top(stack, 1)?.to_vec();
+                    stack.push(elem);
+                }
+
+                OP_NIP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.remove(len - 2);
+                }
+
+                OP_OVER => {
+                    let elem = top(stack, 2)?.to_vec();
+                    stack.push(elem);
+                }
+
+                OP_PICK | OP_ROLL => {
+                    require(stack, 2)?;
+                    let n = read_scriptnum(top(stack, 1)?, require_minimal, 4)?;
+                    stack.pop();
+                    if n < 0 || n as usize >= stack.len() {
+                        return Err(Error::InvalidStackOperation);
+                    }
+                    let index = stack.len() - 1 - n as usize;
+                    let elem = if opcode == OP_ROLL {
+                        stack.remove(index)
+                    } else {
+                        stack[index].clone()
+                    };
+                    stack.push(elem);
+                }
+
+                OP_ROT => {
+                    require(stack, 3)?;
+                    let len = stack.len();
+                    let elem = stack.remove(len - 3);
+                    stack.push(elem);
+                }
+
+                OP_SWAP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+
+                OP_TUCK => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    let elem = stack[len - 1].clone();
+                    stack.insert(len - 2, elem);
+                }
+
+                OP_SIZE => {
+                    let size = build_scriptint(top(stack, 1)?.len() as i64);
+                    stack.push(size);
+                }
+
+                // Bitwise logic
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    let equal = top(stack, 2)? == top(stack, 1)?;
+                    stack.pop();
+                    stack.pop();
+                    if opcode == OP_EQUALVERIFY {
+                        if !equal {
+                            return Err(Error::EqualVerify);
+                        }
+                    } else {
+                        stack.push(bool_elem(equal));
+                    }
+                }
+
+                // Numeric
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    let n = read_scriptnum(top(stack, 1)?, require_minimal, 4)?;
+                    let res = match opcode {
+                        OP_1ADD => n + 1,
+                        OP_1SUB => n - 1,
+                        OP_NEGATE => -n,
+                        OP_ABS => n.abs(),
+                        OP_NOT => (n == 0) as i64,
+                        _ => (n != 0) as i64,
+                    };
+                    stack.pop();
+                    stack.push(build_scriptint(res));
+                }
+
+                OP_ADD | OP_SUB | OP_BOOLAND | OP_BOOLOR | OP_NUMEQUAL | OP_NUMEQUALVERIFY |
+                OP_NUMNOTEQUAL | OP_LESSTHAN | OP_GREATERTHAN | OP_LESSTHANOREQUAL |
+                OP_GREATERTHANOREQUAL | OP_MIN | OP_MAX => {
+                    require(stack, 2)?;
+                    let a = read_scriptnum(top(stack, 2)?, require_minimal, 4)?;
+                    let b = read_scriptnum(top(stack, 1)?, require_minimal, 4)?;
+                    let res = match opcode {
+                        OP_ADD => a + b,
+                        OP_SUB => a - b,
+                        OP_BOOLAND => (a != 0 && b != 0) as i64,
+                        OP_BOOLOR => (a != 0 || b != 0) as i64,
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                        OP_NUMNOTEQUAL => (a != b) as i64,
+                        OP_LESSTHAN => (a < b) as i64,
+                        OP_GREATERTHAN => (a > b) as i64,
+                        OP_LESSTHANOREQUAL => (a <= b) as i64,
+                        OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                        OP_MIN => if a < b { a } else { b },
+                        _ => if a > b { a } else { b },
+                    };
+                    stack.pop();
+                    stack.pop();
+                    if opcode == OP_NUMEQUALVERIFY {
+                        if res == 0 {
+                            return Err(Error::NumEqualVerify);
+                        }
+                    } else {
+                        stack.push(build_scriptint(res));
+                    }
+                }
+
+                OP_WITHIN => {
+                    require(stack, 3)?;
+                    let x = read_scriptnum(top(stack, 3)?, require_minimal, 4)?;
+                    let min = read_scriptnum(top(stack, 2)?, require_minimal, 4)?;
+                    let max = read_scriptnum(top(stack, 1)?, require_minimal, 4)?;
+                    stack.pop();
+                    stack.pop();
+                    stack.pop();
+                    stack.push(bool_elem(min <= x && x < max));
+                }
+
+                // Crypto
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    let elem = pop(stack)?;
+                    let hash = match opcode {
+                        OP_RIPEMD160 => ripemd160::Hash::hash(&elem).to_vec(),
+                        OP_SHA1 => sha1::Hash::hash(&elem).to_vec(),
+                        OP_SHA256 => sha256::Hash::hash(&elem).to_vec(),
+                        OP_HASH160 => hash160::Hash::hash(&elem).to_vec(),
+                        _ => sha256d::Hash::hash(&elem).to_vec(),
+                    };
+                    stack.push(hash);
+                }
+
+                OP_CODESEPARATOR => {
+                    // Hash starts after the code separator
+                    code_start = pc;
+                }
+
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    require(stack, 2)?;
+                    let sig = stack[stack.len() - 2].clone();
+                    let pubkey = stack[stack.len() - 1].clone();
+
+                    // Subset of script starting at the most recent code separator
+                    let mut script_code = bytes[code_start..].to_vec();
+                    // Drop the signature in pre-segwit scripts but not segwit scripts
+                    if sig_version == SigVersion::Base {
+                        let found = find_and_delete(&mut script_code, &push_of(&sig));
+                        if found > 0 && flags.has(VerifyFlags::CONST_SCRIPTCODE) {
+                            return Err(Error::SigFindAndDelete);
+                        }
+                    }
+                    check_signature_encoding(&sig, flags)?;
+                    check_pubkey_encoding(&pubkey, flags, sig_version)?;
+
+                    let success = checker.check_sig(&sig, &pubkey, &Script::from(script_code), sig_version);
+                    if !success && flags.has(VerifyFlags::NULLFAIL) && !sig.is_empty() {
+                        return Err(Error::SigNullFail);
+                    }
+
+                    stack.pop();
+                    stack.pop();
+                    if opcode == OP_CHECKSIGVERIFY {
+                        if !success {
+                            return Err(Error::CheckSigVerify);
+                        }
+                    } else {
+                        stack.push(bool_elem(success));
+                    }
+                }
+
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    let mut i = 1;
+                    let mut keys_count = read_scriptnum(top(stack, i)?, require_minimal, 4)?;
+                    if keys_count < 0 || keys_count > MAX_PUBKEYS_PER_MULTISIG as i64 {
+                        return Err(Error::PubkeyCount);
+                    }
+                    op_count += keys_count as usize;
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(Error::OpCount);
+                    }
+                    i += 1;
+                    let mut ikey = i;
+                    // Position of the last key to be checked, for NULLFAIL
+                    let mut ikey2 = keys_count as usize + 2;
+                    i += keys_count as usize;
+
+                    let mut sigs_count = read_scriptnum(top(stack, i)?, require_minimal, 4)?;
+                    if sigs_count < 0 || sigs_count > keys_count {
+                        return Err(Error::SigCount);
+                    }
+                    i += 1;
+                    let mut isig = i;
+                    i += sigs_count as usize;
+                    require(stack, i)?;
+
+                    // Subset of script starting at the most recent code separator
+                    let mut script_code = bytes[code_start..].to_vec();
+                    // Drop the signatures in pre-segwit scripts but not segwit scripts
+                    for k in 0..sigs_count as usize {
+                        if sig_version == SigVersion::Base {
+                            let sig = push_of(top(stack, isig + k)?);
+                            let found = find_and_delete(&mut script_code, &sig);
+                            if found > 0 && flags.has(VerifyFlags::CONST_SCRIPTCODE) {
+                                return Err(Error::SigFindAndDelete);
+                            }
+                        }
+                    }
+                    let script_code = Script::from(script_code);
+
+                    let mut success = true;
+                    while success && sigs_count > 0 {
+                        let sig = top(stack, isig)?;
+                        let pubkey = top(stack, ikey)?;
+
+                        // Note how this makes the exact order of pubkey/signature evaluation
+                        // distinguishable by CHECKMULTISIG NOT if the STRICTENC flag is set.
+                        check_signature_encoding(sig, flags)?;
+                        check_pubkey_encoding(pubkey, flags, sig_version)?;
+
+                        if checker.check_sig(sig, pubkey, &script_code, sig_version) {
+                            isig += 1;
+                            sigs_count -= 1;
+                        }
+                        ikey += 1;
+                        keys_count -= 1;
+
+                        // If there are more signatures left than keys left,
+                        // then too many signatures have failed. Exit early,
+                        // without checking any further signatures.
+                        if sigs_count > keys_count {
+                            success = false;
+                        }
+                    }
+
+                    // Clean up stack of actual arguments
+                    while i > 1 {
+                        i -= 1;
+                        // If the operation failed, we require that all
+                        // signatures must be empty vector
+                        if !success && flags.has(VerifyFlags::NULLFAIL) && ikey2 == 0 &&
+                           !stack[stack.len() - 1].is_empty() {
+                            return Err(Error::SigNullFail);
+                        }
+                        if ikey2 > 0 {
+                            ikey2 -= 1;
+                        }
+                        stack.pop();
+                    }
+
+                    // A bug causes CHECKMULTISIG to consume one extra argument
+                    // whose contents were not checked in any way.
+                    //
+                    // Unfortunately this is a potential source of mutability,
+                    // so optionally verify it is exactly equal to zero.
+                    let dummy = pop(stack)?;
+                    if flags.has(VerifyFlags::NULLDUMMY) && !dummy.is_empty() {
+                        return Err(Error::SigNullDummy);
+                    }
+
+                    if opcode == OP_CHECKMULTISIGVERIFY {
+                        if !success {
+                            return Err(Error::CheckMultisigVerify);
+                        }
+                    } else {
+                        stack.push(bool_elem(success));
+                    }
+                }
+
+                _ => return Err(Error::BadOpcode),
+            }
+        }
+
+        // Size limits
+        if stack.len() + altstack.len() > MAX_STACK_SIZE {
+            return Err(Error::StackSize);
+        }
+    }
+
+    if !exec_stack.is_empty() {
+        return Err(Error::UnbalancedConditional);
+    }
+
+    Ok(())
+}
+
+impl Script {
+    /// Checks whether the script consists only of push operations (including
+    /// `OP_RESERVED`, as in Bitcoin Core)
+    pub fn is_push_only(&self) -> bool {
+        is_push_only(self)
+    }
+
+    /// Verifies the spend of an output holding this script by the input
+    /// `index` of `spending`, using the native script interpreter.
+    ///
+    /// # Parameters
+    ///  * index - the input index in spending which is spending this transaction
+    ///  * amount - the amount this script guards
+    ///  * spending - the transaction that attempts to spend the output holding this script
+    ///  * flags - the verification flags to enforce
+    ///
+    /// # Panics
+    /// Panics if `index` is greater than or equal to `spending.input.len()`
+    pub fn verify_input(&self, index: usize, amount: u64, spending: &Transaction, flags: VerifyFlags) -> Result<(), Error> {
+        let secp = Secp256k1::verification_only();
+        verify_input_with(&secp, self, index, amount, spending, flags)
+    }
+}
+
+impl Transaction {
+    /// Verify that this transaction is able to spend its inputs, using the
+    /// native script interpreter with the given verification flags.
+    /// The lambda spent should not return the same TxOut twice!
+    pub fn verify_scripts<S>(&self, mut spent: S, flags: VerifyFlags) -> Result<(), Error>
+        where S: FnMut(&::OutPoint) -> Option<::TxOut> {
+        let secp = Secp256k1::verification_only();
+        for (idx, input) in self.input.iter().enumerate() {
+            if let Some(output) = spent(&input.previous_output) {
+                verify_input_with(&secp, &output.script_pubkey, idx, output.value, self, flags)?;
+            } else {
+                return Err(Error::UnknownSpentOutput(input.previous_output));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Verifies input `index` of `spending`, which spends `script_pubkey`
+fn verify_input_with<C: Verification>(
+    secp: &Secp256k1<C>,
+    script_pubkey: &Script,
+    index: usize,
+    amount: u64,
+    spending: &Transaction,
+    flags: VerifyFlags,
+) -> Result<(), Error> {
+    let checker = TransactionSignatureChecker::new(secp, spending, index, amount);
+    let input = &spending.input[index];
+    verify_script(&input.script_sig, script_pubkey, &input.witness, flags, &checker)
+}
+
+/// Reads the next operation of `script` at `*pc`, returning the opcode and
+/// the pushed data for push operations
+fn next_op<'a>(script: &'a [u8], pc: &mut usize) -> Result<(opcodes::All, Option<&'a [u8]>), Error> {
+    let opcode = opcodes::All::from(script[*pc]);
+    *pc += 1;
+    let len = match opcode.classify() {
+        opcodes::Class::PushBytes(n) => n as usize,
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA1) => {
+            let n = read_uint(&script[*pc..], 1)?;
+            *pc += 1;
+            n
+        }
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA2) => {
+            let n = read_uint(&script[*pc..], 2)?;
+            *pc += 2;
+            n
+        }
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA4) => {
+            let n = read_uint(&script[*pc..], 4)?;
+            *pc += 4;
+            n
+        }
+        _ => return Ok((opcode, None)),
+    };
+    if script.len() - *pc < len {
+        return Err(Error::EarlyEndOfScript);
+    }
+    let data = &script[*pc..*pc + len];
+    *pc += len;
+    Ok((opcode, Some(data)))
+}
+
+fn is_push_only(script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    let mut pc = 0;
+    while pc < bytes.len() {
+        match next_op(bytes, &mut pc) {
+            Ok((op, _)) if op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8() => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Checks that `data` was pushed with the smallest possible opcode
+fn is_minimal_push(opcode: opcodes::All, data: &[u8]) -> bool {
+    let op = opcode.into_u8();
+    match data.len() {
+        // Could have used OP_0
+        0 => op == opcodes::all::OP_PUSHBYTES_0.into_u8(),
+        // Could have used OP_1 .. OP_16 or OP_1NEGATE
+        1 if (data[0] >= 1 && data[0] <= 16) || data[0] == 0x81 => false,
+        n if n <= 75 => op as usize == n,
+        n if n <= 255 => opcode == opcodes::all::OP_PUSHDATA1,
+        n if n <= 65535 => opcode == opcodes::all::OP_PUSHDATA2,
+        _ => true,
+    }
+}
+
+/// Decodes a script number of at most `max_size` bytes, optionally
+/// requiring it to be minimally encoded
+fn read_scriptnum(v: &[u8], require_minimal: bool, max_size: usize) -> Result<i64, Error> {
+    if v.len() > max_size {
+        return Err(Error::NumericOverflow);
+    }
+    if require_minimal && !v.is_empty() {
+        // The most significant byte other than the sign bit must be non-zero,
+        // unless it is needed to hold the sign bit of the next byte.
+        let last = v[v.len() - 1];
+        if last & 0x7f == 0 && (v.len() <= 1 || v[v.len() - 2] & 0x80 == 0) {
+            return Err(Error::MinimalData);
+        }
+    }
+    if v.is_empty() {
+        return Ok(0);
+    }
+    let mut ret: i64 = 0;
+    for (i, byte) in v.iter().enumerate() {
+        ret |= (*byte as i64) << (8 * i);
+    }
+    // The sign bit of the most significant byte holds the sign
+    if v[v.len() - 1] & 0x80 != 0 {
+        ret &= !(0x80 << (8 * (v.len() - 1)));
+        ret = -ret;
+    }
+    Ok(ret)
+}
+
+/// Encodes a boolean as a stack element
+fn bool_elem(b: bool) -> Vec<u8> {
+    if b { vec![1] } else { vec![] }
+}
+
+/// Fails unless the stack holds at least `n` elements
+fn require(stack: &[Vec<u8>], n: usize) -> Result<(), Error> {
+    if stack.len() < n {
+        Err(Error::InvalidStackOperation)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the `n`th element from the top of the stack, counting from 1
+fn top(stack: &[Vec<u8>], n: usize) -> Result<&[u8], Error> {
+    require(stack, n)?;
+    Ok(&stack[stack.len() - n])
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, Error> {
+    stack.pop().ok_or(Error::InvalidStackOperation)
+}
+
+/// Serializes the push of `data`, as matched by `FindAndDelete`
+fn push_of(data: &[u8]) -> Vec<u8> {
+    Builder::new().push_slice(data).into_script().into_bytes()
+}
+
+/// Removes all occurrences of `pattern` which start on an operation boundary
+/// from `script`, returning the number of occurrences removed
+fn find_and_delete(script: &mut Vec<u8>, pattern: &[u8]) -> usize {
+    if pattern.is_empty() {
+        return 0;
+    }
+    let mut found = 0;
+    let mut result = Vec::with_capacity(script.len());
+    let mut pc = 0;
+    let mut copied_to = 0;
+    loop {
+        result.extend_from_slice(&script[copied_to..pc]);
+        while script.len() - pc >= pattern.len() && script[pc..pc + pattern.len()] == *pattern {
+            pc += pattern.len();
+            found += 1;
+        }
+        copied_to = pc;
+        if pc >= script.len() || next_op(script, &mut pc).is_err() {
+            break;
+        }
+    }
+    if found > 0 {
+        result.extend_from_slice(&script[copied_to..]);
+        *script = result;
+    }
+    found
+}
+
+/// Removes all `OP_CODESEPARATOR`s from a script code, as done when
+/// computing legacy signature hashes
+fn strip_codeseparators(script_code: &Script) -> Script {
+    let bytes = script_code.as_bytes();
+    if !bytes.contains(&opcodes::all::OP_CODESEPARATOR.into_u8()) {
+        return script_code.clone();
+    }
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut pc = 0;
+    while pc < bytes.len() {
+        let start = pc;
+        match next_op(bytes, &mut pc) {
+            Ok((op, _)) if op == opcodes::all::OP_CODESEPARATOR => {}
+            Ok(_) => ret.extend_from_slice(&bytes[start..pc]),
+            Err(_) => {
+                ret.extend_from_slice(&bytes[start..]);
+                break;
+            }
+        }
+    }
+    Script::from(ret)
+}
+
+/// Checks a signature against the encoding rules selected by `flags`
+fn check_signature_encoding(sig: &[u8], flags: VerifyFlags) -> Result<(), Error> {
+    // Empty signature. Not strictly DER encoded, but allowed to provide a
+    // compact way to provide an invalid signature for use with CHECK(MULTI)SIG
+    if sig.is_empty() {
+        return Ok(());
+    }
+    if (flags.has(VerifyFlags::DERSIG) || flags.has(VerifyFlags::LOW_S) || flags.has(VerifyFlags::STRICTENC)) &&
+       !is_valid_signature_encoding(sig) {
+        return Err(Error::SigDer);
+    }
+    if flags.has(VerifyFlags::LOW_S) && !is_low_der_signature(sig) {
+        return Err(Error::SigHighS);
+    }
+    if flags.has(VerifyFlags::STRICTENC) {
+        let hash_type = sig[sig.len() - 1] & !0x80;
+        if hash_type < 1 || hash_type > 3 {
+            return Err(Error::SigHashType);
+        }
+    }
+    Ok(())
+}
+
+/// Checks a public key against the encoding rules selected by `flags`
+fn check_pubkey_encoding(pubkey: &[u8], flags: VerifyFlags, sig_version: SigVersion) -> Result<(), Error> {
+    let compressed = pubkey.len() == 33 && (pubkey[0] == 0x02 || pubkey[0] == 0x03);
+    let uncompressed = pubkey.len() == 65 && pubkey[0] == 0x04;
+    if flags.has(VerifyFlags::STRICTENC) && !compressed && !uncompressed {
+        return Err(Error::PubkeyType);
+    }
+    // Only compressed keys are accepted in segwit
+    if flags.has(VerifyFlags::WITNESS_PUBKEYTYPE) && sig_version == SigVersion::WitnessV0 && !compressed {
+        return Err(Error::WitnessPubkeyType);
+    }
+    Ok(())
+}
+
+/// A canonical signature exists of: <30> <total len> <02> <len R> <R> <02> <len S> <S> <hashtype>
+/// Where R and S are not negative (their first byte has its highest bit not set), and not
+/// excessively padded (do not start with a 0 byte, unless an otherwise negative number follows,
+/// in which case a single 0 byte is necessary and even required).
+///
+/// See https://bitcointalk.org/index.php?topic=8392.msg127623#msg127623
+///
+/// This function is consensus-critical since BIP66.
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // Minimum and maximum size constraints.
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    // A signature is of type 0x30 (compound).
+    if sig[0] != 0x30 {
+        return false;
+    }
+    // Make sure the length covers the entire signature.
+    if sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    // Extract the length of the R element.
+    let len_r = sig[3] as usize;
+    // Make sure the length of the S element is still inside the signature.
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    // Extract the length of the S element.
+    let len_s = sig[5 + len_r] as usize;
+    // Verify that the length of the signature matches the sum of the length
+    // of the elements.
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+
+    // Check whether the R element is an integer.
+    if sig[2] != 0x02 {
+        return false;
+    }
+    // Zero-length integers are not allowed for R.
+    if len_r == 0 {
+        return false;
+    }
+    // Negative numbers are not allowed for R.
+    if sig[4] & 0x80 != 0 {
+        return false;
+    }
+    // Null bytes at the start of R are not allowed, unless R would
+    // otherwise be interpreted as a negative number.
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+
+    // Check whether the S element is an integer.
+    if sig[len_r + 4] != 0x02 {
+        return false;
+    }
+    // Zero-length integers are not allowed for S.
+    if len_s == 0 {
+        return false;
+    }
+    // Negative numbers are not allowed for S.
+    if sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    // Null bytes at the start of S are not allowed, unless S would otherwise be
+    // interpreted as a negative number.
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+
+    true
+}
+
+/// Checks that a (validly encoded) signature has a low S value
+fn is_low_der_signature(sig: &[u8]) -> bool {
+    match secp256k1::Signature::from_der_lax(&sig[..sig.len() - 1]) {
+        Ok(parsed) => {
+            let mut normalized = parsed;
+            normalized.normalize_s();
+            normalized == parsed
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+    use self::serde_json::Value;
+
+    use super::*;
+    use std::str::FromStr;
+
+    use hashes::hex::{FromHex, ToHex};
+    use secp256k1::rand::thread_rng;
+    use secp256k1::SecretKey;
+
+    use blockdata::opcodes;
+    use blockdata::transaction::{OutPoint, TxIn, TxOut};
+    use util::key::PublicKey;
+
+    thread_local! {
+        // Context creation is slow, so share one between tests
+        static SECP: Secp256k1<secp256k1::All> = Secp256k1::new();
+    }
+
+    /// Maps an interpreter error to the name used by Bitcoin Core's test vectors
+    fn error_name(err: &Error) -> &'static str {
+        match *err {
+            Error::EvalFalse => "EVAL_FALSE",
+            Error::OpReturn => "OP_RETURN",
+            Error::ScriptSize => "SCRIPT_SIZE",
+            Error::PushSize => "PUSH_SIZE",
+            Error::OpCount => "OP_COUNT",
+            Error::StackSize => "STACK_SIZE",
+            Error::SigCount => "SIG_COUNT",
+            Error::PubkeyCount => "PUBKEY_COUNT",
+            Error::Verify => "VERIFY",
+            Error::EqualVerify => "EQUALVERIFY",
+            Error::CheckMultisigVerify => "CHECKMULTISIGVERIFY",
+            Error::CheckSigVerify => "CHECKSIGVERIFY",
+            Error::NumEqualVerify => "NUMEQUALVERIFY",
+            Error::BadOpcode => "BAD_OPCODE",
+            Error::DisabledOpcode => "DISABLED_OPCODE",
+            Error::InvalidStackOperation => "INVALID_STACK_OPERATION",
+            Error::InvalidAltstackOperation => "INVALID_ALTSTACK_OPERATION",
+            Error::UnbalancedConditional => "UNBALANCED_CONDITIONAL",
+            Error::NegativeLocktime => "NEGATIVE_LOCKTIME",
+            Error::UnsatisfiedLocktime => "UNSATISFIED_LOCKTIME",
+            Error::SigHashType => "SIG_HASHTYPE",
+            Error::SigDer => "SIG_DER",
+            Error::MinimalData => "MINIMALDATA",
+            Error::SigPushOnly => "SIG_PUSHONLY",
+            Error::SigHighS => "SIG_HIGH_S",
+            Error::SigNullDummy => "SIG_NULLDUMMY",
+            Error::PubkeyType => "PUBKEYTYPE",
+            Error::CleanStack => "CLEANSTACK",
+            Error::MinimalIf => "MINIMALIF",
+            Error::SigNullFail => "NULLFAIL",
+            Error::DiscourageUpgradableNops => "DISCOURAGE_UPGRADABLE_NOPS",
+            Error::DiscourageUpgradableWitnessProgram => "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM",
+            Error::WitnessProgramWrongLength => "WITNESS_PROGRAM_WRONG_LENGTH",
+            Error::WitnessProgramWitnessEmpty => "WITNESS_PROGRAM_WITNESS_EMPTY",
+            Error::WitnessProgramMismatch => "WITNESS_PROGRAM_MISMATCH",
+            Error::WitnessMalleated => "WITNESS_MALLEATED",
+            Error::WitnessMalleatedP2sh => "WITNESS_MALLEATED_P2SH",
+            Error::WitnessUnexpected => "WITNESS_UNEXPECTED",
+            Error::WitnessPubkeyType => "WITNESS_PUBKEYTYPE",
+            Error::OpCodeSeparator => "OP_CODESEPARATOR",
+            Error::SigFindAndDelete => "SIG_FINDANDDELETE",
+            _ => "UNKNOWN_ERROR",
+        }
+    }
+
+    /// Parses verification flags in the comma-separated format of Bitcoin Core
+    fn parse_flags(s: &str) -> VerifyFlags {
+        let mut flags = VerifyFlags::NONE;
+        for name in s.split(',').filter(|s| !s.is_empty()) {
+            flags |= match name {
+                "NONE" => VerifyFlags::NONE,
+                "P2SH" => VerifyFlags::P2SH,
+                "STRICTENC" => VerifyFlags::STRICTENC,
+                "DERSIG" => VerifyFlags::DERSIG,
+                "LOW_S" => VerifyFlags::LOW_S,
+                "NULLDUMMY" => VerifyFlags::NULLDUMMY,
+                "SIGPUSHONLY" => VerifyFlags::SIGPUSHONLY,
+                "MINIMALDATA" => VerifyFlags::MINIMALDATA,
+                "DISCOURAGE_UPGRADABLE_NOPS" => VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS,
+                "CLEANSTACK" => VerifyFlags::CLEANSTACK,
+                "CHECKLOCKTIMEVERIFY" => VerifyFlags::CHECKLOCKTIMEVERIFY,
+                "CHECKSEQUENCEVERIFY" => VerifyFlags::CHECKSEQUENCEVERIFY,
+                "WITNESS" => VerifyFlags::WITNESS,
+                "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM" => VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM,
+                "MINIMALIF" => VerifyFlags::MINIMALIF,
+                "NULLFAIL" => VerifyFlags::NULLFAIL,
+                "WITNESS_PUBKEYTYPE" => VerifyFlags::WITNESS_PUBKEYTYPE,
+                "CONST_SCRIPTCODE" => VerifyFlags::CONST_SCRIPTCODE,
+                _ => panic!("unknown verification flag {}", name),
+            };
+        }
+        flags
+    }
+
+    /// Looks up an opcode by the name used in Bitcoin Core's script assembly
+    fn parse_opcode(name: &str) -> Option<opcodes::All> {
+        let name = if name.starts_with("OP_") { &name[3..] } else { name };
+        let op = match name {
+            "0" | "FALSE" => opcodes::all::OP_PUSHBYTES_0,
+            "1NEGATE" => opcodes::all::OP_PUSHNUM_NEG1,
+            "TRUE" => opcodes::all::OP_PUSHNUM_1,
+            "NOP2" | "CHECKLOCKTIMEVERIFY" => opcodes::all::OP_CLTV,
+            "NOP3" | "CHECKSEQUENCEVERIFY" => opcodes::all::OP_CSV,
+            _ => {
+                if let Ok(n) = name.parse::<u8>() {
+                    if n >= 1 && n <= 16 {
+                        return Some(opcodes::All::from(opcodes::all::OP_PUSHNUM_1.into_u8() + n - 1));
+                    }
+                    return None;
+                }
+                // Pushes can't be written by name
+                return (opcodes::all::OP_PUSHDATA1.into_u8()..=255)
+                    .map(opcodes::All::from)
+                    .find(|op| format!("{:?}", op)[3..] == *name);
+            }
+        };
+        Some(op)
+    }
+
+    /// Parses a script in the assembly format of Bitcoin Core's test vectors
+    fn parse_asm(s: &str) -> Script {
+        let mut bytes = vec![];
+        for word in s.split_whitespace() {
+            if word.starts_with("0x") && word.len() > 2 {
+                // Raw hex data, inserted NOT pushed onto stack
+                bytes.extend(Vec::<u8>::from_hex(&word[2..]).unwrap());
+            } else if word.len() >= 2 && word.starts_with('\'') && word.ends_with('\'') {
+                // Single-quoted string, pushed as data
+                let builder = Builder::new().push_slice(&word.as_bytes()[1..word.len() - 1]);
+                bytes.extend(builder.into_script().into_bytes());
+            } else if let Some(op) = parse_opcode(word) {
+                bytes.push(op.into_u8());
+            } else if let Ok(n) = i64::from_str(word) {
+                // Decimal numbers, pushed as in Bitcoin Core
+                bytes.extend(Builder::new().push_int(n).into_script().into_bytes());
+            } else {
+                panic!("unparseable script token {}", word);
+            }
+        }
+        Script::from(bytes)
+    }
+
+    /// Builds the transaction creating the output spent in a test, as Bitcoin Core does
+    fn crediting_tx(script_pubkey: &Script, amount: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(0).push_int(0).into_script(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: amount,
+                script_pubkey: script_pubkey.clone(),
+            }],
+        }
+    }
+
+    /// Builds the transaction spending a crediting transaction, as Bitcoin Core does
+    fn spending_tx(script_sig: &Script, witness: &[Vec<u8>], credit: &Transaction) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid: credit.txid(), vout: 0 },
+                script_sig: script_sig.clone(),
+                sequence: 0xffffffff,
+                witness: witness.to_vec(),
+            }],
+            output: vec![TxOut {
+                value: credit.output[0].value,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    fn run_test(script_sig: &Script, script_pubkey: &Script, witness: &[Vec<u8>], amount: u64, flags: VerifyFlags) -> Result<(), Error> {
+        let credit = crediting_tx(script_pubkey, amount);
+        let spend = spending_tx(script_sig, witness, &credit);
+        SECP.with(|secp| {
+            let checker = TransactionSignatureChecker::new(secp, &spend, 0, amount);
+            verify_script(script_sig, script_pubkey, witness, flags, &checker)
+        })
+    }
+
+    #[test]
+    fn flags_test() {
+        let mut flags = VerifyFlags::P2SH | VerifyFlags::WITNESS;
+        assert!(flags.has(VerifyFlags::P2SH));
+        assert!(flags.has(VerifyFlags::WITNESS));
+        assert!(!flags.has(VerifyFlags::DERSIG));
+        assert_eq!(flags.to_string(), "VerifyFlags(P2SH|WITNESS)");
+        flags.remove(VerifyFlags::P2SH);
+        assert_eq!(flags, VerifyFlags::WITNESS);
+        flags.remove(VerifyFlags::P2SH);
+        assert_eq!(flags, VerifyFlags::WITNESS);
+        assert_eq!(VerifyFlags::NONE.to_string(), "VerifyFlags(NONE)");
+        assert_eq!(VerifyFlags::from(0x20001).to_string(), "VerifyFlags(P2SH|0x20000)");
+        assert!(VerifyFlags::STANDARD.has(VerifyFlags::CONST_SCRIPTCODE));
+        assert!(!VerifyFlags::STANDARD.has(VerifyFlags::SIGPUSHONLY));
+    }
+
+    #[test]
+    fn scriptnum_test() {
+        assert_eq!(read_scriptnum(&[], true, 4), Ok(0));
+        assert_eq!(read_scriptnum(&[0x81], true, 4), Ok(-1));
+        assert_eq!(read_scriptnum(&[0x80, 0x00], true, 4), Ok(128));
+        assert_eq!(read_scriptnum(&[0x80, 0x80], true, 4), Ok(-128));
+        assert_eq!(read_scriptnum(&[0xff, 0xff, 0xff, 0x7f], true, 4), Ok(0x7fffffff));
+        assert_eq!(read_scriptnum(&[0xff, 0xff, 0xff, 0xff, 0x00], true, 5), Ok(0xffffffff));
+        assert_eq!(read_scriptnum(&[0xff, 0xff, 0xff, 0xff, 0x00], true, 4), Err(Error::NumericOverflow));
+        // Negative zero and zero padding
+        assert_eq!(read_scriptnum(&[0x80], false, 4), Ok(0));
+        assert_eq!(read_scriptnum(&[0x80], true, 4), Err(Error::MinimalData));
+        assert_eq!(read_scriptnum(&[0x01, 0x00], false, 4), Ok(1));
+        assert_eq!(read_scriptnum(&[0x01, 0x00], true, 4), Err(Error::MinimalData));
+        for n in &[0i64, 1, -1, 127, 128, -255, 256, 0x7fffffff, -0x7fffffff, 0xffffffff] {
+            assert_eq!(read_scriptnum(&build_scriptint(*n), true, 5), Ok(*n));
+        }
+    }
+
+    #[test]
+    fn find_and_delete_test() {
+        fn check(script: &str, pattern: &str, expected: &str, count: usize) {
+            let mut script = Vec::<u8>::from_hex(script).unwrap();
+            let found = find_and_delete(&mut script, &Vec::<u8>::from_hex(pattern).unwrap());
+            assert_eq!(found, count);
+            assert_eq!(script.to_hex(), expected);
+        }
+        // Vectors from Bitcoin Core's script_FindAndDelete test
+        check("0302ff03", "0302ff03", "", 1);
+        check("0302ff030302ff03", "0302ff03", "", 2);
+        check("0302ff030302ff03", "02", "0302ff030302ff03", 0);
+        check("0302ff030302ff03", "ff", "0302ff030302ff03", 0);
+        check("0302ff030302ff03", "03", "02ff0302ff03", 2);
+        check("02feed5169", "feed51", "02feed5169", 0);
+        check("02feed5169", "02feed51", "69", 1);
+        check("516902feed5169", "feed51", "516902feed5169", 0);
+        check("516902feed5169", "02feed51", "516969", 1);
+        check("00000000010000000000", "00", "0100", 8);
+        check("00005151", "0051", "0051", 1);
+        check("0003feed", "03feed", "00", 1);
+        check("0003feed", "00", "03feed", 1);
+        check("0302ff0302ff03", "0302ff03", "02ff03", 1);
+        check("4c00ab", "4c00", "ab", 1);
+        check("4c", "4c", "", 1);
+    }
+
+    #[test]
+    fn signature_encoding_test() {
+        let sig = Vec::<u8>::from_hex("3045022100e3b6cd0b7bb1d2f18d8f0e4f8d3c0d2e72a3a8ca1ea55b5cb1e1c2d3c38ef3c102206b7f3c59c0b02c4c0c9ea3bc4c2f3e59b9c2f1c0f27aa3b6ec2a2e4f4b2d8d8a01").unwrap();
+        assert!(is_valid_signature_encoding(&sig));
+        // Wrong total length
+        let mut bad = sig.clone();
+        bad[1] += 1;
+        assert!(!is_valid_signature_encoding(&bad));
+        // Excessively padded R
+        let mut bad = sig.clone();
+        bad[5] = 0x00;
+        bad[4] = 0x00;
+        assert!(!is_valid_signature_encoding(&bad));
+
+        let strict = VerifyFlags::STRICTENC;
+        let mut undefined_hashtype = sig.clone();
+        *undefined_hashtype.last_mut().unwrap() = 0x21;
+        assert_eq!(check_signature_encoding(&undefined_hashtype, strict), Err(Error::SigHashType));
+        assert_eq!(check_signature_encoding(&undefined_hashtype, VerifyFlags::DERSIG), Ok(()));
+        assert_eq!(check_signature_encoding(&[], strict), Ok(()));
+        assert_eq!(check_signature_encoding(&[0x30], VerifyFlags::NONE), Ok(()));
+        assert_eq!(check_signature_encoding(&[0x30], VerifyFlags::DERSIG), Err(Error::SigDer));
+
+        assert_eq!(check_pubkey_encoding(&[0x02; 33], strict, SigVersion::Base), Ok(()));
+        assert_eq!(check_pubkey_encoding(&[0x06; 65], strict, SigVersion::Base), Err(Error::PubkeyType));
+        assert_eq!(check_pubkey_encoding(&[0x04; 65], VerifyFlags::WITNESS_PUBKEYTYPE, SigVersion::Base), Ok(()));
+        assert_eq!(
+            check_pubkey_encoding(&[0x04; 65], VerifyFlags::WITNESS_PUBKEYTYPE, SigVersion::WitnessV0),
+            Err(Error::WitnessPubkeyType)
+        );
+    }
+
+    #[test]
+    fn script_tests() {
+        // Bitcoin Core's src/test/data/script_tests.json, each entry being
+        // [[wit..., amount]?, scriptSig, scriptPubKey, flags, expected_scripterror, ... comments]
+        let data = include_str!("data/script_tests.json");
+
+        let testdata = serde_json::from_str::<Value>(data).unwrap().as_array().unwrap().clone();
+        let mut count = 0;
+        for t in testdata.iter() {
+            let mut t = t.as_array().unwrap().clone();
+            if t.len() == 1 {
+                continue; // comment
+            }
+            let mut witness = vec![];
+            let mut amount = 0;
+            if t[0].is_array() {
+                let wit = t.remove(0);
+                let wit = wit.as_array().unwrap();
+                for elem in &wit[..wit.len() - 1] {
+                    witness.push(Vec::<u8>::from_hex(elem.as_str().unwrap()).unwrap());
+                }
+                amount = (wit[wit.len() - 1].as_f64().unwrap() * 100_000_000.0).round() as u64;
+            }
+            let script_sig = parse_asm(t[0].as_str().unwrap());
+            let script_pubkey = parse_asm(t[1].as_str().unwrap());
+            let flags = parse_flags(t[2].as_str().unwrap());
+            let expected = t[3].as_str().unwrap();
+
+            let result = run_test(&script_sig, &script_pubkey, &witness, amount, flags);
+            let actual = match result {
+                Ok(()) => "OK",
+                // Bitcoin Core reports non-minimal numbers as unknown errors
+                Err(Error::MinimalData) if expected == "UNKNOWN_ERROR" => "UNKNOWN_ERROR",
+                Err(ref e) => error_name(e),
+            };
+            assert_eq!(actual, expected, "test {:?} failed: {:?}", t, result);
+            count += 1;
+        }
+        assert_eq!(count, 1203);
+    }
+
+    /// A key used to sign test transactions
+    struct TestKey {
+        sk: SecretKey,
+        pk: PublicKey,
+    }
+
+    impl TestKey {
+        fn new(compressed: bool) -> TestKey {
+            let sk = SecretKey::new(&mut thread_rng());
+            TestKey {
+                sk: sk,
+                pk: PublicKey {
+                    compressed: compressed,
+                    key: SECP.with(|secp| secp256k1::PublicKey::from_secret_key(secp, &sk)),
+                },
+            }
+        }
+
+        fn sign(&self, hash: &[u8], hash_type: u8) -> Vec<u8> {
+            let msg = secp256k1::Message::from_slice(hash).unwrap();
+            let mut sig = SECP.with(|secp| secp.sign(&msg, &self.sk)).serialize_der().to_vec();
+            sig.push(hash_type);
+            sig
+        }
+
+        fn to_bytes(&self) -> Vec<u8> {
+            self.pk.to_bytes()
+        }
+    }
+
+    /// Signs the spend of `script_code` in the spending transaction of a test
+    fn sign_legacy(key: &TestKey, script_pubkey: &Script, script_code: &Script, hash_type: u8) -> Vec<u8> {
+        let credit = crediting_tx(script_pubkey, 0);
+        let spend = spending_tx(&Script::new(), &[], &credit);
+        let hash = spend.signature_hash(0, script_code, hash_type as u32);
+        key.sign(&hash[..], hash_type)
+    }
+
+    /// Signs the segwit spend of `script_code` in the spending transaction of a test
+    fn sign_segwit(key: &TestKey, script_pubkey: &Script, script_code: &Script, amount: u64, hash_type: u8) -> Vec<u8> {
+        let credit = crediting_tx(script_pubkey, amount);
+        let spend = spending_tx(&Script::new(), &[], &credit);
+        let hash = SigHashCache::new(&spend).signature_hash_u32(0, script_code, amount, hash_type as u32);
+        key.sign(&hash[..], hash_type)
+    }
+
+    /// Turns a low-S signature into its high-S equivalent
+    fn make_high_s(sig: &[u8]) -> Vec<u8> {
+        let hash_type = sig[sig.len() - 1];
+        let parsed = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap();
+        let compact = parsed.serialize_compact();
+        // n - s
+        let order = Vec::<u8>::from_hex("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141").unwrap();
+        let mut s = [0u8; 32];
+        let mut borrow = 0i16;
+        for i in (0..32).rev() {
+            let mut d = order[i] as i16 - compact[32 + i] as i16 - borrow;
+            borrow = if d < 0 { d += 256; 1 } else { 0 };
+            s[i] = d as u8;
+        }
+        let mut compact_high = compact;
+        compact_high[32..].copy_from_slice(&s);
+        // Serialize by hand, as libsecp256k1 only produces low-S DER
+        fn der_int(v: &[u8]) -> Vec<u8> {
+            let mut v: Vec<u8> = v.iter().cloned().skip_while(|b| *b == 0).collect();
+            if v.is_empty() || v[0] & 0x80 != 0 {
+                v.insert(0, 0);
+            }
+            let mut ret = vec![0x02, v.len() as u8];
+            ret.extend(v);
+            ret
+        }
+        let r = der_int(&compact_high[..32]);
+        let s = der_int(&compact_high[32..]);
+        let mut ret = vec![0x30, (r.len() + s.len()) as u8];
+        ret.extend(r);
+        ret.extend(s);
+        ret.push(hash_type);
+        ret
+    }
+
+    #[test]
+    fn p2pk_p2pkh_test() {
+        let key = TestKey::new(true);
+        let ukey = TestKey::new(false);
+
+        // P2PK
+        let spk = Builder::new().push_slice(&key.to_bytes()).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+        let sig = sign_legacy(&key, &spk, &spk, 0x01);
+        let ssig = Builder::new().push_slice(&sig).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::STANDARD), Ok(()));
+        // Signed with the wrong hashtype byte
+        let mut bad = sig.clone();
+        *bad.last_mut().unwrap() = 0x02;
+        let ssig = Builder::new().push_slice(&bad).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NONE), Err(Error::EvalFalse));
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NULLFAIL), Err(Error::SigNullFail));
+        // Undefined hashtypes are fine without STRICTENC
+        let sig = sign_legacy(&key, &spk, &spk, 0x05);
+        let ssig = Builder::new().push_slice(&sig).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NONE), Ok(()));
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::STRICTENC), Err(Error::SigHashType));
+
+        // High S
+        let sig = sign_legacy(&key, &spk, &spk, 0x01);
+        let ssig = Builder::new().push_slice(&make_high_s(&sig)).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NONE), Ok(()));
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::LOW_S), Err(Error::SigHighS));
+
+        // P2PKH with an uncompressed key
+        let spk = Script::new_p2pkh(&ukey.pk.pubkey_hash());
+        let sig = sign_legacy(&ukey, &spk, &spk, 0x01);
+        let ssig = Builder::new().push_slice(&sig).push_slice(&ukey.to_bytes()).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::STANDARD), Ok(()));
+        // Wrong key
+        let ssig = Builder::new().push_slice(&sig).push_slice(&key.to_bytes()).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::STANDARD), Err(Error::EqualVerify));
+
+        // SIGHASH_SINGLE without a matching output signs the number one
+        let spk = Builder::new().push_slice(&key.to_bytes()).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+        let secp = Secp256k1::verification_only();
+        let credit = crediting_tx(&spk, 0);
+        let mut spend = spending_tx(&Script::new(), &[], &credit);
+        spend.output.clear();
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        let sig = key.sign(&one, 0x03);
+        spend.input[0].script_sig = Builder::new().push_slice(&sig).into_script();
+        let checker = TransactionSignatureChecker::new(&secp, &spend, 0, 0);
+        assert_eq!(verify_script(&spend.input[0].script_sig, &spk, &[], VerifyFlags::STANDARD, &checker), Ok(()));
+    }
+
+    #[test]
+    fn multisig_test() {
+        let keys: Vec<TestKey> = (0..3).map(|_| TestKey::new(true)).collect();
+        let spk = Builder::new()
+            .push_int(2)
+            .push_slice(&keys[0].to_bytes())
+            .push_slice(&keys[1].to_bytes())
+            .push_slice(&keys[2].to_bytes())
+            .push_int(3)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let sig0 = sign_legacy(&keys[0], &spk, &spk, 0x01);
+        let sig2 = sign_legacy(&keys[2], &spk, &spk, 0x01);
+
+        let ssig = Builder::new().push_int(0).push_slice(&sig0).push_slice(&sig2).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::STANDARD), Ok(()));
+        // Signatures in the wrong order
+        let ssig = Builder::new().push_int(0).push_slice(&sig2).push_slice(&sig0).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NONE), Err(Error::EvalFalse));
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NULLFAIL), Err(Error::SigNullFail));
+        // Non-null dummy
+        let ssig = Builder::new().push_int(1).push_slice(&sig0).push_slice(&sig2).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NONE), Ok(()));
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NULLDUMMY), Err(Error::SigNullDummy));
+        // Missing dummy
+        let ssig = Builder::new().push_slice(&sig0).push_slice(&sig2).into_script();
+        assert_eq!(run_test(&ssig, &spk, &[], 0, VerifyFlags::NONE), Err(Error::InvalidStackOperation));
+
+        // P2SH-wrapped
+        let p2sh = spk.to_p2sh();
+        let sig0 = sign_legacy(&keys[0], &p2sh, &spk, 0x01);
+        let sig2 = sign_legacy(&keys[2], &p2sh, &spk, 0x01);
+        let ssig = Builder::new().push_int(0).push_slice(&sig0).push_slice(&sig2).push_slice(spk.as_bytes()).into_script();
+        assert_eq!(run_test(&ssig, &p2sh, &[], 0, VerifyFlags::STANDARD), Ok(()));
+        // P2SH and CLEANSTACK enforce a clean stack
+        let ssig = Builder::new().push_int(1).push_int(0).push_slice(&sig0).push_slice(&sig2).push_slice(spk.as_bytes()).into_script();
+        assert_eq!(run_test(&ssig, &p2sh, &[], 0, VerifyFlags::P2SH), Ok(()));
+        assert_eq!(run_test(&ssig, &p2sh, &[], 0, VerifyFlags::STANDARD), Err(Error::CleanStack));
+    }
+
+    #[test]
+    fn find_and_delete_sig_test() {
+        // A scriptPubKey containing the signature which spends it is only
+        // spendable because FindAndDelete removes it from the script code
+        let key = TestKey::new(true);
+        let code = Builder::new()
+            .push_opcode(opcodes::all::OP_DROP)
+            .push_slice(&key.to_bytes())
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        let credit = crediting_tx(&code, 0);
+        let mut spend = spending_tx(&Script::new(), &[], &credit);
+        let hash = spend.signature_hash(0, &code, 1);
+        let sig = key.sign(&hash[..], 0x01);
+
+        let spk = Builder::new()
+            .push_slice(&sig)
+            .push_opcode(opcodes::all::OP_DROP)
+            .push_slice(&key.to_bytes())
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        spend.input[0].script_sig = Builder::new().push_slice(&sig).into_script();
+        let secp = Secp256k1::verification_only();
+        let checker = TransactionSignatureChecker::new(&secp, &spend, 0, 0);
+        let ssig = &spend.input[0].script_sig;
+        assert_eq!(verify_script(ssig, &spk, &[], VerifyFlags::NONE, &checker), Ok(()));
+        assert_eq!(
+            verify_script(ssig, &spk, &[], VerifyFlags::CONST_SCRIPTCODE, &checker),
+            Err(Error::SigFindAndDelete)
+        );
+    }
+
+    #[test]
+    fn segwit_test() {
+        let key = TestKey::new(true);
+        let ukey = TestKey::new(false);
+        let amount = 100_000;
+
+        // P2WPKH
+        let spk = Script::new_v0_wpkh(&key.pk.wpubkey_hash().unwrap());
+        let code = Script::new_p2pkh(&key.pk.pubkey_hash());
+        let sig = sign_segwit(&key, &spk, &code, amount, 0x01);
+        let witness = vec![sig.clone(), key.to_bytes()];
+        assert_eq!(run_test(&Script::new(), &spk, &witness, amount, VerifyFlags::STANDARD), Ok(()));
+        // The amount is committed to
+        let flags = VerifyFlags::P2SH | VerifyFlags::WITNESS;
+        assert_eq!(run_test(&Script::new(), &spk, &witness, amount + 1, flags), Err(Error::EvalFalse));
+        assert_eq!(run_test(&Script::new(), &spk, &witness, amount + 1, VerifyFlags::STANDARD), Err(Error::SigNullFail));
+        // Witnesses are ignored without the WITNESS flag
+        assert_eq!(run_test(&Script::new(), &spk, &[], amount, VerifyFlags::P2SH), Ok(()));
+
+        // P2SH-P2WPKH
+        let p2sh = spk.to_p2sh();
+        let ssig = Builder::new().push_slice(spk.as_bytes()).into_script();
+        let sig = sign_segwit(&key, &p2sh, &code, amount, 0x01);
+        let witness = vec![sig, key.to_bytes()];
+        assert_eq!(run_test(&ssig, &p2sh, &witness, amount, VerifyFlags::STANDARD), Ok(()));
+
+        // P2WSH with an uncompressed key
+        let ws = Builder::new().push_slice(&ukey.to_bytes()).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+        let spk = ws.to_v0_p2wsh();
+        let sig = sign_segwit(&ukey, &spk, &ws, amount, 0x01);
+        let witness = vec![sig, ws.to_bytes()];
+        assert_eq!(run_test(&Script::new(), &spk, &witness, amount, flags), Ok(()));
+        assert_eq!(
+            run_test(&Script::new(), &spk, &witness, amount, flags | VerifyFlags::WITNESS_PUBKEYTYPE),
+            Err(Error::WitnessPubkeyType)
+        );
+
+        // P2WSH 1-of-2 multisig with SIGHASH_SINGLE|ANYONECANPAY
+        let key2 = TestKey::new(true);
+        let ws = Builder::new()
+            .push_int(1)
+            .push_slice(&key.to_bytes())
+            .push_slice(&key2.to_bytes())
+            .push_int(2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let spk = ws.to_v0_p2wsh();
+        let sig = sign_segwit(&key, &spk, &ws, amount, 0x83);
+        let witness = vec![vec![], sig, ws.to_bytes()];
+        assert_eq!(run_test(&Script::new(), &spk, &witness, amount, VerifyFlags::STANDARD), Ok(()));
+        // Signatures are not removed from witness scripts
+        assert_eq!(run_test(&Script::new(), &spk, &witness, amount, VerifyFlags::STANDARD | VerifyFlags::CONST_SCRIPTCODE), Ok(()));
+    }
+
+    #[test]
+    fn locktime_test() {
+        let secp = Secp256k1::verification_only();
+        let mut tx = spending_tx(&Script::new(), &[], &crediting_tx(&Script::new(), 0));
+        tx.version = 2;
+        tx.lock_time = 100;
+        tx.input[0].sequence = 10;
+
+        let cltv = |n: i64| Builder::new().push_int(n).push_opcode(opcodes::all::OP_CLTV).into_script();
+        let csv = |n: i64| Builder::new().push_int(n).push_opcode(opcodes::all::OP_CSV).into_script();
+        let flags = VerifyFlags::CHECKLOCKTIMEVERIFY | VerifyFlags::CHECKSEQUENCEVERIFY;
+        let run = |tx: &Transaction, spk: &Script| {
+            let checker = TransactionSignatureChecker::new(&secp, tx, 0, 0);
+            verify_script(&Script::new(), spk, &[], flags, &checker)
+        };
+
+        assert_eq!(run(&tx, &cltv(100)), Ok(()));
+        assert_eq!(run(&tx, &cltv(101)), Err(Error::UnsatisfiedLocktime));
+        assert_eq!(run(&tx, &cltv(-1)), Err(Error::NegativeLocktime));
+        assert_eq!(run(&tx, &cltv(500_000_000)), Err(Error::UnsatisfiedLocktime));
+        assert_eq!(run(&tx, &Script::new()), Err(Error::EvalFalse));
+        assert_eq!(run(&tx, &csv(10)), Ok(()));
+        assert_eq!(run(&tx, &csv(11)), Err(Error::UnsatisfiedLocktime));
+        assert_eq!(run(&tx, &csv(-1)), Err(Error::NegativeLocktime));
+        // Disabled relative lock times are NOPs
+        assert_eq!(run(&tx, &csv(1 << 31)), Ok(()));
+        // Time-based relative lock times can't satisfy height-based ones
+        assert_eq!(run(&tx, &csv(1 << 22 | 1)), Err(Error::UnsatisfiedLocktime));
+
+        let mut final_tx = tx.clone();
+        final_tx.input[0].sequence = 0xffffffff;
+        assert_eq!(run(&final_tx, &cltv(100)), Err(Error::UnsatisfiedLocktime));
+        assert_eq!(run(&final_tx, &csv(10)), Err(Error::UnsatisfiedLocktime));
+
+        let mut v1_tx = tx.clone();
+        v1_tx.version = 1;
+        assert_eq!(run(&v1_tx, &csv(10)), Err(Error::UnsatisfiedLocktime));
+    }
+
+    #[test]
+    fn limits_test() {
+        let checker = NullSignatureChecker;
+        let run = |ssig: &Script, spk: &Script| verify_script(ssig, spk, &[], VerifyFlags::P2SH, &checker);
+
+        // 201 non-push operations are fine, 202 are not
+        let mut ops = vec![opcodes::all::OP_NOP.into_u8(); 201];
+        ops.push(opcodes::all::OP_PUSHNUM_1.into_u8());
+        assert_eq!(run(&Script::new(), &Script::from(ops.clone())), Ok(()));
+        ops.insert(0, opcodes::all::OP_NOP.into_u8());
+        assert_eq!(run(&Script::new(), &Script::from(ops)), Err(Error::OpCount));
+
+        // Pushes are limited to 520 bytes
+        let ssig = Builder::new().push_slice(&[1; 520]).into_script();
+        assert_eq!(run(&ssig, &Script::from(vec![opcodes::all::OP_SIZE.into_u8(), 0x75])), Ok(()));
+        let ssig = Builder::new().push_slice(&[1; 521]).into_script();
+        assert_eq!(run(&ssig, &Script::from(vec![0x51])), Err(Error::PushSize));
+
+        // Scripts are limited to 10000 bytes
+        let mut long = vec![opcodes::all::OP_PUSHNUM_1.into_u8()];
+        long.extend(vec![0x00; MAX_SCRIPT_SIZE]);
+        assert_eq!(run(&Script::new(), &Script::from(long)), Err(Error::ScriptSize));
+
+        // The stack is limited to 1000 elements
+        let mut deep = vec![opcodes::all::OP_PUSHNUM_1.into_u8(); 1000];
+        assert_eq!(run(&Script::new(), &Script::from(deep.clone())), Ok(()));
+        deep.push(opcodes::all::OP_PUSHNUM_1.into_u8());
+        assert_eq!(run(&Script::new(), &Script::from(deep)), Err(Error::StackSize));
+
+        // CHECKMULTISIG key counts count towards the operation limit
+        let mut ops = vec![opcodes::all::OP_NOP.into_u8(); 190];
+        ops.extend(vec![0x00, 0x00, 0x5b, opcodes::all::OP_CHECKMULTISIG.into_u8()]);
+        assert_eq!(run(&Script::from(vec![0x00; 11]), &Script::from(ops)), Err(Error::OpCount));
+    }
+}
@@ -0,0 +1,440 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Lock times
+//!
+//! Typed views of the transaction `nLockTime` field ([LockTime]) and of the
+//! input `nSequence` field ([Sequence]), along with the BIP68 relative lock
+//! time a sequence number may hold ([RelativeLockTime]).
+//!
+
+use std::{error, fmt};
+
+/// Lock time values below this threshold are block heights, values at or
+/// above it are UNIX timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Setting this bit in a sequence number disables its relative lock time
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+
+/// Setting this bit in a sequence number makes its relative lock time
+/// time-based (in units of 512 seconds) rather than height-based
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+
+/// The bits of a sequence number which hold its relative lock time value
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+
+/// The granularity of time-based relative lock times, in seconds
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 512;
+
+/// A lock time error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Block height at or above [LOCKTIME_THRESHOLD]
+    InvalidHeight(u32),
+    /// UNIX timestamp below [LOCKTIME_THRESHOLD]
+    InvalidTime(u32),
+    /// Relative lock time in seconds which does not fit in 16 bits of
+    /// 512-second intervals
+    RelativeTimeOverflow(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidHeight(h) => write!(f, "invalid lock time block height: {}", h),
+            Error::InvalidTime(t) => write!(f, "invalid lock time timestamp: {}", t),
+            Error::RelativeTimeOverflow(s) => write!(f, "relative lock time of {} seconds is too large", s),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// An absolute lock time, the `nLockTime` of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LockTime {
+    /// The transaction can be included from this block height on
+    Height(u32),
+    /// The transaction can be included once the median time past of the
+    /// previous block reaches this UNIX timestamp
+    Time(u32),
+}
+
+impl LockTime {
+    /// A lock time of zero, which never locks the transaction
+    pub const ZERO: LockTime = LockTime::Height(0);
+
+    /// Interprets a consensus-encoded `nLockTime`
+    pub fn from_consensus(n: u32) -> LockTime {
+        if n < LOCKTIME_THRESHOLD {
+            LockTime::Height(n)
+        } else {
+            LockTime::Time(n)
+        }
+    }
+
+    /// A lock time by block height, which must be below [LOCKTIME_THRESHOLD]
+    pub fn from_height(height: u32) -> Result<LockTime, Error> {
+        if height < LOCKTIME_THRESHOLD {
+            Ok(LockTime::Height(height))
+        } else {
+            Err(Error::InvalidHeight(height))
+        }
+    }
+
+    /// A lock time by UNIX timestamp, which must be at or above [LOCKTIME_THRESHOLD]
+    pub fn from_time(time: u32) -> Result<LockTime, Error> {
+        if time >= LOCKTIME_THRESHOLD {
+            Ok(LockTime::Time(time))
+        } else {
+            Err(Error::InvalidTime(time))
+        }
+    }
+
+    /// The consensus-encoded `nLockTime`
+    pub fn to_consensus_u32(self) -> u32 {
+        match self {
+            LockTime::Height(n) | LockTime::Time(n) => n,
+        }
+    }
+
+    /// Whether the lock time is a block height
+    pub fn is_block_height(self) -> bool {
+        match self {
+            LockTime::Height(_) => true,
+            LockTime::Time(_) => false,
+        }
+    }
+
+    /// Whether the lock time is a UNIX timestamp
+    pub fn is_block_time(self) -> bool {
+        !self.is_block_height()
+    }
+
+    /// Whether both lock times are heights or both are timestamps; lock
+    /// times of different units can not be compared
+    pub fn is_same_unit(self, other: LockTime) -> bool {
+        self.is_block_height() == other.is_block_height()
+    }
+
+    /// Whether a transaction with this lock time can be included in a block
+    /// at `height` whose previous block has median time past `mtp`, like
+    /// `IsFinalTx()` of Bitcoin Core
+    pub fn is_satisfied_by(self, height: u32, mtp: u32) -> bool {
+        match self {
+            LockTime::Height(n) => n < height,
+            LockTime::Time(n) => n < mtp,
+        }
+    }
+
+    /// Whether a transaction with lock time `other` satisfies this lock,
+    /// as checked by `OP_CHECKLOCKTIMEVERIFY`
+    pub fn is_implied_by(self, other: LockTime) -> bool {
+        self.is_same_unit(other) && self.to_consensus_u32() <= other.to_consensus_u32()
+    }
+}
+
+impl Default for LockTime {
+    fn default() -> LockTime {
+        LockTime::ZERO
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockTime::Height(n) => write!(f, "block height {}", n),
+            LockTime::Time(n) => write!(f, "block time {}", n),
+        }
+    }
+}
+
+impl From<u32> for LockTime {
+    fn from(n: u32) -> LockTime {
+        LockTime::from_consensus(n)
+    }
+}
+
+impl From<LockTime> for u32 {
+    fn from(lock_time: LockTime) -> u32 {
+        lock_time.to_consensus_u32()
+    }
+}
+
+/// A relative lock time, held by the sequence number of an input (BIP68)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RelativeLockTime {
+    /// The input can be included this many blocks after its UTXO
+    Blocks(u16),
+    /// The input can be included this many 512-second intervals after its
+    /// UTXO, by median time past
+    Time(u16),
+}
+
+impl RelativeLockTime {
+    /// A relative lock time in blocks
+    pub fn from_height(blocks: u16) -> RelativeLockTime {
+        RelativeLockTime::Blocks(blocks)
+    }
+
+    /// A relative lock time in 512-second intervals
+    pub fn from_512_second_intervals(intervals: u16) -> RelativeLockTime {
+        RelativeLockTime::Time(intervals)
+    }
+
+    /// A relative lock time of at least `seconds`, rounded up to a multiple
+    /// of 512 seconds
+    pub fn from_seconds_ceil(seconds: u32) -> Result<RelativeLockTime, Error> {
+        let intervals = (seconds / SEQUENCE_LOCKTIME_GRANULARITY)
+            + (seconds % SEQUENCE_LOCKTIME_GRANULARITY != 0) as u32;
+        if intervals > SEQUENCE_LOCKTIME_MASK {
+            return Err(Error::RelativeTimeOverflow(seconds));
+        }
+        Ok(RelativeLockTime::Time(intervals as u16))
+    }
+
+    /// The sequence number enforcing this relative lock time
+    pub fn to_sequence(self) -> Sequence {
+        match self {
+            RelativeLockTime::Blocks(n) => Sequence(n as u32),
+            RelativeLockTime::Time(n) => Sequence(SEQUENCE_LOCKTIME_TYPE_FLAG | n as u32),
+        }
+    }
+
+    /// Whether both lock times are in blocks or both are in time intervals
+    pub fn is_same_unit(self, other: RelativeLockTime) -> bool {
+        match (self, other) {
+            (RelativeLockTime::Blocks(_), RelativeLockTime::Blocks(_)) |
+            (RelativeLockTime::Time(_), RelativeLockTime::Time(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether an input with this relative lock time, spending a UTXO
+    /// confirmed at `utxo_height` in a block whose previous block has
+    /// median time past `utxo_mtp`, can be included in a block at `height`
+    /// whose previous block has median time past `mtp`, like
+    /// `SequenceLocks()` of Bitcoin Core
+    pub fn is_satisfied_by(self, utxo_height: u32, utxo_mtp: u32, height: u32, mtp: u32) -> bool {
+        match self {
+            RelativeLockTime::Blocks(n) => height as u64 >= utxo_height as u64 + n as u64,
+            RelativeLockTime::Time(n) => {
+                mtp as u64 >= utxo_mtp as u64 + n as u64 * SEQUENCE_LOCKTIME_GRANULARITY as u64
+            }
+        }
+    }
+
+    /// Whether an input with relative lock time `other` satisfies this
+    /// lock, as checked by `OP_CHECKSEQUENCEVERIFY`
+    pub fn is_implied_by(self, other: RelativeLockTime) -> bool {
+        match (self, other) {
+            (RelativeLockTime::Blocks(n), RelativeLockTime::Blocks(m)) |
+            (RelativeLockTime::Time(n), RelativeLockTime::Time(m)) => n <= m,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RelativeLockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RelativeLockTime::Blocks(n) => write!(f, "{} blocks", n),
+            RelativeLockTime::Time(n) => write!(f, "{} seconds", n as u32 * SEQUENCE_LOCKTIME_GRANULARITY),
+        }
+    }
+}
+
+/// The sequence number of a transaction input, the `nSequence` field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sequence(pub u32);
+
+impl Sequence {
+    /// The final sequence number, disabling the lock time and replaceability
+    pub const MAX: Sequence = Sequence(0xFFFFFFFF);
+    /// Zero, signaling replaceability with a relative lock time of 0 blocks
+    pub const ZERO: Sequence = Sequence(0);
+    /// Enables the transaction lock time without signaling replaceability
+    pub const ENABLE_LOCKTIME_NO_RBF: Sequence = Sequence(0xFFFFFFFE);
+    /// Signals replaceability, without relative lock time
+    pub const ENABLE_RBF_NO_LOCKTIME: Sequence = Sequence(0xFFFFFFFD);
+
+    /// Interprets a consensus-encoded `nSequence`
+    pub fn from_consensus(n: u32) -> Sequence {
+        Sequence(n)
+    }
+
+    /// The consensus-encoded `nSequence`
+    pub fn to_consensus_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Whether this is the final sequence number, which makes the
+    /// transaction lock time ineffective if all inputs have it
+    pub fn is_final(self) -> bool {
+        self == Sequence::MAX
+    }
+
+    /// Whether the sequence number signals replaceability (BIP125)
+    pub fn is_rbf(self) -> bool {
+        self.0 < Sequence::ENABLE_LOCKTIME_NO_RBF.0
+    }
+
+    /// Whether the sequence number enables the transaction lock time
+    pub fn enables_absolute_lock_time(self) -> bool {
+        !self.is_final()
+    }
+
+    /// Whether the sequence number holds a relative lock time. It is only
+    /// enforced for transactions of version 2 and above.
+    pub fn is_relative_lock_time(self) -> bool {
+        self.0 & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+    }
+
+    /// Whether the sequence number holds a relative lock time in blocks
+    pub fn is_height_locked(self) -> bool {
+        self.is_relative_lock_time() && self.0 & SEQUENCE_LOCKTIME_TYPE_FLAG == 0
+    }
+
+    /// Whether the sequence number holds a relative lock time in 512-second
+    /// intervals
+    pub fn is_time_locked(self) -> bool {
+        self.is_relative_lock_time() && self.0 & SEQUENCE_LOCKTIME_TYPE_FLAG != 0
+    }
+
+    /// The relative lock time held by the sequence number, ignoring the
+    /// bits without consensus meaning
+    pub fn to_relative_lock_time(self) -> Option<RelativeLockTime> {
+        let value = (self.0 & SEQUENCE_LOCKTIME_MASK) as u16;
+        if self.is_height_locked() {
+            Some(RelativeLockTime::Blocks(value))
+        } else if self.is_time_locked() {
+            Some(RelativeLockTime::Time(value))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Sequence {
+        Sequence::MAX
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::LowerHex for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl From<u32> for Sequence {
+    fn from(n: u32) -> Sequence {
+        Sequence(n)
+    }
+}
+
+impl From<Sequence> for u32 {
+    fn from(sequence: Sequence) -> u32 {
+        sequence.0
+    }
+}
+
+impl From<RelativeLockTime> for Sequence {
+    fn from(lock_time: RelativeLockTime) -> Sequence {
+        lock_time.to_sequence()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_time() {
+        assert_eq!(LockTime::from_consensus(0), LockTime::ZERO);
+        assert_eq!(LockTime::from_consensus(499_999_999), LockTime::Height(499_999_999));
+        assert_eq!(LockTime::from_consensus(500_000_000), LockTime::Time(500_000_000));
+        assert_eq!(LockTime::from_height(500_000_000), Err(Error::InvalidHeight(500_000_000)));
+        assert_eq!(LockTime::from_time(499_999_999), Err(Error::InvalidTime(499_999_999)));
+        assert_eq!(u32::from(LockTime::from_time(1_653_195_600).unwrap()), 1_653_195_600);
+
+        let height = LockTime::from_height(700_000).unwrap();
+        assert!(height.is_block_height());
+        assert!(!height.is_satisfied_by(700_000, 1_653_195_600));
+        assert!(height.is_satisfied_by(700_001, 0));
+        let time = LockTime::from_time(1_653_195_600).unwrap();
+        assert!(time.is_block_time());
+        assert!(!time.is_satisfied_by(u32::max_value(), 1_653_195_600));
+        assert!(time.is_satisfied_by(0, 1_653_195_601));
+
+        assert!(height.is_implied_by(LockTime::Height(700_000)));
+        assert!(!height.is_implied_by(LockTime::Height(699_999)));
+        assert!(!height.is_implied_by(time));
+        assert!(!time.is_same_unit(height));
+    }
+
+    #[test]
+    fn sequence() {
+        assert!(Sequence::MAX.is_final());
+        assert!(!Sequence::MAX.is_rbf());
+        assert!(!Sequence::MAX.enables_absolute_lock_time());
+        assert!(!Sequence::ENABLE_LOCKTIME_NO_RBF.is_rbf());
+        assert!(Sequence::ENABLE_LOCKTIME_NO_RBF.enables_absolute_lock_time());
+        assert!(Sequence::ENABLE_RBF_NO_LOCKTIME.is_rbf());
+        assert_eq!(Sequence::ENABLE_RBF_NO_LOCKTIME.to_relative_lock_time(), None);
+        assert!(Sequence::ZERO.is_rbf());
+        assert_eq!(Sequence::ZERO.to_relative_lock_time(), Some(RelativeLockTime::Blocks(0)));
+
+        let blocks = Sequence::from_consensus(144);
+        assert!(blocks.is_height_locked());
+        assert!(!blocks.is_time_locked());
+        assert_eq!(blocks.to_relative_lock_time(), Some(RelativeLockTime::from_height(144)));
+        let time = Sequence::from_consensus(0x00400000 | 0x7f0000 | 10);
+        assert!(time.is_time_locked());
+        assert_eq!(time.to_relative_lock_time(), Some(RelativeLockTime::from_512_second_intervals(10)));
+        assert_eq!(Sequence::from(RelativeLockTime::Time(10)), Sequence(0x0040000a));
+        assert_eq!(format!("{:x}", Sequence::MAX), "ffffffff");
+    }
+
+    #[test]
+    fn relative_lock_time() {
+        assert_eq!(RelativeLockTime::from_seconds_ceil(512), Ok(RelativeLockTime::Time(1)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(513), Ok(RelativeLockTime::Time(2)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(0xffff * 512), Ok(RelativeLockTime::Time(0xffff)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(0xffff * 512 + 1), Err(Error::RelativeTimeOverflow(0xffff * 512 + 1)));
+        assert_eq!(RelativeLockTime::Time(2).to_string(), "1024 seconds");
+
+        let blocks = RelativeLockTime::from_height(10);
+        assert!(!blocks.is_satisfied_by(100, 0, 109, 0));
+        assert!(blocks.is_satisfied_by(100, 0, 110, 0));
+        let time = RelativeLockTime::from_512_second_intervals(2);
+        assert!(!time.is_satisfied_by(0, 1_600_000_000, 1000, 1_600_001_023));
+        assert!(time.is_satisfied_by(0, 1_600_000_000, 0, 1_600_001_024));
+
+        assert!(blocks.is_implied_by(RelativeLockTime::Blocks(10)));
+        assert!(!blocks.is_implied_by(RelativeLockTime::Blocks(9)));
+        assert!(!blocks.is_implied_by(RelativeLockTime::Time(10)));
+        assert!(!time.is_same_unit(blocks));
+    }
+}
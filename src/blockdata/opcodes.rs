@@ -417,6 +417,8 @@ pub mod all {
     // Every other opcode acts as OP_RETURN
     /// Synonym for OP_RETURN
     pub const OP_RETURN_186: All = All {code: 0xba};
+    /// Tapscript OP_CHECKSIGADD (BIP342), which reuses the OP_RETURN_186 code
+    pub const OP_CHECKSIGADD: All = OP_RETURN_186;
     /// Synonym for OP_RETURN
     pub const OP_RETURN_187: All = All {code: 0xbb};
     /// Synonym for OP_RETURN
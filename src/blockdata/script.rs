@@ -31,13 +31,15 @@ use std::{error, fmt, io, str};
 
 use hash_types::{PubkeyHash, WPubkeyHash, ScriptHash, WScriptHash};
 use blockdata::opcodes;
+use blockdata::interpreter;
+use blockdata::locktime::{LockTime, Sequence};
 use consensus::{encode, Decodable, Encodable};
 use hashes::{Hash, hex};
 #[cfg(feature="bitcoinconsensus")] use bitcoinconsensus;
 #[cfg(feature="bitcoinconsensus")] use std::convert;
-#[cfg(feature="bitcoinconsensus")] use OutPoint;
+use OutPoint;
 
-use util::key::PublicKey;
+use util::key::{PublicKey, XOnlyPublicKey};
 
 #[derive(Clone, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
 /// A Bitcoin script
@@ -108,10 +110,93 @@ pub enum Error {
     EarlyEndOfScript,
     /// Tried to read an array off the stack as a number when it was more than 4 bytes
     NumericOverflow,
+    /// Script evaluated without error but finished with a false/empty top stack element
+    EvalFalse,
+    /// An executed `OP_RETURN` was encountered
+    OpReturn,
+    /// The script exceeds the maximum script size
+    ScriptSize,
+    /// A push exceeds the maximum element size of 520 bytes
+    PushSize,
+    /// The script exceeds the maximum number of non-push operations
+    OpCount,
+    /// The combined main and alt stacks exceed the maximum stack size
+    StackSize,
+    /// The signature count of a `CHECKMULTISIG` is negative or exceeds the key count
+    SigCount,
+    /// The public key count of a `CHECKMULTISIG` is negative or exceeds the limit
+    PubkeyCount,
+    /// An `OP_VERIFY` failed
+    Verify,
+    /// An `OP_EQUALVERIFY` failed
+    EqualVerify,
+    /// An `OP_CHECKMULTISIGVERIFY` failed
+    CheckMultisigVerify,
+    /// An `OP_CHECKSIGVERIFY` failed
+    CheckSigVerify,
+    /// An `OP_NUMEQUALVERIFY` failed
+    NumEqualVerify,
+    /// An opcode is missing or unknown
+    BadOpcode,
+    /// A disabled opcode was encountered
+    DisabledOpcode,
+    /// An operation was attempted with too few elements on the stack
+    InvalidStackOperation,
+    /// An operation was attempted with too few elements on the alt stack
+    InvalidAltstackOperation,
+    /// An `OP_IF`/`OP_NOTIF`/`OP_ELSE`/`OP_ENDIF` is unbalanced
+    UnbalancedConditional,
+    /// A locktime argument of `OP_CHECKLOCKTIMEVERIFY`/`OP_CHECKSEQUENCEVERIFY` is negative
+    NegativeLocktime,
+    /// The locktime requirement of `OP_CHECKLOCKTIMEVERIFY`/`OP_CHECKSEQUENCEVERIFY` is not met
+    UnsatisfiedLocktime,
+    /// A signature has an undefined sighash type (`STRICTENC`)
+    SigHashType,
+    /// A signature is not strict DER (`DERSIG`)
+    SigDer,
+    /// A push or number is not minimally encoded (`MINIMALDATA`)
+    MinimalData,
+    /// A scriptSig contains non-push operations (`SIGPUSHONLY` or P2SH)
+    SigPushOnly,
+    /// A signature has a high S value (`LOW_S`)
+    SigHighS,
+    /// The dummy element of a `CHECKMULTISIG` is not empty (`NULLDUMMY`)
+    SigNullDummy,
+    /// A public key is neither compressed nor uncompressed (`STRICTENC`)
+    PubkeyType,
+    /// The stack is not clean after evaluation (`CLEANSTACK`)
+    CleanStack,
+    /// The argument of `OP_IF`/`OP_NOTIF` is not minimal (`MINIMALIF`)
+    MinimalIf,
+    /// A failing signature check had a non-empty signature (`NULLFAIL`)
+    SigNullFail,
+    /// An upgradable NOP was executed (`DISCOURAGE_UPGRADABLE_NOPS`)
+    DiscourageUpgradableNops,
+    /// An unknown witness program version was spent
+    /// (`DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM`)
+    DiscourageUpgradableWitnessProgram,
+    /// A version 0 witness program has a length other than 20 or 32 bytes
+    WitnessProgramWrongLength,
+    /// A witness program was spent with an empty witness
+    WitnessProgramWitnessEmpty,
+    /// The witness does not match the witness program
+    WitnessProgramMismatch,
+    /// A native witness program was spent with a non-empty scriptSig
+    WitnessMalleated,
+    /// A P2SH-wrapped witness program was spent with a scriptSig other than the
+    /// single push of the redeem script
+    WitnessMalleatedP2sh,
+    /// A witness was provided for an input which is not a witness program
+    WitnessUnexpected,
+    /// A witness script uses an uncompressed public key (`WITNESS_PUBKEYTYPE`)
+    WitnessPubkeyType,
+    /// `OP_CODESEPARATOR` was used in a non-segwit script (`CONST_SCRIPTCODE`)
+    OpCodeSeparator,
+    /// A signature was found in the script code (`CONST_SCRIPTCODE`)
+    SigFindAndDelete,
     #[cfg(feature="bitcoinconsensus")]
     /// Error validating the script with bitcoinconsensus library
     BitcoinConsensus(bitcoinconsensus::Error),
-    #[cfg(feature="bitcoinconsensus")]
     /// Can not find the spent output
     UnknownSpentOutput(OutPoint),
     #[cfg(feature="bitcoinconsensus")]
@@ -125,9 +210,49 @@ impl fmt::Display for Error {
             Error::NonMinimalPush => "non-minimal datapush",
             Error::EarlyEndOfScript => "unexpected end of script",
             Error::NumericOverflow => "numeric overflow (number on stack larger than 4 bytes)",
+            Error::EvalFalse => "script evaluated without error but finished with a false/empty top stack element",
+            Error::OpReturn => "OP_RETURN was encountered",
+            Error::ScriptSize => "script is too big",
+            Error::PushSize => "push value size limit exceeded",
+            Error::OpCount => "operation limit exceeded",
+            Error::StackSize => "stack size limit exceeded",
+            Error::SigCount => "signature count negative or greater than pubkey count",
+            Error::PubkeyCount => "pubkey count negative or limit exceeded",
+            Error::Verify => "script failed an OP_VERIFY operation",
+            Error::EqualVerify => "script failed an OP_EQUALVERIFY operation",
+            Error::CheckMultisigVerify => "script failed an OP_CHECKMULTISIGVERIFY operation",
+            Error::CheckSigVerify => "script failed an OP_CHECKSIGVERIFY operation",
+            Error::NumEqualVerify => "script failed an OP_NUMEQUALVERIFY operation",
+            Error::BadOpcode => "opcode missing or not understood",
+            Error::DisabledOpcode => "attempted to use a disabled opcode",
+            Error::InvalidStackOperation => "operation not valid with the current stack size",
+            Error::InvalidAltstackOperation => "operation not valid with the current altstack size",
+            Error::UnbalancedConditional => "invalid OP_IF construction",
+            Error::NegativeLocktime => "negative locktime",
+            Error::UnsatisfiedLocktime => "locktime requirement not satisfied",
+            Error::SigHashType => "signature hash type missing or not understood",
+            Error::SigDer => "non-canonical DER signature",
+            Error::MinimalData => "data push larger than necessary",
+            Error::SigPushOnly => "only push operators allowed in signatures",
+            Error::SigHighS => "non-canonical signature: S value is unnecessarily high",
+            Error::SigNullDummy => "dummy CHECKMULTISIG argument must be zero",
+            Error::PubkeyType => "public key is neither compressed or uncompressed",
+            Error::CleanStack => "stack size must be exactly one after execution",
+            Error::MinimalIf => "OP_IF/NOTIF argument must be minimal",
+            Error::SigNullFail => "signature must be zero for failed CHECK(MULTI)SIG operation",
+            Error::DiscourageUpgradableNops => "NOPx reserved for soft-fork upgrades",
+            Error::DiscourageUpgradableWitnessProgram => "witness version reserved for soft-fork upgrades",
+            Error::WitnessProgramWrongLength => "witness program has incorrect length",
+            Error::WitnessProgramWitnessEmpty => "witness program was passed an empty witness",
+            Error::WitnessProgramMismatch => "witness program hash mismatch",
+            Error::WitnessMalleated => "witness requires empty scriptSig",
+            Error::WitnessMalleatedP2sh => "witness requires only-redeemscript scriptSig",
+            Error::WitnessUnexpected => "witness provided for non-witness script",
+            Error::WitnessPubkeyType => "using non-compressed keys in segwit",
+            Error::OpCodeSeparator => "using OP_CODESEPARATOR in non-witness script",
+            Error::SigFindAndDelete => "signature is found in scriptCode",
             #[cfg(feature="bitcoinconsensus")]
             Error::BitcoinConsensus(ref _n) => "bitcoinconsensus verification failed",
-            #[cfg(feature="bitcoinconsensus")]
             Error::UnknownSpentOutput(ref _point) => "unknown spent output Transaction::verify()",
             #[cfg(feature="bitcoinconsensus")]
             Error::SerializationError => "can not serialize the spending transaction in Transaction::verify()",
@@ -148,12 +273,12 @@ impl convert::From<bitcoinconsensus::Error> for Error {
     }
 }
 /// Helper to encode an integer in script format
-fn build_scriptint(n: i64) -> Vec<u8> {
+pub(crate) fn build_scriptint(n: i64) -> Vec<u8> {
     if n == 0 { return vec![] }
 
     let neg = n < 0;
 
-    let mut abs = if neg { -n } else { n } as usize;
+    let mut abs = if neg { -n } else { n } as u64;
     let mut v = vec![];
     while abs > 0xFF {
         v.push((abs & 0xFF) as u8);
@@ -389,6 +514,14 @@ impl Script {
             self.0[1] == opcodes::all::OP_PUSHBYTES_20.into_u8()
     }
 
+    /// Checks whether a script pubkey is a p2tr output
+    #[inline]
+    pub fn is_v1_p2tr(&self) -> bool {
+        self.0.len() == 34 &&
+            self.0[0] == opcodes::all::OP_PUSHNUM_1.into_u8() &&
+            self.0[1] == opcodes::all::OP_PUSHBYTES_32.into_u8()
+    }
+
     /// Check if this is an OP_RETURN output
     pub fn is_op_return (&self) -> bool {
         !self.0.is_empty() && (opcodes::All::from(self.0[0]) == opcodes::all::OP_RETURN)
@@ -400,6 +533,70 @@ impl Script {
                                opcodes::All::from(self.0[0]).classify() == opcodes::Class::IllegalOp)
     }
 
+    /// The minimum value in satoshis an output with this script must have
+    /// not to be considered dust by Bitcoin Core's default relay policy,
+    /// i.e. what it costs to create and spend it at a 3 sat/vbyte dust
+    /// relay fee. Zero for `OP_RETURN` outputs.
+    pub fn dust_value(&self) -> u64 {
+        // Mirrors GetDustThreshold() of Bitcoin Core
+        const DUST_RELAY_TX_FEE: u64 = 3000;
+        if self.is_op_return() {
+            return 0;
+        }
+        let output_size = 8 + encode::VarInt(self.len() as u64).len() + self.len();
+        let spend_size = if self.is_witness_program() {
+            32 + 4 + 1 + 107 / 4 + 4
+        } else {
+            32 + 4 + 1 + 107 + 4
+        };
+        (output_size + spend_size) as u64 * DUST_RELAY_TX_FEE / 1000
+    }
+
+    /// Counts the signature operations of the script, an `OP_CHECKMULTISIG`
+    /// directly preceded by `OP_1` to `OP_16` counting as that many. This is
+    /// how P2SH redeem scripts and witness scripts are counted.
+    pub fn count_sigops(&self) -> usize {
+        self.count_sigops_internal(true)
+    }
+
+    /// Counts the signature operations of the script the legacy way, every
+    /// `OP_CHECKMULTISIG` counting as the maximum number of public keys.
+    pub fn count_sigops_legacy(&self) -> usize {
+        self.count_sigops_internal(false)
+    }
+
+    /// Mirrors CScript::GetSigOpCount() of Bitcoin Core
+    fn count_sigops_internal(&self, accurate: bool) -> usize {
+        let mut n = 0;
+        let mut last_op = None;
+        for instruction in self.instructions() {
+            let op = match instruction {
+                Ok(Instruction::Op(op)) => op,
+                Ok(Instruction::PushBytes(_)) => {
+                    last_op = None;
+                    continue;
+                }
+                Err(_) => break,
+            };
+            match op {
+                opcodes::all::OP_CHECKSIG | opcodes::all::OP_CHECKSIGVERIFY => n += 1,
+                opcodes::all::OP_CHECKMULTISIG | opcodes::all::OP_CHECKMULTISIGVERIFY => {
+                    let pushnum = last_op.map(opcodes::All::into_u8).filter(|&code| {
+                        code >= opcodes::all::OP_PUSHNUM_1.into_u8() &&
+                            code <= opcodes::all::OP_PUSHNUM_16.into_u8()
+                    });
+                    n += match pushnum {
+                        Some(code) if accurate => (code - opcodes::all::OP_PUSHNUM_1.into_u8() + 1) as usize,
+                        _ => interpreter::MAX_PUBKEYS_PER_MULTISIG,
+                    };
+                }
+                _ => {}
+            }
+            last_op = Some(op);
+        }
+        n
+    }
+
     /// Iterate over the script in the form of `Instruction`s, which are an enum covering
     /// opcodes, datapushes and errors. At most one error will be returned and then the
     /// iterator will end. To instead iterate over the script as sequence of bytes, treat
@@ -669,6 +866,18 @@ impl Builder {
         else { self.push_scriptint(data) }
     }
 
+    /// Adds instructions to push a lock time, as the argument of an
+    /// `OP_CHECKLOCKTIMEVERIFY`
+    pub fn push_lock_time(self, lock_time: LockTime) -> Builder {
+        self.push_int(lock_time.to_consensus_u32() as i64)
+    }
+
+    /// Adds instructions to push a sequence number, as the argument of an
+    /// `OP_CHECKSEQUENCEVERIFY`
+    pub fn push_sequence(self, sequence: Sequence) -> Builder {
+        self.push_int(sequence.to_consensus_u32() as i64)
+    }
+
     /// Adds instructions to push an integer onto the stack, using the explicit
     /// encoding regardless of the availability of dedicated opcodes.
     pub fn push_scriptint(self, data: i64) -> Builder {
@@ -713,6 +922,11 @@ impl Builder {
         }
     }
 
+    /// Pushes a 32-byte x-only public key, as used in tapscript
+    pub fn push_x_only_key(self, key: &XOnlyPublicKey) -> Builder {
+        self.push_slice(&key.serialize()[..])
+    }
+
     /// Adds a single opcode to the script
     pub fn push_opcode(mut self, data: opcodes::All) -> Builder {
         self.0.push(data.into_u8());
@@ -886,6 +1100,9 @@ mod test {
         let keystr = "41042e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af191923a2964c177f5b5923ae500fca49e99492d534aa3759d6b25a8bc971b133";
         let key = PublicKey::from_str(&keystr[2..]).unwrap();
         script = script.push_key(&key); comp.extend(Vec::from_hex(keystr).unwrap().iter().cloned()); assert_eq!(&script[..], &comp[..]);
+        let keystr = "202e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af";
+        let key = XOnlyPublicKey::from_str(&keystr[2..]).unwrap();
+        script = script.push_x_only_key(&key); comp.extend(Vec::from_hex(keystr).unwrap().iter().cloned()); assert_eq!(&script[..], &comp[..]);
 
         // opcodes
         script = script.push_opcode(opcodes::all::OP_CHECKSIG); comp.push(0xACu8); assert_eq!(&script[..], &comp[..]);
@@ -936,6 +1153,28 @@ mod test {
         assert_eq!(op_return.to_hex(), "6a24aa21a9ed20280f53f2d21663cac89e6bd2ad19edbabb048cda08e73ed19e9268d0afea2a");
     }
 
+    #[test]
+    fn script_builder_lock_times() {
+        use blockdata::locktime::RelativeLockTime;
+
+        let cltv = |lock_time| Builder::new()
+            .push_lock_time(lock_time)
+            .push_opcode(opcodes::all::OP_CLTV)
+            .into_script();
+        assert_eq!(cltv(LockTime::Height(16)).to_hex(), "60b1");
+        assert_eq!(cltv(LockTime::Height(700_000)).to_hex(), "0360ae0ab1");
+        assert_eq!(cltv(LockTime::Time(1_653_195_600)).to_hex(), "0450c38962b1");
+
+        let csv = |sequence| Builder::new()
+            .push_sequence(sequence)
+            .push_opcode(opcodes::all::OP_CSV)
+            .into_script();
+        assert_eq!(csv(RelativeLockTime::Blocks(144).into()).to_hex(), "029000b2");
+        assert_eq!(csv(RelativeLockTime::Time(10).into()).to_hex(), "030a0040b2");
+        // Values with the top bit set need a fifth byte not to be negative
+        assert_eq!(csv(Sequence(0x80000000)).to_hex(), "050000008000b2");
+    }
+
     #[test]
     fn script_builder_verify() {
         let simple = Builder::new()
@@ -1049,6 +1288,17 @@ mod test {
         assert_eq!(hex_script!("6aa9149eb21980dc9d413d8eac27314938b9da920ee53e87").is_provably_unspendable(), true);
     }
 
+    #[test]
+    fn dust_value_test() {
+        let p2pkh = Script::from_hex("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").unwrap();
+        assert_eq!(p2pkh.dust_value(), 546);
+        let p2wpkh = Script::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+        assert_eq!(p2wpkh.dust_value(), 294);
+        let p2tr = Script::from_hex("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343").unwrap();
+        assert_eq!(p2tr.dust_value(), 330);
+        assert_eq!(Script::from_hex("6a").unwrap().dust_value(), 0);
+    }
+
     #[test]
     fn op_return_test() {
         assert_eq!(hex_script!("6aa9149eb21980dc9d413d8eac27314938b9da920ee53e87").is_op_return(), true);
@@ -1056,6 +1306,22 @@ mod test {
         assert_eq!(hex_script!("").is_op_return(), false);
     }
 
+    #[test]
+    fn count_sigops_test() {
+        // p2pkh and p2pk
+        assert_eq!(hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac").count_sigops(), 1);
+        assert_eq!(hex_script!("410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac").count_sigops_legacy(), 1);
+        // 2-of-3 multisig
+        let multisig = hex_script!("522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d42103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d453ae");
+        assert_eq!(multisig.count_sigops(), 3);
+        assert_eq!(multisig.count_sigops_legacy(), 20);
+        // CHECKMULTISIG not preceded by a small number, then CHECKSIGVERIFY
+        assert_eq!(hex_script!("00aead").count_sigops(), 21);
+        // counting stops at an invalid push
+        assert_eq!(hex_script!("acac4c").count_sigops(), 2);
+        assert_eq!(hex_script!("").count_sigops(), 0);
+    }
+
     #[test]
     #[cfg(feature = "serde")]
     fn script_json_serialize() {
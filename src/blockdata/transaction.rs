@@ -30,9 +30,12 @@ use hashes::{self, Hash, sha256d};
 use hashes::hex::FromHex;
 
 use util::endian;
+use util::amount::{VSize, Weight};
 use blockdata::constants::WITNESS_SCALE_FACTOR;
+use blockdata::locktime::{LockTime, RelativeLockTime, Sequence};
 #[cfg(feature="bitcoinconsensus")] use blockdata::script;
-use blockdata::script::Script;
+use blockdata::opcodes;
+use blockdata::script::{Instruction, Script};
 use consensus::{encode, Decodable, Encodable};
 use hash_types::{SigHash, Txid, Wtxid};
 use VarInt;
@@ -205,6 +208,19 @@ impl Default for TxIn {
     }
 }
 
+impl TxIn {
+    /// Whether the input signals replaceability, per BIP125
+    pub fn is_rbf(&self) -> bool {
+        Sequence(self.sequence).is_rbf()
+    }
+
+    /// The BIP68 relative lock time of the input, which is only enforced
+    /// in transactions of version 2 and above
+    pub fn relative_lock_time(&self) -> Option<RelativeLockTime> {
+        Sequence(self.sequence).to_relative_lock_time()
+    }
+}
+
 /// A transaction output, which defines new coins to be created from old ones.
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -394,6 +410,11 @@ impl Transaction {
     /// get evaluated and which don't, which we don't have the information to
     /// determine.
     ///
+    /// When `SIGHASH_SINGLE` is used on an input without a corresponding output,
+    /// consensus signs the hash `0000...0001` instead, which is returned as is.
+    /// [`util::sighash::SigHashCache`](::util::sighash::SigHashCache) reports
+    /// this case as an error instead.
+    ///
     /// # Panics
     /// Panics if `input_index` is greater than or equal to `self.input.len()`
     ///
@@ -403,6 +424,13 @@ impl Transaction {
         script_pubkey: &Script,
         sighash_u32: u32
     ) -> SigHash {
+        let (sighash, _) = SigHashType::from_u32(sighash_u32).split_anyonecanpay_flag();
+        if sighash == SigHashType::Single && input_index >= self.output.len() {
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            return SigHash::from_inner(one);
+        }
+
         let mut engine = SigHash::engine();
         self.encode_signing_data_to(&mut engine, input_index, script_pubkey, sighash_u32)
             .expect("engines don't error");
@@ -418,6 +446,18 @@ impl Transaction {
         self.get_scaled_size(WITNESS_SCALE_FACTOR)
     }
 
+    /// The BIP141 weight of this transaction, see [Transaction::get_weight]
+    #[inline]
+    pub fn weight(&self) -> Weight {
+        Weight::from_wu(self.get_weight() as u64)
+    }
+
+    /// The virtual size of this transaction: its weight divided by 4, rounded up
+    #[inline]
+    pub fn vsize(&self) -> VSize {
+        self.weight().to_vsize()
+    }
+
     /// Gets the regular byte-wise consensus-serialized size of this transaction.
     #[inline]
     pub fn get_size(&self) -> usize {
@@ -478,10 +518,98 @@ impl Transaction {
         Ok(())
     }
 
+    /// Whether the lock time of the transaction is enforced: it is not zero
+    /// and not all inputs have a final sequence number
+    pub fn is_lock_time_enabled(&self) -> bool {
+        self.lock_time != 0 && self.input.iter().any(|i| !Sequence(i.sequence).is_final())
+    }
+
+    /// Whether the transaction can be included in a block at `height` whose
+    /// previous block has median time past `mtp`, as far as its lock time is
+    /// concerned. Relative lock times are not checked as they depend on the
+    /// spent outputs.
+    pub fn is_final(&self, height: u32, mtp: u32) -> bool {
+        !self.is_lock_time_enabled() || LockTime::from_consensus(self.lock_time).is_satisfied_by(height, mtp)
+    }
+
+    /// Whether any input signals replaceability, per BIP125
+    pub fn is_explicitly_rbf(&self) -> bool {
+        self.input.iter().any(TxIn::is_rbf)
+    }
+
     /// Is this a coin base transaction?
     pub fn is_coin_base(&self) -> bool {
         self.input.len() == 1 && self.input[0].previous_output.is_null()
     }
+
+    /// Counts the signature operations of the input and output scripts the
+    /// legacy way, see [Script::count_sigops_legacy]
+    pub fn legacy_sigop_count(&self) -> usize {
+        let input: usize = self.input.iter().map(|i| i.script_sig.count_sigops_legacy()).sum();
+        let output: usize = self.output.iter().map(|o| o.script_pubkey.count_sigops_legacy()).sum();
+        input + output
+    }
+
+    /// The BIP141 signature operation cost of the transaction: its legacy
+    /// sigops scaled by the witness scale factor, plus the sigops of the P2SH
+    /// redeem scripts and witness programs it spends. Inputs whose spent
+    /// output is not returned by `spent` are not counted.
+    pub fn total_sigop_cost<S>(&self, mut spent: S) -> usize
+        where S: FnMut(&OutPoint) -> Option<TxOut> {
+        let mut cost = self.legacy_sigop_count() * WITNESS_SCALE_FACTOR;
+        if self.is_coin_base() {
+            return cost;
+        }
+        for input in &self.input {
+            let prevout = match spent(&input.previous_output) {
+                Some(prevout) => prevout,
+                None => continue,
+            };
+            let redeem_script = if prevout.script_pubkey.is_p2sh() {
+                p2sh_redeem_script(&input.script_sig)
+            } else {
+                None
+            };
+            let program = match redeem_script {
+                Some(ref redeem_script) => {
+                    cost += redeem_script.count_sigops() * WITNESS_SCALE_FACTOR;
+                    redeem_script
+                }
+                None => &prevout.script_pubkey,
+            };
+            cost += witness_sigops(program, &input.witness);
+        }
+        cost
+    }
+}
+
+/// The last push of a push-only scriptSig, which is the redeem script when
+/// spending a P2SH output
+fn p2sh_redeem_script(script_sig: &Script) -> Option<Script> {
+    let mut last = None;
+    for instruction in script_sig.instructions() {
+        match instruction {
+            Ok(Instruction::PushBytes(data)) => last = Some(data),
+            Ok(Instruction::Op(op)) if op.into_u8() <= opcodes::all::OP_PUSHNUM_16.into_u8() => {
+                last = Some(&[][..])
+            }
+            _ => return None,
+        }
+    }
+    last.map(|data| Script::from(data.to_vec()))
+}
+
+/// Signature operations of a witness program spent with `witness`, zero if
+/// `script` is not a known witness program
+fn witness_sigops(script: &Script, witness: &[Vec<u8>]) -> usize {
+    if !script.is_witness_program() || script[0] != 0 {
+        return 0;
+    }
+    match script.len() - 2 {
+        20 => 1,
+        32 => witness.last().map_or(0, |w| Script::from(w.clone()).count_sigops()),
+        _ => 0,
+    }
 }
 
 impl_consensus_encoding!(TxOut, value, script_pubkey);
@@ -701,6 +829,7 @@ mod tests {
     use blockdata::script::Script;
     use consensus::encode::serialize;
     use consensus::encode::deserialize;
+    use util::amount::{VSize, Weight};
 
     use hashes::Hash;
     use hashes::hex::FromHex;
@@ -769,6 +898,29 @@ mod tests {
         assert!(!tx.is_coin_base());
     }
 
+    #[test]
+    fn test_lock_time() {
+        use blockdata::locktime::RelativeLockTime;
+
+        let mut tx = Transaction { version: 2, lock_time: 700_000, input: vec![TxIn::default()], output: vec![] };
+        assert!(!tx.is_lock_time_enabled());
+        assert!(tx.is_final(0, 0));
+        assert!(!tx.is_explicitly_rbf());
+        assert_eq!(tx.input[0].relative_lock_time(), None);
+
+        tx.input[0].sequence = 0xFFFFFFFE;
+        assert!(tx.is_lock_time_enabled());
+        assert!(!tx.is_final(700_000, 0));
+        assert!(tx.is_final(700_001, 0));
+        assert!(!tx.is_explicitly_rbf());
+
+        tx.input[0].sequence = 10;
+        assert!(tx.is_explicitly_rbf());
+        assert_eq!(tx.input[0].relative_lock_time(), Some(RelativeLockTime::Blocks(10)));
+        tx.lock_time = 0;
+        assert!(!tx.is_lock_time_enabled());
+    }
+
     #[test]
     fn test_nonsegwit_transaction() {
         let tx_bytes = Vec::from_hex("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap();
@@ -824,6 +976,8 @@ mod tests {
         assert_eq!(format!("{:x}", realtx.wtxid()),
                    "80b7d8a82d5d5bf92905b06f2014dd699e03837ca172e3a59d51426ebbe3e7f5".to_string());
         assert_eq!(realtx.get_weight(), 442);
+        assert_eq!(realtx.weight(), Weight::from_wu(442));
+        assert_eq!(realtx.vsize(), VSize::from_vb(111));
         assert_eq!(realtx.get_size(), tx_bytes.len());
     }
 
@@ -1337,6 +1491,109 @@ mod tests {
             _ => panic!("Wrong error type"),
         }
     }
+
+    #[test]
+    fn test_sigop_cost() {
+        use blockdata::script::Builder;
+        use super::TxOut;
+
+        let multisig = hex_script!("522102632178d046673c9729d828cfee388e121f497707f810c131e0d3fc0fe0bd66d62103a0951ec7d3a9da9de171617026442fcd30f34d66100fab539853b43f508787d452ae");
+        let outpoint = |vout| OutPoint::new(Txid::hash(&[1]), vout);
+        let input = |vout, script_sig: Script, witness: Vec<Vec<u8>>| TxIn {
+            previous_output: outpoint(vout),
+            script_sig: script_sig,
+            sequence: 0xffffffff,
+            witness: witness,
+        };
+        let p2wpkh = hex_script!("0014ee61d57ab51b9d212335b1dba62794ac20d2bcf9");
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                // p2pkh
+                input(0, Builder::new().push_slice(&[1; 71]).push_slice(&[2; 33]).into_script(), vec![]),
+                // p2sh multisig
+                input(1, Builder::new().push_int(0).push_slice(&[1; 71]).push_slice(&multisig[..]).into_script(), vec![]),
+                // p2wsh multisig
+                input(2, Script::new(), vec![vec![], vec![1; 71], multisig.to_bytes()]),
+                // p2sh-p2wpkh
+                input(3, Builder::new().push_slice(&p2wpkh[..]).into_script(), vec![vec![1; 71], vec![2; 33]]),
+                // unknown
+                input(4, Script::new(), vec![]),
+            ],
+            output: vec![TxOut { value: 0, script_pubkey: hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac") }],
+        };
+        assert_eq!(tx.legacy_sigop_count(), 1);
+
+        let spent = |point: &OutPoint| {
+            let script_pubkey = match point.vout {
+                0 => hex_script!("76a914ee61d57ab51b9d212335b1dba62794ac20d2bcf988ac"),
+                1 => multisig.to_p2sh(),
+                2 => multisig.to_v0_p2wsh(),
+                3 => p2wpkh.to_p2sh(),
+                _ => return None,
+            };
+            Some(TxOut { value: 0, script_pubkey: script_pubkey })
+        };
+        // 1 legacy output sigop and 2 redeem script sigops, scaled, plus 2
+        // witness script sigops and 1 for p2wpkh
+        assert_eq!(tx.total_sigop_cost(spent), 4 + 8 + 2 + 1);
+    }
+
+    #[test]
+    fn test_transaction_verify_scripts() {
+        use hashes::hex::FromHex;
+        use std::collections::HashMap;
+        use blockdata::interpreter::VerifyFlags;
+        use blockdata::script;
+        // a random recent segwit transaction from blockchain using both old and segwit inputs
+        let mut spending: Transaction = deserialize(Vec::from_hex("020000000001031cfbc8f54fbfa4a33a30068841371f80dbfe166211242213188428f437445c91000000006a47304402206fbcec8d2d2e740d824d3d36cc345b37d9f65d665a99f5bd5c9e8d42270a03a8022013959632492332200c2908459547bf8dbf97c65ab1a28dec377d6f1d41d3d63e012103d7279dfb90ce17fe139ba60a7c41ddf605b25e1c07a4ddcb9dfef4e7d6710f48feffffff476222484f5e35b3f0e43f65fc76e21d8be7818dd6a989c160b1e5039b7835fc00000000171600140914414d3c94af70ac7e25407b0689e0baa10c77feffffffa83d954a62568bbc99cc644c62eb7383d7c2a2563041a0aeb891a6a4055895570000000017160014795d04cc2d4f31480d9a3710993fbd80d04301dffeffffff06fef72f000000000017a91476fd7035cd26f1a32a5ab979e056713aac25796887a5000f00000000001976a914b8332d502a529571c6af4be66399cd33379071c588ac3fda0500000000001976a914fc1d692f8de10ae33295f090bea5fe49527d975c88ac522e1b00000000001976a914808406b54d1044c429ac54c0e189b0d8061667e088ac6eb68501000000001976a914dfab6085f3a8fb3e6710206a5a959313c5618f4d88acbba20000000000001976a914eb3026552d7e3f3073457d0bee5d4757de48160d88ac0002483045022100bee24b63212939d33d513e767bc79300051f7a0d433c3fcf1e0e3bf03b9eb1d70220588dc45a9ce3a939103b4459ce47500b64e23ab118dfc03c9caa7d6bfc32b9c601210354fd80328da0f9ae6eef2b3a81f74f9a6f66761fadf96f1d1d22b1fd6845876402483045022100e29c7e3a5efc10da6269e5fc20b6a1cb8beb92130cc52c67e46ef40aaa5cac5f0220644dd1b049727d991aece98a105563416e10a5ac4221abac7d16931842d5c322012103960b87412d6e169f30e12106bdf70122aabb9eb61f455518322a18b920a4dfa887d30700")
+            .unwrap().as_slice()).unwrap();
+        let spent1: Transaction = deserialize(Vec::from_hex("020000000001040aacd2c49f5f3c0968cfa8caf9d5761436d95385252e3abb4de8f5dcf8a582f20000000017160014bcadb2baea98af0d9a902e53a7e9adff43b191e9feffffff96cd3c93cac3db114aafe753122bd7d1afa5aa4155ae04b3256344ecca69d72001000000171600141d9984579ceb5c67ebfbfb47124f056662fe7adbfeffffffc878dd74d3a44072eae6178bb94b9253177db1a5aaa6d068eb0e4db7631762e20000000017160014df2a48cdc53dae1aba7aa71cb1f9de089d75aac3feffffffe49f99275bc8363f5f593f4eec371c51f62c34ff11cc6d8d778787d340d6896c0100000017160014229b3b297a0587e03375ab4174ef56eeb0968735feffffff03360d0f00000000001976a9149f44b06f6ee92ddbc4686f71afe528c09727a5c788ac24281b00000000001976a9140277b4f68ff20307a2a9f9b4487a38b501eb955888ac227c0000000000001976a9148020cd422f55eef8747a9d418f5441030f7c9c7788ac0247304402204aa3bd9682f9a8e101505f6358aacd1749ecf53a62b8370b97d59243b3d6984f02200384ad449870b0e6e89c92505880411285ecd41cf11e7439b973f13bad97e53901210205b392ffcb83124b1c7ce6dd594688198ef600d34500a7f3552d67947bbe392802473044022033dfd8d190a4ae36b9f60999b217c775b96eb10dee3a1ff50fb6a75325719106022005872e4e36d194e49ced2ebcf8bb9d843d842e7b7e0eb042f4028396088d292f012103c9d7cbf369410b090480de2aa15c6c73d91b9ffa7d88b90724614b70be41e98e0247304402207d952de9e59e4684efed069797e3e2d993e9f98ec8a9ccd599de43005fe3f713022076d190cc93d9513fc061b1ba565afac574e02027c9efbfa1d7b71ab8dbb21e0501210313ad44bc030cc6cb111798c2bf3d2139418d751c1e79ec4e837ce360cc03b97a024730440220029e75edb5e9413eb98d684d62a077b17fa5b7cc19349c1e8cc6c4733b7b7452022048d4b9cae594f03741029ff841e35996ef233701c1ea9aa55c301362ea2e2f68012103590657108a72feb8dc1dec022cf6a230bb23dc7aaa52f4032384853b9f8388baf9d20700")
+            .unwrap().as_slice()).unwrap();
+        let spent2: Transaction = deserialize(Vec::from_hex("0200000000010166c3d39490dc827a2594c7b17b7d37445e1f4b372179649cd2ce4475e3641bbb0100000017160014e69aa750e9bff1aca1e32e57328b641b611fc817fdffffff01e87c5d010000000017a914f3890da1b99e44cd3d52f7bcea6a1351658ea7be87024830450221009eb97597953dc288de30060ba02d4e91b2bde1af2ecf679c7f5ab5989549aa8002202a98f8c3bd1a5a31c0d72950dd6e2e3870c6c5819a6c3db740e91ebbbc5ef4800121023f3d3b8e74b807e32217dea2c75c8d0bd46b8665b3a2d9b3cb310959de52a09bc9d20700")
+            .unwrap().as_slice()).unwrap();
+        let spent3: Transaction = deserialize(Vec::from_hex("01000000027a1120a30cef95422638e8dab9dedf720ec614b1b21e451a4957a5969afb869d000000006a47304402200ecc318a829a6cad4aa9db152adbf09b0cd2de36f47b53f5dade3bc7ef086ca702205722cda7404edd6012eedd79b2d6f24c0a0c657df1a442d0a2166614fb164a4701210372f4b97b34e9c408741cd1fc97bcc7ffdda6941213ccfde1cb4075c0f17aab06ffffffffc23b43e5a18e5a66087c0d5e64d58e8e21fcf83ce3f5e4f7ecb902b0e80a7fb6010000006b483045022100f10076a0ea4b4cf8816ed27a1065883efca230933bf2ff81d5db6258691ff75202206b001ef87624e76244377f57f0c84bc5127d0dd3f6e0ef28b276f176badb223a01210309a3a61776afd39de4ed29b622cd399d99ecd942909c36a8696cfd22fc5b5a1affffffff0200127a000000000017a914f895e1dd9b29cb228e9b06a15204e3b57feaf7cc8769311d09000000001976a9144d00da12aaa51849d2583ae64525d4a06cd70fde88ac00000000")
+            .unwrap().as_slice()).unwrap();
+
+        let mut spent = HashMap::new();
+        spent.insert(spent1.txid(), spent1);
+        spent.insert(spent2.txid(), spent2);
+        spent.insert(spent3.txid(), spent3);
+        let mut spent2 = spent.clone();
+        let mut spent3 = spent.clone();
+
+        spending.verify_scripts(|point: &OutPoint| {
+            if let Some(tx) = spent.remove(&point.txid) {
+                return tx.output.get(point.vout as usize).cloned();
+            }
+            None
+        }, VerifyFlags::STANDARD).unwrap();
+
+        // test that we fail with repeated use of same input
+        let mut double_spending = spending.clone();
+        let re_use = double_spending.input[0].clone();
+        double_spending.input.push (re_use);
+
+        assert!(double_spending.verify_scripts(|point: &OutPoint| {
+            if let Some(tx) = spent2.remove(&point.txid) {
+                return tx.output.get(point.vout as usize).cloned();
+            }
+            None
+        }, VerifyFlags::STANDARD).is_err());
+
+        // test that we get a failure if we corrupt a signature
+        spending.input[1].witness[0][10] = 42;
+        match spending.verify_scripts(|point: &OutPoint| {
+            if let Some(tx) = spent3.remove(&point.txid) {
+                return tx.output.get(point.vout as usize).cloned();
+            }
+            None
+        }, VerifyFlags::STANDARD).err().unwrap() {
+            script::Error::SigNullFail => {},
+            e => panic!("Wrong error type: {:?}", e),
+        }
+    }
 }
 
 #[cfg(all(test, feature = "unstable"))]
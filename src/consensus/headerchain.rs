@@ -0,0 +1,590 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Header chain
+//!
+//! Storage and validation of a tree of block headers, following the chain
+//! with the most cumulative work, as needed by headers-first and SPV clients.
+//!
+//! Every header is checked against its parent for linkage, median-time-past,
+//! required difficulty (the retargeting every
+//! [`Params::difficulty_adjustment_interval`] blocks, and the 20-minute
+//! minimum difficulty rule of networks allowing it, such as testnet) and
+//! proof of work. Timestamps are not checked against the current time, which
+//! is up to the caller.
+//!
+
+use std::{error, fmt};
+use std::collections::HashMap;
+
+use blockdata::block::BlockHeader;
+use blockdata::constants::genesis_block;
+use consensus::Params;
+use hash_types::BlockHash;
+use util::uint::Uint256;
+
+/// Number of past blocks whose timestamps the median-time-past is computed from
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Ways a header may be rejected by a [`HeaderChain`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The previous block of the header is unknown
+    UnknownPrevBlock(BlockHash),
+    /// The timestamp of the header is not after the median time of the past
+    /// blocks
+    TimeTooOld {
+        /// Timestamp of the header
+        time: u32,
+        /// Median time of the past blocks
+        median_time_past: u32,
+    },
+    /// The header does not have the difficulty required at its height
+    BadDifficultyBits {
+        /// Required compact target
+        expected: u32,
+        /// Compact target of the header
+        actual: u32,
+    },
+    /// The hash of the header does not meet its target
+    BadProofOfWork,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownPrevBlock(ref hash) => write!(f, "unknown previous block {}", hash),
+            Error::TimeTooOld { time, median_time_past } =>
+                write!(f, "block time {} is not after median time past {}", time, median_time_past),
+            Error::BadDifficultyBits { expected, actual } =>
+                write!(f, "incorrect difficulty bits {:#010x}, expected {:#010x}", actual, expected),
+            Error::BadProofOfWork => f.write_str("block hash does not meet its target"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A header stored in a [`HeaderChain`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HeaderEntry {
+    /// The header itself
+    pub header: BlockHeader,
+    /// Height of the header
+    pub height: u32,
+    /// Total work of the chain ending with this header, genesis included
+    pub chain_work: Uint256,
+}
+
+/// How accepting a header changed a [`HeaderChain`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ChainUpdate {
+    /// The header was already known, nothing changed
+    AlreadyKnown,
+    /// The header extends the best chain, and is its new tip
+    Extended {
+        /// Height of the new tip
+        height: u32,
+    },
+    /// The header was stored on a side chain which does not have more work
+    /// than the best chain
+    SideChain {
+        /// Height of the header
+        height: u32,
+    },
+    /// The header made a side chain the best chain
+    Reorganized {
+        /// Height of the last block common to both chains
+        fork_height: u32,
+        /// Blocks no longer in the best chain, from the old tip down to the fork
+        disconnected: Vec<BlockHash>,
+        /// Blocks which joined the best chain, from the fork up to the new tip
+        connected: Vec<BlockHash>,
+    },
+}
+
+/// Computes the compact target following a difficulty adjustment period
+/// whose first block was mined at `first_block_time` and whose last block,
+/// with compact target `last_bits`, was mined at `last_block_time`.
+pub fn calculate_next_work_required(
+    last_bits: u32,
+    first_block_time: u32,
+    last_block_time: u32,
+    params: &Params,
+) -> u32 {
+    if params.no_pow_retargeting {
+        return last_bits;
+    }
+
+    let timespan = params.pow_target_timespan as i64;
+    let mut actual_timespan = last_block_time as i64 - first_block_time as i64;
+    if actual_timespan < timespan / 4 {
+        actual_timespan = timespan / 4;
+    }
+    if actual_timespan > timespan * 4 {
+        actual_timespan = timespan * 4;
+    }
+
+    // target * actual_timespan / timespan, split so that it cannot overflow
+    // for networks with a very easy proof of work limit
+    let target = BlockHeader::u256_from_compact_target(last_bits);
+    let actual_timespan = Uint256::from_u64(actual_timespan as u64).expect("fits");
+    let timespan = Uint256::from_u64(timespan as u64).expect("fits");
+    let (quotient, remainder) = (target / timespan, target % timespan);
+    if quotient > params.pow_limit / actual_timespan {
+        return BlockHeader::compact_target_from_u256(&params.pow_limit);
+    }
+    let mut target = quotient * actual_timespan + remainder * actual_timespan / timespan;
+    if target > params.pow_limit {
+        target = params.pow_limit;
+    }
+    BlockHeader::compact_target_from_u256(&target)
+}
+
+/// A tree of validated block headers rooted at the genesis block, along with
+/// the chain with the most work among them
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    params: Params,
+    entries: HashMap<BlockHash, HeaderEntry>,
+    best_chain: Vec<BlockHash>,
+}
+
+impl HeaderChain {
+    /// Creates a chain holding only the genesis block of `params.network`
+    pub fn new(params: Params) -> HeaderChain {
+        let genesis = genesis_block(params.network).header;
+        HeaderChain::with_genesis(params, genesis)
+    }
+
+    /// Creates a chain holding only the given genesis header, for chains
+    /// other than the built-in ones
+    pub fn with_genesis(params: Params, genesis: BlockHeader) -> HeaderChain {
+        let hash = genesis.block_hash();
+        let mut entries = HashMap::new();
+        entries.insert(hash, HeaderEntry {
+            header: genesis,
+            height: 0,
+            chain_work: genesis.work(),
+        });
+        HeaderChain {
+            params: params,
+            entries: entries,
+            best_chain: vec![hash],
+        }
+    }
+
+    /// The consensus parameters headers are validated with
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Hash of the tip of the best chain
+    pub fn tip(&self) -> BlockHash {
+        *self.best_chain.last().expect("genesis is always present")
+    }
+
+    /// The tip of the best chain
+    pub fn tip_entry(&self) -> &HeaderEntry {
+        &self.entries[&self.tip()]
+    }
+
+    /// Height of the tip of the best chain
+    pub fn height(&self) -> u32 {
+        (self.best_chain.len() - 1) as u32
+    }
+
+    /// Total work of the best chain
+    pub fn chain_work(&self) -> Uint256 {
+        self.tip_entry().chain_work
+    }
+
+    /// Returns the stored header with the given hash, on any chain
+    pub fn get(&self, hash: &BlockHash) -> Option<&HeaderEntry> {
+        self.entries.get(hash)
+    }
+
+    /// Returns the ancestor at `height` of the block with the given hash, on
+    /// any chain
+    pub fn get_ancestor(&self, hash: &BlockHash, height: u32) -> Option<&HeaderEntry> {
+        match self.entries.get(hash) {
+            Some(entry) if entry.height >= height => Some(self.ancestor(hash, height)),
+            _ => None,
+        }
+    }
+
+    /// Returns the hash of the block at `height` in the best chain
+    pub fn hash_at(&self, height: u32) -> Option<BlockHash> {
+        self.best_chain.get(height as usize).cloned()
+    }
+
+    /// Returns the header at `height` in the best chain
+    pub fn header_at(&self, height: u32) -> Option<&BlockHeader> {
+        self.hash_at(height).map(|hash| &self.entries[&hash].header)
+    }
+
+    /// Whether the block with the given hash is in the best chain
+    pub fn is_in_best_chain(&self, hash: &BlockHash) -> bool {
+        match self.entries.get(hash) {
+            Some(entry) => self.best_chain.get(entry.height as usize) == Some(hash),
+            None => false,
+        }
+    }
+
+    /// Returns block locator hashes for the tip of the best chain, to be sent
+    /// in `getheaders` and `getblocks` messages: the ten last blocks, then
+    /// exponentially fewer down to the genesis block.
+    pub fn locator_hashes(&self) -> Vec<BlockHash> {
+        let mut hashes = vec![];
+        let mut height = self.height() as i64;
+        let mut step = 1;
+        while height > 0 {
+            hashes.push(self.best_chain[height as usize]);
+            if hashes.len() >= 10 {
+                step *= 2;
+            }
+            height -= step;
+        }
+        hashes.push(self.best_chain[0]);
+        hashes
+    }
+
+    /// Returns the median timestamp of the block with the given hash and the
+    /// ones before it, which the timestamp of its child must exceed
+    pub fn median_time_past(&self, hash: &BlockHash) -> Option<u32> {
+        let mut entry = self.entries.get(hash)?;
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        loop {
+            times.push(entry.header.time);
+            if times.len() == MEDIAN_TIME_SPAN || entry.height == 0 {
+                break;
+            }
+            entry = &self.entries[&entry.header.prev_blockhash];
+        }
+        times.sort();
+        Some(times[times.len() / 2])
+    }
+
+    /// Returns the compact target required for a child of the block with hash
+    /// `prev` mined at `time`
+    pub fn next_bits_required(&self, prev: &BlockHash, time: u32) -> Option<u32> {
+        let last = self.entries.get(prev)?;
+        let interval = self.params.difficulty_adjustment_interval() as u32;
+
+        if (last.height + 1) % interval != 0 {
+            if self.params.allow_min_difficulty_blocks {
+                let pow_limit_bits = BlockHeader::compact_target_from_u256(&self.params.pow_limit);
+                // A block mined more than twice the target spacing after its
+                // parent may have the minimum difficulty
+                if time as u64 > last.header.time as u64 + 2 * self.params.pow_target_spacing {
+                    return Some(pow_limit_bits);
+                }
+                // Otherwise it has the difficulty of the last block which was
+                // not mined under this rule
+                let mut entry = last;
+                while entry.height % interval != 0 && entry.header.bits == pow_limit_bits {
+                    entry = &self.entries[&entry.header.prev_blockhash];
+                }
+                return Some(entry.header.bits);
+            }
+            return Some(last.header.bits);
+        }
+
+        let first = self.ancestor(prev, last.height + 1 - interval);
+        Some(calculate_next_work_required(last.header.bits, first.header.time, last.header.time, &self.params))
+    }
+
+    /// Validates `header` and stores it, switching the best chain to the one
+    /// it ends if that chain has more work.
+    pub fn accept(&mut self, header: BlockHeader) -> Result<ChainUpdate, Error> {
+        let hash = header.block_hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(ChainUpdate::AlreadyKnown);
+        }
+        let prev = match self.entries.get(&header.prev_blockhash) {
+            Some(entry) => *entry,
+            None => return Err(Error::UnknownPrevBlock(header.prev_blockhash)),
+        };
+
+        let median_time_past = self.median_time_past(&header.prev_blockhash).expect("known block");
+        if header.time <= median_time_past {
+            return Err(Error::TimeTooOld {
+                time: header.time,
+                median_time_past: median_time_past,
+            });
+        }
+        let expected = self.next_bits_required(&header.prev_blockhash, header.time).expect("known block");
+        if header.bits != expected {
+            return Err(Error::BadDifficultyBits {
+                expected: expected,
+                actual: header.bits,
+            });
+        }
+        if header.validate_pow(&header.target()).is_err() {
+            return Err(Error::BadProofOfWork);
+        }
+
+        let entry = HeaderEntry {
+            header: header,
+            height: prev.height + 1,
+            chain_work: prev.chain_work + header.work(),
+        };
+        self.entries.insert(hash, entry);
+
+        if entry.chain_work <= self.chain_work() {
+            return Ok(ChainUpdate::SideChain { height: entry.height });
+        }
+        if header.prev_blockhash == self.tip() {
+            self.best_chain.push(hash);
+            return Ok(ChainUpdate::Extended { height: entry.height });
+        }
+
+        let mut connected = vec![];
+        let mut cursor = hash;
+        while !self.is_in_best_chain(&cursor) {
+            connected.push(cursor);
+            cursor = self.entries[&cursor].header.prev_blockhash;
+        }
+        connected.reverse();
+        let fork_height = self.entries[&cursor].height;
+        let mut disconnected = self.best_chain.split_off(fork_height as usize + 1);
+        disconnected.reverse();
+        self.best_chain.extend(connected.iter().cloned());
+        Ok(ChainUpdate::Reorganized {
+            fork_height: fork_height,
+            disconnected: disconnected,
+            connected: connected,
+        })
+    }
+
+    /// Returns the ancestor at `height` of the known block `hash`
+    fn ancestor(&self, hash: &BlockHash, height: u32) -> &HeaderEntry {
+        let mut hash = *hash;
+        loop {
+            let entry = &self.entries[&hash];
+            if entry.height == height {
+                return entry;
+            }
+            if self.best_chain.get(entry.height as usize) == Some(&hash) {
+                return &self.entries[&self.best_chain[height as usize]];
+            }
+            hash = entry.header.prev_blockhash;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blockdata::block::BlockHeader;
+    use consensus::encode::deserialize;
+    use hash_types::TxMerkleNode;
+    use hashes::hex::FromHex;
+    use hashes::Hash;
+    use network::constants::Network;
+    use test_utils::grind_nonce;
+
+    /// Mines a child of `prev` with the given timestamp and difficulty. The
+    /// merkle root tells apart siblings with the same timestamp.
+    fn mine(prev: &BlockHeader, time: u32, bits: u32, salt: u8) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 4,
+            prev_blockhash: prev.block_hash(),
+            merkle_root: TxMerkleNode::hash(&[salt]),
+            time: time,
+            bits: bits,
+            nonce: 0,
+        };
+        grind_nonce(&mut header);
+        header
+    }
+
+    /// Regtest-like parameters retargeting every four blocks
+    fn retarget_params(allow_min_difficulty_blocks: bool) -> Params {
+        let mut params = Params::new(Network::Regtest);
+        params.pow_target_timespan = 4 * params.pow_target_spacing;
+        params.no_pow_retargeting = false;
+        params.allow_min_difficulty_blocks = allow_min_difficulty_blocks;
+        params
+    }
+
+    #[test]
+    fn next_work_required() {
+        // Signet blocks 2016 and 4032
+        let params = Params::new(Network::Signet);
+        assert_eq!(calculate_next_work_required(503543726, 1598918400, 1599332177, &params), 503394215);
+        assert_eq!(calculate_next_work_required(503394215, 1599332844, 1600591200, &params), 503397348);
+
+        // Adjustments are bounded by a factor of four, and by the limit
+        let params = Params::new(Network::Bitcoin);
+        let timespan = params.pow_target_timespan as u32;
+        assert_eq!(calculate_next_work_required(0x1b0404cb, 0, 1, &params), 0x1b010132);
+        assert_eq!(calculate_next_work_required(0x1b0404cb, 0, timespan / 4, &params), 0x1b010132);
+        assert_eq!(calculate_next_work_required(0x1b0404cb, 0, timespan * 5, &params), 0x1b10132c);
+        assert_eq!(calculate_next_work_required(0x1d00ffff, 0, timespan * 2, &params), 0x1d00ffff);
+        assert_eq!(calculate_next_work_required(0x1d00ffff, 0, timespan, &Params::new(Network::Regtest)), 0x1d00ffff);
+        // Easing the easiest targets does not overflow
+        assert_eq!(calculate_next_work_required(0x207fffff, 0, timespan * 4, &retarget_params(false)), 0x207fffff);
+    }
+
+    #[test]
+    fn mainnet_headers() {
+        let block_1: BlockHeader = deserialize(&Vec::<u8>::from_hex(
+            "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd\
+            1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299"
+        ).unwrap()).unwrap();
+        let block_2: BlockHeader = deserialize(&Vec::<u8>::from_hex(
+            "010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000d5fdcc54\
+            1e25de1c7a5addedf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd61"
+        ).unwrap()).unwrap();
+
+        let mut chain = HeaderChain::new(Params::new(Network::Bitcoin));
+        let genesis = chain.tip();
+        assert_eq!(chain.accept(block_2), Err(Error::UnknownPrevBlock(block_1.block_hash())));
+
+        let mut bad_pow = block_1;
+        bad_pow.nonce += 1;
+        assert_eq!(chain.accept(bad_pow), Err(Error::BadProofOfWork));
+        let mut bad_bits = block_1;
+        bad_bits.bits = 0x1c00ffff;
+        assert_eq!(chain.accept(bad_bits), Err(Error::BadDifficultyBits { expected: 0x1d00ffff, actual: 0x1c00ffff }));
+
+        assert_eq!(chain.accept(block_1), Ok(ChainUpdate::Extended { height: 1 }));
+        assert_eq!(chain.accept(block_1), Ok(ChainUpdate::AlreadyKnown));
+        assert_eq!(chain.accept(block_2), Ok(ChainUpdate::Extended { height: 2 }));
+        assert_eq!(chain.height(), 2);
+        assert_eq!(chain.tip(), block_2.block_hash());
+        assert_eq!(chain.header_at(1), Some(&block_1));
+        assert_eq!(chain.locator_hashes(), vec![block_2.block_hash(), block_1.block_hash(), genesis]);
+        assert_eq!(chain.chain_work(), Uint256::from_u64(3 * 0x100010001).unwrap());
+        assert_eq!(chain.median_time_past(&block_2.block_hash()), Some(block_1.time));
+    }
+
+    #[test]
+    fn median_time_past() {
+        let mut chain = HeaderChain::new(Params::new(Network::Regtest));
+        let mut tip = *chain.header_at(0).unwrap();
+        for _ in 0..MEDIAN_TIME_SPAN {
+            tip = mine(&tip, tip.time + 600, tip.bits, 0);
+            chain.accept(tip).unwrap();
+        }
+        // The past eleven blocks are the first to the eleventh
+        let median_time_past = chain.header_at(6).unwrap().time;
+        assert_eq!(chain.median_time_past(&tip.block_hash()), Some(median_time_past));
+
+        let too_old = mine(&tip, median_time_past, tip.bits, 0);
+        assert_eq!(chain.accept(too_old), Err(Error::TimeTooOld { time: median_time_past, median_time_past: median_time_past }));
+        let next = mine(&tip, median_time_past + 1, tip.bits, 0);
+        assert_eq!(chain.accept(next), Ok(ChainUpdate::Extended { height: 12 }));
+    }
+
+    #[test]
+    fn reorg() {
+        let mut chain = HeaderChain::new(Params::new(Network::Regtest));
+        let genesis = *chain.header_at(0).unwrap();
+        let bits = genesis.bits;
+
+        let a1 = mine(&genesis, genesis.time + 600, bits, 1);
+        let a2 = mine(&a1, a1.time + 600, bits, 1);
+        let b1 = mine(&genesis, genesis.time + 600, bits, 2);
+        let b2 = mine(&b1, b1.time + 600, bits, 2);
+        let b3 = mine(&b2, b2.time + 600, bits, 2);
+
+        assert_eq!(chain.accept(a1), Ok(ChainUpdate::Extended { height: 1 }));
+        assert_eq!(chain.accept(a2), Ok(ChainUpdate::Extended { height: 2 }));
+        // First seen wins among chains with the same work
+        assert_eq!(chain.accept(b1), Ok(ChainUpdate::SideChain { height: 1 }));
+        assert_eq!(chain.accept(b2), Ok(ChainUpdate::SideChain { height: 2 }));
+        assert_eq!(chain.tip(), a2.block_hash());
+        assert!(!chain.is_in_best_chain(&b2.block_hash()));
+
+        assert_eq!(chain.accept(b3), Ok(ChainUpdate::Reorganized {
+            fork_height: 0,
+            disconnected: vec![a2.block_hash(), a1.block_hash()],
+            connected: vec![b1.block_hash(), b2.block_hash(), b3.block_hash()],
+        }));
+        assert_eq!(chain.tip(), b3.block_hash());
+        assert_eq!(chain.height(), 3);
+        assert_eq!(chain.hash_at(1), Some(b1.block_hash()));
+        assert!(chain.is_in_best_chain(&b2.block_hash()));
+        assert!(!chain.is_in_best_chain(&a2.block_hash()));
+        assert_eq!(chain.get(&a2.block_hash()).unwrap().height, 2);
+        assert_eq!(chain.get_ancestor(&a2.block_hash(), 1).unwrap().header, a1);
+        assert_eq!(chain.get_ancestor(&b3.block_hash(), 1).unwrap().header, b1);
+        assert!(chain.get_ancestor(&a2.block_hash(), 3).is_none());
+        assert_eq!(chain.chain_work(), Uint256::from_u64(4 * 2).unwrap());
+    }
+
+    #[test]
+    fn retarget() {
+        let params = retarget_params(false);
+        let pow_limit = params.pow_limit;
+        let pow_limit_bits = BlockHeader::compact_target_from_u256(&pow_limit);
+        let mut chain = HeaderChain::new(params);
+        let mut tip = *chain.header_at(0).unwrap();
+        for _ in 0..3 {
+            tip = mine(&tip, tip.time + 60, pow_limit_bits, 0);
+            chain.accept(tip).unwrap();
+        }
+
+        // The first period was mined ten times too fast, but the difficulty
+        // only quadruples
+        let harder = BlockHeader::compact_target_from_u256(&(pow_limit >> 2));
+        assert_eq!(chain.next_bits_required(&tip.block_hash(), tip.time + 60), Some(harder));
+        let easy = mine(&tip, tip.time + 60, pow_limit_bits, 0);
+        assert_eq!(chain.accept(easy), Err(Error::BadDifficultyBits { expected: harder, actual: pow_limit_bits }));
+        tip = mine(&tip, tip.time + 60, harder, 0);
+        assert_eq!(chain.accept(tip), Ok(ChainUpdate::Extended { height: 4 }));
+
+        // No minimum difficulty blocks on this network
+        let late = mine(&tip, tip.time + 3600, pow_limit_bits, 0);
+        assert_eq!(chain.accept(late), Err(Error::BadDifficultyBits { expected: harder, actual: pow_limit_bits }));
+
+        // The next period is so slow that the difficulty is divided by four
+        for _ in 0..3 {
+            tip = mine(&tip, tip.time + 7200, harder, 0);
+            chain.accept(tip).unwrap();
+        }
+        let easier = BlockHeader::compact_target_from_u256(&BlockHeader::u256_from_compact_target(harder).mul_u32(4));
+        assert_eq!(chain.next_bits_required(&tip.block_hash(), tip.time), Some(easier));
+    }
+
+    #[test]
+    fn min_difficulty_blocks() {
+        let params = retarget_params(true);
+        let pow_limit_bits = BlockHeader::compact_target_from_u256(&params.pow_limit);
+        let mut chain = HeaderChain::new(params);
+        let mut tip = *chain.header_at(0).unwrap();
+        for _ in 0..4 {
+            let bits = chain.next_bits_required(&tip.block_hash(), tip.time + 60).unwrap();
+            tip = mine(&tip, tip.time + 60, bits, 0);
+            chain.accept(tip).unwrap();
+        }
+        let harder = tip.bits;
+        assert!(harder != pow_limit_bits);
+
+        // More than twenty minutes after its parent, a block may be mined at
+        // the minimum difficulty
+        assert_eq!(chain.next_bits_required(&tip.block_hash(), tip.time + 1200), Some(harder));
+        tip = mine(&tip, tip.time + 1201, pow_limit_bits, 0);
+        assert_eq!(chain.accept(tip), Ok(ChainUpdate::Extended { height: 5 }));
+
+        // Its children come back to the last regular difficulty
+        let easy = mine(&tip, tip.time + 60, pow_limit_bits, 0);
+        assert_eq!(chain.accept(easy), Err(Error::BadDifficultyBits { expected: harder, actual: pow_limit_bits }));
+        tip = mine(&tip, tip.time + 60, harder, 0);
+        assert_eq!(chain.accept(tip), Ok(ChainUpdate::Extended { height: 6 }));
+    }
+}
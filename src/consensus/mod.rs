@@ -19,7 +19,11 @@
 //!
 
 pub mod encode;
+pub mod headerchain;
 pub mod params;
+pub mod signet;
+pub mod validation;
+pub mod versionbits;
 
 pub use self::encode::{Encodable, Decodable, WriteExt, ReadExt};
 pub use self::encode::{serialize, deserialize, deserialize_partial};
@@ -17,7 +17,10 @@
 //! This module provides predefined set of parameters for different chains.
 //!
 
+use blockdata::constants::COIN_VALUE;
+use blockdata::script::Script;
 use network::constants::Network;
+use hashes::hex::FromHex;
 use util::uint::Uint256;
 
 /// Lowest possible difficulty for Mainnet. See comment on Params::pow_limit for more info.
@@ -49,11 +52,17 @@ const MAX_BITS_REGTEST: Uint256 = Uint256([
     0x7fffff0000000000u64,
 ]);
 
+/// Challenge of the default signet, a 1-of-2 multisig.
+const DEFAULT_SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
 #[derive(Debug, Clone)]
 /// Parameters that influence chain consensus.
 pub struct Params {
     /// Network for which parameters are valid.
     pub network: Network,
+    /// Number of blocks after which the block subsidy is halved, see
+    /// [`Params::block_subsidy`].
+    pub subsidy_halving_interval: u32,
     /// Time when BIP16 becomes active.
     pub bip16_time: u32,
     /// Block height at which BIP34 becomes active.
@@ -85,6 +94,10 @@ pub struct Params {
     pub allow_min_difficulty_blocks: bool,
     /// Determines whether retargeting is disabled for this network or not.
     pub no_pow_retargeting: bool,
+    /// The script signet blocks must satisfy (BIP325), `None` if blocks are
+    /// not signed. [`Params::new`] sets the challenge of the default signet,
+    /// custom signets are configured with their own.
+    pub signet_challenge: Option<Script>,
 }
 
 impl Params {
@@ -93,6 +106,7 @@ impl Params {
         match network {
             Network::Bitcoin => Params {
                 network: Network::Bitcoin,
+                subsidy_halving_interval: 210000,
                 bip16_time: 1333238400,                 // Apr 1 2012
                 bip34_height: 227931, // 000000000000024b89b42a942fe0d9fea3bb44ab7bd1b19115dd6a759c0808b8
                 bip65_height: 388381, // 000000000000000004c2b624ed5d7756c508d90fd0da2c7c679febfa6c4735f0
@@ -104,9 +118,11 @@ impl Params {
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: false,
                 no_pow_retargeting: false,
+                signet_challenge: None,
             },
             Network::Testnet => Params {
                 network: Network::Testnet,
+                subsidy_halving_interval: 210000,
                 bip16_time: 1333238400,                 // Apr 1 2012
                 bip34_height: 21111, // 0000000023b3a96d3484e5abb3755c413e7d41500f8e2a5c3f0dd01299cd8ef8
                 bip65_height: 581885, // 00000000007f6655f22f98e72ed80d8b06dc761d5da09df0fa1dc4be4f861eb6
@@ -118,9 +134,11 @@ impl Params {
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: true,
                 no_pow_retargeting: false,
+                signet_challenge: None,
             },
             Network::Signet => Params {
                 network: Network::Signet,
+                subsidy_halving_interval: 210000,
                 bip16_time: 1333238400,                 // Apr 1 2012
                 bip34_height: 1,
                 bip65_height: 1,
@@ -132,9 +150,11 @@ impl Params {
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: false,
                 no_pow_retargeting: false,
+                signet_challenge: Some(Script::from(Vec::from_hex(DEFAULT_SIGNET_CHALLENGE).expect("valid hex"))),
             },
             Network::Regtest => Params {
                 network: Network::Regtest,
+                subsidy_halving_interval: 150,
                 bip16_time: 1333238400,  // Apr 1 2012
                 bip34_height: 100000000, // not activated on regtest
                 bip65_height: 1351,
@@ -146,6 +166,7 @@ impl Params {
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: true,
                 no_pow_retargeting: true,
+                signet_challenge: None,
             },
         }
     }
@@ -154,4 +175,43 @@ impl Params {
     pub fn difficulty_adjustment_interval(&self) -> u64 {
         self.pow_target_timespan / self.pow_target_spacing
     }
+
+    /// Calculates the block subsidy in satoshis at the given height: 50 BTC,
+    /// halved every [`Params::subsidy_halving_interval`] blocks.
+    pub fn block_subsidy(&self, height: u32) -> u64 {
+        let halvings = height / self.subsidy_halving_interval;
+        // Shifting by 64 or more is undefined, the subsidy is long zero anyway
+        if halvings >= 64 {
+            return 0;
+        }
+        (50 * COIN_VALUE) >> halvings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Params;
+    use blockdata::constants::COIN_VALUE;
+    use network::constants::Network;
+
+    #[test]
+    fn block_subsidy() {
+        let params = Params::new(Network::Bitcoin);
+        assert_eq!(params.block_subsidy(0), 50 * COIN_VALUE);
+        assert_eq!(params.block_subsidy(209_999), 50 * COIN_VALUE);
+        assert_eq!(params.block_subsidy(210_000), 25 * COIN_VALUE);
+        assert_eq!(params.block_subsidy(840_000), 3 * COIN_VALUE + COIN_VALUE / 8);
+        assert_eq!(params.block_subsidy(6_720_000), 1);
+        assert_eq!(params.block_subsidy(6_930_000), 0);
+        assert_eq!(params.block_subsidy(u32::max_value()), 0);
+
+        // The total supply stays below 21 million bitcoins
+        let total: u64 = (0..64).map(|i| params.block_subsidy(i * 210_000) * 210_000).sum();
+        assert_eq!(total, 2_099_999_997_690_000);
+
+        let params = Params::new(Network::Regtest);
+        assert_eq!(params.block_subsidy(149), 50 * COIN_VALUE);
+        assert_eq!(params.block_subsidy(150), 25 * COIN_VALUE);
+        assert_eq!(params.block_subsidy(64 * 150), 0);
+    }
 }
@@ -0,0 +1,413 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Signet block solutions
+//!
+//! Implementation of [BIP325](https://github.com/bitcoin/bips/blob/master/bip-0325.mediawiki).
+//! A signet block is valid only if it carries a solution to the challenge
+//! script of the chain. The solution, a scriptSig and a witness, is pushed
+//! in the witness commitment output of the coinbase after [`SIGNET_HEADER`].
+//! It spends the output of a virtual `to_spend` transaction committing to
+//! the block, in a virtual `to_sign` transaction: see [`SignetTxs`].
+//!
+//! Signing a block amounts to signing input 0 of `to_sign`, which spends
+//! an output of value 0 holding the challenge, and storing the solution
+//! with [`set_signet_solution`]; [`sign_signet_block`] does both. Since the
+//! solution is excluded from the merkle root the signature commits to, this
+//! is done once the block is complete, before its proof of work.
+//!
+
+use std::{error, fmt, io};
+
+use blockdata::block::Block;
+use blockdata::interpreter::VerifyFlags;
+use blockdata::opcodes;
+use blockdata::script::{self, Builder, Instruction, Script};
+use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+use consensus::encode::{self, Decodable, Encodable};
+use consensus::Params;
+use hash_types::TxMerkleNode;
+use util::hash::bitcoin_merkle_root_inline;
+
+/// Header of the signet solution in the witness commitment output
+pub const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// Header of the output holding a witness commitment: `OP_RETURN`, a 36 byte
+/// push and the commitment tag
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Flags the solution is verified with
+pub fn signet_verify_flags() -> VerifyFlags {
+    let mut flags = VerifyFlags::P2SH;
+    flags.add(VerifyFlags::WITNESS);
+    flags.add(VerifyFlags::DERSIG);
+    flags.add(VerifyFlags::NULLDUMMY)
+}
+
+/// A signet solution error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The block has no transactions
+    EmptyBlock,
+    /// The block has no witness commitment to hold a solution
+    NoWitnessCommitment,
+    /// The solution could not be parsed, or has trailing data
+    InvalidSolution,
+    /// The solution does not satisfy the challenge
+    Script(script::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::EmptyBlock => f.write_str("block has no transactions"),
+            Error::NoWitnessCommitment => f.write_str("block has no witness commitment"),
+            Error::InvalidSolution => f.write_str("invalid signet solution encoding"),
+            Error::Script(ref e) => write!(f, "signet solution does not satisfy the challenge: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            Error::Script(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<script::Error> for Error {
+    fn from(e: script::Error) -> Error {
+        Error::Script(e)
+    }
+}
+
+/// The solution of a signet block: the scriptSig and witness of the input
+/// of `to_sign`
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SignetSolution {
+    /// The scriptSig
+    pub script_sig: Script,
+    /// The witness stack
+    pub witness: Vec<Vec<u8>>,
+}
+
+impl_consensus_encoding!(SignetSolution, script_sig, witness);
+
+/// The virtual transactions of BIP325 for a block
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SignetTxs {
+    /// Transaction with a single output of value 0 holding the challenge,
+    /// whose input commits to the block
+    pub to_spend: Transaction,
+    /// Transaction spending `to_spend` with the solution of the block
+    pub to_sign: Transaction,
+}
+
+impl SignetTxs {
+    /// Builds the transactions of `block` for `challenge`. The block must
+    /// have a witness commitment; if it has no solution, an empty scriptSig
+    /// and witness are used, which satisfy trivial challenges such as
+    /// `OP_TRUE`.
+    pub fn new(block: &Block, challenge: &Script) -> Result<SignetTxs, Error> {
+        if block.txdata.is_empty() {
+            return Err(Error::EmptyBlock);
+        }
+
+        let mut coinbase = block.txdata[0].clone();
+        let solution = match witness_commitment_index(&coinbase) {
+            Some(index) => {
+                let script_pubkey = &mut coinbase.output[index].script_pubkey;
+                // The solution is left out of the merkle root it signs
+                match replace_solution(script_pubkey, &SIGNET_HEADER) {
+                    Some((stripped, data)) => {
+                        *script_pubkey = stripped;
+                        decode_solution(&data)?
+                    }
+                    None => SignetSolution::default(),
+                }
+            }
+            None => return Err(Error::NoWitnessCommitment),
+        };
+
+        let mut hashes: Vec<_> = block.txdata.iter().map(|tx| tx.txid().as_hash()).collect();
+        hashes[0] = coinbase.txid().as_hash();
+        let merkle_root: TxMerkleNode = bitcoin_merkle_root_inline(&mut hashes).into();
+
+        let mut block_data = Vec::with_capacity(72);
+        block.header.version.consensus_encode(&mut block_data).expect("vecs don't error");
+        block.header.prev_blockhash.consensus_encode(&mut block_data).expect("vecs don't error");
+        merkle_root.consensus_encode(&mut block_data).expect("vecs don't error");
+        block.header.time.consensus_encode(&mut block_data).expect("vecs don't error");
+
+        let to_spend = Transaction {
+            version: 0,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new()
+                    .push_opcode(opcodes::OP_FALSE)
+                    .push_slice(&block_data)
+                    .into_script(),
+                sequence: 0,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: 0, script_pubkey: challenge.clone() }],
+        };
+        let to_sign = Transaction {
+            version: 0,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(to_spend.txid(), 0),
+                script_sig: solution.script_sig,
+                sequence: 0,
+                witness: solution.witness,
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script(),
+            }],
+        };
+        Ok(SignetTxs { to_spend: to_spend, to_sign: to_sign })
+    }
+
+    /// Checks that the solution in `to_sign` satisfies the challenge
+    pub fn verify(&self) -> Result<(), Error> {
+        let challenge = &self.to_spend.output[0];
+        challenge.script_pubkey.verify_input(0, challenge.value, &self.to_sign, signet_verify_flags())?;
+        Ok(())
+    }
+}
+
+/// Checks the signet solution of `block` against `params.signet_challenge`,
+/// as CheckSignetBlockSolution() of Bitcoin Core does. Chains without
+/// challenge, and genesis blocks, always pass.
+pub fn check_signet_block(block: &Block, params: &Params) -> Result<(), Error> {
+    let challenge = match params.signet_challenge {
+        Some(ref challenge) => challenge,
+        None => return Ok(()),
+    };
+    // Only a genesis block has no parent
+    if block.header.prev_blockhash == Default::default() {
+        return Ok(());
+    }
+    SignetTxs::new(block, challenge)?.verify()
+}
+
+/// Stores `solution` in the witness commitment output of the coinbase of
+/// `block`, replacing any previous solution, and updates the merkle root.
+/// The witness commitment must already be there, see
+/// [`CoinbaseBuilder::build_block`](::blockdata::coinbase::CoinbaseBuilder::build_block).
+pub fn set_signet_solution(block: &mut Block, solution: &SignetSolution) -> Result<(), Error> {
+    if block.txdata.is_empty() {
+        return Err(Error::EmptyBlock);
+    }
+    let index = witness_commitment_index(&block.txdata[0]).ok_or(Error::NoWitnessCommitment)?;
+
+    let mut data = SIGNET_HEADER.to_vec();
+    solution.consensus_encode(&mut data).expect("vecs don't error");
+    let script_pubkey = &mut block.txdata[0].output[index].script_pubkey;
+    *script_pubkey = match replace_solution(script_pubkey, &data) {
+        Some((replaced, _)) => replaced,
+        None => Builder::from(script_pubkey.to_bytes()).push_slice(&data).into_script(),
+    };
+
+    block.header.merkle_root = block.merkle_root();
+    Ok(())
+}
+
+/// Signs `block` for `challenge`: `sign` is given the transactions of the
+/// block and returns the solution spending `to_spend`, which is stored in
+/// the block. The block must have a witness commitment.
+pub fn sign_signet_block<F>(block: &mut Block, challenge: &Script, sign: F) -> Result<(), Error>
+    where F: FnOnce(&SignetTxs) -> SignetSolution
+{
+    // The signed coinbase keeps the signet header where the solution goes
+    set_signet_solution(block, &SignetSolution::default())?;
+    let solution = sign(&SignetTxs::new(block, challenge)?);
+    set_signet_solution(block, &solution)
+}
+
+/// Index of the output holding the witness commitment, the last one
+/// starting with the commitment header
+fn witness_commitment_index(coinbase: &Transaction) -> Option<usize> {
+    coinbase.output.iter().rposition(|o| {
+        o.script_pubkey.len() >= 38 && o.script_pubkey[0..6] == WITNESS_COMMITMENT_HEADER
+    })
+}
+
+/// Finds the first push of `script_pubkey` starting with the signet header
+/// and holding more, and returns the script with `replacement` pushed in its
+/// place along with the data following the header
+fn replace_solution(script_pubkey: &Script, replacement: &[u8]) -> Option<(Script, Vec<u8>)> {
+    let mut builder = Builder::new();
+    let mut solution = None;
+    for instruction in script_pubkey.instructions() {
+        builder = match instruction {
+            Ok(Instruction::PushBytes(&[])) => builder.push_opcode(opcodes::OP_FALSE),
+            Ok(Instruction::PushBytes(data)) => {
+                if solution.is_none() && data.len() > SIGNET_HEADER.len() && data.starts_with(&SIGNET_HEADER) {
+                    solution = Some(data[SIGNET_HEADER.len()..].to_vec());
+                    builder.push_slice(replacement)
+                } else {
+                    builder.push_slice(data)
+                }
+            }
+            Ok(Instruction::Op(op)) => builder.push_opcode(op),
+            // Like Bitcoin Core, drop everything from the first parse error
+            Err(_) => break,
+        };
+    }
+    solution.map(|data| (builder.into_script(), data))
+}
+
+fn decode_solution(data: &[u8]) -> Result<SignetSolution, Error> {
+    let mut cursor = io::Cursor::new(data);
+    let solution: Result<SignetSolution, encode::Error> = Decodable::consensus_decode(&mut cursor);
+    match solution {
+        Ok(solution) if cursor.position() as usize == data.len() => Ok(solution),
+        _ => Err(Error::InvalidSolution),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blockdata::block::BlockHeader;
+    use blockdata::coinbase::CoinbaseBuilder;
+    use blockdata::constants::genesis_block;
+    use consensus::serialize;
+    use consensus::validation::{self, check_block};
+    use network::constants::Network;
+    use secp256k1::{Message, Secp256k1, SecretKey};
+    use test_utils::mine;
+    use util::key::PublicKey;
+
+    fn block(params: &Params, force_witness_commitment: bool) -> Block {
+        let header = BlockHeader {
+            version: 0x20000000,
+            prev_blockhash: genesis_block(params.network).block_hash(),
+            merkle_root: Default::default(),
+            time: 1_600_000_000,
+            bits: BlockHeader::compact_target_from_u256(&params.pow_limit),
+            nonce: 0,
+        };
+        let mut builder = CoinbaseBuilder::new(1).add_output(Script::new_op_return(&[]), params.block_subsidy(1));
+        if force_witness_commitment {
+            builder = builder.force_witness_commitment();
+        }
+        builder.build_block(header, vec![]).unwrap()
+    }
+
+    #[test]
+    fn trivial_challenge() {
+        let mut params = Params::new(Network::Regtest);
+        let block = block(&params, true);
+        assert_eq!(check_signet_block(&block, &params), Ok(()));
+
+        params.signet_challenge = Some(Builder::new().push_opcode(opcodes::OP_TRUE).into_script());
+        assert_eq!(check_signet_block(&block, &params), Ok(()));
+        // A witness commitment is required, even without solution
+        let uncommitted = self::block(&params, false);
+        assert_eq!(check_signet_block(&uncommitted, &params), Err(Error::NoWitnessCommitment));
+        let txs = SignetTxs::new(&block, params.signet_challenge.as_ref().unwrap()).unwrap();
+        assert_eq!(txs.to_spend.input[0].script_sig.len(), 74);
+        assert_eq!(txs.to_sign.input[0].previous_output, OutPoint::new(txs.to_spend.txid(), 0));
+
+        params.signet_challenge = Some(Builder::new().push_opcode(opcodes::OP_FALSE).into_script());
+        assert_eq!(check_signet_block(&block, &params), Err(Error::Script(script::Error::EvalFalse)));
+        let mined = mine(block);
+        assert_eq!(check_block(&mined, &params), Err(validation::Error::BadSignetSolution(Error::Script(script::Error::EvalFalse))));
+        assert_eq!(check_block(&genesis_block(Network::Regtest), &params), Ok(()));
+
+        let mut empty = mined.clone();
+        empty.txdata.clear();
+        assert_eq!(check_signet_block(&empty, &params), Err(Error::EmptyBlock));
+    }
+
+    #[test]
+    fn sign_block() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pk = PublicKey { compressed: true, key: secp256k1::PublicKey::from_secret_key(&secp, &sk) };
+        let challenge = Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_key(&pk)
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let mut params = Params::new(Network::Regtest);
+        params.signet_challenge = Some(challenge.clone());
+
+        let unsigned = block(&params, true);
+        assert!(check_signet_block(&unsigned, &params).is_err());
+        assert_eq!(set_signet_solution(&mut block(&params, false), &SignetSolution::default()), Err(Error::NoWitnessCommitment));
+
+        let sign = |txs: &SignetTxs| {
+            let sighash = txs.to_sign.signature_hash(0, &challenge, 0x01);
+            let msg = Message::from_slice(&sighash[..]).unwrap();
+            let mut sig = secp.sign(&msg, &sk).serialize_der().to_vec();
+            sig.push(0x01);
+            SignetSolution {
+                script_sig: Builder::new().push_opcode(opcodes::OP_FALSE).push_slice(&sig).into_script(),
+                witness: vec![],
+            }
+        };
+
+        let mut signed = unsigned.clone();
+        sign_signet_block(&mut signed, &challenge, sign).unwrap();
+        assert_eq!(check_signet_block(&signed, &params), Ok(()));
+        let txs = SignetTxs::new(&signed, &challenge).unwrap();
+        let solution = sign(&txs);
+        assert_eq!(txs.to_sign.input[0].script_sig, solution.script_sig);
+        assert_eq!(txs.verify(), Ok(()));
+        // The solution follows the witness commitment, in a PUSHDATA1
+        let commitment = signed.txdata[0].output[1].script_pubkey.clone();
+        assert_eq!(commitment.len(), 38 + 2 + 4 + serialize(&solution).len());
+        assert_eq!(&commitment[40..44], &SIGNET_HEADER[..]);
+
+        let signed = mine(signed);
+        assert_eq!(check_block(&signed, &params), Ok(()));
+
+        // The signature commits to the header and the transactions
+        let mut tampered = signed.clone();
+        tampered.header.time += 1;
+        assert!(check_signet_block(&tampered, &params).is_err());
+        let mut tampered = signed.clone();
+        tampered.txdata[0].output[0].value -= 1;
+        assert!(check_signet_block(&tampered, &params).is_err());
+
+        // Signing again replaces the solution
+        let mut resigned = signed.clone();
+        resigned.header.time += 1;
+        sign_signet_block(&mut resigned, &challenge, sign).unwrap();
+        assert_eq!(check_signet_block(&resigned, &params), Ok(()));
+        let solution = sign(&SignetTxs::new(&resigned, &challenge).unwrap());
+        assert_eq!(resigned.txdata[0].output[1].script_pubkey.len(), 38 + 2 + 4 + serialize(&solution).len());
+
+        // Trailing data is rejected
+        let mut data = serialize(&solution);
+        data.push(0);
+        let mut trailing = signed.clone();
+        let mut script_pubkey = trailing.txdata[0].output[1].script_pubkey.to_bytes();
+        script_pubkey.truncate(38);
+        let mut push = SIGNET_HEADER.to_vec();
+        push.extend_from_slice(&data);
+        trailing.txdata[0].output[1].script_pubkey = Builder::from(script_pubkey).push_slice(&push).into_script();
+        assert_eq!(check_signet_block(&trailing, &params), Err(Error::InvalidSolution));
+    }
+}
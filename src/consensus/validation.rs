@@ -0,0 +1,738 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Block validation
+//!
+//! Consensus checks of full blocks, mirroring Bitcoin Core. [`check_block`]
+//! performs the checks which only need the block itself, as CheckBlock()
+//! does: proof of work, merkle root and its malleability (CVE-2012-2459),
+//! size and weight limits, coinbase placement, per transaction sanity and
+//! legacy sigop limits.
+//!
+//! [`check_block_contextual`] performs the checks which need to know where
+//! the block is connected and which outputs it spends: version and BIP34
+//! height rules from [`Params`], lock times, the witness commitment, coinbase
+//! maturity, sigop cost, and the coinbase not claiming more than the subsidy
+//! and fees. Scripts are not verified; use [`Transaction::verify_scripts`] for
+//! that. Neither are header rules relating the block to its ancestors, such as
+//! difficulty and median-time-past, which a
+//! [`HeaderChain`](::consensus::headerchain::HeaderChain) enforces.
+//!
+//! Both return typed errors which map to the `reject` message codes through
+//! [`Error::reject_reason`].
+//!
+
+use std::{error, fmt};
+use std::collections::{HashMap, HashSet};
+
+use hashes::{sha256d, Hash, HashEngine};
+use blockdata::block::Block;
+use blockdata::constants::{max_money, COINBASE_MATURITY, MAX_BLOCK_SIGOPS_COST, MAX_BLOCK_WEIGHT, WITNESS_SCALE_FACTOR};
+use blockdata::script;
+use blockdata::transaction::{OutPoint, Transaction, TxOut};
+use consensus::{signet, Params};
+use hash_types::TxMerkleNode;
+use network::constants::Network;
+use network::message_network::RejectReason;
+
+/// Ways a transaction may be invalid
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TxError {
+    /// The transaction has no inputs
+    NoInputs,
+    /// The transaction has no outputs
+    NoOutputs,
+    /// The transaction without witness data exceeds the block weight limit
+    Oversize,
+    /// An output value exceeds the money supply
+    OutputTooLarge,
+    /// The total output value exceeds the money supply
+    OutputsTotalTooLarge,
+    /// An outpoint is spent twice by the transaction
+    DuplicateInputs,
+    /// The coinbase scriptSig is not between 2 and 100 bytes long
+    BadCoinbaseLength,
+    /// A non-coinbase transaction spends the null outpoint
+    NullPrevout,
+    /// The lock time of the transaction is not satisfied
+    NonFinal,
+    /// The spent output is unknown or already spent
+    MissingInput(OutPoint),
+    /// A coinbase output is spent before reaching maturity
+    PrematureCoinbaseSpend(OutPoint),
+    /// The total input value exceeds the money supply
+    InputsTotalTooLarge,
+    /// The outputs are worth more than the inputs
+    InputsBelowOutputs {
+        /// Total value of the inputs
+        input: u64,
+        /// Total value of the outputs
+        output: u64,
+    },
+}
+
+impl TxError {
+    /// The reason code of a `reject` message for this error
+    pub fn reject_reason(&self) -> RejectReason {
+        RejectReason::Invalid
+    }
+
+    /// The reason string Bitcoin Core gives for this error
+    pub fn reject_message(&self) -> &'static str {
+        match *self {
+            TxError::NoInputs => "bad-txns-vin-empty",
+            TxError::NoOutputs => "bad-txns-vout-empty",
+            TxError::Oversize => "bad-txns-oversize",
+            TxError::OutputTooLarge => "bad-txns-vout-toolarge",
+            TxError::OutputsTotalTooLarge => "bad-txns-txouttotal-toolarge",
+            TxError::DuplicateInputs => "bad-txns-inputs-duplicate",
+            TxError::BadCoinbaseLength => "bad-cb-length",
+            TxError::NullPrevout => "bad-txns-prevout-null",
+            TxError::NonFinal => "bad-txns-nonfinal",
+            TxError::MissingInput(..) => "bad-txns-inputs-missingorspent",
+            TxError::PrematureCoinbaseSpend(..) => "bad-txns-premature-spend-of-coinbase",
+            TxError::InputsTotalTooLarge => "bad-txns-inputvalues-outofrange",
+            TxError::InputsBelowOutputs { .. } => "bad-txns-in-belowout",
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TxError::NoInputs => f.write_str("transaction has no inputs"),
+            TxError::NoOutputs => f.write_str("transaction has no outputs"),
+            TxError::Oversize => f.write_str("transaction exceeds the block weight limit"),
+            TxError::OutputTooLarge => f.write_str("output value exceeds the money supply"),
+            TxError::OutputsTotalTooLarge => f.write_str("total output value exceeds the money supply"),
+            TxError::DuplicateInputs => f.write_str("transaction spends the same output twice"),
+            TxError::BadCoinbaseLength => f.write_str("coinbase scriptSig length is not between 2 and 100 bytes"),
+            TxError::NullPrevout => f.write_str("non-coinbase transaction spends the null outpoint"),
+            TxError::NonFinal => f.write_str("transaction lock time is not satisfied"),
+            TxError::MissingInput(ref outpoint) => write!(f, "spent output {} is missing or spent", outpoint),
+            TxError::PrematureCoinbaseSpend(ref outpoint) => write!(f, "coinbase output {} is not mature", outpoint),
+            TxError::InputsTotalTooLarge => f.write_str("total input value exceeds the money supply"),
+            TxError::InputsBelowOutputs { input, output } =>
+                write!(f, "outputs worth {} exceed inputs worth {}", output, input),
+        }
+    }
+}
+
+impl error::Error for TxError {}
+
+/// Ways a block may be invalid
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The block hash does not meet its target, or the target is above the
+    /// proof of work limit
+    HighHash,
+    /// The merkle root of the header does not commit to the transactions
+    BadMerkleRoot,
+    /// The transaction list has duplicate subtrees, allowing it to be mutated
+    /// without changing the merkle root (CVE-2012-2459)
+    MutatedMerkleTree,
+    /// The block has no transactions, or exceeds the size limit without
+    /// witness data
+    BadLength,
+    /// The block exceeds the weight limit
+    BadWeight,
+    /// The first transaction is not a coinbase
+    MissingCoinbase,
+    /// A transaction other than the first is a coinbase
+    MultipleCoinbases,
+    /// The transaction at the given index is invalid
+    BadTransaction(usize, TxError),
+    /// The block exceeds the sigop cost limit
+    TooManySigops,
+    /// The block version is obsolete at the block height
+    BadVersion(i32),
+    /// The coinbase does not start with the block height, per BIP34
+    BadCoinbaseHeight,
+    /// The witness commitment of the coinbase is missing or wrong
+    BadWitnessCommitment,
+    /// The coinbase claims more than the block subsidy and fees
+    BadCoinbaseAmount {
+        /// Total value of the coinbase outputs
+        value: u64,
+        /// Block subsidy plus fees
+        limit: u64,
+    },
+    /// The block does not satisfy the signet challenge
+    BadSignetSolution(signet::Error),
+}
+
+impl Error {
+    /// The reason code of a `reject` message for this error
+    pub fn reject_reason(&self) -> RejectReason {
+        match *self {
+            Error::BadVersion(..) => RejectReason::Obsolete,
+            Error::BadTransaction(_, ref e) => e.reject_reason(),
+            _ => RejectReason::Invalid,
+        }
+    }
+
+    /// The reason string Bitcoin Core gives for this error
+    pub fn reject_message(&self) -> &'static str {
+        match *self {
+            Error::HighHash => "high-hash",
+            Error::BadMerkleRoot => "bad-txnmrklroot",
+            Error::MutatedMerkleTree => "bad-txns-duplicate",
+            Error::BadLength => "bad-blk-length",
+            Error::BadWeight => "bad-blk-weight",
+            Error::MissingCoinbase => "bad-cb-missing",
+            Error::MultipleCoinbases => "bad-cb-multiple",
+            Error::BadTransaction(_, ref e) => e.reject_message(),
+            Error::TooManySigops => "bad-blk-sigops",
+            Error::BadVersion(..) => "bad-version",
+            Error::BadCoinbaseHeight => "bad-cb-height",
+            Error::BadWitnessCommitment => "bad-witness-merkle-match",
+            Error::BadCoinbaseAmount { .. } => "bad-cb-amount",
+            Error::BadSignetSolution(..) => "bad-signet-blksig",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::HighHash => f.write_str("block hash does not meet its target"),
+            Error::BadMerkleRoot => f.write_str("merkle root mismatch"),
+            Error::MutatedMerkleTree => f.write_str("duplicate transactions in merkle tree"),
+            Error::BadLength => f.write_str("block has no transactions or exceeds the size limit"),
+            Error::BadWeight => f.write_str("block exceeds the weight limit"),
+            Error::MissingCoinbase => f.write_str("first transaction is not a coinbase"),
+            Error::MultipleCoinbases => f.write_str("more than one coinbase"),
+            Error::BadTransaction(index, ref e) => write!(f, "transaction {} is invalid: {}", index, e),
+            Error::TooManySigops => f.write_str("block exceeds the sigop cost limit"),
+            Error::BadVersion(version) => write!(f, "obsolete block version {:#x}", version),
+            Error::BadCoinbaseHeight => f.write_str("coinbase does not start with the block height"),
+            Error::BadWitnessCommitment => f.write_str("witness commitment mismatch"),
+            Error::BadCoinbaseAmount { value, limit } =>
+                write!(f, "coinbase pays {} which exceeds {}", value, limit),
+            Error::BadSignetSolution(ref e) => write!(f, "invalid signet block: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[doc(hidden)]
+impl From<(usize, TxError)> for Error {
+    fn from((index, e): (usize, TxError)) -> Error {
+        Error::BadTransaction(index, e)
+    }
+}
+
+/// An unspent transaction output, as looked up by [`check_block_contextual`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Utxo {
+    /// The output itself
+    pub txout: TxOut,
+    /// Height of the block which created the output
+    pub height: u32,
+    /// Whether the output was created by a coinbase transaction
+    pub is_coinbase: bool,
+}
+
+/// Size of the transaction serialized without witness data
+fn stripped_size(tx: &Transaction) -> usize {
+    (tx.get_weight() - tx.get_size()) / (WITNESS_SCALE_FACTOR - 1)
+}
+
+/// Performs the context-free checks of a transaction, as CheckTransaction()
+/// of Bitcoin Core does
+pub fn check_transaction(tx: &Transaction) -> Result<(), TxError> {
+    if tx.input.is_empty() {
+        return Err(TxError::NoInputs);
+    }
+    if tx.output.is_empty() {
+        return Err(TxError::NoOutputs);
+    }
+    if stripped_size(tx) * WITNESS_SCALE_FACTOR > MAX_BLOCK_WEIGHT as usize {
+        return Err(TxError::Oversize);
+    }
+
+    let max_money = max_money(Network::Bitcoin);
+    let mut total = 0u64;
+    for output in &tx.output {
+        if output.value > max_money {
+            return Err(TxError::OutputTooLarge);
+        }
+        total += output.value;
+        if total > max_money {
+            return Err(TxError::OutputsTotalTooLarge);
+        }
+    }
+
+    let mut outpoints = HashSet::with_capacity(tx.input.len());
+    if tx.input.iter().any(|input| !outpoints.insert(input.previous_output)) {
+        return Err(TxError::DuplicateInputs);
+    }
+
+    if tx.is_coin_base() {
+        match tx.input[0].script_sig.len() {
+            2..=100 => {}
+            _ => return Err(TxError::BadCoinbaseLength),
+        }
+    } else if tx.input.iter().any(|input| input.previous_output.is_null()) {
+        return Err(TxError::NullPrevout);
+    }
+    Ok(())
+}
+
+/// Computes the merkle root of the transactions, and whether two siblings of
+/// the tree are identical, in which case the transaction list can be mutated
+/// without changing the root
+fn merkle_root_mutated(txdata: &[Transaction]) -> (TxMerkleNode, bool) {
+    let mut hashes: Vec<sha256d::Hash> = txdata.iter().map(|tx| tx.txid().as_hash()).collect();
+    let mut mutated = false;
+    while hashes.len() > 1 {
+        if hashes.chunks(2).any(|pair| pair.len() == 2 && pair[0] == pair[1]) {
+            mutated = true;
+        }
+        hashes = hashes.chunks(2).map(|pair| {
+            let mut engine = sha256d::Hash::engine();
+            engine.input(&pair[0][..]);
+            engine.input(&pair[pair.len() - 1][..]);
+            sha256d::Hash::from_engine(engine)
+        }).collect();
+    }
+    let root = hashes.first().cloned().unwrap_or_default();
+    (root.into(), mutated)
+}
+
+/// Performs the checks of a block which do not depend on the chain it is
+/// connected to, as CheckBlock() of Bitcoin Core does
+pub fn check_block(block: &Block, params: &Params) -> Result<(), Error> {
+    let target = block.header.target();
+    if target == Default::default() || target > params.pow_limit
+        || block.header.validate_pow(&target).is_err() {
+        return Err(Error::HighHash);
+    }
+    signet::check_signet_block(block, params).map_err(Error::BadSignetSolution)?;
+
+    let (merkle_root, mutated) = merkle_root_mutated(&block.txdata);
+    if merkle_root != block.header.merkle_root {
+        return Err(Error::BadMerkleRoot);
+    }
+    if mutated {
+        return Err(Error::MutatedMerkleTree);
+    }
+
+    let max_weight = MAX_BLOCK_WEIGHT as usize;
+    let size = block.get_size();
+    let weight = block.get_weight();
+    let stripped_size = (weight - size) / (WITNESS_SCALE_FACTOR - 1);
+    if block.txdata.is_empty()
+        || block.txdata.len() * WITNESS_SCALE_FACTOR > max_weight
+        || stripped_size * WITNESS_SCALE_FACTOR > max_weight {
+        return Err(Error::BadLength);
+    }
+    if weight > max_weight {
+        return Err(Error::BadWeight);
+    }
+
+    if !block.txdata[0].is_coin_base() {
+        return Err(Error::MissingCoinbase);
+    }
+    if block.txdata[1..].iter().any(Transaction::is_coin_base) {
+        return Err(Error::MultipleCoinbases);
+    }
+
+    let mut sigops = 0;
+    for (index, tx) in block.txdata.iter().enumerate() {
+        check_transaction(tx).map_err(|e| Error::BadTransaction(index, e))?;
+        sigops += tx.legacy_sigop_count();
+    }
+    if sigops * WITNESS_SCALE_FACTOR > MAX_BLOCK_SIGOPS_COST {
+        return Err(Error::TooManySigops);
+    }
+    Ok(())
+}
+
+/// Performs the checks of a block connected at `height` which depend on its
+/// ancestors and the outputs it spends, and returns the fees it collects.
+/// The block is expected to have passed [`check_block`].
+///
+/// Transactions must have lock times satisfied at `lock_time_cutoff`: the
+/// median time past of the previous block once BIP113 is active, the block
+/// time before. `spent` looks up the outputs spent by the block which are
+/// not created by the block itself.
+pub fn check_block_contextual<S>(
+    block: &Block,
+    height: u32,
+    lock_time_cutoff: u32,
+    params: &Params,
+    mut spent: S,
+) -> Result<u64, Error>
+    where S: FnMut(&OutPoint) -> Option<Utxo>
+{
+    let version = block.header.version;
+    if (version < 2 && height >= params.bip34_height)
+        || (version < 3 && height >= params.bip66_height)
+        || (version < 4 && height >= params.bip65_height) {
+        return Err(Error::BadVersion(version));
+    }
+
+    for (index, tx) in block.txdata.iter().enumerate() {
+        if !tx.is_final(height, lock_time_cutoff) {
+            return Err(Error::BadTransaction(index, TxError::NonFinal));
+        }
+    }
+
+    let coinbase = match block.coinbase() {
+        Some(coinbase) if coinbase.is_coin_base() => coinbase,
+        _ => return Err(Error::MissingCoinbase),
+    };
+    if height >= params.bip34_height {
+        let expected = script::Builder::new().push_int(height as i64).into_script();
+        if !coinbase.input[0].script_sig.as_bytes().starts_with(expected.as_bytes()) {
+            return Err(Error::BadCoinbaseHeight);
+        }
+    }
+
+    if !block.check_witness_commitment() {
+        return Err(Error::BadWitnessCommitment);
+    }
+
+    let max_money = max_money(params.network);
+    let mut created = HashMap::new();
+    let mut spent_outpoints = HashSet::new();
+    let mut fees = 0;
+    let mut sigop_cost = 0;
+    for (index, tx) in block.txdata.iter().enumerate() {
+        if index == 0 {
+            sigop_cost += tx.total_sigop_cost(|_| None);
+        } else {
+            let mut prevouts = Vec::with_capacity(tx.input.len());
+            let mut input_value = 0u64;
+            for input in &tx.input {
+                let outpoint = input.previous_output;
+                let utxo = if spent_outpoints.insert(outpoint) {
+                    created.remove(&outpoint).or_else(|| spent(&outpoint))
+                } else {
+                    None
+                };
+                let utxo = utxo.ok_or((index, TxError::MissingInput(outpoint)))?;
+                if utxo.is_coinbase && height.saturating_sub(utxo.height) < COINBASE_MATURITY {
+                    return Err((index, TxError::PrematureCoinbaseSpend(outpoint)).into());
+                }
+                input_value += utxo.txout.value;
+                if utxo.txout.value > max_money || input_value > max_money {
+                    return Err((index, TxError::InputsTotalTooLarge).into());
+                }
+                prevouts.push((outpoint, utxo.txout));
+            }
+
+            let output_value: u64 = tx.output.iter().map(|o| o.value).sum();
+            if input_value < output_value {
+                let e = TxError::InputsBelowOutputs { input: input_value, output: output_value };
+                return Err((index, e).into());
+            }
+            fees += input_value - output_value;
+
+            sigop_cost += tx.total_sigop_cost(|outpoint| {
+                prevouts.iter().find(|prevout| prevout.0 == *outpoint).map(|prevout| prevout.1.clone())
+            });
+        }
+        if sigop_cost > MAX_BLOCK_SIGOPS_COST {
+            return Err(Error::TooManySigops);
+        }
+
+        let txid = tx.txid();
+        for (vout, output) in tx.output.iter().enumerate() {
+            let utxo = Utxo { txout: output.clone(), height: height, is_coinbase: index == 0 };
+            created.insert(OutPoint::new(txid, vout as u32), utxo);
+        }
+    }
+
+    let value: u64 = coinbase.output.iter().map(|o| o.value).sum();
+    let limit = params.block_subsidy(height) + fees;
+    if value > limit {
+        return Err(Error::BadCoinbaseAmount { value: value, limit: limit });
+    }
+    Ok(fees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blockdata::block::BlockHeader;
+    use blockdata::constants::{genesis_block, COIN_VALUE};
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::transaction::TxIn;
+    use consensus::encode::deserialize;
+    use hash_types::Txid;
+    use hashes::hex::FromHex;
+    use test_utils;
+
+    // Block 100000 of mainnet
+    const BLOCK_100000: &str = "0100000050120119172a610421a6c3011dd330d9df07b63616c2cc1f1cd00200000000006657a9252aacd5c0b2940996ecff952228c3067cc38d4885efb5a4ac4247e9f337221b4d4c86041b0f2b57100401000000010000000000000000000000000000000000000000000000000000000000000000ffffffff08044c86041b020602ffffffff0100f2052a010000004341041b0e8c2567c12536aa13357b79a073dc4444acb83c4ec7a0e2f99dd7457516c5817242da796924ca4e99947d087fedf9ce467cb9f7c6287078f801df276fdf84ac000000000100000001032e38e9c0a84c6046d687d10556dcacc41d275ec55fc00779ac88fdf357a187000000008c493046022100c352d3dd993a981beba4a63ad15c209275ca9470abfcd57da93b58e4eb5dce82022100840792bc1f456062819f15d33ee7055cf7b5ee1af1ebcc6028d9cdb1c3af7748014104f46db5e9d61a9dc27b8d64ad23e7383a4e6ca164593c2527c038c0857eb67ee8e825dca65046b82c9331586c82e0fd1f633f25f87c161bc6f8a630121df2b3d3ffffffff0200e32321000000001976a914c398efa9c392ba6013c5e04ee729755ef7f58b3288ac000fe208010000001976a914948c765a6914d43f2a7ac177da2c2f6b52de3d7c88ac000000000100000001c33ebff2a709f13d9f9a7569ab16a32786af7d7e2de09265e41c61d078294ecf010000008a4730440220032d30df5ee6f57fa46cddb5eb8d0d9fe8de6b342d27942ae90a3231e0ba333e02203deee8060fdc70230a7f5b4ad7d7bc3e628cbe219a886b84269eaeb81e26b4fe014104ae31c31bf91278d99b8377a35bbce5b27d9fff15456839e919453fc7b3f721f0ba403ff96c9deeb680e5fd341c0fc3a7b90da4631ee39560639db462e9cb850fffffffff0240420f00000000001976a914b0dcbf97eabf4404e31d952477ce822dadbe7e1088acc060d211000000001976a9146b1281eec25ab4e1e0793ff4e08ab1abb3409cd988ac0000000001000000010b6072b386d4a773235237f64c1126ac3b240c84b917a3909ba1c43ded5f51f4000000008c493046022100bb1ad26df930a51cce110cf44f7a48c3c561fd977500b1ae5d6b6fd13d0b3f4a022100c5b42951acedff14abba2736fd574bdb465f3e6f8da12e2c5303954aca7f78f3014104a7135bfe824c97ecc01ec7d7e336185c81e2aa2c41ab175407c09484ce9694b44953fcb751206564a9c24dd094d42fdbfdd5aad3e063ce6af4cfaaea4ea14fbbffffffff0140420f00000000001976a91439aa3d569e06a1d7926dc4be1193c99bf2eb9ee088ac00000000";
+
+    /// Sets the merkle root of `block` and grinds its nonce
+    fn mine(mut block: Block) -> Block {
+        block.header.merkle_root = block.merkle_root();
+        test_utils::mine(block)
+    }
+
+    fn coinbase(height: u32, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(height as i64).push_opcode(opcodes::OP_FALSE).into_script(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: value, script_pubkey: Script::new() }],
+        }
+    }
+
+    fn spend(outpoints: &[OutPoint], value: u64, salt: u8) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: outpoints.iter().map(|outpoint| TxIn {
+                previous_output: *outpoint,
+                script_sig: Builder::new().push_slice(&[salt]).into_script(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }).collect(),
+            output: vec![TxOut { value: value, script_pubkey: Script::new() }],
+        }
+    }
+
+    fn block(version: i32, txdata: Vec<Transaction>) -> Block {
+        let params = Params::new(Network::Regtest);
+        mine(Block {
+            header: BlockHeader {
+                version: version,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                time: 1_600_000_000,
+                bits: BlockHeader::compact_target_from_u256(&params.pow_limit),
+                nonce: 0,
+            },
+            txdata: txdata,
+        })
+    }
+
+    #[test]
+    fn check_real_blocks() {
+        for &network in &[Network::Bitcoin, Network::Testnet, Network::Signet, Network::Regtest] {
+            assert_eq!(check_block(&genesis_block(network), &Params::new(network)), Ok(()));
+        }
+
+        let block: Block = deserialize(&Vec::from_hex(BLOCK_100000).unwrap()).unwrap();
+        let params = Params::new(Network::Bitcoin);
+        assert_eq!(check_block(&block, &params), Ok(()));
+
+        let mut bad = block.clone();
+        bad.header.nonce += 1;
+        assert_eq!(check_block(&bad, &params), Err(Error::HighHash));
+
+        let mut bad = block.clone();
+        bad.txdata.swap(1, 2);
+        assert_eq!(check_block(&bad, &params), Err(Error::BadMerkleRoot));
+        assert_eq!(check_block(&bad, &params).unwrap_err().reject_message(), "bad-txnmrklroot");
+
+        let mut bad = block;
+        bad.txdata[1].output[0].value = 21_000_001 * COIN_VALUE;
+        bad.header.merkle_root = bad.merkle_root();
+        // The merkle root changed, so does the hash
+        assert_eq!(check_block(&bad, &params), Err(Error::HighHash));
+    }
+
+    #[test]
+    fn mutated_merkle_tree() {
+        let txdata = vec![coinbase(1, 50 * COIN_VALUE), spend(&[OutPoint::new(Txid::hash(&[1]), 0)], 1, 0), spend(&[OutPoint::new(Txid::hash(&[2]), 0)], 1, 0)];
+        let params = Params::new(Network::Regtest);
+        let valid = block(4, txdata);
+        assert_eq!(check_block(&valid, &params), Ok(()));
+
+        // Duplicating the odd last transaction does not change the root
+        let mut mutated = valid.clone();
+        let last = mutated.txdata[2].clone();
+        mutated.txdata.push(last);
+        assert_eq!(mutated.merkle_root(), valid.header.merkle_root);
+        assert_eq!(mutated.block_hash(), valid.block_hash());
+        let e = check_block(&mutated, &params).unwrap_err();
+        assert_eq!(e, Error::MutatedMerkleTree);
+        assert_eq!(e.reject_reason(), RejectReason::Invalid);
+    }
+
+    #[test]
+    fn check_block_errors() {
+        let params = Params::new(Network::Regtest);
+        let outpoint = OutPoint::new(Txid::hash(&[1]), 0);
+
+        let no_coinbase = block(4, vec![spend(&[outpoint], 1, 0)]);
+        assert_eq!(check_block(&no_coinbase, &params), Err(Error::MissingCoinbase));
+
+        let two_coinbases = block(4, vec![coinbase(1, 1), coinbase(2, 1)]);
+        assert_eq!(check_block(&two_coinbases, &params), Err(Error::MultipleCoinbases));
+
+        let mut cb = coinbase(1, 1);
+        cb.input[0].script_sig = Builder::new().push_int(1).into_script();
+        let short_coinbase = block(4, vec![cb]);
+        assert_eq!(check_block(&short_coinbase, &params), Err(Error::BadTransaction(0, TxError::BadCoinbaseLength)));
+
+        let double_input = block(4, vec![coinbase(1, 1), spend(&[outpoint, outpoint], 1, 0)]);
+        assert_eq!(check_block(&double_input, &params), Err(Error::BadTransaction(1, TxError::DuplicateInputs)));
+
+        let null_prevout = block(4, vec![coinbase(1, 1), spend(&[outpoint, OutPoint::null()], 1, 0)]);
+        assert_eq!(check_block(&null_prevout, &params), Err(Error::BadTransaction(1, TxError::NullPrevout)));
+
+        let mut tx = spend(&[outpoint], 1, 0);
+        tx.output[0].script_pubkey = Script::from(vec![opcodes::all::OP_CHECKSIG.into_u8(); 20_001]);
+        assert_eq!(tx.legacy_sigop_count(), 20_001);
+        let too_many_sigops = block(4, vec![coinbase(1, 1), tx]);
+        assert_eq!(check_block(&too_many_sigops, &params), Err(Error::TooManySigops));
+
+        let mut tx = spend(&[outpoint], 1, 0);
+        tx.output.push(TxOut { value: 21_000_000 * COIN_VALUE, script_pubkey: Script::new() });
+        let too_much = block(4, vec![coinbase(1, 1), tx]);
+        assert_eq!(check_block(&too_much, &params), Err(Error::BadTransaction(1, TxError::OutputsTotalTooLarge)));
+
+        let mut tx = spend(&[outpoint], 1, 0);
+        tx.output[0].script_pubkey = Script::from(vec![0; 1_000_000]);
+        let too_big = block(4, vec![coinbase(1, 1), tx]);
+        assert_eq!(check_block(&too_big, &params), Err(Error::BadLength));
+    }
+
+    #[test]
+    fn contextual() {
+        let params = Params::new(Network::Regtest);
+        let subsidy = 50 * COIN_VALUE;
+        let mature = OutPoint::new(Txid::hash(&[1]), 0);
+        let immature = OutPoint::new(Txid::hash(&[2]), 0);
+        let utxos = |outpoint: &OutPoint| {
+            let height = if *outpoint == mature { 1 } else if *outpoint == immature { 50 } else { return None };
+            Some(Utxo {
+                txout: TxOut { value: 10 * COIN_VALUE, script_pubkey: Script::new() },
+                height: height,
+                is_coinbase: true,
+            })
+        };
+
+        // Spends a mature coinbase, and an output of the same block
+        let tx = spend(&[mature], 9 * COIN_VALUE, 0);
+        let child = spend(&[OutPoint::new(tx.txid(), 0)], 8 * COIN_VALUE, 0);
+        let valid = block(4, vec![coinbase(101, subsidy + 2 * COIN_VALUE), tx.clone(), child.clone()]);
+        assert_eq!(check_block(&valid, &params), Ok(()));
+        assert_eq!(check_block_contextual(&valid, 101, 0, &params, utxos), Ok(2 * COIN_VALUE));
+
+        let greedy = block(4, vec![coinbase(101, subsidy + 2 * COIN_VALUE + 1), tx.clone(), child.clone()]);
+        assert_eq!(
+            check_block_contextual(&greedy, 101, 0, &params, utxos),
+            Err(Error::BadCoinbaseAmount { value: subsidy + 2 * COIN_VALUE + 1, limit: subsidy + 2 * COIN_VALUE })
+        );
+
+        // The subsidy halves every 150 blocks on regtest
+        let halved = block(4, vec![coinbase(150, subsidy / 2 + COIN_VALUE), tx.clone()]);
+        assert_eq!(check_block_contextual(&halved, 150, 0, &params, utxos), Ok(COIN_VALUE));
+        let halved = block(4, vec![coinbase(150, subsidy), tx.clone()]);
+        assert_eq!(
+            check_block_contextual(&halved, 150, 0, &params, utxos).unwrap_err().reject_message(),
+            "bad-cb-amount"
+        );
+
+        let premature = block(4, vec![coinbase(101, COIN_VALUE), spend(&[immature], COIN_VALUE, 0)]);
+        assert_eq!(
+            check_block_contextual(&premature, 101, 0, &params, utxos),
+            Err(Error::BadTransaction(1, TxError::PrematureCoinbaseSpend(immature)))
+        );
+        assert!(check_block_contextual(&premature, 150, 0, &params, utxos).is_ok());
+
+        let double_spend = block(4, vec![coinbase(101, subsidy), tx.clone(), spend(&[mature], COIN_VALUE, 1)]);
+        assert_eq!(
+            check_block_contextual(&double_spend, 101, 0, &params, utxos),
+            Err(Error::BadTransaction(2, TxError::MissingInput(mature)))
+        );
+
+        let reordered = block(4, vec![coinbase(101, subsidy), child.clone(), tx.clone()]);
+        assert_eq!(
+            check_block_contextual(&reordered, 101, 0, &params, utxos),
+            Err(Error::BadTransaction(1, TxError::MissingInput(child.input[0].previous_output)))
+        );
+
+        let overspend = block(4, vec![coinbase(101, subsidy), spend(&[mature], 11 * COIN_VALUE, 0)]);
+        assert_eq!(
+            check_block_contextual(&overspend, 101, 0, &params, utxos),
+            Err(Error::BadTransaction(1, TxError::InputsBelowOutputs { input: 10 * COIN_VALUE, output: 11 * COIN_VALUE }))
+        );
+
+        let mut locked = spend(&[mature], COIN_VALUE, 0);
+        locked.lock_time = 200;
+        locked.input[0].sequence = 0;
+        let non_final = block(4, vec![coinbase(101, COIN_VALUE), locked]);
+        assert_eq!(
+            check_block_contextual(&non_final, 101, 0, &params, utxos),
+            Err(Error::BadTransaction(1, TxError::NonFinal))
+        );
+        assert!(check_block_contextual(&non_final, 201, 0, &params, utxos).is_ok());
+    }
+
+    #[test]
+    fn contextual_heights() {
+        let mut params = Params::new(Network::Regtest);
+        params.bip34_height = 10;
+        let no_utxos = |_: &OutPoint| None;
+
+        let at = |version: i32, height: u32| {
+            check_block_contextual(&block(version, vec![coinbase(height, 0)]), height, 0, &params, no_utxos)
+        };
+        let e = at(3, 1400).unwrap_err();
+        assert_eq!(e, Error::BadVersion(3));
+        assert_eq!(e.reject_reason(), RejectReason::Obsolete);
+        assert_eq!(at(3, 1350), Ok(0));
+        assert_eq!(at(2, 1300), Err(Error::BadVersion(2)));
+        assert_eq!(at(2, 1250), Ok(0));
+        assert_eq!(at(1, 10), Err(Error::BadVersion(1)));
+        assert_eq!(at(1, 9), Ok(0));
+
+        let wrong_height = block(4, vec![coinbase(1400, 0)]);
+        assert_eq!(check_block_contextual(&wrong_height, 1401, 0, &params, no_utxos), Err(Error::BadCoinbaseHeight));
+    }
+
+    #[test]
+    fn witness_commitment() {
+        let params = Params::new(Network::Regtest);
+        let outpoint = OutPoint::new(Txid::hash(&[1]), 0);
+        let utxos = |_: &OutPoint| Some(Utxo {
+            txout: TxOut { value: COIN_VALUE, script_pubkey: Script::new() },
+            height: 1,
+            is_coinbase: false,
+        });
+
+        let mut tx = spend(&[outpoint], COIN_VALUE, 0);
+        tx.input[0].witness = vec![vec![1]];
+        let mut cb = coinbase(1, 0);
+        cb.input[0].witness = vec![vec![0; 32]];
+        let mut missing = block(4, vec![cb.clone(), tx.clone()]);
+        assert_eq!(check_block(&missing, &params), Ok(()));
+        assert_eq!(check_block_contextual(&missing, 1, 0, &params, utxos), Err(Error::BadWitnessCommitment));
+
+        let commitment = Block::compute_witness_commitment(&missing.witness_root(), &[0; 32]);
+        let mut script_pubkey = vec![0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+        script_pubkey.extend_from_slice(&commitment[..]);
+        cb.output.push(TxOut { value: 0, script_pubkey: Script::from(script_pubkey) });
+        missing.txdata[0] = cb;
+        let committed = mine(missing);
+        assert_eq!(check_block_contextual(&committed, 1, 0, &params, utxos), Ok(0));
+    }
+}
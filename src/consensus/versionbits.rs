@@ -0,0 +1,459 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Version bits
+//!
+//! Soft fork deployments signalled by miners through bits of the block
+//! version, as specified by BIP9, with the minimum activation height of
+//! BIP8 used by Speedy Trial.
+//!
+//! The state of a deployment changes only at the end of a period of
+//! [`Params::miner_confirmation_window`] blocks. Once the median time past
+//! reaches its start time, a deployment is started, and gets locked in when
+//! at least [`Params::rule_change_activation_threshold`] blocks of a period
+//! signal it. It becomes active one period later, but not before its minimum
+//! activation height. A started deployment which is not locked in by its
+//! timeout fails.
+//!
+
+use std::collections::HashMap;
+
+use consensus::headerchain::{HeaderChain, HeaderEntry};
+use hash_types::BlockHash;
+use network::constants::Network;
+
+/// Value of the top three bits of block versions signalling with version bits
+pub const VERSIONBITS_TOP_BITS: i32 = 0x20000000;
+/// Mask of the top three bits of block versions
+pub const VERSIONBITS_TOP_MASK: i32 = 0xE0000000u32 as i32;
+/// Number of bits available for signalling
+pub const VERSIONBITS_NUM_BITS: u8 = 29;
+
+/// Start time of a deployment which is always active
+pub const ALWAYS_ACTIVE: i64 = -1;
+/// Start time of a deployment which never activates
+pub const NEVER_ACTIVE: i64 = -2;
+/// Timeout of a deployment which does not time out
+pub const NO_TIMEOUT: i64 = ::std::i64::MAX;
+
+/// A soft fork deployment signalled with version bits
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Deployment {
+    /// The version bit signalling the deployment, below
+    /// [`VERSIONBITS_NUM_BITS`]
+    pub bit: u8,
+    /// Median time past from which the deployment may be signalled, or
+    /// [`ALWAYS_ACTIVE`] or [`NEVER_ACTIVE`]
+    pub start_time: i64,
+    /// Median time past from which the deployment fails if not locked in, or
+    /// [`NO_TIMEOUT`]
+    pub timeout: i64,
+    /// Height of the first block the deployment may be active at
+    pub min_activation_height: u32,
+}
+
+/// The state of a [`Deployment`] for a block
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ThresholdState {
+    /// The deployment has not started yet
+    Defined,
+    /// Blocks may signal the deployment
+    Started,
+    /// The deployment has been signalled by enough blocks, and will activate
+    LockedIn,
+    /// The rules of the deployment are enforced
+    Active,
+    /// The deployment timed out without being locked in
+    Failed,
+}
+
+/// Signalling statistics of the current period of a started deployment
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Statistics {
+    /// Number of blocks in a period
+    pub period: u32,
+    /// Number of signalling blocks needed in a period to lock in
+    pub threshold: u32,
+    /// Number of blocks of the period so far
+    pub elapsed: u32,
+    /// Number of signalling blocks of the period so far
+    pub count: u32,
+    /// Whether the deployment may still be locked in at the end of the period
+    pub possible: bool,
+}
+
+/// Cache of deployment states at period boundaries, to be shared by all
+/// state queries on a given [`HeaderChain`]
+#[derive(Clone, Debug, Default)]
+pub struct VersionBitsCache {
+    states: HashMap<(Deployment, BlockHash), ThresholdState>,
+}
+
+impl VersionBitsCache {
+    /// Creates an empty cache
+    pub fn new() -> VersionBitsCache {
+        VersionBitsCache::default()
+    }
+
+    /// Forgets all cached states
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+}
+
+impl Deployment {
+    /// The dummy deployment Bitcoin Core uses for testing the state machine
+    pub fn testdummy(network: Network) -> Deployment {
+        match network {
+            Network::Regtest => Deployment {
+                bit: 28,
+                start_time: 0,
+                timeout: NO_TIMEOUT,
+                min_activation_height: 0,
+            },
+            _ => Deployment {
+                bit: 28,
+                start_time: 1199145601, // January 1, 2008
+                timeout: 1230767999,    // December 31, 2008
+                min_activation_height: 0,
+            },
+        }
+    }
+
+    /// The deployment of Taproot (BIPs 340-342)
+    pub fn taproot(network: Network) -> Deployment {
+        match network {
+            Network::Bitcoin => Deployment {
+                bit: 2,
+                start_time: 1619222400, // April 24th, 2021
+                timeout: 1628640000,    // August 11th, 2021
+                min_activation_height: 709632,
+            },
+            Network::Testnet => Deployment {
+                bit: 2,
+                start_time: 1619222400, // April 24th, 2021
+                timeout: 1628640000,    // August 11th, 2021
+                min_activation_height: 0,
+            },
+            Network::Signet | Network::Regtest => Deployment {
+                bit: 2,
+                start_time: ALWAYS_ACTIVE,
+                timeout: NO_TIMEOUT,
+                min_activation_height: 0,
+            },
+        }
+    }
+
+    /// The bit of the block version signalling the deployment, zero if
+    /// `bit` is out of range and the deployment can never be signalled
+    pub fn mask(&self) -> i32 {
+        if self.bit < VERSIONBITS_NUM_BITS {
+            1 << self.bit
+        } else {
+            0
+        }
+    }
+
+    /// Whether a block with version `version` signals the deployment
+    pub fn is_signalled_by(&self, version: i32) -> bool {
+        version & VERSIONBITS_TOP_MASK == VERSIONBITS_TOP_BITS && version & self.mask() != 0
+    }
+
+    /// Returns the state of the deployment for the child of the block `prev`
+    /// of `chain`, or `None` if that block is unknown
+    pub fn state(&self, chain: &HeaderChain, prev: &BlockHash, cache: &mut VersionBitsCache) -> Option<ThresholdState> {
+        if self.start_time == ALWAYS_ACTIVE {
+            return Some(ThresholdState::Active);
+        }
+        if self.start_time == NEVER_ACTIVE {
+            return Some(ThresholdState::Failed);
+        }
+        let period = chain.params().miner_confirmation_window;
+        let threshold = chain.params().rule_change_activation_threshold;
+
+        // Walk back the last blocks of periods until one whose state is known
+        let mut to_compute = vec![];
+        let mut cursor = period_end(chain, prev, period)?;
+        let mut state = loop {
+            let (hash, entry) = match cursor {
+                Some(end) => end,
+                None => break ThresholdState::Defined,
+            };
+            if let Some(&state) = cache.states.get(&(*self, hash)) {
+                break state;
+            }
+            if (chain.median_time_past(&hash).expect("known block") as i64) < self.start_time {
+                cache.states.insert((*self, hash), ThresholdState::Defined);
+                break ThresholdState::Defined;
+            }
+            to_compute.push((hash, *entry));
+            cursor = if entry.height >= period {
+                let prev_end = chain.get_ancestor(&hash, entry.height - period).expect("known block");
+                Some((prev_end.header.block_hash(), prev_end))
+            } else {
+                None
+            };
+        };
+
+        // Then compute the states of the following periods
+        while let Some((hash, entry)) = to_compute.pop() {
+            let median_time_past = chain.median_time_past(&hash).expect("known block") as i64;
+            state = match state {
+                ThresholdState::Defined if median_time_past >= self.start_time => ThresholdState::Started,
+                ThresholdState::Started => {
+                    if self.count_signalling(chain, &entry, period) >= threshold {
+                        ThresholdState::LockedIn
+                    } else if median_time_past >= self.timeout {
+                        ThresholdState::Failed
+                    } else {
+                        ThresholdState::Started
+                    }
+                }
+                ThresholdState::LockedIn if entry.height + 1 >= self.min_activation_height => ThresholdState::Active,
+                state => state,
+            };
+            cache.states.insert((*self, hash), state);
+        }
+        Some(state)
+    }
+
+    /// Returns the signalling statistics of the period the child of the block
+    /// `prev` of `chain` belongs to, counting the blocks up to `prev`, or
+    /// `None` if that block is unknown
+    pub fn statistics(&self, chain: &HeaderChain, prev: &BlockHash) -> Option<Statistics> {
+        let entry = chain.get(prev)?;
+        let period = chain.params().miner_confirmation_window;
+        let threshold = chain.params().rule_change_activation_threshold;
+        let elapsed = (entry.height + 1) % period;
+        let count = self.count_signalling(chain, entry, elapsed);
+        Some(Statistics {
+            period: period,
+            threshold: threshold,
+            elapsed: elapsed,
+            count: count,
+            possible: period - threshold >= elapsed - count,
+        })
+    }
+
+    /// Counts the blocks signalling the deployment among `entry` and the
+    /// blocks before it, `blocks` in total
+    fn count_signalling(&self, chain: &HeaderChain, entry: &HeaderEntry, blocks: u32) -> u32 {
+        let mut count = 0;
+        let mut entry = entry;
+        for _ in 0..blocks {
+            if self.is_signalled_by(entry.header.version) {
+                count += 1;
+            }
+            if entry.height == 0 {
+                break;
+            }
+            entry = chain.get(&entry.header.prev_blockhash).expect("known block");
+        }
+        count
+    }
+}
+
+/// The last block of a period with its hash, `None` before the first period
+type PeriodEnd<'a> = Option<(BlockHash, &'a HeaderEntry)>;
+
+/// Returns the last block of the period before the one the child of `prev`
+/// belongs to, `Some(None)` if that is the first period, or `None` if `prev`
+/// is unknown
+fn period_end<'a>(chain: &'a HeaderChain, prev: &BlockHash, period: u32) -> Option<PeriodEnd<'a>> {
+    let entry = chain.get(prev)?;
+    let height = entry.height + 1;
+    if height < period {
+        return Some(None);
+    }
+    let end = chain.get_ancestor(prev, height - height % period - 1).expect("known block");
+    Some(Some((end.header.block_hash(), end)))
+}
+
+/// Computes the version of a block mined on top of `prev`: the version bits
+/// prefix, with the bits of the `deployments` being started or locked in
+/// set. Returns `None` if `prev` is unknown.
+pub fn compute_block_version(
+    chain: &HeaderChain,
+    prev: &BlockHash,
+    deployments: &[Deployment],
+    cache: &mut VersionBitsCache,
+) -> Option<i32> {
+    let mut version = VERSIONBITS_TOP_BITS;
+    for deployment in deployments {
+        match deployment.state(chain, prev, cache)? {
+            ThresholdState::Started | ThresholdState::LockedIn => version |= deployment.mask(),
+            _ => {}
+        }
+    }
+    Some(version)
+}
+
+/// Returns the bits signalled by a block with version `version`, none if its
+/// version does not use version bits
+pub fn signalled_bits(version: i32) -> Vec<u8> {
+    if version & VERSIONBITS_TOP_MASK != VERSIONBITS_TOP_BITS {
+        return vec![];
+    }
+    (0..VERSIONBITS_NUM_BITS).filter(|bit| version & (1 << bit) != 0).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blockdata::block::BlockHeader;
+    use consensus::Params;
+    use hash_types::TxMerkleNode;
+    use hashes::Hash;
+    use test_utils::grind_nonce;
+
+    const PERIOD: u32 = 10;
+    const THRESHOLD: u32 = 8;
+
+    /// A regtest chain with periods of ten blocks, eight of which must
+    /// signal, and blocks every ten minutes
+    fn chain() -> HeaderChain {
+        let mut params = Params::new(Network::Regtest);
+        params.miner_confirmation_window = PERIOD;
+        params.rule_change_activation_threshold = THRESHOLD;
+        HeaderChain::new(params)
+    }
+
+    /// Extends the best chain with a block of the given version
+    fn extend(chain: &mut HeaderChain, version: i32) -> BlockHash {
+        let tip = *chain.tip_entry();
+        let mut header = BlockHeader {
+            version: version,
+            prev_blockhash: chain.tip(),
+            merkle_root: TxMerkleNode::hash(&[]),
+            time: tip.header.time + 600,
+            bits: tip.header.bits,
+            nonce: 0,
+        };
+        grind_nonce(&mut header);
+        chain.accept(header).unwrap();
+        header.block_hash()
+    }
+
+    #[test]
+    fn version_bits() {
+        let deployment = Deployment::taproot(Network::Bitcoin);
+        assert_eq!(deployment.mask(), 4);
+        assert!(deployment.is_signalled_by(0x20000004));
+        assert!(deployment.is_signalled_by(0x3fffffff));
+        assert!(!deployment.is_signalled_by(0x20000000));
+        // Version 4 has the bit set, but does not use version bits
+        assert!(!deployment.is_signalled_by(4));
+        assert!(!deployment.is_signalled_by(0x60000004));
+
+        // Bits outside of the version bits are never signalled
+        for &bit in [VERSIONBITS_NUM_BITS, 31, 32, 255].iter() {
+            let deployment = Deployment { bit: bit, ..deployment };
+            assert_eq!(deployment.mask(), 0);
+            assert!(!deployment.is_signalled_by(0x3fffffff));
+            assert!(!deployment.is_signalled_by(-1));
+        }
+
+        assert_eq!(signalled_bits(0x20000004), vec![2]);
+        assert_eq!(signalled_bits(0x30000005), vec![0, 2, 28]);
+        assert_eq!(signalled_bits(0x20000000), Vec::<u8>::new());
+        assert_eq!(signalled_bits(4), Vec::<u8>::new());
+        assert_eq!(signalled_bits(VERSIONBITS_TOP_MASK | 4), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn state_machine() {
+        let mut chain = chain();
+        let genesis_time = chain.tip_entry().header.time as i64;
+        // The median time past of the block at height h is that of h - 5 from
+        // height 10, so the start is reached at the end of the second period
+        let activating = Deployment {
+            bit: 2,
+            start_time: genesis_time + 10 * 600,
+            timeout: genesis_time + 40 * 600,
+            min_activation_height: 50,
+        };
+        let failing = Deployment {
+            bit: 1,
+            start_time: genesis_time + 10 * 600,
+            timeout: genesis_time + 20 * 600,
+            min_activation_height: 0,
+        };
+        let always = Deployment::taproot(Network::Regtest);
+        let never = Deployment { start_time: NEVER_ACTIVE, ..failing };
+        let deployments = [activating, failing];
+        let mut cache = VersionBitsCache::new();
+
+        let mut states = vec![];
+        let mut versions = vec![];
+        for height in 1..=60 {
+            let prev = chain.tip();
+            let version = compute_block_version(&chain, &prev, &deployments, &mut cache).unwrap();
+            states.push(activating.state(&chain, &prev, &mut cache).unwrap());
+            versions.push(version);
+            assert_eq!(always.state(&chain, &prev, &mut cache), Some(ThresholdState::Active));
+            assert_eq!(never.state(&chain, &prev, &mut cache), Some(ThresholdState::Failed));
+
+            // Eight blocks of the third period signal the first deployment,
+            // version 4 does not count even though it has its bit set
+            let version = match height {
+                20..=27 => version & !failing.mask(),
+                28 | 29 => 4,
+                _ => version,
+            };
+            extend(&mut chain, version);
+        }
+
+        let state_at = |height: usize| states[height - 1];
+        assert_eq!(state_at(1), ThresholdState::Defined);
+        assert_eq!(state_at(19), ThresholdState::Defined);
+        assert_eq!(state_at(20), ThresholdState::Started);
+        assert_eq!(state_at(29), ThresholdState::Started);
+        assert_eq!(state_at(30), ThresholdState::LockedIn);
+        // Locked in for two periods because of the minimum activation height
+        assert_eq!(state_at(49), ThresholdState::LockedIn);
+        assert_eq!(state_at(50), ThresholdState::Active);
+        assert_eq!(state_at(60), ThresholdState::Active);
+
+        assert_eq!(versions[18], VERSIONBITS_TOP_BITS);
+        assert_eq!(versions[19], VERSIONBITS_TOP_BITS | 4 | 2);
+        assert_eq!(versions[29], VERSIONBITS_TOP_BITS | 4);
+        assert_eq!(versions[49], VERSIONBITS_TOP_BITS);
+
+        let tip = chain.tip();
+        let mut fresh = VersionBitsCache::new();
+        assert_eq!(failing.state(&chain, &tip, &mut fresh), Some(ThresholdState::Failed));
+        assert_eq!(activating.state(&chain, &tip, &mut fresh), Some(ThresholdState::Active));
+        let block_25 = chain.hash_at(25).unwrap();
+        assert_eq!(activating.state(&chain, &block_25, &mut fresh), Some(ThresholdState::Started));
+        assert_eq!(activating.state(&chain, &BlockHash::hash(&[]), &mut fresh), None);
+    }
+
+    #[test]
+    fn statistics() {
+        let mut chain = chain();
+        let deployment = Deployment::testdummy(Network::Regtest);
+        for height in 1..=13 {
+            let version = if height % 2 == 0 { VERSIONBITS_TOP_BITS | deployment.mask() } else { 4 };
+            extend(&mut chain, version);
+        }
+        // Blocks 10 to 13 of the second period, two of which signal
+        let stats = deployment.statistics(&chain, &chain.tip()).unwrap();
+        assert_eq!(stats, Statistics { period: PERIOD, threshold: THRESHOLD, elapsed: 4, count: 2, possible: true });
+
+        extend(&mut chain, 4);
+        let stats = deployment.statistics(&chain, &chain.tip()).unwrap();
+        assert_eq!((stats.elapsed, stats.count, stats.possible), (5, 2, false));
+    }
+}
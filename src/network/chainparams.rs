@@ -0,0 +1,240 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Chain parameters
+//!
+//! [`Network`] only knows about the four built-in chains. [`ChainParams`]
+//! gathers everything identifying a chain — network magic, address and key
+//! prefixes, genesis block and consensus parameters — in one value, so that
+//! other chains (custom signets, private regtests, ...) can be described by
+//! starting from the closest built-in chain and overriding some fields:
+//!
+//! ```rust
+//! use bitcoin::network::chainparams::ChainParams;
+//! use bitcoin::network::constants::Network;
+//!
+//! let params = ChainParams {
+//!     magic: 0x0b1d2f3e,
+//!     default_port: 28444,
+//!     bech32_hrp: "mwrt".to_owned(),
+//!     ..ChainParams::new(Network::Regtest)
+//! };
+//! assert_eq!(params.network, Network::Regtest);
+//! ```
+//!
+//! Values parsed with a `ChainParams` are tagged with its `network` field,
+//! which determines the rules they follow everywhere a [`Network`] is used.
+//!
+
+use blockdata::block::Block;
+use blockdata::constants::genesis_block;
+use blockdata::script::Script;
+use consensus::{serialize, Params};
+use hashes::{sha256d, Hash};
+use network::constants::Network;
+use util::endian;
+
+/// Parameters identifying a chain
+#[derive(Clone, Debug)]
+pub struct ChainParams {
+    /// The built-in network this chain is based on
+    pub network: Network,
+    /// Network magic, see [`Network::magic`]
+    pub magic: u32,
+    /// Default P2P port
+    pub default_port: u16,
+    /// Base58 version byte of P2PKH addresses
+    pub p2pkh_prefix: u8,
+    /// Base58 version byte of P2SH addresses
+    pub p2sh_prefix: u8,
+    /// Base58 version byte of WIF private keys
+    pub secret_key_prefix: u8,
+    /// Version bytes of BIP32 extended public keys
+    pub xpub_prefix: [u8; 4],
+    /// Version bytes of BIP32 extended private keys
+    pub xpriv_prefix: [u8; 4],
+    /// Human readable part of bech32 addresses
+    pub bech32_hrp: String,
+    /// The genesis block
+    pub genesis_block: Block,
+    /// Consensus parameters, including the proof of work limit
+    pub consensus: Params,
+}
+
+impl ChainParams {
+    /// Parameters of a built-in network
+    pub fn new(network: Network) -> ChainParams {
+        let (default_port, mainnet) = match network {
+            Network::Bitcoin => (8333, true),
+            Network::Testnet => (18333, false),
+            Network::Signet => (38333, false),
+            Network::Regtest => (18444, false),
+        };
+        ChainParams {
+            network: network,
+            magic: network.magic(),
+            default_port: default_port,
+            p2pkh_prefix: if mainnet { 0 } else { 111 },
+            p2sh_prefix: if mainnet { 5 } else { 196 },
+            secret_key_prefix: if mainnet { 128 } else { 239 },
+            xpub_prefix: if mainnet { [0x04, 0x88, 0xB2, 0x1E] } else { [0x04, 0x35, 0x87, 0xCF] },
+            xpriv_prefix: if mainnet { [0x04, 0x88, 0xAD, 0xE4] } else { [0x04, 0x35, 0x83, 0x94] },
+            bech32_hrp: match network {
+                Network::Bitcoin => "bc",
+                Network::Testnet | Network::Signet => "tb",
+                Network::Regtest => "bcrt",
+            }.to_owned(),
+            genesis_block: genesis_block(network),
+            consensus: Params::new(network),
+        }
+    }
+
+    /// Parameters of a signet with the given challenge. As specified by
+    /// BIP325, the network magic is the first four bytes of the double
+    /// SHA256 of the challenge as a single data push; everything else is
+    /// shared with the default signet.
+    pub fn custom_signet(challenge: Script) -> ChainParams {
+        let hash = sha256d::Hash::hash(&serialize(&challenge));
+        let mut params = ChainParams::new(Network::Signet);
+        params.magic = endian::slice_to_u32_le(&hash[0..4]);
+        params.consensus.signet_challenge = Some(challenge);
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use consensus::encode::{self, deserialize};
+    use consensus::headerchain::HeaderChain;
+    use network::message::{NetworkMessage, RawNetworkMessage};
+    use secp256k1::Secp256k1;
+    use blockdata::opcodes;
+    use blockdata::script::Builder;
+    use util::address::Address;
+    use util::bip32::{self, ExtendedPrivKey, ExtendedPubKey};
+    use util::key::{self, PrivateKey};
+
+    fn custom() -> ChainParams {
+        ChainParams {
+            magic: 0x0b1d2f3e,
+            default_port: 28444,
+            p2pkh_prefix: 50,
+            p2sh_prefix: 55,
+            secret_key_prefix: 180,
+            xpub_prefix: [0x01, 0x02, 0x03, 0x04],
+            xpriv_prefix: [0x05, 0x06, 0x07, 0x08],
+            bech32_hrp: "mwrt".to_owned(),
+            ..ChainParams::new(Network::Regtest)
+        }
+    }
+
+    #[test]
+    fn builtin() {
+        for &network in &[Network::Bitcoin, Network::Testnet, Network::Signet, Network::Regtest] {
+            let params = ChainParams::new(network);
+            assert_eq!(Network::from_magic(params.magic), Some(network));
+            assert_eq!(params.consensus.network, network);
+
+            // The built-in encodings are the ones of the parameters
+            let addr = Address::p2wpkh(&"033bc8c83c52df5712229a2f72206d90192366c36428cb0c12b6af98324d97bfbc".parse().unwrap(), network).unwrap();
+            let s = addr.to_string_with_params(&params);
+            assert_eq!(s, addr.to_string());
+            assert_eq!(Address::from_str_with_params(&s, &params).unwrap(), addr);
+
+            let addr = Address::p2sh(&Script::new(), network);
+            assert_eq!(addr.to_string_with_params(&params), addr.to_string());
+        }
+        let challenge = Builder::new().push_opcode(opcodes::OP_TRUE).into_script();
+        let params = ChainParams::custom_signet(challenge.clone());
+        assert_eq!(params.magic, 0xbd6fd254);
+        assert_eq!(params.consensus.signet_challenge, Some(challenge));
+        // The magic of the default signet derives from its challenge
+        let challenge = ChainParams::new(Network::Signet).consensus.signet_challenge.unwrap();
+        assert_eq!(ChainParams::custom_signet(challenge).magic, Network::Signet.magic());
+
+        let params = ChainParams::new(Network::Bitcoin);
+        assert_eq!(params.genesis_block.block_hash().to_string(), "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+        assert_eq!(params.default_port, 8333);
+    }
+
+    #[test]
+    fn custom_chain() {
+        let params = custom();
+        let regtest = ChainParams::new(Network::Regtest);
+        let secp = Secp256k1::new();
+
+        // Addresses
+        let pk = "033bc8c83c52df5712229a2f72206d90192366c36428cb0c12b6af98324d97bfbc".parse().unwrap();
+        for addr in &[
+            Address::p2pkh(&pk, Network::Regtest),
+            Address::p2sh(&Script::new(), Network::Regtest),
+            Address::p2wpkh(&pk, Network::Regtest).unwrap(),
+        ] {
+            let s = addr.to_string_with_params(&params);
+            assert_ne!(s, addr.to_string());
+            assert_eq!(Address::from_str_with_params(&s, &params).unwrap(), *addr);
+            assert!(Address::from_str_with_params(&addr.to_string(), &params).is_err());
+            assert!(Address::from_str_with_params(&s, &regtest).is_err());
+        }
+        let s = Address::p2wpkh(&pk, Network::Regtest).unwrap().to_string_with_params(&params);
+        assert!(s.starts_with("mwrt1q"));
+        assert_eq!(Address::from_str_with_params(&s.to_uppercase(), &params).unwrap().network, Network::Regtest);
+        assert!(Address::from_str_with_params("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", &params).is_err());
+
+        // WIF
+        let sk = PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
+        let wif = sk.to_wif_with_params(&params);
+        assert_eq!(PrivateKey::from_wif_with_params(&wif, &params).unwrap().key, sk.key);
+        assert_eq!(PrivateKey::from_wif_with_params(&wif, &params).unwrap().network, Network::Regtest);
+        match PrivateKey::from_wif_with_params(&sk.to_wif(), &params) {
+            Err(key::Error::Base58(_)) => {},
+            e => panic!("unexpected {:?}", e),
+        }
+
+        // BIP32
+        let xpriv = ExtendedPrivKey::new_master(Network::Regtest, &[42; 32]).unwrap();
+        let xpub = ExtendedPubKey::from_private(&secp, &xpriv);
+        assert_eq!(&xpriv.encode_with_params(&params)[0..4], &params.xpriv_prefix);
+        assert_eq!(&xpub.encode_with_params(&params)[0..4], &params.xpub_prefix);
+        let s = xpriv.to_string_with_params(&params);
+        assert_eq!(ExtendedPrivKey::from_str_with_params(&s, &params).unwrap(), xpriv);
+        assert!(ExtendedPrivKey::from_str(&s).is_err());
+        let s = xpub.to_string_with_params(&params);
+        assert_eq!(ExtendedPubKey::from_str_with_params(&s, &params).unwrap(), xpub);
+        match ExtendedPubKey::from_str_with_params(&xpub.to_string(), &params) {
+            Err(bip32::Error::UnknownVersion(v)) => assert_eq!(v, [0x04, 0x35, 0x87, 0xCF]),
+            e => panic!("unexpected {:?}", e),
+        }
+
+        // P2P messages
+        let msg = RawNetworkMessage::with_params(&params, NetworkMessage::Verack);
+        let decoded: RawNetworkMessage = deserialize(&serialize(&msg)).unwrap();
+        assert!(decoded.check_magic(&params).is_ok());
+        match decoded.check_magic(&regtest) {
+            Err(encode::Error::UnexpectedNetworkMagic { expected, actual }) => {
+                assert_eq!(expected, regtest.magic);
+                assert_eq!(actual, params.magic);
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+
+        // Header chain
+        let chain = HeaderChain::with_genesis(params.consensus.clone(), params.genesis_block.header);
+        assert_eq!(chain.tip(), params.genesis_block.block_hash());
+    }
+}
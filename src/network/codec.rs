@@ -0,0 +1,454 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Incremental message codec
+//!
+//! Non-blocking counterpart of `StreamReader`, decoding `RawNetworkMessage`s
+//! from bytes as they become available in any buffer, as needed by
+//! asynchronous runtimes. The message header is validated as soon as it is
+//! received: messages for another network, invalid commands and payloads
+//! larger than the limit of their command are rejected before the payload is
+//! allocated. The checksum is verified before the payload is decoded.
+//!
+//! The codec consumes bytes from the caller's buffer, so it can be wrapped
+//! into the codec traits of a runtime with a few lines:
+//!
+//! ```ignore
+//! impl tokio_util::codec::Decoder for MyCodec {
+//!     type Item = RawNetworkMessage;
+//!     type Error = MyError;
+//!
+//!     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+//!         let (consumed, message) = self.decoder.decode(src)?;
+//!         src.advance(consumed);
+//!         Ok(message)
+//!     }
+//! }
+//! ```
+//!
+
+use std::{cmp, error, fmt, io};
+
+use hashes::{sha256d, Hash};
+
+use consensus::encode::{self, CheckedData, Encodable};
+use network::message::{CommandString, NetworkMessage, RawNetworkMessage};
+use util::bloom::MAX_BLOOM_FILTER_SIZE;
+
+/// Length of the header of a message
+pub const HEADER_LEN: usize = 24;
+
+/// Maximum length of any message payload
+pub const MAX_PAYLOAD_LEN: usize = 4_000_000;
+
+/// Maximum number of entries of `addr` and `addrv2` messages
+const MAX_ADDR_TO_SEND: usize = 1_000;
+
+/// Maximum number of entries of inventory messages
+const MAX_INV_SZ: usize = 50_000;
+
+/// Maximum number of headers of a `headers` message
+const MAX_HEADERS_RESULTS: usize = 2_000;
+
+/// Maximum number of hashes in a block locator
+const MAX_LOCATOR_SZ: usize = 101;
+
+/// Maximum length of the user agent of a `version` message
+const MAX_SUBVERSION_LENGTH: usize = 256;
+
+/// Maximum length of the encoding of a count
+const MAX_VARINT_LEN: usize = 9;
+
+/// The maximum payload length of messages with the given command
+pub fn max_payload_len(command: &str) -> usize {
+    match command {
+        "verack" | "sendheaders" | "mempool" | "getaddr" | "filterclear" | "wtxidrelay" | "sendaddrv2" => 0,
+        "ping" | "pong" | "feefilter" => 8,
+        "sendcmpct" => 9,
+        // version, services, timestamp, two addresses, nonce, user agent,
+        // start height and relay flag
+        "version" => 4 + 8 + 8 + 2 * 26 + 8 + MAX_VARINT_LEN + MAX_SUBVERSION_LENGTH + 4 + 1,
+        // Timestamped addresses of at most 512 bytes in `addrv2`
+        "addr" => MAX_VARINT_LEN + MAX_ADDR_TO_SEND * 30,
+        "addrv2" => MAX_VARINT_LEN + MAX_ADDR_TO_SEND * (4 + MAX_VARINT_LEN + 1 + MAX_VARINT_LEN + 512 + 2),
+        "inv" | "getdata" | "notfound" => MAX_VARINT_LEN + MAX_INV_SZ * 36,
+        "getblocks" | "getheaders" => 4 + MAX_VARINT_LEN + MAX_LOCATOR_SZ * 32 + 32,
+        // Headers are followed by an empty transaction count
+        "headers" => MAX_VARINT_LEN + MAX_HEADERS_RESULTS * 81,
+        "filterload" => MAX_VARINT_LEN + MAX_BLOOM_FILTER_SIZE + 4 + 4 + 1,
+        "filteradd" => MAX_VARINT_LEN + 520,
+        "getcfilters" | "getcfheaders" => 1 + 4 + 32,
+        "getcfcheckpt" => 1 + 32,
+        "cfheaders" => 1 + 32 + 32 + MAX_VARINT_LEN + MAX_HEADERS_RESULTS * 32,
+        _ => MAX_PAYLOAD_LEN,
+    }
+}
+
+/// A message decoding error. The stream is unusable after an error.
+#[derive(Debug)]
+pub enum Error {
+    /// The magic of the message does not match the network
+    WrongMagic {
+        /// The expected magic
+        expected: u32,
+        /// The magic of the message
+        actual: u32,
+    },
+    /// The command is not null-padded ASCII
+    InvalidCommand,
+    /// The payload is longer than allowed for its command
+    PayloadTooLarge {
+        /// The command of the message
+        command: CommandString,
+        /// The length of the payload
+        len: usize,
+        /// The maximum length of the payload
+        max: usize,
+    },
+    /// The checksum of the payload is wrong
+    InvalidChecksum {
+        /// The checksum of the header
+        expected: [u8; 4],
+        /// The checksum of the payload
+        actual: [u8; 4],
+    },
+    /// The payload could not be decoded
+    Decode(encode::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::WrongMagic { expected, actual } => write!(f, "wrong magic: expected {:#x}, actual {:#x}", expected, actual),
+            Error::InvalidCommand => f.write_str("invalid command"),
+            Error::PayloadTooLarge { ref command, len, max } => write!(f, "{} payload too large: {} bytes, max {}", command, len, max),
+            Error::InvalidChecksum { expected: ref e, actual: ref a } => write!(f, "invalid checksum: expected {:?}, actual {:?}", e, a),
+            Error::Decode(ref e) => write!(f, "payload decoding failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            Error::Decode(ref e) => Some(e),
+            Error::WrongMagic { .. }
+            | Error::InvalidCommand
+            | Error::PayloadTooLarge { .. }
+            | Error::InvalidChecksum { .. } => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(err: encode::Error) -> Self {
+        Error::Decode(err)
+    }
+}
+
+/// A validated message header
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Header {
+    command: CommandString,
+    checksum: [u8; 4],
+}
+
+/// Incremental decoder of `RawNetworkMessage`s
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Decoder {
+    magic: u32,
+    header_bytes: Vec<u8>,
+    header: Option<Header>,
+    payload: Vec<u8>,
+    payload_len: usize,
+}
+
+impl Decoder {
+    /// Creates a decoder of messages of the network identified by `magic`
+    pub fn new(magic: u32) -> Decoder {
+        Decoder {
+            magic: magic,
+            header_bytes: Vec::with_capacity(HEADER_LEN),
+            header: None,
+            payload: vec![],
+            payload_len: 0,
+        }
+    }
+
+    /// Decodes bytes from `buf`, returning the number of bytes consumed and
+    /// a message if one was completed. Bytes are consumed up to the end of
+    /// the first completed message, so the decoder should be called again
+    /// with the rest of the buffer until it returns no message.
+    pub fn decode(&mut self, buf: &[u8]) -> Result<(usize, Option<RawNetworkMessage>), Error> {
+        let mut consumed = 0;
+        if self.header.is_none() {
+            let take = cmp::min(HEADER_LEN - self.header_bytes.len(), buf.len());
+            let start = self.header_bytes.len();
+            self.header_bytes.extend_from_slice(&buf[..take]);
+            consumed += take;
+            self.check_magic(start)?;
+            if self.header_bytes.len() < HEADER_LEN {
+                return Ok((consumed, None));
+            }
+            self.parse_header()?;
+        }
+
+        let take = cmp::min(self.payload_len - self.payload.len(), buf.len() - consumed);
+        self.payload.extend_from_slice(&buf[consumed..consumed + take]);
+        consumed += take;
+        if self.payload.len() < self.payload_len {
+            return Ok((consumed, None));
+        }
+
+        let header = self.header.take().expect("header was parsed");
+        self.header_bytes.clear();
+        let payload = ::std::mem::replace(&mut self.payload, vec![]);
+        let mut actual = [0; 4];
+        actual.copy_from_slice(&sha256d::Hash::hash(&payload)[..4]);
+        if actual != header.checksum {
+            return Err(Error::InvalidChecksum { expected: header.checksum, actual: actual });
+        }
+        let message = RawNetworkMessage {
+            magic: self.magic,
+            payload: NetworkMessage::deserialize_payload(header.command, payload)?,
+        };
+        Ok((consumed, Some(message)))
+    }
+
+    /// Checks the magic bytes received from position `start` on
+    fn check_magic(&self, start: usize) -> Result<(), Error> {
+        let magic = encode::serialize(&self.magic);
+        let end = cmp::min(self.header_bytes.len(), 4);
+        if start < end && self.header_bytes[start..end] != magic[start..end] {
+            let mut actual = [0; 4];
+            actual[..end].copy_from_slice(&self.header_bytes[..end]);
+            return Err(Error::WrongMagic {
+                expected: self.magic,
+                actual: encode::deserialize(&actual).expect("four bytes"),
+            });
+        }
+        Ok(())
+    }
+
+    fn parse_header(&mut self) -> Result<(), Error> {
+        let raw_command = &self.header_bytes[4..16];
+        let command_len = raw_command.iter().position(|b| *b == 0).unwrap_or(12);
+        if raw_command[command_len..].iter().any(|b| *b != 0)
+            || raw_command[..command_len].iter().any(|b| *b < b' ' || *b > b'~') {
+            return Err(Error::InvalidCommand);
+        }
+        let command = String::from_utf8(raw_command[..command_len].to_vec()).expect("ASCII");
+        let command = CommandString::try_from(command).expect("at most 12 bytes");
+
+        let len: u32 = encode::deserialize(&self.header_bytes[16..20]).expect("four bytes");
+        let max = max_payload_len(command.as_ref());
+        if len as usize > max {
+            return Err(Error::PayloadTooLarge { command: command, len: len as usize, max: max });
+        }
+
+        let mut checksum = [0; 4];
+        checksum.copy_from_slice(&self.header_bytes[20..24]);
+        self.payload = Vec::with_capacity(len as usize);
+        self.payload_len = len as usize;
+        self.header = Some(Header {
+            command: command,
+            checksum: checksum,
+        });
+        Ok(())
+    }
+}
+
+/// Encoder of `NetworkMessage`s
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Encoder {
+    magic: u32,
+}
+
+impl Encoder {
+    /// Creates an encoder of messages of the network identified by `magic`
+    pub fn new(magic: u32) -> Encoder {
+        Encoder {
+            magic: magic,
+        }
+    }
+
+    /// Encodes `message` into `dst`, returning the number of bytes written
+    pub fn encode<W: io::Write>(&self, message: &NetworkMessage, mut dst: W) -> Result<usize, io::Error> {
+        let mut len = self.magic.consensus_encode(&mut dst)?;
+        len += message.command().consensus_encode(&mut dst)?;
+        len += CheckedData(message.serialize_payload()).consensus_encode(&mut dst)?;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use consensus::encode::serialize;
+    use network::constants::Network;
+    use blockdata::block::BlockHeader;
+    use network::address::Address;
+    use network::constants::ServiceFlags;
+    use network::message_blockdata::Inventory;
+    use network::message_compact_blocks::SendCmpct;
+    use network::message_network::VersionMessage;
+
+    fn messages() -> Vec<NetworkMessage> {
+        vec![
+            NetworkMessage::Verack,
+            NetworkMessage::Ping(0x0102030405060708),
+            NetworkMessage::Inv(vec![Inventory::Transaction(Default::default()); 10]),
+            NetworkMessage::FilterAdd(vec![1, 2, 3]),
+        ]
+    }
+
+    #[test]
+    fn roundtrip() {
+        let magic = Network::Bitcoin.magic();
+        let encoder = Encoder::new(magic);
+        let mut bytes = vec![];
+        for message in messages() {
+            let raw = RawNetworkMessage { magic: magic, payload: message.clone() };
+            let mut encoded = vec![];
+            assert_eq!(encoder.encode(&message, &mut encoded).unwrap(), encoded.len());
+            assert_eq!(encoded, serialize(&raw));
+            bytes.extend(encoded);
+        }
+
+        // All at once
+        let mut decoder = Decoder::new(magic);
+        let mut decoded = vec![];
+        let mut pos = 0;
+        loop {
+            let (consumed, message) = decoder.decode(&bytes[pos..]).unwrap();
+            pos += consumed;
+            match message {
+                Some(message) => decoded.push(message.payload),
+                None => break,
+            }
+        }
+        assert_eq!(pos, bytes.len());
+        assert_eq!(decoded, messages());
+
+        // Byte by byte
+        let mut decoded = vec![];
+        for byte in &bytes {
+            let (consumed, message) = decoder.decode(&[*byte]).unwrap();
+            assert_eq!(consumed, 1);
+            decoded.extend(message.map(|m| m.payload));
+        }
+        assert_eq!(decoded, messages());
+        assert_eq!(decoder.decode(&[]).unwrap(), (0, None));
+    }
+
+    #[test]
+    fn validation() {
+        let magic = Network::Bitcoin.magic();
+        let ping = serialize(&RawNetworkMessage { magic: magic, payload: NetworkMessage::Ping(1) });
+
+        // The first wrong byte of the magic is detected
+        let mut decoder = Decoder::new(Network::Testnet.magic());
+        match decoder.decode(&ping[..1]) {
+            Err(Error::WrongMagic { expected, actual }) => {
+                assert_eq!(expected, Network::Testnet.magic());
+                assert_eq!(actual, magic & 0xff);
+            }
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        let mut bad = ping.clone();
+        bad[20] ^= 1;
+        match Decoder::new(magic).decode(&bad) {
+            Err(Error::InvalidChecksum { expected, actual }) => {
+                assert_eq!(expected[..], bad[20..24]);
+                assert_eq!(actual[..], ping[20..24]);
+            }
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        let mut bad = ping.clone();
+        bad[8] = b'\n';
+        match Decoder::new(magic).decode(&bad) {
+            Err(Error::InvalidCommand) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+        let mut bad = ping.clone();
+        bad[10] = b'x';
+        match Decoder::new(magic).decode(&bad) {
+            Err(Error::InvalidCommand) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        // Oversized payloads are rejected from the header alone
+        let mut bad = ping.clone();
+        bad[16] = 9;
+        match Decoder::new(magic).decode(&bad[..HEADER_LEN]) {
+            Err(Error::PayloadTooLarge { ref command, len: 9, max: 8 }) if command.as_ref() == "ping" => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+        let mut huge = serialize(&magic);
+        huge.extend_from_slice(b"block\0\0\0\0\0\0\0");
+        huge.extend(serialize(&(MAX_PAYLOAD_LEN as u32 + 1)));
+        huge.extend_from_slice(&[0; 4]);
+        match Decoder::new(magic).decode(&huge[..HEADER_LEN]) {
+            Err(Error::PayloadTooLarge { len, max: MAX_PAYLOAD_LEN, .. }) => assert_eq!(len, MAX_PAYLOAD_LEN + 1),
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        // Malformed payloads with a valid checksum
+        let raw = RawNetworkMessage {
+            magic: magic,
+            payload: NetworkMessage::Unknown { command: CommandString::try_from("tx").unwrap(), payload: vec![1, 2] },
+        };
+        let mut bytes = vec![];
+        Encoder::new(magic).encode(&raw.payload, &mut bytes).unwrap();
+        match Decoder::new(magic).decode(&bytes) {
+            Err(Error::Decode(_)) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn payload_limits() {
+        let address = Address::new(&"[2001:db8::1]:8333".parse().unwrap(), ServiceFlags::from(u64::max_value()));
+        let mut version = VersionMessage::new(ServiceFlags::from(u64::max_value()), 0, address.clone(), address, 0, "x".repeat(MAX_SUBVERSION_LENGTH), 0);
+        version.relay = true;
+        let header = BlockHeader {
+            version: 0,
+            prev_blockhash: Default::default(),
+            merkle_root: Default::default(),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+        };
+        let limited = vec![
+            NetworkMessage::Version(version),
+            NetworkMessage::Ping(0),
+            NetworkMessage::SendCmpct(SendCmpct { send_compact: true, version: 2 }),
+            NetworkMessage::Addr(vec![(0, Address::new(&"127.0.0.1:8333".parse().unwrap(), ServiceFlags::from(u64::max_value()))); MAX_ADDR_TO_SEND]),
+            NetworkMessage::Inv(vec![Inventory::WitnessTransaction(Default::default()); MAX_INV_SZ]),
+            NetworkMessage::Headers(vec![header; MAX_HEADERS_RESULTS]),
+            NetworkMessage::FilterAdd(vec![0; 520]),
+        ];
+        for message in limited {
+            let len = message.serialize_payload().len();
+            assert!(len <= max_payload_len(message.cmd()), "{} payload of {} bytes", message.cmd(), len);
+        }
+        assert_eq!(max_payload_len("verack"), 0);
+        assert_eq!(max_payload_len("block"), MAX_PAYLOAD_LEN);
+        assert_eq!(max_payload_len("unknown"), MAX_PAYLOAD_LEN);
+    }
+}
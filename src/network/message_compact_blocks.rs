@@ -0,0 +1,31 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP152 Compact Blocks network messages
+//!
+//! The `cmpctblock`, `getblocktxn` and `blocktxn` messages carry the data
+//! structures of [`util::bip152`](::util::bip152) directly.
+//!
+
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+/// sendcmpct message
+pub struct SendCmpct {
+    /// Whether new blocks should be announced with `cmpctblock` messages
+    /// rather than with `inv` or `headers` messages
+    pub send_compact: bool,
+    /// Compact block version: 1 for short ids of txids, 2 for short ids
+    /// of wtxids
+    pub version: u64,
+}
+impl_consensus_encoding!(SendCmpct, send_compact, version);
@@ -18,7 +18,7 @@
 //! capabilities
 //!
 
-use std::io;
+use std::{cmp, io};
 use std::borrow::Cow;
 
 use network::address::Address;
@@ -27,6 +27,7 @@ use consensus::{Encodable, Decodable, ReadExt};
 use consensus::encode;
 use network::message::CommandString;
 use hashes::sha256d;
+use util::amount::FeeRate;
 
 /// Some simple messages
 
@@ -143,9 +144,57 @@ pub struct Reject {
 
 impl_consensus_encoding!(Reject, message, ccode, reason, hash);
 
+/// The minimum fee rate of transactions to be announced to a peer, as sent
+/// in the `feefilter` message (BIP133). The rate is kept in satoshis per
+/// 1000 virtual bytes, the unit of the wire format, so it round-trips
+/// exactly.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct FeeFilter(u64);
+
+impl FeeFilter {
+    /// Creates a fee filter of `sat_kvb` satoshis per 1000 virtual bytes
+    pub fn from_sat_per_kvb(sat_kvb: u64) -> FeeFilter {
+        FeeFilter(sat_kvb)
+    }
+
+    /// The fee filter in satoshis per 1000 virtual bytes
+    pub fn as_sat_per_kvb(self) -> u64 {
+        self.0
+    }
+
+    /// The fee rate of the filter, rounded up to whole satoshis per 1000
+    /// weight units
+    pub fn fee_rate(self) -> FeeRate {
+        FeeRate::from_sat_per_kvb(self.0)
+    }
+}
+
+impl From<FeeRate> for FeeFilter {
+    fn from(fee_rate: FeeRate) -> FeeFilter {
+        FeeFilter(fee_rate.as_sat_per_kvb().unwrap_or(u64::max_value()))
+    }
+}
+
+impl Encodable for FeeFilter {
+    fn consensus_encode<W: io::Write>(&self, e: W) -> Result<usize, io::Error> {
+        // Sent as a signed integer, saturating
+        (cmp::min(self.0, i64::max_value() as u64) as i64).consensus_encode(e)
+    }
+}
+
+impl Decodable for FeeFilter {
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        let sat_kvb = i64::consensus_decode(d)?;
+        if sat_kvb < 0 {
+            return Err(encode::Error::ParseFailed("negative fee filter"));
+        }
+        Ok(FeeFilter(sat_kvb as u64))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::VersionMessage;
+    use super::{FeeFilter, VersionMessage};
 
     use hashes::hex::FromHex;
     use network::constants::ServiceFlags;
@@ -171,4 +220,20 @@ mod tests {
 
         assert_eq!(serialize(&real_decode), from_sat);
     }
+
+    #[test]
+    fn fee_filter_test() {
+        // Values which are not a whole number of sat/kwu round-trip
+        for &sat_kvb in &[0u64, 1, 999, 1001, 1000, 123_456_789] {
+            let filter = FeeFilter::from_sat_per_kvb(sat_kvb);
+            let encoded = serialize(&filter);
+            assert_eq!(encoded, serialize(&(sat_kvb as i64)));
+            assert_eq!(deserialize::<FeeFilter>(&encoded).unwrap().as_sat_per_kvb(), sat_kvb);
+        }
+        assert_eq!(FeeFilter::from_sat_per_kvb(1001).fee_rate().as_sat_per_kwu(), 251);
+        assert_eq!(FeeFilter::from(FeeFilter::from_sat_per_kvb(1000).fee_rate()).as_sat_per_kvb(), 1000);
+
+        assert!(deserialize::<FeeFilter>(&serialize(&-1i64)).is_err());
+        assert_eq!(serialize(&FeeFilter::from_sat_per_kvb(u64::max_value())), serialize(&i64::max_value()));
+    }
 }
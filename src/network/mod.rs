@@ -23,6 +23,7 @@ use std::io;
 use std::error;
 
 pub mod constants;
+pub mod chainparams;
 
 pub mod address;
 pub use self::address::Address;
@@ -30,7 +31,12 @@ pub mod message;
 pub mod message_blockdata;
 pub mod message_network;
 pub mod message_filter;
+pub mod message_compact_blocks;
 pub mod stream_reader;
+pub mod v2_transport;
+pub mod peer;
+#[cfg(feature = "async")]
+pub mod codec;
 
 /// Network error
 #[derive(Debug)]
@@ -0,0 +1,726 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Peer state machine
+//!
+//! This module implements the connection management of a single peer without
+//! performing any I/O. Bytes received from the peer are fed into a `Peer`,
+//! which in return produces the messages to be sent to the peer and events
+//! for the application. The `version`/`verack` handshake, the negotiation of
+//! optional features before `verack`, `ping`/`pong` keepalive and the
+//! detection of misbehaving peers are handled by the state machine.
+//!
+//! Time is supplied by the caller, which should call `Peer::tick`
+//! periodically to drive timeouts and keepalive pings.
+//!
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use consensus::encode::{self, MAX_VEC_SIZE};
+use network::constants::ServiceFlags;
+use network::message::{NetworkMessage, RawNetworkMessage};
+use network::message_network::VersionMessage;
+
+/// Lowest protocol version of peers we connect to by default
+pub const MIN_PEER_PROTO_VERSION: u32 = 31800;
+
+/// Protocol version from which `wtxidrelay` may be negotiated (BIP339)
+pub const WTXID_RELAY_VERSION: u32 = 70016;
+
+/// Length of the header of a message
+const HEADER_LEN: usize = 24;
+
+/// Misbehaviour score at which a peer gets disconnected
+pub const BAN_SCORE: u32 = 100;
+
+/// Configuration of a peer connection
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Config {
+    /// Magic bytes of the network of the connection
+    pub magic: u32,
+    /// Lowest protocol version accepted from the peer
+    pub min_version: u32,
+    /// Services the peer is required to offer
+    pub required_services: ServiceFlags,
+    /// Whether to negotiate transaction relay by wtxid (BIP339)
+    pub wtxid_relay: bool,
+    /// Whether to ask for `addrv2` address relay (BIP155)
+    pub addrv2: bool,
+    /// Time allowed for the handshake to complete
+    pub handshake_timeout: Duration,
+    /// Interval between keepalive pings
+    pub ping_interval: Duration,
+    /// Time after which an unanswered ping disconnects the peer
+    pub ping_timeout: Duration,
+}
+
+impl Config {
+    /// Creates a configuration with default settings for the network
+    /// identified by `magic`
+    pub fn new(magic: u32) -> Config {
+        Config {
+            magic: magic,
+            min_version: MIN_PEER_PROTO_VERSION,
+            required_services: ServiceFlags::NONE,
+            wtxid_relay: true,
+            addrv2: true,
+            handshake_timeout: Duration::from_secs(60),
+            ping_interval: Duration::from_secs(2 * 60),
+            ping_timeout: Duration::from_secs(20 * 60),
+        }
+    }
+}
+
+/// A protocol violation of the peer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Misbehaviour {
+    /// A message other than `version` was received before the `version`
+    NoVersion,
+    /// A second `version` was received
+    DuplicateVersion,
+    /// A message other than `verack` or feature negotiation was received
+    /// before the `verack`
+    NoVerack,
+    /// A second `verack` was received
+    DuplicateVerack,
+    /// A feature negotiation message was received after the `verack`
+    LateFeatureNegotiation,
+    /// A `pong` was received without an outstanding `ping` or with the
+    /// wrong nonce
+    UnsolicitedPong,
+}
+
+impl Misbehaviour {
+    /// The amount the misbehaviour score of the peer is increased by
+    pub fn score(self) -> u32 {
+        match self {
+            Misbehaviour::NoVersion
+            | Misbehaviour::DuplicateVersion
+            | Misbehaviour::NoVerack
+            | Misbehaviour::DuplicateVerack
+            | Misbehaviour::UnsolicitedPong => 10,
+            Misbehaviour::LateFeatureNegotiation => BAN_SCORE,
+        }
+    }
+}
+
+/// The reason a peer was disconnected
+#[derive(Debug)]
+pub enum DisconnectReason {
+    /// The peer uses a protocol version lower than `Config::min_version`
+    ObsoleteVersion(u32),
+    /// The peer does not offer the required services
+    MissingServices(ServiceFlags),
+    /// The peer echoed our own `version` nonce, so we're connected to ourselves
+    SelfConnection,
+    /// A message for another network was received
+    WrongMagic(u32),
+    /// A message could not be decoded
+    Malformed(encode::Error),
+    /// The handshake did not complete in time
+    HandshakeTimeout,
+    /// A `ping` was not answered in time
+    PingTimeout,
+    /// The misbehaviour score reached `BAN_SCORE`
+    Banned,
+}
+
+/// Information about a peer, available once the handshake completed
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PeerInfo {
+    /// The `version` message sent by the peer
+    pub version: VersionMessage,
+    /// The protocol version used on the connection
+    pub protocol_version: u32,
+    /// Whether transactions are announced by wtxid
+    pub wtxid_relay: bool,
+    /// Whether the peer wants to receive `addrv2` messages
+    pub addrv2: bool,
+}
+
+/// An event produced by the state machine
+#[derive(Debug)]
+pub enum Event {
+    /// The handshake completed
+    Connected(PeerInfo),
+    /// A message for the application was received
+    Message(NetworkMessage),
+    /// The peer violated the protocol
+    Misbehaved(Misbehaviour),
+    /// The connection should be closed. No further messages are produced
+    /// after this event.
+    Disconnected(DisconnectReason),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    AwaitingVersion,
+    AwaitingVerack,
+    Connected,
+    Disconnected,
+}
+
+/// Sans-I/O state machine of a single peer connection
+#[derive(Debug)]
+pub struct Peer {
+    config: Config,
+    our_version: VersionMessage,
+    their_version: Option<VersionMessage>,
+    their_wtxid_relay: bool,
+    their_addrv2: bool,
+    inbound: bool,
+    state: State,
+    started: Instant,
+    score: u32,
+    ping_nonce: u64,
+    ping: Option<(u64, Instant)>,
+    next_ping: Option<Instant>,
+    latency: Option<Duration>,
+    buffer: Vec<u8>,
+    outgoing: VecDeque<NetworkMessage>,
+    events: VecDeque<Event>,
+}
+
+impl Peer {
+    /// Creates the state machine of a connection, where `our_version` is the
+    /// `version` message we send. The outbound side of the connection sends
+    /// its `version` immediately, the inbound side once it received the
+    /// peer's.
+    pub fn new(config: Config, our_version: VersionMessage, inbound: bool, now: Instant) -> Peer {
+        let mut peer = Peer {
+            config: config,
+            ping_nonce: our_version.nonce,
+            our_version: our_version,
+            their_version: None,
+            their_wtxid_relay: false,
+            their_addrv2: false,
+            inbound: inbound,
+            state: State::AwaitingVersion,
+            started: now,
+            score: 0,
+            ping: None,
+            next_ping: None,
+            latency: None,
+            buffer: vec![],
+            outgoing: VecDeque::new(),
+            events: VecDeque::new(),
+        };
+        if !inbound {
+            let version = NetworkMessage::Version(peer.our_version.clone());
+            peer.outgoing.push_back(version);
+        }
+        peer
+    }
+
+    /// Whether the handshake completed and the peer is not disconnected
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Connected
+    }
+
+    /// Whether the peer was disconnected
+    pub fn is_disconnected(&self) -> bool {
+        self.state == State::Disconnected
+    }
+
+    /// The current misbehaviour score of the peer
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    /// The round trip time of the last answered `ping`
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Queues a message to be sent to the peer
+    pub fn send(&mut self, message: NetworkMessage) {
+        if self.state != State::Disconnected {
+            self.outgoing.push_back(message);
+        }
+    }
+
+    /// Returns the next message to be sent to the peer
+    pub fn poll_message(&mut self) -> Option<RawNetworkMessage> {
+        let magic = self.config.magic;
+        self.outgoing.pop_front().map(|payload| RawNetworkMessage {
+            magic: magic,
+            payload: payload,
+        })
+    }
+
+    /// Returns the next event for the application
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Processes bytes received from the peer
+    pub fn receive_bytes(&mut self, bytes: &[u8], now: Instant) {
+        self.buffer.extend_from_slice(bytes);
+        while self.state != State::Disconnected {
+            if self.buffer.len() >= 4 {
+                let magic: u32 = encode::deserialize(&self.buffer[..4]).expect("four bytes");
+                if magic != self.config.magic {
+                    self.disconnect(DisconnectReason::WrongMagic(magic));
+                    return;
+                }
+            }
+            // Wait for the complete message before decoding it, so that any
+            // decoding error means the message is malformed
+            if self.buffer.len() < HEADER_LEN {
+                return;
+            }
+            let payload_len: u32 = encode::deserialize(&self.buffer[16..20]).expect("four bytes");
+            let payload_len = payload_len as usize;
+            if payload_len > MAX_VEC_SIZE {
+                let error = encode::Error::OversizedVectorAllocation { requested: payload_len, max: MAX_VEC_SIZE };
+                return self.disconnect(DisconnectReason::Malformed(error));
+            }
+            if self.buffer.len() < HEADER_LEN + payload_len {
+                return;
+            }
+            let message = encode::deserialize::<RawNetworkMessage>(&self.buffer[..HEADER_LEN + payload_len]);
+            self.buffer.drain(..HEADER_LEN + payload_len);
+            match message {
+                Ok(message) => self.receive(message.payload, now),
+                Err(e) => self.disconnect(DisconnectReason::Malformed(e)),
+            }
+        }
+    }
+
+    /// Processes a message received from the peer, for use with a transport
+    /// doing its own framing
+    pub fn receive(&mut self, message: NetworkMessage, now: Instant) {
+        match self.state {
+            State::AwaitingVersion => match message {
+                NetworkMessage::Version(version) => self.receive_version(version),
+                _ => self.misbehaved(Misbehaviour::NoVersion),
+            },
+            State::AwaitingVerack => match message {
+                NetworkMessage::Version(_) => self.misbehaved(Misbehaviour::DuplicateVersion),
+                NetworkMessage::WtxidRelay => self.their_wtxid_relay = true,
+                NetworkMessage::SendAddrV2 => self.their_addrv2 = true,
+                NetworkMessage::Verack => self.connected(now),
+                _ => self.misbehaved(Misbehaviour::NoVerack),
+            },
+            State::Connected => match message {
+                NetworkMessage::Version(_) => self.misbehaved(Misbehaviour::DuplicateVersion),
+                NetworkMessage::Verack => self.misbehaved(Misbehaviour::DuplicateVerack),
+                NetworkMessage::WtxidRelay
+                | NetworkMessage::SendAddrV2 => self.misbehaved(Misbehaviour::LateFeatureNegotiation),
+                NetworkMessage::Ping(nonce) => self.outgoing.push_back(NetworkMessage::Pong(nonce)),
+                NetworkMessage::Pong(nonce) => match self.ping {
+                    Some((expected, sent)) if expected == nonce => {
+                        self.latency = Some(now.duration_since(sent));
+                        self.ping = None;
+                    }
+                    _ => self.misbehaved(Misbehaviour::UnsolicitedPong),
+                },
+                message => self.events.push_back(Event::Message(message)),
+            },
+            State::Disconnected => {}
+        }
+    }
+
+    /// Drives timeouts and keepalive pings
+    pub fn tick(&mut self, now: Instant) {
+        match self.state {
+            State::AwaitingVersion | State::AwaitingVerack => {
+                if now.duration_since(self.started) >= self.config.handshake_timeout {
+                    self.disconnect(DisconnectReason::HandshakeTimeout);
+                }
+            }
+            State::Connected => {
+                if let Some((_, sent)) = self.ping {
+                    if now.duration_since(sent) >= self.config.ping_timeout {
+                        self.disconnect(DisconnectReason::PingTimeout);
+                    }
+                } else if self.next_ping.map_or(true, |next| now >= next) {
+                    self.send_ping(now);
+                }
+            }
+            State::Disconnected => {}
+        }
+    }
+
+    /// The protocol version used on the connection
+    fn protocol_version(&self, theirs: &VersionMessage) -> u32 {
+        cmp::min(self.our_version.version, theirs.version)
+    }
+
+    fn receive_version(&mut self, version: VersionMessage) {
+        if version.version < self.config.min_version {
+            return self.disconnect(DisconnectReason::ObsoleteVersion(version.version));
+        }
+        if !version.services.has(self.config.required_services) {
+            return self.disconnect(DisconnectReason::MissingServices(version.services));
+        }
+        if version.nonce == self.our_version.nonce {
+            return self.disconnect(DisconnectReason::SelfConnection);
+        }
+
+        if self.inbound {
+            let ours = NetworkMessage::Version(self.our_version.clone());
+            self.outgoing.push_back(ours);
+        }
+        // Features are negotiated between `version` and `verack`
+        if self.config.wtxid_relay && self.protocol_version(&version) >= WTXID_RELAY_VERSION {
+            self.outgoing.push_back(NetworkMessage::WtxidRelay);
+        }
+        if self.config.addrv2 {
+            self.outgoing.push_back(NetworkMessage::SendAddrV2);
+        }
+        self.outgoing.push_back(NetworkMessage::Verack);
+        self.their_version = Some(version);
+        self.state = State::AwaitingVerack;
+    }
+
+    fn connected(&mut self, now: Instant) {
+        let version = self.their_version.clone().expect("verack is only accepted after version");
+        let protocol_version = self.protocol_version(&version);
+        let info = PeerInfo {
+            wtxid_relay: self.config.wtxid_relay
+                && self.their_wtxid_relay
+                && protocol_version >= WTXID_RELAY_VERSION,
+            addrv2: self.their_addrv2,
+            protocol_version: protocol_version,
+            version: version,
+        };
+        self.state = State::Connected;
+        self.events.push_back(Event::Connected(info));
+        self.send_ping(now);
+    }
+
+    fn send_ping(&mut self, now: Instant) {
+        self.ping_nonce = self.ping_nonce.wrapping_add(1);
+        self.outgoing.push_back(NetworkMessage::Ping(self.ping_nonce));
+        self.ping = Some((self.ping_nonce, now));
+        self.next_ping = Some(now + self.config.ping_interval);
+    }
+
+    fn misbehaved(&mut self, misbehaviour: Misbehaviour) {
+        self.score = self.score.saturating_add(misbehaviour.score());
+        self.events.push_back(Event::Misbehaved(misbehaviour));
+        if self.score >= BAN_SCORE {
+            self.disconnect(DisconnectReason::Banned);
+        }
+    }
+
+    fn disconnect(&mut self, reason: DisconnectReason) {
+        self.state = State::Disconnected;
+        self.outgoing.clear();
+        self.buffer.clear();
+        self.events.push_back(Event::Disconnected(reason));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use consensus::encode::serialize;
+    use hashes::{sha256d, Hash};
+    use network::address::Address;
+    use network::constants::{Network, PROTOCOL_VERSION};
+
+    fn version(version: u32, nonce: u64) -> VersionMessage {
+        let address = Address::new(&"127.0.0.1:8333".parse().unwrap(), ServiceFlags::NONE);
+        let mut message = VersionMessage::new(
+            ServiceFlags::NETWORK | ServiceFlags::WITNESS,
+            1620000000,
+            address.clone(),
+            address,
+            nonce,
+            "/rust-bitcoin/".to_string(),
+            0,
+        );
+        message.version = version;
+        message
+    }
+
+    fn pair(outbound_version: u32, inbound_version: u32, now: Instant) -> (Peer, Peer) {
+        let config = Config::new(Network::Bitcoin.magic());
+        let outbound = Peer::new(config.clone(), version(outbound_version, 1), false, now);
+        let inbound = Peer::new(config, version(inbound_version, 2), true, now);
+        (outbound, inbound)
+    }
+
+    /// Delivers all queued messages of `from` to `to`, returning their commands
+    fn deliver(from: &mut Peer, to: &mut Peer, now: Instant) -> Vec<&'static str> {
+        let mut commands = vec![];
+        while let Some(message) = from.poll_message() {
+            commands.push(message.cmd());
+            to.receive_bytes(&serialize(&message), now);
+        }
+        commands
+    }
+
+    /// Exchanges messages until both sides are idle
+    fn pump(a: &mut Peer, b: &mut Peer, now: Instant) {
+        while !deliver(a, b, now).is_empty() | !deliver(b, a, now).is_empty() {}
+    }
+
+    fn events(peer: &mut Peer) -> Vec<Event> {
+        let mut events = vec![];
+        while let Some(event) = peer.poll_event() {
+            events.push(event);
+        }
+        events
+    }
+
+    fn connected_info(peer: &mut Peer) -> PeerInfo {
+        match events(peer).pop() {
+            Some(Event::Connected(info)) => info,
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let now = Instant::now();
+        let (mut outbound, mut inbound) = pair(WTXID_RELAY_VERSION, WTXID_RELAY_VERSION, now);
+
+        assert_eq!(deliver(&mut inbound, &mut outbound, now), Vec::<&str>::new());
+        assert_eq!(deliver(&mut outbound, &mut inbound, now), vec!["version"]);
+        assert_eq!(deliver(&mut inbound, &mut outbound, now), vec!["version", "wtxidrelay", "sendaddrv2", "verack"]);
+        assert_eq!(deliver(&mut outbound, &mut inbound, now), vec!["wtxidrelay", "sendaddrv2", "verack", "ping"]);
+        assert_eq!(deliver(&mut inbound, &mut outbound, now), vec!["ping", "pong"]);
+        assert_eq!(deliver(&mut outbound, &mut inbound, now), vec!["pong"]);
+        assert!(outbound.is_connected() && inbound.is_connected());
+        assert_eq!(outbound.latency(), Some(Duration::from_secs(0)));
+        assert_eq!(inbound.latency(), Some(Duration::from_secs(0)));
+
+        let info = connected_info(&mut outbound);
+        assert_eq!(info.version, version(WTXID_RELAY_VERSION, 2));
+        assert_eq!(info.protocol_version, WTXID_RELAY_VERSION);
+        assert!(info.wtxid_relay && info.addrv2);
+        assert_eq!(connected_info(&mut inbound).version.nonce, 1);
+
+        // Application messages are passed through
+        outbound.send(NetworkMessage::MemPool);
+        pump(&mut outbound, &mut inbound, now);
+        match &events(&mut inbound)[..] {
+            [Event::Message(NetworkMessage::MemPool)] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+        assert_eq!(outbound.score() + inbound.score(), 0);
+    }
+
+    #[test]
+    fn feature_negotiation() {
+        let now = Instant::now();
+
+        // No wtxid relay below its protocol version
+        let (mut outbound, mut inbound) = pair(PROTOCOL_VERSION, WTXID_RELAY_VERSION, now);
+        deliver(&mut outbound, &mut inbound, now);
+        assert_eq!(deliver(&mut inbound, &mut outbound, now), vec!["version", "sendaddrv2", "verack"]);
+        pump(&mut outbound, &mut inbound, now);
+        let info = connected_info(&mut inbound);
+        assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+        assert!(!info.wtxid_relay && info.addrv2);
+
+        // Features disabled on one side
+        let mut config = Config::new(Network::Bitcoin.magic());
+        config.wtxid_relay = false;
+        config.addrv2 = false;
+        let mut outbound = Peer::new(config, version(WTXID_RELAY_VERSION, 1), false, now);
+        let mut inbound = Peer::new(Config::new(Network::Bitcoin.magic()), version(WTXID_RELAY_VERSION, 2), true, now);
+        pump(&mut outbound, &mut inbound, now);
+        let outbound_info = connected_info(&mut outbound);
+        assert!(!outbound_info.wtxid_relay && outbound_info.addrv2);
+        let inbound_info = connected_info(&mut inbound);
+        assert!(!inbound_info.wtxid_relay && !inbound_info.addrv2);
+    }
+
+    #[test]
+    fn keepalive() {
+        let now = Instant::now();
+        let (mut outbound, mut inbound) = pair(PROTOCOL_VERSION, PROTOCOL_VERSION, now);
+        pump(&mut outbound, &mut inbound, now);
+        events(&mut outbound);
+
+        // Nothing to do before the ping interval
+        let config = Config::new(Network::Bitcoin.magic());
+        outbound.tick(now + config.ping_interval / 2);
+        assert!(outbound.poll_message().is_none());
+
+        let later = now + config.ping_interval;
+        outbound.tick(later);
+        assert_eq!(deliver(&mut outbound, &mut inbound, later), vec!["ping"]);
+        let answered = later + Duration::from_millis(150);
+        assert_eq!(deliver(&mut inbound, &mut outbound, answered), vec!["pong"]);
+        assert_eq!(outbound.latency(), Some(Duration::from_millis(150)));
+
+        // An unanswered ping times out
+        let later = later + config.ping_interval;
+        outbound.tick(later);
+        assert_eq!(outbound.poll_message().map(|m| m.cmd()), Some("ping"));
+        outbound.tick(later + config.ping_timeout);
+        match &events(&mut outbound)[..] {
+            [Event::Disconnected(DisconnectReason::PingTimeout)] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+        assert!(outbound.is_disconnected());
+
+        // Pongs must match the outstanding ping
+        inbound.receive(NetworkMessage::Pong(42), later);
+        match &events(&mut inbound)[..] {
+            [Event::Connected(_), Event::Misbehaved(Misbehaviour::UnsolicitedPong)] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+        assert_eq!(inbound.score(), 10);
+    }
+
+    #[test]
+    fn handshake_failures() {
+        let now = Instant::now();
+        let magic = Network::Bitcoin.magic();
+
+        let (mut outbound, _) = pair(PROTOCOL_VERSION, PROTOCOL_VERSION, now);
+        outbound.tick(now + Config::new(magic).handshake_timeout);
+        match &events(&mut outbound)[..] {
+            [Event::Disconnected(DisconnectReason::HandshakeTimeout)] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+        assert!(outbound.poll_message().is_none());
+
+        let (mut outbound, mut inbound) = pair(PROTOCOL_VERSION, 31799, now);
+        pump(&mut outbound, &mut inbound, now);
+        match &events(&mut outbound)[..] {
+            [Event::Disconnected(DisconnectReason::ObsoleteVersion(31799))] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+
+        let mut config = Config::new(magic);
+        config.required_services = ServiceFlags::COMPACT_FILTERS;
+        let mut outbound = Peer::new(config, version(PROTOCOL_VERSION, 1), false, now);
+        let mut inbound = Peer::new(Config::new(magic), version(PROTOCOL_VERSION, 2), true, now);
+        pump(&mut outbound, &mut inbound, now);
+        match &events(&mut outbound)[..] {
+            [Event::Disconnected(DisconnectReason::MissingServices(services))] => {
+                assert_eq!(*services, ServiceFlags::NETWORK | ServiceFlags::WITNESS)
+            },
+            e => panic!("unexpected events {:?}", e),
+        }
+
+        let mut outbound = Peer::new(Config::new(magic), version(PROTOCOL_VERSION, 1), false, now);
+        let mut inbound = Peer::new(Config::new(magic), version(PROTOCOL_VERSION, 1), true, now);
+        deliver(&mut outbound, &mut inbound, now);
+        match &events(&mut inbound)[..] {
+            [Event::Disconnected(DisconnectReason::SelfConnection)] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+        assert!(inbound.poll_message().is_none());
+
+        let (_, mut inbound) = pair(PROTOCOL_VERSION, PROTOCOL_VERSION, now);
+        let message = RawNetworkMessage { magic: Network::Testnet.magic(), payload: NetworkMessage::Verack };
+        inbound.receive_bytes(&serialize(&message)[..4], now);
+        match &events(&mut inbound)[..] {
+            [Event::Disconnected(DisconnectReason::WrongMagic(magic))] => assert_eq!(*magic, Network::Testnet.magic()),
+            e => panic!("unexpected events {:?}", e),
+        }
+
+        let (_, mut inbound) = pair(PROTOCOL_VERSION, PROTOCOL_VERSION, now);
+        let mut bytes = serialize(&RawNetworkMessage { magic: magic, payload: NetworkMessage::Ping(1) });
+        bytes[20] ^= 1;
+        inbound.receive_bytes(&bytes, now);
+        match &events(&mut inbound)[..] {
+            [Event::Disconnected(DisconnectReason::Malformed(encode::Error::InvalidChecksum { .. }))] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let now = Instant::now();
+        let magic = Network::Bitcoin.magic();
+        let (_, mut inbound) = pair(PROTOCOL_VERSION, PROTOCOL_VERSION, now);
+
+        // A `ping` with a valid checksum but a payload too short for a nonce
+        let payload = [1, 2, 3, 4];
+        let mut bytes = serialize(&magic);
+        bytes.extend_from_slice(b"ping\0\0\0\0\0\0\0\0");
+        bytes.extend(serialize(&(payload.len() as u32)));
+        bytes.extend_from_slice(&sha256d::Hash::hash(&payload)[..4]);
+        bytes.extend_from_slice(&payload);
+        for _ in 0..3 {
+            bytes.extend(serialize(&RawNetworkMessage { magic: magic, payload: NetworkMessage::Verack }));
+        }
+
+        inbound.receive_bytes(&bytes, now);
+        assert!(inbound.is_disconnected());
+        match &events(&mut inbound)[..] {
+            [Event::Disconnected(DisconnectReason::Malformed(encode::Error::Io(_)))] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+
+        // Oversized payloads are rejected from the header
+        let (_, mut inbound) = pair(PROTOCOL_VERSION, PROTOCOL_VERSION, now);
+        let mut header = serialize(&RawNetworkMessage { magic: magic, payload: NetworkMessage::Verack });
+        header[16..20].copy_from_slice(&serialize(&(MAX_VEC_SIZE as u32 + 1)));
+        inbound.receive_bytes(&header, now);
+        match &events(&mut inbound)[..] {
+            [Event::Disconnected(DisconnectReason::Malformed(encode::Error::OversizedVectorAllocation { .. }))] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+    }
+
+    #[test]
+    fn misbehaviour() {
+        let now = Instant::now();
+        let (mut outbound, mut inbound) = pair(PROTOCOL_VERSION, PROTOCOL_VERSION, now);
+
+        // Messages out of order are scored
+        inbound.receive(NetworkMessage::MemPool, now);
+        deliver(&mut outbound, &mut inbound, now);
+        inbound.receive(NetworkMessage::GetAddr, now);
+        inbound.receive(NetworkMessage::Version(version(PROTOCOL_VERSION, 3)), now);
+        match &events(&mut inbound)[..] {
+            [Event::Misbehaved(Misbehaviour::NoVersion),
+             Event::Misbehaved(Misbehaviour::NoVerack),
+             Event::Misbehaved(Misbehaviour::DuplicateVersion)] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+        assert_eq!(inbound.score(), 30);
+
+        // Messages may be split and coalesced arbitrarily
+        deliver(&mut inbound, &mut outbound, now);
+        let mut bytes = vec![];
+        while let Some(message) = outbound.poll_message() {
+            bytes.extend(serialize(&message));
+        }
+        for chunk in bytes.chunks(5) {
+            inbound.receive_bytes(chunk, now);
+        }
+        assert!(inbound.is_connected());
+
+        inbound.receive(NetworkMessage::Verack, now);
+        inbound.receive(NetworkMessage::SendAddrV2, now);
+        match &events(&mut inbound)[..] {
+            [Event::Connected(_),
+             Event::Misbehaved(Misbehaviour::DuplicateVerack),
+             Event::Misbehaved(Misbehaviour::LateFeatureNegotiation),
+             Event::Disconnected(DisconnectReason::Banned)] => {},
+            e => panic!("unexpected events {:?}", e),
+        }
+
+        // Nothing happens after disconnection
+        inbound.send(NetworkMessage::MemPool);
+        inbound.receive(NetworkMessage::MemPool, now);
+        inbound.tick(now + Duration::from_secs(3600));
+        assert!(inbound.poll_message().is_none());
+        assert!(inbound.poll_event().is_none());
+    }
+}
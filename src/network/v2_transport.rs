@@ -0,0 +1,899 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP324 v2 encrypted transport
+//!
+//! Implementation of the version 2 P2P transport protocol defined in
+//! [BIP324](https://github.com/bitcoin/bips/blob/master/bip-0324.mediawiki).
+//! Peers exchange ElligatorSwift encoded public keys, derive a set of session
+//! keys from the shared secret and then exchange packets encrypted with
+//! forward-secure ChaCha20Poly1305. Messages are identified by a one-byte
+//! short ID when one is assigned, or by their full command otherwise.
+//!
+
+use std::{error, fmt, io};
+use std::io::{Cursor, Read, Write};
+
+use hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use secp256k1::{Secp256k1, SecretKey, Signing, Verification};
+
+use consensus::encode::{self, Decodable, Encodable};
+use network::message::{CommandString, NetworkMessage};
+use util::chacha20_poly1305::{self, ChaCha20, ChaCha20Poly1305};
+use util::ellswift::ElligatorSwift;
+
+/// Maximum number of garbage bytes sent before the garbage terminator
+pub const MAX_GARBAGE_LEN: usize = 4095;
+
+/// Length of the garbage terminators
+pub const GARBAGE_TERMINATOR_LEN: usize = 16;
+
+/// Maximum length of the contents of a packet
+pub const MAX_CONTENTS_LEN: usize = (1 << 24) - 1;
+
+/// Maximum length of the contents of a received packet, a message with a
+/// full command and a payload of at most 4,000,000 bytes
+pub const MAX_RECEIVED_CONTENTS_LEN: usize = 1 + 12 + 4_000_000;
+
+/// Length of the encrypted length field preceding every packet
+pub const LENGTH_FIELD_LEN: usize = 3;
+
+/// Length of the encrypted header byte of every packet
+const HEADER_LEN: usize = 1;
+
+/// Header bit marking packets which should be ignored by the receiver
+const IGNORE_BIT: u8 = 0x80;
+
+/// Number of packets encrypted with a key before it is replaced
+const REKEY_INTERVAL: u64 = 224;
+
+/// Number of bytes of packet overhead on top of its contents
+pub const PACKET_OVERHEAD: usize = LENGTH_FIELD_LEN + HEADER_LEN + chacha20_poly1305::TAG_LEN;
+
+/// Short message IDs, the position in the list being the ID
+const SHORT_IDS: [&str; 29] = [
+    "", "addr", "block", "blocktxn", "cmpctblock", "feefilter", "filteradd", "filterclear",
+    "filterload", "getblocks", "getblocktxn", "getdata", "getheaders", "headers", "inv",
+    "mempool", "merkleblock", "notfound", "ping", "pong", "sendcmpct", "tx", "getcfilters",
+    "cfilter", "getcfheaders", "cfheaders", "getcfcheckpt", "cfcheckpt", "addrv2",
+];
+
+/// A v2 transport error
+#[derive(Debug)]
+pub enum Error {
+    /// And I/O error
+    Io(io::Error),
+    /// The peer started a v1 connection
+    V1Peer,
+    /// Garbage longer than `MAX_GARBAGE_LEN` was supplied
+    GarbageTooLong(usize),
+    /// The peer's garbage terminator was not found
+    NoGarbageTerminator,
+    /// A packet failed authentication
+    Decryption(chacha20_poly1305::Error),
+    /// The contents of a packet exceed `MAX_CONTENTS_LEN`, or
+    /// `MAX_RECEIVED_CONTENTS_LEN` for a received packet
+    ContentsTooLarge(usize),
+    /// A message payload could not be decoded
+    Encode(encode::Error),
+    /// The message has no contents
+    EmptyMessage,
+    /// The message type has an unassigned short ID
+    UnknownShortId(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => fmt::Display::fmt(e, f),
+            Error::V1Peer => f.write_str("peer is using the v1 transport"),
+            Error::GarbageTooLong(len) => write!(f, "garbage too long: {} bytes", len),
+            Error::NoGarbageTerminator => f.write_str("garbage terminator not found"),
+            Error::Decryption(ref e) => write!(f, "packet decryption failed: {}", e),
+            Error::ContentsTooLarge(len) => write!(f, "packet contents too large: {} bytes", len),
+            Error::Encode(ref e) => write!(f, "message decoding failed: {}", e),
+            Error::EmptyMessage => f.write_str("empty message"),
+            Error::UnknownShortId(id) => write!(f, "unknown short message ID: {}", id),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Decryption(ref e) => Some(e),
+            Error::Encode(ref e) => Some(e),
+            Error::V1Peer
+            | Error::GarbageTooLong(..)
+            | Error::NoGarbageTerminator
+            | Error::ContentsTooLarge(..)
+            | Error::EmptyMessage
+            | Error::UnknownShortId(..) => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[doc(hidden)]
+impl From<chacha20_poly1305::Error> for Error {
+    fn from(err: chacha20_poly1305::Error) -> Self {
+        Error::Decryption(err)
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for Error {
+    fn from(err: encode::Error) -> Self {
+        Error::Encode(err)
+    }
+}
+
+/// HKDF-SHA256 extraction step
+fn hkdf_extract(salt: &[u8], ikm: &[u8]) -> Hmac<sha256::Hash> {
+    let mut engine = HmacEngine::<sha256::Hash>::new(salt);
+    engine.input(ikm);
+    Hmac::from_engine(engine)
+}
+
+/// HKDF-SHA256 expansion step for outputs of at most one hash length
+fn hkdf_expand(prk: &Hmac<sha256::Hash>, info: &[u8], out: &mut [u8]) {
+    assert!(out.len() <= sha256::Hash::LEN);
+    let mut engine = HmacEngine::<sha256::Hash>::new(&prk[..]);
+    engine.input(info);
+    engine.input(&[1]);
+    let okm = Hmac::<sha256::Hash>::from_engine(engine);
+    let len = out.len();
+    out.copy_from_slice(&okm[..len]);
+}
+
+/// Computes the shared secret of a v2 connection
+pub fn shared_secret<C: Verification>(
+    secp: &Secp256k1<C>,
+    secret_key: &SecretKey,
+    ellswift_initiator: &ElligatorSwift,
+    ellswift_responder: &ElligatorSwift,
+    initiating: bool,
+) -> [u8; 32] {
+    let theirs = if initiating { ellswift_responder } else { ellswift_initiator };
+    let x = theirs.xonly_ecdh(secp, secret_key);
+
+    let tag = sha256::Hash::hash(b"bip324_ellswift_xonly_ecdh");
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(&ellswift_initiator[..]);
+    engine.input(&ellswift_responder[..]);
+    engine.input(&x);
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// The keys of a v2 session, derived from the shared secret
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SessionKeys {
+    /// Identifier of the session, which can be compared out of band
+    pub session_id: [u8; 32],
+    /// Key of the initiator's length cipher
+    pub initiator_length: [u8; 32],
+    /// Key of the initiator's packet cipher
+    pub initiator_packet: [u8; 32],
+    /// Key of the responder's length cipher
+    pub responder_length: [u8; 32],
+    /// Key of the responder's packet cipher
+    pub responder_packet: [u8; 32],
+    /// Garbage terminator sent by the initiator
+    pub initiator_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    /// Garbage terminator sent by the responder
+    pub responder_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+}
+
+impl SessionKeys {
+    /// Derives the session keys from the shared secret and the network magic
+    pub fn derive(shared_secret: &[u8; 32], magic: u32) -> SessionKeys {
+        let mut salt = b"bitcoin_v2_shared_secret".to_vec();
+        magic.consensus_encode(&mut salt).expect("engines don't error");
+        let prk = hkdf_extract(&salt, shared_secret);
+
+        let mut keys = SessionKeys {
+            session_id: [0; 32],
+            initiator_length: [0; 32],
+            initiator_packet: [0; 32],
+            responder_length: [0; 32],
+            responder_packet: [0; 32],
+            initiator_garbage_terminator: [0; GARBAGE_TERMINATOR_LEN],
+            responder_garbage_terminator: [0; GARBAGE_TERMINATOR_LEN],
+        };
+        hkdf_expand(&prk, b"session_id", &mut keys.session_id);
+        hkdf_expand(&prk, b"initiator_L", &mut keys.initiator_length);
+        hkdf_expand(&prk, b"initiator_P", &mut keys.initiator_packet);
+        hkdf_expand(&prk, b"responder_L", &mut keys.responder_length);
+        hkdf_expand(&prk, b"responder_P", &mut keys.responder_packet);
+        let mut terminators = [0; 2 * GARBAGE_TERMINATOR_LEN];
+        hkdf_expand(&prk, b"garbage_terminators", &mut terminators);
+        keys.initiator_garbage_terminator.copy_from_slice(&terminators[..GARBAGE_TERMINATOR_LEN]);
+        keys.responder_garbage_terminator.copy_from_slice(&terminators[GARBAGE_TERMINATOR_LEN..]);
+        keys
+    }
+}
+
+/// Forward-secure ChaCha20 stream cipher, used to encrypt packet lengths
+#[derive(Debug)]
+struct FSChaCha20 {
+    cipher: ChaCha20,
+    chunk_counter: u64,
+    rekey_counter: u64,
+}
+
+impl FSChaCha20 {
+    fn new(key: &[u8; 32]) -> FSChaCha20 {
+        FSChaCha20 {
+            cipher: ChaCha20::new(key, &FSChaCha20::nonce(0), 0),
+            chunk_counter: 0,
+            rekey_counter: 0,
+        }
+    }
+
+    fn nonce(rekey_counter: u64) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&encode::serialize(&rekey_counter));
+        nonce
+    }
+
+    /// Encrypts or decrypts a chunk in place
+    fn crypt(&mut self, chunk: &mut [u8]) {
+        self.cipher.apply_keystream(chunk);
+        self.chunk_counter += 1;
+        if self.chunk_counter % REKEY_INTERVAL == 0 {
+            let mut key = [0; 32];
+            self.cipher.keystream(&mut key);
+            self.rekey_counter += 1;
+            self.cipher = ChaCha20::new(&key, &FSChaCha20::nonce(self.rekey_counter), 0);
+        }
+    }
+}
+
+/// Forward-secure ChaCha20Poly1305 AEAD, used to encrypt packets
+#[derive(Debug)]
+struct FSChaCha20Poly1305 {
+    aead: ChaCha20Poly1305,
+    packet_counter: u32,
+    rekey_counter: u64,
+}
+
+impl FSChaCha20Poly1305 {
+    fn new(key: &[u8; 32]) -> FSChaCha20Poly1305 {
+        FSChaCha20Poly1305 {
+            aead: ChaCha20Poly1305::new(key),
+            packet_counter: 0,
+            rekey_counter: 0,
+        }
+    }
+
+    fn nonce(&self, packet_counter: u32) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..4].copy_from_slice(&encode::serialize(&packet_counter));
+        nonce[4..].copy_from_slice(&encode::serialize(&self.rekey_counter));
+        nonce
+    }
+
+    fn next_packet(&mut self) {
+        self.packet_counter += 1;
+        if u64::from(self.packet_counter) == REKEY_INTERVAL {
+            let mut key = [0; 32];
+            self.aead.keystream(&self.nonce(0xffffffff), 1, &mut key);
+            self.aead = ChaCha20Poly1305::new(&key);
+            self.packet_counter = 0;
+            self.rekey_counter += 1;
+        }
+    }
+
+    fn encrypt(&mut self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = self.aead.encrypt(&self.nonce(self.packet_counter), aad, plaintext);
+        self.next_packet();
+        ciphertext
+    }
+
+    fn decrypt(&mut self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, chacha20_poly1305::Error> {
+        let plaintext = self.aead.decrypt(&self.nonce(self.packet_counter), aad, ciphertext);
+        self.next_packet();
+        plaintext
+    }
+}
+
+/// A decrypted packet
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Packet {
+    /// Whether the sender flagged the packet to be ignored
+    pub ignore: bool,
+    /// Contents of the packet
+    pub contents: Vec<u8>,
+}
+
+/// The packet ciphers of both directions of a v2 session
+#[derive(Debug)]
+pub struct Cipher {
+    send_length: FSChaCha20,
+    send_packet: FSChaCha20Poly1305,
+    recv_length: FSChaCha20,
+    recv_packet: FSChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Creates the ciphers of the initiating or responding side of a session
+    pub fn new(keys: &SessionKeys, initiating: bool) -> Cipher {
+        let initiator_length = FSChaCha20::new(&keys.initiator_length);
+        let initiator_packet = FSChaCha20Poly1305::new(&keys.initiator_packet);
+        let responder_length = FSChaCha20::new(&keys.responder_length);
+        let responder_packet = FSChaCha20Poly1305::new(&keys.responder_packet);
+        if initiating {
+            Cipher {
+                send_length: initiator_length,
+                send_packet: initiator_packet,
+                recv_length: responder_length,
+                recv_packet: responder_packet,
+            }
+        } else {
+            Cipher {
+                send_length: responder_length,
+                send_packet: responder_packet,
+                recv_length: initiator_length,
+                recv_packet: initiator_packet,
+            }
+        }
+    }
+
+    /// Encrypts a packet with the given contents, authenticating `aad`
+    pub fn encrypt(&mut self, contents: &[u8], aad: &[u8], ignore: bool) -> Result<Vec<u8>, Error> {
+        if contents.len() > MAX_CONTENTS_LEN {
+            return Err(Error::ContentsTooLarge(contents.len()));
+        }
+        let mut plaintext = Vec::with_capacity(HEADER_LEN + contents.len());
+        plaintext.push(if ignore { IGNORE_BIT } else { 0 });
+        plaintext.extend_from_slice(contents);
+
+        let mut packet = Vec::with_capacity(PACKET_OVERHEAD + contents.len());
+        packet.extend_from_slice(&encode::serialize(&(contents.len() as u32))[..LENGTH_FIELD_LEN]);
+        self.send_length.crypt(&mut packet);
+        packet.extend(self.send_packet.encrypt(aad, &plaintext));
+        Ok(packet)
+    }
+
+    /// Decrypts the length field of a packet, returning the number of bytes
+    /// of the rest of the packet
+    pub fn decrypt_length(&mut self, length: [u8; LENGTH_FIELD_LEN]) -> Result<usize, Error> {
+        let mut length = length;
+        self.recv_length.crypt(&mut length);
+        let contents_len = length[0] as usize | (length[1] as usize) << 8 | (length[2] as usize) << 16;
+        if contents_len > MAX_RECEIVED_CONTENTS_LEN {
+            return Err(Error::ContentsTooLarge(contents_len));
+        }
+        Ok(contents_len + HEADER_LEN + chacha20_poly1305::TAG_LEN)
+    }
+
+    /// Decrypts the rest of a packet after its length field
+    pub fn decrypt(&mut self, ciphertext: &[u8], aad: &[u8]) -> Result<Packet, Error> {
+        let mut plaintext = self.recv_packet.decrypt(aad, ciphertext)?;
+        let ignore = plaintext[0] & IGNORE_BIT != 0;
+        plaintext.remove(0);
+        Ok(Packet {
+            ignore: ignore,
+            contents: plaintext,
+        })
+    }
+}
+
+/// Serializes a message into the contents of a v2 packet
+pub fn serialize_message(message: &NetworkMessage) -> Vec<u8> {
+    let command = message.command();
+    let mut contents = match SHORT_IDS.iter().skip(1).position(|id| *id == command.as_ref()) {
+        Some(pos) => vec![pos as u8 + 1],
+        None => {
+            let mut contents = vec![0];
+            command.consensus_encode(&mut contents).expect("engines don't error");
+            contents
+        }
+    };
+    contents.extend(message.serialize_payload());
+    contents
+}
+
+/// Deserializes a message from the contents of a v2 packet
+pub fn deserialize_message(contents: &[u8]) -> Result<NetworkMessage, Error> {
+    if contents.is_empty() {
+        return Err(Error::EmptyMessage);
+    }
+    let (command, payload) = if contents[0] == 0 {
+        let mut cursor = Cursor::new(&contents[1..]);
+        let command = CommandString::consensus_decode(&mut cursor)?;
+        (command, &contents[1 + cursor.position() as usize..])
+    } else {
+        match SHORT_IDS.get(contents[0] as usize) {
+            Some(id) => (CommandString::try_from(*id).expect("short IDs are valid commands"), &contents[1..]),
+            None => return Err(Error::UnknownShortId(contents[0])),
+        }
+    };
+    let message = NetworkMessage::deserialize_payload(command, payload.to_vec())?;
+    Ok(message)
+}
+
+/// Configuration of the handshake of a v2 connection
+#[derive(Debug)]
+pub struct Handshake {
+    magic: u32,
+    initiating: bool,
+    secret_key: SecretKey,
+    ellswift: ElligatorSwift,
+    garbage: Vec<u8>,
+}
+
+impl Handshake {
+    /// Creates a handshake using an ephemeral `secret_key` for the network
+    /// identified by `magic`. The `entropy` is used to randomize the
+    /// encoding of the public key and should be freshly generated.
+    pub fn new<C: Signing>(
+        secp: &Secp256k1<C>,
+        magic: u32,
+        initiating: bool,
+        secret_key: SecretKey,
+        entropy: &[u8; 32],
+    ) -> Handshake {
+        Handshake {
+            magic: magic,
+            initiating: initiating,
+            ellswift: ElligatorSwift::from_secret_key(secp, &secret_key, entropy),
+            secret_key: secret_key,
+            garbage: vec![],
+        }
+    }
+
+    /// Sets the garbage sent after our public key, which should be of random
+    /// length and contents
+    pub fn garbage(mut self, garbage: Vec<u8>) -> Handshake {
+        self.garbage = garbage;
+        self
+    }
+
+    /// Performs the handshake over `stream`
+    pub fn connect<C: Verification, S: Read + Write>(
+        self,
+        secp: &Secp256k1<C>,
+        mut stream: S,
+    ) -> Result<V2Stream<S>, Error> {
+        if self.garbage.len() > MAX_GARBAGE_LEN {
+            return Err(Error::GarbageTooLong(self.garbage.len()));
+        }
+
+        let mut ours = self.ellswift[..].to_vec();
+        ours.extend_from_slice(&self.garbage);
+        if self.initiating {
+            stream.write_all(&ours)?;
+            stream.flush()?;
+        }
+
+        let mut theirs = [0; 64];
+        stream.read_exact(&mut theirs[..16])?;
+        if !self.initiating {
+            let mut v1_prefix = encode::serialize(&self.magic);
+            v1_prefix.extend_from_slice(b"version\0\0\0\0\0");
+            if theirs[..16] == v1_prefix[..] {
+                return Err(Error::V1Peer);
+            }
+        }
+        stream.read_exact(&mut theirs[16..])?;
+        let theirs = ElligatorSwift::from_array(theirs);
+
+        let (initiator, responder) = if self.initiating {
+            (&self.ellswift, &theirs)
+        } else {
+            stream.write_all(&ours)?;
+            (&theirs, &self.ellswift)
+        };
+        let secret = shared_secret(secp, &self.secret_key, initiator, responder, self.initiating);
+        let keys = SessionKeys::derive(&secret, self.magic);
+        let mut cipher = Cipher::new(&keys, self.initiating);
+        let (our_terminator, their_terminator) = if self.initiating {
+            (keys.initiator_garbage_terminator, keys.responder_garbage_terminator)
+        } else {
+            (keys.responder_garbage_terminator, keys.initiator_garbage_terminator)
+        };
+
+        // Garbage terminator followed by the version packet, which
+        // authenticates our garbage
+        let mut packet = our_terminator.to_vec();
+        packet.extend(cipher.encrypt(&[], &self.garbage, false)?);
+        stream.write_all(&packet)?;
+        stream.flush()?;
+
+        // Their garbage up to the terminator
+        let mut their_garbage = vec![];
+        loop {
+            let mut byte = [0];
+            stream.read_exact(&mut byte)?;
+            their_garbage.push(byte[0]);
+            if their_garbage.ends_with(&their_terminator) {
+                let len = their_garbage.len() - GARBAGE_TERMINATOR_LEN;
+                their_garbage.truncate(len);
+                break;
+            }
+            if their_garbage.len() >= MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN {
+                return Err(Error::NoGarbageTerminator);
+            }
+        }
+
+        // Their version packet, possibly preceded by decoys. Only the first
+        // packet authenticates the garbage.
+        let mut v2 = V2Stream {
+            stream: stream,
+            cipher: cipher,
+            session_id: keys.session_id,
+        };
+        let mut aad = their_garbage;
+        while v2.receive_packet(&aad)?.ignore {
+            aad = vec![];
+        }
+        Ok(v2)
+    }
+}
+
+/// An established v2 connection
+#[derive(Debug)]
+pub struct V2Stream<S: Read + Write> {
+    /// Underlying stream
+    pub stream: S,
+    cipher: Cipher,
+    session_id: [u8; 32],
+}
+
+impl<S: Read + Write> V2Stream<S> {
+    /// The identifier of the session, equal on both sides of the connection
+    pub fn session_id(&self) -> [u8; 32] {
+        self.session_id
+    }
+
+    /// Sends a message to the peer
+    pub fn send(&mut self, message: &NetworkMessage) -> Result<(), Error> {
+        let packet = self.cipher.encrypt(&serialize_message(message), &[], false)?;
+        self.stream.write_all(&packet)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Sends a decoy packet of `len` bytes, which the peer ignores
+    pub fn send_decoy(&mut self, len: usize) -> Result<(), Error> {
+        let packet = self.cipher.encrypt(&vec![0; len], &[], true)?;
+        self.stream.write_all(&packet)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Receives the next message from the peer, skipping any decoy packets
+    pub fn receive(&mut self) -> Result<NetworkMessage, Error> {
+        loop {
+            let packet = self.receive_packet(&[])?;
+            if !packet.ignore {
+                return deserialize_message(&packet.contents);
+            }
+        }
+    }
+
+    fn receive_packet(&mut self, aad: &[u8]) -> Result<Packet, Error> {
+        let mut length = [0; LENGTH_FIELD_LEN];
+        self.stream.read_exact(&mut length)?;
+        let mut ciphertext = vec![0; self.cipher.decrypt_length(length)?];
+        self.stream.read_exact(&mut ciphertext)?;
+        self.cipher.decrypt(&ciphertext, aad)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    use hashes::hex::{FromHex, ToHex};
+    use secp256k1::PublicKey;
+    use network::constants::Network;
+    use network::message_network::VersionMessage;
+    use network::constants::ServiceFlags;
+    use network::address::Address;
+
+    /// One end of an in-memory duplex stream
+    #[derive(Debug)]
+    struct Duplex {
+        tx: Sender<Vec<u8>>,
+        rx: Receiver<Vec<u8>>,
+        buf: Vec<u8>,
+    }
+
+    impl Duplex {
+        fn pair() -> (Duplex, Duplex) {
+            let (tx_a, rx_a) = channel();
+            let (tx_b, rx_b) = channel();
+            (Duplex { tx: tx_a, rx: rx_b, buf: vec![] }, Duplex { tx: tx_b, rx: rx_a, buf: vec![] })
+        }
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.buf.is_empty() {
+                match self.rx.recv() {
+                    Ok(data) => self.buf = data,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let len = ::std::cmp::min(buf.len(), self.buf.len());
+            buf[..len].copy_from_slice(&self.buf[..len]);
+            self.buf.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.tx.send(buf.to_vec()).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn secret_key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn hkdf_rfc5869() {
+        // RFC 5869 test case 1, truncated to a single block
+        let ikm = [0x0b; 22];
+        let salt = Vec::from_hex("000102030405060708090a0b0c").unwrap();
+        let info = Vec::from_hex("f0f1f2f3f4f5f6f7f8f9").unwrap();
+        let prk = hkdf_extract(&salt, &ikm);
+        assert_eq!(prk[..].to_hex(), "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5");
+        let mut okm = [0; 32];
+        hkdf_expand(&prk, &info, &mut okm);
+        assert_eq!(okm.to_hex(), "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf");
+    }
+
+    #[test]
+    fn short_ids() {
+        let ping = NetworkMessage::Ping(0x0102030405060708);
+        let contents = serialize_message(&ping);
+        assert_eq!(contents.to_hex(), "120807060504030201");
+        assert_eq!(deserialize_message(&contents).unwrap(), ping);
+
+        let contents = serialize_message(&NetworkMessage::Verack);
+        assert_eq!(contents.to_hex(), "0076657261636b000000000000");
+        assert_eq!(deserialize_message(&contents).unwrap(), NetworkMessage::Verack);
+
+        for &(ref message, id) in [(NetworkMessage::FilterClear, 7u8), (NetworkMessage::MemPool, 15)].iter() {
+            assert_eq!(serialize_message(message), vec![id]);
+            assert_eq!(&deserialize_message(&[id]).unwrap(), message);
+        }
+        match deserialize_message(&[29]) {
+            Err(Error::UnknownShortId(29)) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+        match deserialize_message(&[]) {
+            Err(Error::EmptyMessage) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn packet_encryption() {
+        // Shared secret and derived keys from the first packet encoding test
+        // vector of BIP324, packets cross-checked against the reference
+        // implementation of the cipher suite
+        let secret = <[u8; 32]>::from_hex("c6992a117f5edbea70c3f511d32d26b9798be4b81a62eaee1a5acaa8459a3592").unwrap();
+        let keys = SessionKeys::derive(&secret, Network::Bitcoin.magic());
+        assert_eq!(keys.session_id.to_hex(), "ce72dffb015da62b0d0f5474cab8bc72605225b0cee3f62312ec680ec5f41ba5");
+        assert_eq!(keys.initiator_garbage_terminator.to_hex(), "faef555dfcdb936425d84aba524758f3");
+        assert_eq!(keys.responder_garbage_terminator.to_hex(), "02cb8ff24307a6e27de3b4e7ea3fa65b");
+
+        let mut initiator = Cipher::new(&keys, true);
+        let mut responder = Cipher::new(&keys, false);
+        let garbage = vec![0xab; 10];
+        let packet = initiator.encrypt(&[], &garbage, false).unwrap();
+        assert_eq!(packet.to_hex(), "b5f0c55fc70fe7491980778b9ab1428bc089fc47");
+        let len = responder.decrypt_length([packet[0], packet[1], packet[2]]).unwrap();
+        assert_eq!(len, packet.len() - LENGTH_FIELD_LEN);
+        assert_eq!(responder.decrypt(&packet[LENGTH_FIELD_LEN..], &garbage).unwrap(), Packet { ignore: false, contents: vec![] });
+
+        // Run through a few rekeys in both directions
+        for i in 1..1000u32 {
+            let contents = encode::serialize(&i);
+            let ignore = i % 3 == 0;
+            let packet = responder.encrypt(&contents, &[], ignore).unwrap();
+            if i == 999 {
+                assert_eq!(packet.to_hex(), "5a66db26541fe6e4c5f9c8465eee133f872edae0ce15c6f9");
+            }
+            let len = initiator.decrypt_length([packet[0], packet[1], packet[2]]).unwrap();
+            assert_eq!(len, packet.len() - LENGTH_FIELD_LEN);
+            assert_eq!(initiator.decrypt(&packet[LENGTH_FIELD_LEN..], &[]).unwrap(), Packet { ignore: ignore, contents: contents });
+        }
+
+        // Tampering is detected
+        let mut packet = initiator.encrypt(b"hello", &[], false).unwrap();
+        packet[5] ^= 1;
+        responder.decrypt_length([packet[0], packet[1], packet[2]]).unwrap();
+        match responder.decrypt(&packet[LENGTH_FIELD_LEN..], &[]) {
+            Err(Error::Decryption(chacha20_poly1305::Error::InvalidTag)) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        // Lengths above the largest message are refused before reading the
+        // rest of the packet
+        let packet = initiator.encrypt(&vec![0; MAX_RECEIVED_CONTENTS_LEN + 1], &[], false).unwrap();
+        match responder.decrypt_length([packet[0], packet[1], packet[2]]) {
+            Err(Error::ContentsTooLarge(4_000_014)) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+
+    #[test]
+    fn packet_encoding_vectors() {
+        let secp = Secp256k1::new();
+
+        // Rows of BIP324's packet_encoding_test_vectors.csv: in_idx,
+        // in_priv_ours, in_ellswift_ours, in_ellswift_theirs, in_initiating,
+        // in_contents, in_multiply, in_aad, in_ignore, mid_x_theirs,
+        // mid_x_shared, mid_shared_secret, out_session_id, out_ciphertext
+        let vectors = [
+            (1, "61062ea5071d800bbfd59e2e8b53d47d194b095ae5a4df04936b49772ef0d4d7",
+             "ec0adff257bbfe500c188c80b4fdd640f6b45a482bbc15fc7cef5931deff0aa186f6eb9bba7b85dc4dcc28b28722de1e3d9108b985e2967045668f66098e475b",
+             "a4a94dfce69b4a2a0a099313d10f9f7e7d649d60501c9e1d274c300e0d89aafaffffffffffffffffffffffffffffffffffffffffffffffffffffffff8faf88d5",
+             true, "8e", 1, "", false,
+             "0c71defa3fafd74cb835102acd81490963f6b72d889495e06561375bd65f6ffc",
+             "4eb2bf85bd00939468ea2abb25b63bc642e3d1eb8b967fb90caa2d89e716050e",
+             "c6992a117f5edbea70c3f511d32d26b9798be4b81a62eaee1a5acaa8459a3592",
+             "ce72dffb015da62b0d0f5474cab8bc72605225b0cee3f62312ec680ec5f41ba5",
+             "7530d2a18720162ac09c25329a60d75adf36eda3c3"),
+            (999, "1f9c581b35231838f0f17cf0c979835baccb7f3abbbb96ffcc318ab71e6e126f",
+             "a1855e10e94e00baa23041d916e259f7044e491da6171269694763f018c7e63693d29575dcb464ac816baa1be353ba12e3876cba7628bd0bd8e755e721eb0140",
+             "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f0000000000000000000000000000000000000000000000000000000000000000",
+             false, "3eb1d4e98035cfd8eeb29bac969ed3824a", 1, "", false,
+             "edd1fd3e327ce90cc7a3542614289aee9682003e9cf7dcc9cf2ca9743be5aa0c",
+             "c40eb6190caf399c9007254ad5e5fa20d64af2b41696599c59b2191d16992955",
+             "a0138f564f74d0ad70bc337dacc9d0bf1d2349364caf1188a1e6e8ddb3b7b184",
+             "9267c54560607de73f18c563b76a2442718879c52dd39852885d4a3c9912c9ea",
+             "1da1bcf589f9b61872f45b7fa5371dd3f8bdf5d515b0c5f9fe9f0044afb8dc0aa1cd39a8c4"),
+        ];
+        for &(idx, priv_ours, ellswift_ours, ellswift_theirs, initiating, contents, multiply, aad, ignore,
+              x_theirs, x_shared, secret, session_id, ciphertext) in vectors.iter() {
+            let secret_key = SecretKey::from_slice(&Vec::from_hex(priv_ours).unwrap()).unwrap();
+            let ours: ElligatorSwift = ellswift_ours.parse().unwrap();
+            let theirs: ElligatorSwift = ellswift_theirs.parse().unwrap();
+            assert_eq!(ours.decode()[..], PublicKey::from_secret_key(&secp, &secret_key).serialize()[1..]);
+            assert_eq!(theirs.decode().to_hex(), x_theirs);
+            assert_eq!(theirs.xonly_ecdh(&secp, &secret_key).to_hex(), x_shared);
+
+            let (initiator, responder) = if initiating { (&ours, &theirs) } else { (&theirs, &ours) };
+            let shared = shared_secret(&secp, &secret_key, initiator, responder, initiating);
+            assert_eq!(shared.to_hex(), secret);
+            let keys = SessionKeys::derive(&shared, Network::Bitcoin.magic());
+            assert_eq!(keys.session_id.to_hex(), session_id);
+
+            // The packet is the `idx`th one sent in its direction
+            let mut sender = Cipher::new(&keys, initiating);
+            let mut receiver = Cipher::new(&keys, !initiating);
+            for _ in 0..idx {
+                let packet = sender.encrypt(&[], &[], false).unwrap();
+                receiver.decrypt_length([packet[0], packet[1], packet[2]]).unwrap();
+                receiver.decrypt(&packet[LENGTH_FIELD_LEN..], &[]).unwrap();
+            }
+            let chunk = Vec::from_hex(contents).unwrap();
+            let mut contents = Vec::with_capacity(chunk.len() * multiply);
+            for _ in 0..multiply {
+                contents.extend_from_slice(&chunk);
+            }
+            let aad = Vec::from_hex(aad).unwrap();
+            let packet = sender.encrypt(&contents, &aad, ignore).unwrap();
+            assert_eq!(packet.to_hex(), ciphertext);
+            let len = receiver.decrypt_length([packet[0], packet[1], packet[2]]).unwrap();
+            assert_eq!(len, packet.len() - LENGTH_FIELD_LEN);
+            assert_eq!(receiver.decrypt(&packet[LENGTH_FIELD_LEN..], &aad).unwrap(), Packet { ignore: ignore, contents: contents });
+        }
+    }
+
+    #[test]
+    fn duplex_connection() {
+        let secp = Secp256k1::new();
+        let magic = Network::Bitcoin.magic();
+        let (a, b) = Duplex::pair();
+
+        let responder = thread::spawn(move || {
+            let secp = Secp256k1::new();
+            let mut stream = Handshake::new(&secp, magic, false, secret_key(2), &[2; 32])
+                .garbage(vec![0x55; MAX_GARBAGE_LEN])
+                .connect(&secp, b)
+                .unwrap();
+            let session_id = stream.session_id();
+            // Echo every message until the peer closes the connection
+            while let Ok(message) = stream.receive() {
+                stream.send_decoy(100).unwrap();
+                stream.send(&message).unwrap();
+            }
+            session_id
+        });
+
+        let mut stream = Handshake::new(&secp, magic, true, secret_key(1), &[1; 32])
+            .garbage(vec![0xaa; 17])
+            .connect(&secp, a)
+            .unwrap();
+
+        let version = NetworkMessage::Version(VersionMessage::new(
+            ServiceFlags::NETWORK,
+            0,
+            Address::new(&"127.0.0.1:8333".parse().unwrap(), ServiceFlags::NONE),
+            Address::new(&"127.0.0.1:8333".parse().unwrap(), ServiceFlags::NONE),
+            0,
+            "/rust-bitcoin/".to_string(),
+            0,
+        ));
+        let messages = vec![version, NetworkMessage::Verack, NetworkMessage::Ping(42), NetworkMessage::MemPool];
+        for _ in 0..100 {
+            for message in &messages {
+                stream.send(message).unwrap();
+                assert_eq!(&stream.receive().unwrap(), message);
+            }
+        }
+        let session_id = stream.session_id();
+        drop(stream);
+        assert_eq!(responder.join().unwrap(), session_id);
+    }
+
+    #[test]
+    fn v1_peer_and_garbage() {
+        let secp = Secp256k1::new();
+        let magic = Network::Bitcoin.magic();
+
+        let mut v1 = encode::serialize(&magic);
+        v1.extend_from_slice(b"version\0\0\0\0\0");
+        v1.extend_from_slice(&[0; 48]);
+        let (mut a, b) = Duplex::pair();
+        a.write_all(&v1).unwrap();
+        match Handshake::new(&secp, magic, false, secret_key(2), &[2; 32]).connect(&secp, b) {
+            Err(Error::V1Peer) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        let (_a, b) = Duplex::pair();
+        let handshake = Handshake::new(&secp, magic, true, secret_key(1), &[1; 32])
+            .garbage(vec![0; MAX_GARBAGE_LEN + 1]);
+        match handshake.connect(&secp, b) {
+            Err(Error::GarbageTooLong(4096)) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+
+        // A peer which never sends our terminator
+        let (mut a, b) = Duplex::pair();
+        let theirs = ElligatorSwift::from_secret_key(&secp, &secret_key(2), &[2; 32]);
+        a.write_all(&theirs[..]).unwrap();
+        a.write_all(&[0; MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN]).unwrap();
+        match Handshake::new(&secp, magic, true, secret_key(1), &[1; 32]).connect(&secp, b) {
+            Err(Error::NoGarbageTerminator) => {},
+            e => panic!("unexpected result {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Test utilities
+//!
+//! Internal helpers shared by the unit tests of several modules
+
+use blockdata::block::{Block, BlockHeader};
+
+/// Increments the nonce of `header` until it meets its own target
+pub fn grind_nonce(header: &mut BlockHeader) {
+    while header.validate_pow(&header.target()).is_err() {
+        header.nonce += 1;
+    }
+}
+
+/// Grinds the nonce of the header of `block`
+pub fn mine(mut block: Block) -> Block {
+    grind_nonce(&mut block.header);
+    block
+}
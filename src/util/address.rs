@@ -41,12 +41,15 @@ use std::str::FromStr;
 use std::error;
 
 use bech32;
+use secp256k1::{schnorrsig, Secp256k1, Verification};
 use hashes::Hash;
 use hash_types::{PubkeyHash, WPubkeyHash, ScriptHash, WScriptHash};
 use blockdata::script;
 use network::constants::Network;
+use network::chainparams::ChainParams;
 use util::base58;
 use util::key;
+use util::taproot::{TapBranchHash, TaprootSpendInfo};
 
 /// Address error.
 #[derive(Debug, PartialEq)]
@@ -55,6 +58,13 @@ pub enum Error {
     Base58(base58::Error),
     /// Bech32 encoding error
     Bech32(bech32::Error),
+    /// The checksum variant does not match the witness version (BIP350)
+    InvalidBech32Variant {
+        /// The variant required by the witness version
+        expected: bech32::Variant,
+        /// The variant of the parsed address
+        found: bech32::Variant,
+    },
     /// The bech32 payload was empty
     EmptyBech32Payload,
     /// Script version must be 0 to 16 inclusive
@@ -72,6 +82,9 @@ impl fmt::Display for Error {
         match *self {
             Error::Base58(ref e) => write!(f, "base58: {}", e),
             Error::Bech32(ref e) => write!(f, "bech32: {}", e),
+            Error::InvalidBech32Variant { expected, found } => write!(f,
+                "invalid bech32 checksum variant found {:?} when {:?} was expected", found, expected,
+            ),
             Error::EmptyBech32Payload => write!(f, "the bech32 payload was empty"),
             Error::InvalidWitnessVersion(v) => write!(f, "invalid witness script version: {}", v),
             Error::InvalidWitnessProgramLength(l) => write!(f,
@@ -122,6 +135,8 @@ pub enum AddressType {
     P2wpkh,
     /// pay-to-witness-script-hash
     P2wsh,
+    /// pay-to-taproot
+    P2tr,
 }
 
 impl fmt::Display for AddressType {
@@ -131,6 +146,7 @@ impl fmt::Display for AddressType {
             AddressType::P2sh => "p2sh",
             AddressType::P2wpkh => "p2wpkh",
             AddressType::P2wsh => "p2wsh",
+            AddressType::P2tr => "p2tr",
         })
     }
 }
@@ -143,6 +159,7 @@ impl FromStr for AddressType {
             "p2sh" => Ok(AddressType::P2sh),
             "p2wpkh" => Ok(AddressType::P2wpkh),
             "p2wsh" => Ok(AddressType::P2wsh),
+            "p2tr" => Ok(AddressType::P2tr),
             _ => Err(()),
         }
     }
@@ -294,6 +311,27 @@ impl Address {
         }
     }
 
+    /// Create a pay to taproot address from an untweaked internal key and the
+    /// merkle root of its script tree, if any. The output key is the internal
+    /// key tweaked as specified in BIP341.
+    pub fn p2tr<C: Verification>(
+        secp: &Secp256k1<C>,
+        internal_key: schnorrsig::PublicKey,
+        merkle_root: Option<TapBranchHash>,
+        network: Network,
+    ) -> Address {
+        let output_key = TaprootSpendInfo::new_key_spend(secp, internal_key, merkle_root)
+            .expect("tweak is a hash, out of range with negligible probability")
+            .output_key();
+        Address {
+            network: network,
+            payload: Payload::WitnessProgram {
+                version: bech32::u5::try_from_u8(1).expect("1<32"),
+                program: output_key.serialize().to_vec(),
+            },
+        }
+    }
+
     /// Create a pay to script address that embeds a witness pay to script hash address
     /// This is a segwit address type that looks familiar (as p2sh) to legacy clients
     pub fn p2shwsh(script: &script::Script, network: Network) -> Address {
@@ -325,6 +363,8 @@ impl Address {
                         32 => Some(AddressType::P2wsh),
                         _ => None,
                     },
+                    // BIP-341 p2tr addresses.
+                    1 if prog.len() == 32 => Some(AddressType::P2tr),
                     _ => None,
                 }
             }
@@ -354,24 +394,62 @@ impl Address {
     }
 }
 
-impl Display for Address {
+impl Address {
+    /// Formats the address with the prefixes of the chain `params`
+    pub fn to_string_with_params(&self, params: &ChainParams) -> String {
+        AddressDisplay {
+            address: self,
+            p2pkh_prefix: params.p2pkh_prefix,
+            p2sh_prefix: params.p2sh_prefix,
+            bech32_hrp: &params.bech32_hrp,
+        }.to_string()
+    }
+
+    /// Parses an address using the prefixes of the chain `params`. The
+    /// address gets `params.network` as its network.
+    pub fn from_str_with_params(s: &str, params: &ChainParams) -> Result<Address, Error> {
+        if find_bech32_prefix(s).eq_ignore_ascii_case(&params.bech32_hrp) {
+            return Ok(Address {
+                payload: decode_bech32_payload(s)?,
+                network: params.network,
+            });
+        }
+
+        let data = decode_base58_payload(s)?;
+        let payload = if data[0] == params.p2pkh_prefix {
+            Payload::PubkeyHash(PubkeyHash::from_slice(&data[1..]).unwrap())
+        } else if data[0] == params.p2sh_prefix {
+            Payload::ScriptHash(ScriptHash::from_slice(&data[1..]).unwrap())
+        } else {
+            return Err(Error::Base58(base58::Error::InvalidVersion(vec![data[0]])));
+        };
+        Ok(Address {
+            network: params.network,
+            payload: payload,
+        })
+    }
+}
+
+/// An address along with the prefixes to display it with
+struct AddressDisplay<'a> {
+    address: &'a Address,
+    p2pkh_prefix: u8,
+    p2sh_prefix: u8,
+    bech32_hrp: &'a str,
+}
+
+impl<'a> Display for AddressDisplay<'a> {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        match self.payload {
+        match self.address.payload {
             Payload::PubkeyHash(ref hash) => {
                 let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
-                    Network::Bitcoin => 0,
-                    Network::Testnet | Network::Signet | Network::Regtest => 111,
-                };
+                prefixed[0] = self.p2pkh_prefix;
                 prefixed[1..].copy_from_slice(&hash[..]);
                 base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
             }
             Payload::ScriptHash(ref hash) => {
                 let mut prefixed = [0; 21];
-                prefixed[0] = match self.network {
-                    Network::Bitcoin => 5,
-                    Network::Testnet | Network::Signet | Network::Regtest => 196,
-                };
+                prefixed[0] = self.p2sh_prefix;
                 prefixed[1..].copy_from_slice(&hash[..]);
                 base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
             }
@@ -379,12 +457,7 @@ impl Display for Address {
                 version: ver,
                 program: ref prog,
             } => {
-                let hrp = match self.network {
-                    Network::Bitcoin => "bc",
-                    Network::Testnet | Network::Signet  => "tb",
-                    Network::Regtest => "bcrt",
-                };
-                let mut bech32_writer = bech32::Bech32Writer::new(hrp, fmt)?;
+                let mut bech32_writer = bech32::Bech32Writer::new(self.bech32_hrp, bech32_variant(ver), fmt)?;
                 bech32::WriteBase32::write_u5(&mut bech32_writer, ver)?;
                 bech32::ToBase32::write_base32(&prog, &mut bech32_writer)
             }
@@ -392,6 +465,32 @@ impl Display for Address {
     }
 }
 
+impl Display for Address {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let mainnet = self.network == Network::Bitcoin;
+        let display = AddressDisplay {
+            address: self,
+            p2pkh_prefix: if mainnet { 0 } else { 111 },
+            p2sh_prefix: if mainnet { 5 } else { 196 },
+            bech32_hrp: match self.network {
+                Network::Bitcoin => "bc",
+                Network::Testnet | Network::Signet  => "tb",
+                Network::Regtest => "bcrt",
+            },
+        };
+        Display::fmt(&display, fmt)
+    }
+}
+
+/// The bech32 checksum variant of a witness version, as specified in BIP350.
+fn bech32_variant(version: bech32::u5) -> bech32::Variant {
+    if version.to_u8() == 0 {
+        bech32::Variant::Bech32
+    } else {
+        bech32::Variant::Bech32m
+    }
+}
+
 /// Extract the bech32 prefix.
 /// Returns the same slice when no prefix is found.
 fn find_bech32_prefix(bech32: &str) -> &str {
@@ -402,6 +501,56 @@ fn find_bech32_prefix(bech32: &str) -> &str {
     }
 }
 
+/// Decodes the payload of a bech32 address, whatever its prefix
+fn decode_bech32_payload(s: &str) -> Result<Payload, Error> {
+    let (_, payload, variant) = bech32::decode(s)?;
+    if payload.is_empty() {
+        return Err(Error::EmptyBech32Payload);
+    }
+
+    // Get the script version and program (converted from 5-bit to 8-bit)
+    let (version, program): (bech32::u5, Vec<u8>) = {
+        let (v, p5) = payload.split_at(1);
+        (v[0], bech32::FromBase32::from_base32(p5)?)
+    };
+
+    // Generic segwit checks.
+    if version.to_u8() > 16 {
+        return Err(Error::InvalidWitnessVersion(version.to_u8()));
+    }
+    if variant != bech32_variant(version) {
+        return Err(Error::InvalidBech32Variant {
+            expected: bech32_variant(version),
+            found: variant,
+        });
+    }
+    if program.len() < 2 || program.len() > 40 {
+        return Err(Error::InvalidWitnessProgramLength(program.len()));
+    }
+
+    // Specific segwit v0 check.
+    if version.to_u8() == 0 && (program.len() != 20 && program.len() != 32) {
+        return Err(Error::InvalidSegwitV0ProgramLength(program.len()));
+    }
+
+    Ok(Payload::WitnessProgram {
+        version: version,
+        program: program,
+    })
+}
+
+/// Decodes a base58 address into its version byte followed by the hash
+fn decode_base58_payload(s: &str) -> Result<Vec<u8>, Error> {
+    if s.len() > 50 {
+        return Err(Error::Base58(base58::Error::InvalidLength(s.len() * 11 / 15)));
+    }
+    let data = base58::from_check(s)?;
+    if data.len() != 21 {
+        return Err(Error::Base58(base58::Error::InvalidLength(data.len())));
+    }
+    Ok(data)
+}
+
 impl FromStr for Address {
     type Err = Error;
 
@@ -415,49 +564,14 @@ impl FromStr for Address {
             _ => None,
         };
         if let Some(network) = bech32_network {
-            // decode as bech32
-            let (_, payload) = bech32::decode(s)?;
-            if payload.is_empty() {
-                return Err(Error::EmptyBech32Payload);
-            }
-
-            // Get the script version and program (converted from 5-bit to 8-bit)
-            let (version, program): (bech32::u5, Vec<u8>) = {
-                let (v, p5) = payload.split_at(1);
-                (v[0], bech32::FromBase32::from_base32(p5)?)
-            };
-
-            // Generic segwit checks.
-            if version.to_u8() > 16 {
-                return Err(Error::InvalidWitnessVersion(version.to_u8()));
-            }
-            if program.len() < 2 || program.len() > 40 {
-                return Err(Error::InvalidWitnessProgramLength(program.len()));
-            }
-
-            // Specific segwit v0 check.
-            if version.to_u8() == 0 && (program.len() != 20 && program.len() != 32) {
-                return Err(Error::InvalidSegwitV0ProgramLength(program.len()));
-            }
-
             return Ok(Address {
-                payload: Payload::WitnessProgram {
-                    version: version,
-                    program: program,
-                },
+                payload: decode_bech32_payload(s)?,
                 network: network,
             });
         }
 
         // Base58
-        if s.len() > 50 {
-            return Err(Error::Base58(base58::Error::InvalidLength(s.len() * 11 / 15)));
-        }
-        let data = base58::from_check(s)?;
-        if data.len() != 21 {
-            return Err(Error::Base58(base58::Error::InvalidLength(data.len())));
-        }
-
+        let data = decode_base58_payload(s)?;
         let (network, payload) = match data[0] {
             0 => (
                 Network::Bitcoin,
@@ -650,14 +764,61 @@ mod tests {
     }
 
     #[test]
-    fn test_bip173_vectors() {
+    fn test_p2tr() {
+        // BIP341 test vector, key path spending only
+        let secp = Secp256k1::verification_only();
+        let internal_key = schnorrsig::PublicKey::from_slice(
+            &hex!("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d")
+        ).unwrap();
+        let addr = Address::p2tr(&secp, internal_key, None, Bitcoin);
+        assert_eq!(&addr.to_string(), "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5");
+        assert_eq!(addr.address_type(), Some(AddressType::P2tr));
+        roundtrips(&addr);
+
+        // With a script tree
+        let internal_key = schnorrsig::PublicKey::from_slice(
+            &hex!("187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27")
+        ).unwrap();
+        let merkle_root = TapBranchHash::from_slice(
+            &hex!("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21")
+        ).unwrap();
+        let addr = Address::p2tr(&secp, internal_key, Some(merkle_root), Bitcoin);
+        assert_eq!(&addr.to_string(), "bc1pz37fc4cn9ah8anwm4xqqhvxygjf9rjf2resrw8h8w4tmvcs0863sa2e586");
+        assert_eq!(
+            addr.script_pubkey(),
+            hex_script!("5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3")
+        );
+        roundtrips(&addr);
+
+        assert_eq!("p2tr".parse::<AddressType>(), Ok(AddressType::P2tr));
+        assert_eq!(AddressType::P2tr.to_string(), "p2tr");
+    }
+
+    #[test]
+    fn test_bech32_variant() {
+        // A v1 address with a bech32 checksum, and a v0 address with a bech32m one
+        assert_eq!(
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd".parse::<Address>(),
+            Err(Error::InvalidBech32Variant { expected: bech32::Variant::Bech32m, found: bech32::Variant::Bech32 })
+        );
+        assert_eq!(
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh".parse::<Address>(),
+            Err(Error::InvalidBech32Variant { expected: bech32::Variant::Bech32, found: bech32::Variant::Bech32m })
+        );
+    }
+
+    #[test]
+    fn test_bip173_350_vectors() {
+        // Test vectors valid under both BIP-173 and BIP-350
         let valid_vectors = [
             ("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", "0014751e76e8199196d454941c45d1b3a323f1433bd6"),
             ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7", "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262"),
-            ("bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7k7grplx", "5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6"),
-            ("BC1SW50QA3JX3S", "6002751e"),
-            ("bc1zw508d6qejxtdg4y5r3zarvaryvg6kdaj", "5210751e76e8199196d454941c45d1b3a323"),
+            ("bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y", "5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6"),
+            ("BC1SW50QGDZ25J", "6002751e"),
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", "5210751e76e8199196d454941c45d1b3a323"),
             ("tb1qqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesrxh6hy", "0020000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433"),
+            ("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c", "5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0", "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"),
         ];
         for vector in &valid_vectors {
             let addr: Address = vector.0.parse().unwrap();
@@ -666,6 +827,22 @@ mod tests {
         }
 
         let invalid_vectors = [
+            // BIP-350 test vectors
+            "tc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq5zuyut",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd",
+            "tb1z0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqglt7rf",
+            "BC1S0XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ54WELL",
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh",
+            "tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47",
+            "bc1p38j9r5y49hruaue7wxjce0updqjuyyx0kh56v8s25huc6995vvpql3jow4",
+            "BC130XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ7ZWS8R",
+            "bc1pw5dgrnzv",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v8n0nx0muaewav253zgeav",
+            "tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq47Zagq",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v07qwwzcrf",
+            "tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vpggkg4j",
+            "bc1gmk9yu",
+            // BIP-173 test vectors
             "tc1qw508d6qejxtdg4y5r3zarvary0c5xw7kg3g4ty",
             "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5",
             "BC13W508D6QEJXTDG4Y5R3ZARVARY0C5XW7KN40WF2",
@@ -675,7 +852,10 @@ mod tests {
             "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7",
             "bc1zw508d6qejxtdg4y5r3zarvaryvqyzf3du",
             "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3pjxtptv",
-            "bc1gmk9yu",
+            // BIP-173 valid vectors made obsolete by BIP-350
+            "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7k7grplx",
+            "BC1SW50QA3JX3S",
+            "bc1zw508d6qejxtdg4y5r3zarvaryvg6kdaj",
         ];
         for vector in &invalid_vectors {
             assert!(vector.parse::<Address>().is_err());
@@ -10,17 +10,21 @@
 
 //! Amounts
 //!
-//! This module mainly introduces the [Amount] and [SignedAmount] types.
-//! We refer to the documentation on the types for more information.
+//! This module mainly introduces the [Amount] and [SignedAmount] types,
+//! along with the [Weight], [VSize] and [FeeRate] types used to compute
+//! fees. We refer to the documentation on the types for more information.
 //!
 
 use std::default;
 use std::error;
 use std::fmt::{self, Write};
+use std::iter;
 use std::ops;
 use std::str::FromStr;
 use std::cmp::Ordering;
 
+use blockdata::constants::WITNESS_SCALE_FACTOR;
+
 /// A set of denominations in which amounts can be expressed.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum Denomination {
@@ -415,6 +419,13 @@ impl Amount {
         self.0.checked_rem(rhs).map(Amount)
     }
 
+    /// Checked division by a weight, giving the fee rate this amount pays
+    /// as a fee for it, rounded down.
+    /// Returns [None] if overflow occurred or the weight is zero.
+    pub fn checked_div_by_weight(self, rhs: Weight) -> Option<FeeRate> {
+        self.0.checked_mul(1000)?.checked_div(rhs.as_wu()).map(FeeRate::from_sat_per_kwu)
+    }
+
     /// Convert to a signed amount.
     pub fn to_signed(self) -> Result<SignedAmount, ParseAmountError> {
         if self.as_sat() > SignedAmount::max_value().as_sat() as u64 {
@@ -849,6 +860,466 @@ impl FromStr for SignedAmount {
     }
 }
 
+/// Splits a `<integer> <unit>` string, the unit being optional
+fn parse_integer_with_unit(s: &str) -> Result<(u64, Option<&str>), ParseAmountError> {
+    let mut split = s.splitn(3, ' ');
+    let value = split.next().unwrap_or("");
+    let unit = split.next();
+    if split.next().is_some() || value.is_empty() {
+        return Err(ParseAmountError::InvalidFormat);
+    }
+    if value.len() > 50 {
+        return Err(ParseAmountError::InputTooLarge);
+    }
+    if value.starts_with('-') {
+        return Err(ParseAmountError::Negative);
+    }
+    if let Some(c) = value.chars().find(|c| !c.is_ascii_digit()) {
+        return Err(ParseAmountError::InvalidCharacter(c));
+    }
+    let value = u64::from_str(value).map_err(|_| ParseAmountError::TooBig)?;
+    Ok((value, unit))
+}
+
+/// Weight
+///
+/// The weight of a transaction or block in weight units, as defined by
+/// BIP141. Witness data counts as one weight unit per byte and the rest
+/// of the serialized data as four.
+///
+/// Like [Amount], the operations from [std::ops] panic on overflow and
+/// checked methods are available.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Weight(u64);
+
+impl Weight {
+    /// Zero weight units.
+    pub const ZERO: Weight = Weight(0);
+    /// The maximum weight of a block.
+    pub const MAX_BLOCK: Weight = Weight(4_000_000);
+
+    /// Create a [Weight] from a number of weight units.
+    pub fn from_wu(wu: u64) -> Weight {
+        Weight(wu)
+    }
+
+    /// Create a [Weight] from a number of virtual bytes.
+    /// Returns [None] if overflow occurred.
+    pub fn from_vb(vb: u64) -> Option<Weight> {
+        vb.checked_mul(WITNESS_SCALE_FACTOR as u64).map(Weight)
+    }
+
+    /// Get the number of weight units.
+    pub fn as_wu(self) -> u64 {
+        self.0
+    }
+
+    /// Convert to virtual bytes, rounding up.
+    pub fn to_vsize(self) -> VSize {
+        VSize(self.0 / WITNESS_SCALE_FACTOR as u64 + (self.0 % WITNESS_SCALE_FACTOR as u64 != 0) as u64)
+    }
+
+    /// Checked addition.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_add(self, rhs: Weight) -> Option<Weight> {
+        self.0.checked_add(rhs.0).map(Weight)
+    }
+
+    /// Checked subtraction.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_sub(self, rhs: Weight) -> Option<Weight> {
+        self.0.checked_sub(rhs.0).map(Weight)
+    }
+
+    /// Checked multiplication.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_mul(self, rhs: u64) -> Option<Weight> {
+        self.0.checked_mul(rhs).map(Weight)
+    }
+
+    /// Checked integer division.
+    /// Returns [None] if `rhs` is zero.
+    pub fn checked_div(self, rhs: u64) -> Option<Weight> {
+        self.0.checked_div(rhs).map(Weight)
+    }
+}
+
+impl fmt::Debug for Weight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Weight({} wu)", self.0)
+    }
+}
+
+impl fmt::Display for Weight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} wu", self.0)
+    }
+}
+
+/// Parses a number of weight units, optionally followed by ` wu`.
+impl FromStr for Weight {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_integer_with_unit(s)? {
+            (wu, None) | (wu, Some("wu")) => Ok(Weight(wu)),
+            (_, Some(unit)) => Err(ParseAmountError::UnknownDenomination(unit.to_owned())),
+        }
+    }
+}
+
+impl From<VSize> for Weight {
+    /// Panics if the weight does not fit in a u64.
+    fn from(vsize: VSize) -> Weight {
+        Weight::from_vb(vsize.0).expect("VSize to Weight overflow")
+    }
+}
+
+impl ops::Add for Weight {
+    type Output = Weight;
+
+    fn add(self, rhs: Weight) -> Self::Output {
+        self.checked_add(rhs).expect("Weight addition error")
+    }
+}
+
+impl ops::AddAssign for Weight {
+    fn add_assign(&mut self, other: Weight) {
+        *self = *self + other
+    }
+}
+
+impl ops::Sub for Weight {
+    type Output = Weight;
+
+    fn sub(self, rhs: Weight) -> Self::Output {
+        self.checked_sub(rhs).expect("Weight subtraction error")
+    }
+}
+
+impl ops::SubAssign for Weight {
+    fn sub_assign(&mut self, other: Weight) {
+        *self = *self - other
+    }
+}
+
+impl ops::Mul<u64> for Weight {
+    type Output = Weight;
+
+    fn mul(self, rhs: u64) -> Self::Output {
+        self.checked_mul(rhs).expect("Weight multiplication error")
+    }
+}
+
+impl ops::Div<u64> for Weight {
+    type Output = Weight;
+
+    fn div(self, rhs: u64) -> Self::Output {
+        self.checked_div(rhs).expect("Weight division error")
+    }
+}
+
+impl iter::Sum for Weight {
+    fn sum<I: Iterator<Item = Weight>>(iter: I) -> Weight {
+        iter.fold(Weight::ZERO, ops::Add::add)
+    }
+}
+
+/// VSize
+///
+/// The virtual size of a transaction or block in virtual bytes: its
+/// [Weight] divided by four, rounded up.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VSize(u64);
+
+impl VSize {
+    /// Zero virtual bytes.
+    pub const ZERO: VSize = VSize(0);
+
+    /// Create a [VSize] from a number of virtual bytes.
+    pub fn from_vb(vb: u64) -> VSize {
+        VSize(vb)
+    }
+
+    /// Get the number of virtual bytes.
+    pub fn as_vb(self) -> u64 {
+        self.0
+    }
+
+    /// Checked addition.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_add(self, rhs: VSize) -> Option<VSize> {
+        self.0.checked_add(rhs.0).map(VSize)
+    }
+
+    /// Checked subtraction.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_sub(self, rhs: VSize) -> Option<VSize> {
+        self.0.checked_sub(rhs.0).map(VSize)
+    }
+
+    /// Checked multiplication.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_mul(self, rhs: u64) -> Option<VSize> {
+        self.0.checked_mul(rhs).map(VSize)
+    }
+
+    /// Checked integer division.
+    /// Returns [None] if `rhs` is zero.
+    pub fn checked_div(self, rhs: u64) -> Option<VSize> {
+        self.0.checked_div(rhs).map(VSize)
+    }
+}
+
+impl fmt::Debug for VSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VSize({} vB)", self.0)
+    }
+}
+
+impl fmt::Display for VSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} vB", self.0)
+    }
+}
+
+/// Parses a number of virtual bytes, optionally followed by ` vB`.
+impl FromStr for VSize {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_integer_with_unit(s)? {
+            (vb, None) | (vb, Some("vB")) => Ok(VSize(vb)),
+            (_, Some(unit)) => Err(ParseAmountError::UnknownDenomination(unit.to_owned())),
+        }
+    }
+}
+
+impl From<Weight> for VSize {
+    fn from(weight: Weight) -> VSize {
+        weight.to_vsize()
+    }
+}
+
+impl ops::Add for VSize {
+    type Output = VSize;
+
+    fn add(self, rhs: VSize) -> Self::Output {
+        self.checked_add(rhs).expect("VSize addition error")
+    }
+}
+
+impl ops::AddAssign for VSize {
+    fn add_assign(&mut self, other: VSize) {
+        *self = *self + other
+    }
+}
+
+impl ops::Sub for VSize {
+    type Output = VSize;
+
+    fn sub(self, rhs: VSize) -> Self::Output {
+        self.checked_sub(rhs).expect("VSize subtraction error")
+    }
+}
+
+impl ops::SubAssign for VSize {
+    fn sub_assign(&mut self, other: VSize) {
+        *self = *self - other
+    }
+}
+
+impl ops::Mul<u64> for VSize {
+    type Output = VSize;
+
+    fn mul(self, rhs: u64) -> Self::Output {
+        self.checked_mul(rhs).expect("VSize multiplication error")
+    }
+}
+
+impl ops::Div<u64> for VSize {
+    type Output = VSize;
+
+    fn div(self, rhs: u64) -> Self::Output {
+        self.checked_div(rhs).expect("VSize division error")
+    }
+}
+
+/// FeeRate
+///
+/// A fee rate, stored in satoshis per 1000 weight units. One satoshi per
+/// virtual byte is 250 satoshis per 1000 weight units.
+///
+/// Fees computed from a fee rate are rounded up, so that the resulting
+/// fee rate is never below the requested one.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    /// A zero fee rate.
+    pub const ZERO: FeeRate = FeeRate(0);
+    /// Bitcoin Core's default minimum relay fee rate of 1 sat/vB.
+    pub const MIN_RELAY: FeeRate = FeeRate(250);
+    /// Bitcoin Core's default dust relay fee rate of 3 sat/vB.
+    pub const DUST_RELAY: FeeRate = FeeRate(750);
+
+    /// Create a [FeeRate] from satoshis per 1000 weight units.
+    pub fn from_sat_per_kwu(sat_kwu: u64) -> FeeRate {
+        FeeRate(sat_kwu)
+    }
+
+    /// Create a [FeeRate] from satoshis per virtual byte.
+    /// Returns [None] if overflow occurred.
+    pub fn from_sat_per_vb(sat_vb: u64) -> Option<FeeRate> {
+        sat_vb.checked_mul(1000 / WITNESS_SCALE_FACTOR as u64).map(FeeRate)
+    }
+
+    /// Create a [FeeRate] from satoshis per 1000 virtual bytes, the unit
+    /// used by Bitcoin Core, rounding up.
+    pub fn from_sat_per_kvb(sat_kvb: u64) -> FeeRate {
+        FeeRate(sat_kvb / WITNESS_SCALE_FACTOR as u64 + (sat_kvb % WITNESS_SCALE_FACTOR as u64 != 0) as u64)
+    }
+
+    /// Get the fee rate in satoshis per 1000 weight units.
+    pub fn as_sat_per_kwu(self) -> u64 {
+        self.0
+    }
+
+    /// Get the fee rate in satoshis per virtual byte, rounding down.
+    pub fn as_sat_per_vb_floor(self) -> u64 {
+        self.0 / (1000 / WITNESS_SCALE_FACTOR as u64)
+    }
+
+    /// Get the fee rate in satoshis per virtual byte, rounding up.
+    pub fn as_sat_per_vb_ceil(self) -> u64 {
+        let sat_vb = self.as_sat_per_vb_floor();
+        sat_vb + (self.0 % (1000 / WITNESS_SCALE_FACTOR as u64) != 0) as u64
+    }
+
+    /// Get the fee rate in satoshis per 1000 virtual bytes.
+    /// Returns [None] if overflow occurred.
+    pub fn as_sat_per_kvb(self) -> Option<u64> {
+        self.0.checked_mul(WITNESS_SCALE_FACTOR as u64)
+    }
+
+    /// The fee for `weight` at this fee rate, rounded up.
+    /// Returns [None] if overflow occurred.
+    pub fn fee_wu(self, weight: Weight) -> Option<Amount> {
+        let sat = self.0.checked_mul(weight.0)?.checked_add(999)?;
+        Some(Amount::from_sat(sat / 1000))
+    }
+
+    /// The fee for `vsize` at this fee rate, rounded up.
+    /// Returns [None] if overflow occurred.
+    pub fn fee_vb(self, vsize: VSize) -> Option<Amount> {
+        self.fee_wu(Weight::from_vb(vsize.0)?)
+    }
+
+    /// Checked addition.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_add(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_add(rhs.0).map(FeeRate)
+    }
+
+    /// Checked subtraction.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_sub(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_sub(rhs.0).map(FeeRate)
+    }
+
+    /// Checked multiplication.
+    /// Returns [None] if overflow occurred.
+    pub fn checked_mul(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_mul(rhs).map(FeeRate)
+    }
+
+    /// Checked integer division.
+    /// Returns [None] if `rhs` is zero.
+    pub fn checked_div(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_div(rhs).map(FeeRate)
+    }
+}
+
+impl fmt::Debug for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FeeRate({} sat/kwu)", self.0)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} sat/kwu", self.0)
+    }
+}
+
+/// Parses an integer fee rate followed by ` sat/kwu`, ` sat/vB` or
+/// ` sat/kvB`, satoshis per 1000 weight units being assumed without unit.
+impl FromStr for FeeRate {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_integer_with_unit(s)? {
+            (sat, None) | (sat, Some("sat/kwu")) => Ok(FeeRate(sat)),
+            (sat, Some("sat/vB")) => FeeRate::from_sat_per_vb(sat).ok_or(ParseAmountError::TooBig),
+            (sat, Some("sat/kvB")) => Ok(FeeRate::from_sat_per_kvb(sat)),
+            (_, Some(unit)) => Err(ParseAmountError::UnknownDenomination(unit.to_owned())),
+        }
+    }
+}
+
+impl ops::Add for FeeRate {
+    type Output = FeeRate;
+
+    fn add(self, rhs: FeeRate) -> Self::Output {
+        self.checked_add(rhs).expect("FeeRate addition error")
+    }
+}
+
+impl ops::Sub for FeeRate {
+    type Output = FeeRate;
+
+    fn sub(self, rhs: FeeRate) -> Self::Output {
+        self.checked_sub(rhs).expect("FeeRate subtraction error")
+    }
+}
+
+impl ops::Mul<u64> for FeeRate {
+    type Output = FeeRate;
+
+    fn mul(self, rhs: u64) -> Self::Output {
+        self.checked_mul(rhs).expect("FeeRate multiplication error")
+    }
+}
+
+impl ops::Div<u64> for FeeRate {
+    type Output = FeeRate;
+
+    fn div(self, rhs: u64) -> Self::Output {
+        self.checked_div(rhs).expect("FeeRate division error")
+    }
+}
+
+/// The fee for a weight, rounded up.
+impl ops::Mul<Weight> for FeeRate {
+    type Output = Amount;
+
+    fn mul(self, rhs: Weight) -> Self::Output {
+        self.fee_wu(rhs).expect("FeeRate multiplication error")
+    }
+}
+
+/// The fee rate paid by a fee for a weight, rounded down.
+impl ops::Div<Weight> for Amount {
+    type Output = FeeRate;
+
+    fn div(self, rhs: Weight) -> Self::Output {
+        self.checked_div_by_weight(rhs).expect("Amount division error")
+    }
+}
+
 #[cfg(feature = "serde")]
 pub mod serde {
     // methods are implementation of a standardized serde-specific signature
@@ -1275,6 +1746,100 @@ mod tests {
         assert_eq!(SignedAmount::from_str("-42 satoshi BTC"), Err(ParseAmountError::InvalidFormat));
     }
 
+    #[test]
+    fn weight_vsize() {
+        let wu = Weight::from_wu;
+
+        assert_eq!(Weight::from_vb(3), Some(wu(12)));
+        assert_eq!(Weight::from_vb(u64::max_value()), None);
+        assert_eq!(wu(12).to_vsize(), VSize::from_vb(3));
+        assert_eq!(wu(13).to_vsize(), VSize::from_vb(4));
+        assert_eq!(Weight::from(VSize::from_vb(4)), wu(16));
+        assert_eq!(VSize::from(wu(1)), VSize::from_vb(1));
+
+        assert_eq!(wu(10) + wu(5), wu(15));
+        assert_eq!(wu(10) - wu(5), wu(5));
+        assert_eq!(wu(10) * 3, wu(30));
+        assert_eq!(wu(10) / 3, wu(3));
+        assert_eq!(vec![wu(1), wu(2), wu(3)].into_iter().sum::<Weight>(), wu(6));
+        assert_eq!(wu(5).checked_sub(wu(6)), None);
+        assert_eq!(wu(u64::max_value()).checked_add(wu(1)), None);
+        assert_eq!(VSize::from_vb(5).checked_div(0), None);
+        assert_eq!(VSize::from_vb(5) + VSize::from_vb(6), VSize::from_vb(11));
+
+        let result = panic::catch_unwind(|| wu(u64::max_value()) * 2);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fee_rate() {
+        let sat = Amount::from_sat;
+
+        assert_eq!(FeeRate::from_sat_per_vb(1), Some(FeeRate::MIN_RELAY));
+        assert_eq!(FeeRate::from_sat_per_vb(u64::max_value()), None);
+        assert_eq!(FeeRate::from_sat_per_kvb(1000), FeeRate::MIN_RELAY);
+        assert_eq!(FeeRate::from_sat_per_kvb(1001), FeeRate::from_sat_per_kwu(251));
+        assert_eq!(FeeRate::DUST_RELAY.as_sat_per_kvb(), Some(3000));
+        assert_eq!(FeeRate::from_sat_per_kwu(300).as_sat_per_vb_floor(), 1);
+        assert_eq!(FeeRate::from_sat_per_kwu(300).as_sat_per_vb_ceil(), 2);
+        assert_eq!(FeeRate::from_sat_per_kwu(500).as_sat_per_vb_ceil(), 2);
+
+        // 141 vbytes at 1 sat/vB, rounded up
+        assert_eq!(FeeRate::MIN_RELAY.fee_wu(Weight::from_wu(561)), Some(sat(141)));
+        assert_eq!(FeeRate::MIN_RELAY.fee_vb(VSize::from_vb(141)), Some(sat(141)));
+        assert_eq!(FeeRate::MIN_RELAY * Weight::from_wu(560), sat(140));
+        assert_eq!(FeeRate::from_sat_per_kwu(u64::max_value()).fee_wu(Weight::from_wu(2)), None);
+        assert_eq!(sat(140) / Weight::from_wu(560), FeeRate::MIN_RELAY);
+        assert_eq!(sat(140).checked_div_by_weight(Weight::ZERO), None);
+
+        assert_eq!(FeeRate::MIN_RELAY + FeeRate::DUST_RELAY, FeeRate::from_sat_per_kwu(1000));
+        assert_eq!(FeeRate::MIN_RELAY.checked_sub(FeeRate::DUST_RELAY), None);
+        assert_eq!(FeeRate::MIN_RELAY * 3, FeeRate::DUST_RELAY);
+        assert_eq!(FeeRate::DUST_RELAY / 3, FeeRate::MIN_RELAY);
+    }
+
+    #[test]
+    fn weight_fee_rate_from_str() {
+        assert_eq!(Weight::from_str("400"), Ok(Weight::from_wu(400)));
+        assert_eq!(Weight::from_str("400 wu"), Ok(Weight::from_wu(400)));
+        assert_eq!(Weight::from_str("400 vB"), Err(ParseAmountError::UnknownDenomination("vB".to_owned())));
+        assert_eq!(VSize::from_str("100 vB"), Ok(VSize::from_vb(100)));
+        assert_eq!(FeeRate::from_str("250"), Ok(FeeRate::MIN_RELAY));
+        assert_eq!(FeeRate::from_str("250 sat/kwu"), Ok(FeeRate::MIN_RELAY));
+        assert_eq!(FeeRate::from_str("3 sat/vB"), Ok(FeeRate::DUST_RELAY));
+        assert_eq!(FeeRate::from_str("1000 sat/kvB"), Ok(FeeRate::MIN_RELAY));
+
+        assert_eq!(Weight::from_str(""), Err(ParseAmountError::InvalidFormat));
+        assert_eq!(Weight::from_str("1 wu wu"), Err(ParseAmountError::InvalidFormat));
+        assert_eq!(Weight::from_str("-1"), Err(ParseAmountError::Negative));
+        assert_eq!(Weight::from_str("1.5"), Err(ParseAmountError::InvalidCharacter('.')));
+        assert_eq!(Weight::from_str("18446744073709551616"), Err(ParseAmountError::TooBig));
+        assert_eq!(FeeRate::from_str("18446744073709551615 sat/vB"), Err(ParseAmountError::TooBig));
+
+        for weight in &[Weight::ZERO, Weight::MAX_BLOCK] {
+            assert_eq!(Weight::from_str(&weight.to_string()), Ok(*weight));
+        }
+        assert_eq!(VSize::from_vb(141).to_string(), "141 vB");
+        assert_eq!(FeeRate::from_str(&FeeRate::DUST_RELAY.to_string()), Ok(FeeRate::DUST_RELAY));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_weight_fee_rate() {
+        serde_test::assert_tokens(&Weight::from_wu(400), &[
+            serde_test::Token::NewtypeStruct { name: "Weight" },
+            serde_test::Token::U64(400),
+        ]);
+        serde_test::assert_tokens(&VSize::from_vb(100), &[
+            serde_test::Token::NewtypeStruct { name: "VSize" },
+            serde_test::Token::U64(100),
+        ]);
+        serde_test::assert_tokens(&FeeRate::MIN_RELAY, &[
+            serde_test::Token::NewtypeStruct { name: "FeeRate" },
+            serde_test::Token::U64(250),
+        ]);
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn serde_as_sat() {
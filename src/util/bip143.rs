@@ -173,15 +173,28 @@ impl<R: Deref<Target=Transaction>> SigHashCache<R> {
     /// std::io::Write trait.
     pub fn encode_signing_data_to<Write: io::Write>(
         &mut self,
-        mut writer: Write,
+        writer: Write,
         input_index: usize,
         script_code: &Script,
         value: u64,
         sighash_type: SigHashType,
+    ) -> Result<(), encode::Error> {
+        self.encode_signing_data_to_u32(writer, input_index, script_code, value, sighash_type.as_u32())
+    }
+
+    /// Encode the BIP143 signing data for a raw sighash flag, which is
+    /// committed to as-is even if it is not one of the standard types
+    pub(crate) fn encode_signing_data_to_u32<Write: io::Write>(
+        &mut self,
+        mut writer: Write,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: u32,
     ) -> Result<(), encode::Error> {
         let zero_hash = sha256d::Hash::default();
 
-        let (sighash, anyone_can_pay) = sighash_type.split_anyonecanpay_flag();
+        let (sighash, anyone_can_pay) = SigHashType::from_u32(sighash_type).split_anyonecanpay_flag();
 
         self.tx.version.consensus_encode(&mut writer)?;
 
@@ -219,7 +232,7 @@ impl<R: Deref<Target=Transaction>> SigHashCache<R> {
         }
 
         self.tx.lock_time.consensus_encode(&mut writer)?;
-        sighash_type.as_u32().consensus_encode(&mut writer)?;
+        sighash_type.consensus_encode(&mut writer)?;
         Ok(())
     }
 
@@ -231,9 +244,20 @@ impl<R: Deref<Target=Transaction>> SigHashCache<R> {
         script_code: &Script,
         value: u64,
         sighash_type: SigHashType
+    ) -> SigHash {
+        self.signature_hash_u32(input_index, script_code, value, sighash_type.as_u32())
+    }
+
+    /// Compute the BIP143 sighash for a raw sighash flag
+    pub(crate) fn signature_hash_u32(
+        &mut self,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: u32
     ) -> SigHash {
         let mut enc = SigHash::engine();
-        self.encode_signing_data_to(&mut enc, input_index, script_code, value, sighash_type)
+        self.encode_signing_data_to_u32(&mut enc, input_index, script_code, value, sighash_type)
             .expect("engines don't error");
         SigHash::from_engine(enc)
     }
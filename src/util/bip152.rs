@@ -0,0 +1,617 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP152 compact blocks
+//!
+//! Data structures of [BIP152](https://github.com/bitcoin/bips/blob/master/bip-0152.mediawiki)
+//! compact block relay: a block is announced as its header, a few prefilled
+//! transactions and 6 byte short ids of the other ones, which the receiver
+//! looks up in its mempool. Transactions it doesn't know are requested with
+//! a `getblocktxn` message and provided by a `blocktxn` message.
+//!
+//! ```rust
+//! use std::collections::HashMap;
+//! use bitcoin::blockdata::constants::genesis_block;
+//! use bitcoin::network::constants::Network;
+//! use bitcoin::util::bip152::HeaderAndShortIds;
+//!
+//! let block = genesis_block(Network::Bitcoin);
+//! let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+//!
+//! // The receiving side, with an empty mempool
+//! let mempool = HashMap::new();
+//! let partial = compact.reconstruct(|short_id| mempool.get(short_id).cloned()).unwrap();
+//! assert!(partial.missing().is_empty());
+//! assert_eq!(partial.into_block().unwrap(), block);
+//! ```
+//!
+
+use std::{error, fmt, io, u16};
+use std::collections::HashSet;
+
+use hashes::{sha256, siphash24, Hash};
+use hash_types::BlockHash;
+use blockdata::block::{Block, BlockHeader};
+use blockdata::constants::{MAX_BLOCK_WEIGHT, MIN_TRANSACTION_WEIGHT};
+use blockdata::transaction::Transaction;
+use consensus::encode::{self, Decodable, Encodable, VarInt};
+use util::endian;
+
+/// A BIP152 error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The compact block version is neither 1 nor 2
+    UnknownVersion(u64),
+    /// The indexes of transactions to prefill are not increasing or out of
+    /// the block
+    InvalidPrefill,
+    /// A requested transaction index is out of the block
+    IndexOutOfRange(u16),
+    /// The prefilled transactions of a compact block are inconsistent with
+    /// its short ids, or it has too many transactions
+    InvalidCompactBlock,
+    /// Two transactions of a compact block have the same short id, so that
+    /// it cannot be reconstructed from a mempool
+    ShortIdCollision,
+    /// A `blocktxn` message doesn't provide the missing transactions of the
+    /// block being reconstructed
+    UnexpectedTransactions,
+    /// Some transactions of the block are still missing
+    IncompleteBlock,
+    /// The reconstructed transactions don't match the header or the witness
+    /// commitment, because of a short id collision with a mempool
+    /// transaction
+    MerkleRootMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnknownVersion(v) => write!(f, "unknown compact block version {}", v),
+            Error::InvalidPrefill => f.write_str("invalid prefilled transaction indexes"),
+            Error::IndexOutOfRange(i) => write!(f, "transaction index {} out of the block", i),
+            Error::InvalidCompactBlock => f.write_str("invalid compact block"),
+            Error::ShortIdCollision => f.write_str("short transaction id collision"),
+            Error::UnexpectedTransactions => f.write_str("unexpected block transactions"),
+            Error::IncompleteBlock => f.write_str("missing block transactions"),
+            Error::MerkleRootMismatch => f.write_str("reconstructed block doesn't match its merkle root"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A short transaction id: a SipHash-2-4 of a txid (version 1) or wtxid
+/// (version 2) keyed by the block header and a nonce, truncated to 6 bytes
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ShortId([u8; 6]);
+
+impl_array_newtype!(ShortId, u8, 6);
+impl_bytes_newtype!(ShortId, 6);
+
+impl ShortId {
+    /// The SipHash keys of the short ids of a block: the first two little
+    /// endian 64 bit integers of the SHA256 of its header followed by the
+    /// nonce
+    pub fn siphash_keys(header: &BlockHeader, nonce: u64) -> (u64, u64) {
+        let mut engine = sha256::Hash::engine();
+        header.consensus_encode(&mut engine).expect("engines don't error");
+        nonce.consensus_encode(&mut engine).expect("engines don't error");
+        let hash = sha256::Hash::from_engine(engine);
+        (endian::slice_to_u64_le(&hash[0..8]), endian::slice_to_u64_le(&hash[8..16]))
+    }
+
+    /// Computes the short id of a txid or wtxid with the given keys
+    pub fn with_siphash_keys(txid: &[u8], keys: (u64, u64)) -> ShortId {
+        let hash = siphash24::Hash::hash_to_u64_with_keys(keys.0, keys.1, txid);
+        ShortId::from(&endian::u64_to_array_le(hash)[0..6])
+    }
+}
+
+impl Encodable for ShortId {
+    #[inline]
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, io::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl Decodable for ShortId {
+    #[inline]
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        Ok(ShortId(Decodable::consensus_decode(d)?))
+    }
+}
+
+/// A transaction provided in full by a compact block
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PrefilledTransaction {
+    /// Index of the transaction in the block. It is differentially encoded
+    /// on the wire, relative to the previous prefilled transaction.
+    pub index: u16,
+    /// The transaction
+    pub tx: Transaction,
+}
+
+/// A compact block, as sent by a `cmpctblock` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HeaderAndShortIds {
+    /// The block header
+    pub header: BlockHeader,
+    /// The nonce keying the short ids along with the header
+    pub nonce: u64,
+    /// Short ids of the transactions which are not prefilled, in order
+    pub short_ids: Vec<ShortId>,
+    /// Transactions provided in full, by increasing index. The coinbase
+    /// always is.
+    pub prefilled_txs: Vec<PrefilledTransaction>,
+}
+
+impl Encodable for HeaderAndShortIds {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, io::Error> {
+        let mut len = self.header.consensus_encode(&mut s)?;
+        len += self.nonce.consensus_encode(&mut s)?;
+        len += self.short_ids.consensus_encode(&mut s)?;
+        len += VarInt(self.prefilled_txs.len() as u64).consensus_encode(&mut s)?;
+        let mut next = 0u64;
+        for prefilled in &self.prefilled_txs {
+            len += encode_index_diff(prefilled.index, &mut next, &mut s)?;
+            len += prefilled.tx.consensus_encode(&mut s)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for HeaderAndShortIds {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let header = Decodable::consensus_decode(&mut d)?;
+        let nonce = Decodable::consensus_decode(&mut d)?;
+        let short_ids: Vec<ShortId> = Decodable::consensus_decode(&mut d)?;
+        let count = VarInt::consensus_decode(&mut d)?.0;
+        if count > u16::MAX as u64 + 1 {
+            return Err(encode::Error::ParseFailed("too many prefilled transactions"));
+        }
+        // Grown as transactions are read, the count being unchecked
+        let mut prefilled_txs = vec![];
+        let mut next = 0u64;
+        for _ in 0..count {
+            prefilled_txs.push(PrefilledTransaction {
+                index: decode_index_diff(&mut next, &mut d)?,
+                tx: Decodable::consensus_decode(&mut d)?,
+            });
+        }
+        if short_ids.len() + prefilled_txs.len() > u16::MAX as usize {
+            return Err(encode::Error::ParseFailed("compact block indexes overflow 16 bits"));
+        }
+        Ok(HeaderAndShortIds {
+            header: header,
+            nonce: nonce,
+            short_ids: short_ids,
+            prefilled_txs: prefilled_txs,
+        })
+    }
+}
+
+impl HeaderAndShortIds {
+    /// Creates the compact block of `block`, with short ids of txids for
+    /// version 1 and of wtxids for version 2. Besides the coinbase, the
+    /// transactions at the increasing `prefill` indexes are prefilled.
+    ///
+    /// Version 1 compact blocks are relayed to peers which don't support
+    /// segwit, so that their prefilled transactions are stripped of their
+    /// witnesses. The same `nonce` shouldn't be used for different blocks.
+    pub fn from_block(block: &Block, nonce: u64, version: u64, prefill: &[usize]) -> Result<HeaderAndShortIds, Error> {
+        if version != 1 && version != 2 {
+            return Err(Error::UnknownVersion(version));
+        }
+        if block.txdata.len() > u16::MAX as usize
+            || prefill.windows(2).any(|w| w[0] >= w[1])
+            || prefill.last().map_or(false, |&i| i >= block.txdata.len()) {
+            return Err(Error::InvalidPrefill);
+        }
+
+        let keys = ShortId::siphash_keys(&block.header, nonce);
+        let mut short_ids = Vec::with_capacity(block.txdata.len());
+        let mut prefilled_txs = Vec::with_capacity(prefill.len() + 1);
+        let mut prefill = prefill.iter().peekable();
+        for (index, tx) in block.txdata.iter().enumerate() {
+            if prefill.peek() == Some(&&index) {
+                prefill.next();
+            } else if index != 0 {
+                short_ids.push(match version {
+                    1 => ShortId::with_siphash_keys(&tx.txid()[..], keys),
+                    _ => ShortId::with_siphash_keys(&tx.wtxid()[..], keys),
+                });
+                continue;
+            }
+            let mut tx = tx.clone();
+            if version == 1 {
+                for input in &mut tx.input {
+                    input.witness.clear();
+                }
+            }
+            prefilled_txs.push(PrefilledTransaction { index: index as u16, tx: tx });
+        }
+
+        Ok(HeaderAndShortIds {
+            header: block.header,
+            nonce: nonce,
+            short_ids: short_ids,
+            prefilled_txs: prefilled_txs,
+        })
+    }
+
+    /// The SipHash keys of the short ids of this compact block, to be
+    /// used with [`ShortId::with_siphash_keys`] to index a mempool
+    pub fn siphash_keys(&self) -> (u64, u64) {
+        ShortId::siphash_keys(&self.header, self.nonce)
+    }
+
+    /// Starts reconstructing the block from the prefilled transactions and
+    /// the transactions `lookup` finds for short ids, typically in the
+    /// mempool. The transactions which weren't found are reported by
+    /// [`PartialBlock::missing`].
+    pub fn reconstruct<F>(&self, mut lookup: F) -> Result<PartialBlock, Error>
+        where F: FnMut(&ShortId) -> Option<Transaction>
+    {
+        let tx_count = self.short_ids.len() + self.prefilled_txs.len();
+        if tx_count == 0 || tx_count > (MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT) as usize {
+            return Err(Error::InvalidCompactBlock);
+        }
+
+        let mut txdata: Vec<Option<Transaction>> = vec![None; tx_count];
+        let mut previous = None;
+        for prefilled in &self.prefilled_txs {
+            let index = prefilled.index as usize;
+            if index >= tx_count || previous.map_or(false, |p| index <= p) {
+                return Err(Error::InvalidCompactBlock);
+            }
+            txdata[index] = Some(prefilled.tx.clone());
+            previous = Some(index);
+        }
+
+        let mut seen = HashSet::with_capacity(self.short_ids.len());
+        if !self.short_ids.iter().all(|id| seen.insert(*id)) {
+            return Err(Error::ShortIdCollision);
+        }
+        let mut short_ids = self.short_ids.iter();
+        for tx in txdata.iter_mut().filter(|tx| tx.is_none()) {
+            let short_id = short_ids.next().expect("one short id per missing transaction");
+            *tx = lookup(short_id);
+        }
+
+        Ok(PartialBlock {
+            header: self.header,
+            txdata: txdata,
+        })
+    }
+}
+
+/// A block being reconstructed from a compact block
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    txdata: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    /// The block header
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// Indexes of the transactions still missing
+    pub fn missing(&self) -> Vec<u16> {
+        self.txdata.iter()
+            .enumerate()
+            .filter(|&(_, tx)| tx.is_none())
+            .map(|(index, _)| index as u16)
+            .collect()
+    }
+
+    /// The `getblocktxn` request for the missing transactions
+    pub fn request(&self) -> BlockTransactionsRequest {
+        BlockTransactionsRequest {
+            block_hash: self.header.block_hash(),
+            indexes: self.missing(),
+        }
+    }
+
+    /// Fills the missing transactions with the ones of a `blocktxn`
+    /// message answering [`PartialBlock::request`]
+    pub fn fill(&mut self, txs: &BlockTransactions) -> Result<(), Error> {
+        let missing = self.txdata.iter().filter(|tx| tx.is_none()).count();
+        if txs.block_hash != self.header.block_hash() || txs.transactions.len() != missing {
+            return Err(Error::UnexpectedTransactions);
+        }
+        let slots = self.txdata.iter_mut().filter(|tx| tx.is_none());
+        for (slot, tx) in slots.zip(txs.transactions.iter()) {
+            *slot = Some(tx.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns the full block once no transaction is missing, after checking
+    /// it against the merkle root of the header and the witness commitment.
+    /// A mismatch means that a mempool transaction had the short id of a
+    /// block transaction, and the full block must be requested instead.
+    pub fn into_block(self) -> Result<Block, Error> {
+        let mut txdata = Vec::with_capacity(self.txdata.len());
+        for tx in self.txdata {
+            match tx {
+                Some(tx) => txdata.push(tx),
+                None => return Err(Error::IncompleteBlock),
+            }
+        }
+        let block = Block { header: self.header, txdata: txdata };
+        if !block.check_merkle_root() || !block.check_witness_commitment() {
+            return Err(Error::MerkleRootMismatch);
+        }
+        Ok(block)
+    }
+}
+
+/// A request for transactions of a block, as sent by a `getblocktxn` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockTransactionsRequest {
+    /// Hash of the block
+    pub block_hash: BlockHash,
+    /// Increasing indexes of the requested transactions. They are
+    /// differentially encoded on the wire.
+    pub indexes: Vec<u16>,
+}
+
+impl Encodable for BlockTransactionsRequest {
+    fn consensus_encode<S: io::Write>(&self, mut s: S) -> Result<usize, io::Error> {
+        let mut len = self.block_hash.consensus_encode(&mut s)?;
+        len += VarInt(self.indexes.len() as u64).consensus_encode(&mut s)?;
+        let mut next = 0u64;
+        for index in &self.indexes {
+            len += encode_index_diff(*index, &mut next, &mut s)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for BlockTransactionsRequest {
+    fn consensus_decode<D: io::Read>(mut d: D) -> Result<Self, encode::Error> {
+        let block_hash = Decodable::consensus_decode(&mut d)?;
+        let count = VarInt::consensus_decode(&mut d)?.0;
+        if count > u16::MAX as u64 + 1 {
+            return Err(encode::Error::ParseFailed("too many block transaction indexes"));
+        }
+        let mut indexes = Vec::with_capacity(count as usize);
+        let mut next = 0u64;
+        for _ in 0..count {
+            indexes.push(decode_index_diff(&mut next, &mut d)?);
+        }
+        Ok(BlockTransactionsRequest {
+            block_hash: block_hash,
+            indexes: indexes,
+        })
+    }
+}
+
+/// Transactions of a block, as sent by a `blocktxn` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockTransactions {
+    /// Hash of the block
+    pub block_hash: BlockHash,
+    /// The requested transactions, in order
+    pub transactions: Vec<Transaction>,
+}
+
+impl_consensus_encoding!(BlockTransactions, block_hash, transactions);
+
+impl BlockTransactions {
+    /// Answers a `getblocktxn` request for `block`
+    pub fn from_request(request: &BlockTransactionsRequest, block: &Block) -> Result<BlockTransactions, Error> {
+        let mut transactions = Vec::with_capacity(request.indexes.len());
+        for &index in &request.indexes {
+            match block.txdata.get(index as usize) {
+                Some(tx) => transactions.push(tx.clone()),
+                None => return Err(Error::IndexOutOfRange(index)),
+            }
+        }
+        Ok(BlockTransactions {
+            block_hash: request.block_hash,
+            transactions: transactions,
+        })
+    }
+}
+
+/// Encodes `index` as its difference with the index following the previous
+/// one, `next`
+fn encode_index_diff<S: io::Write>(index: u16, next: &mut u64, s: S) -> Result<usize, io::Error> {
+    let index = index as u64;
+    if index < *next {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "transaction indexes are not increasing"));
+    }
+    let len = VarInt(index - *next).consensus_encode(s)?;
+    *next = index + 1;
+    Ok(len)
+}
+
+/// Decodes a differentially encoded index, see [`encode_index_diff`]
+fn decode_index_diff<D: io::Read>(next: &mut u64, d: D) -> Result<u16, encode::Error> {
+    let index = match next.checked_add(VarInt::consensus_decode(d)?.0) {
+        Some(index) if index <= u16::MAX as u64 => index,
+        _ => return Err(encode::Error::ParseFailed("transaction index overflows 16 bits")),
+    };
+    *next = index + 1;
+    Ok(index as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use hash_types::{TxMerkleNode, Txid};
+    use hashes::hex::FromHex;
+
+    fn dummy_tx(nonce: u8) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 2,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(&[nonce]), 0),
+                script_sig: Script::new(),
+                sequence: 1,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: 1, script_pubkey: Script::new() }],
+        }
+    }
+
+    fn dummy_block(tx_count: u8) -> Block {
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: BlockHash::hash(&[0]),
+                merkle_root: TxMerkleNode::hash(&[1]),
+                time: 2,
+                bits: 3,
+                nonce: 4,
+            },
+            txdata: (0..tx_count).map(dummy_tx).collect(),
+        };
+        block.header.merkle_root = block.merkle_root();
+        block
+    }
+
+    #[test]
+    fn from_block() {
+        let block = dummy_block(3);
+
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+        assert_eq!(compact.nonce, 42);
+        assert_eq!(compact.short_ids.len(), 2);
+        assert_eq!(compact.prefilled_txs, vec![PrefilledTransaction { index: 0, tx: block.txdata[0].clone() }]);
+
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[0, 1, 2]).unwrap();
+        assert!(compact.short_ids.is_empty());
+        assert_eq!(compact.prefilled_txs.iter().map(|p| p.index).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(&serialize(&compact)[88..91], &[0, 3, 0]);
+
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[2]).unwrap();
+        assert_eq!(compact.prefilled_txs.iter().map(|p| p.index).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(deserialize::<HeaderAndShortIds>(&serialize(&compact)).unwrap(), compact);
+
+        assert_eq!(HeaderAndShortIds::from_block(&block, 42, 3, &[]), Err(Error::UnknownVersion(3)));
+        assert_eq!(HeaderAndShortIds::from_block(&block, 42, 2, &[2, 1]), Err(Error::InvalidPrefill));
+        assert_eq!(HeaderAndShortIds::from_block(&block, 42, 2, &[3]), Err(Error::InvalidPrefill));
+    }
+
+    #[test]
+    fn compact_block_vector() {
+        // Tested with Elements implementation of compact blocks.
+        let raw_block = Vec::<u8>::from_hex("000000206c750a364035aefd5f81508a08769975116d9195312ee4520dceac39e1fdc62c4dc67473b8e354358c1e610afeaff7410858bd45df43e2940f8a62bd3d5e3ac943c2975cffff7f200000000002020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff04016b0101ffffffff020006062a0100000001510000000000000000266a24aa21a9ed4a3d9f3343dafcc0d6f6d4310f2ee5ce273ed34edca6c75db3a73e7f368734200120000000000000000000000000000000000000000000000000000000000000000000000000020000000001021fc20ba2bd745507b8e00679e3b362558f9457db374ca28ffa5243f4c23a4d5f00000000171600147c9dea14ffbcaec4b575e03f05ceb7a81cd3fcbffdffffff915d689be87b43337f42e26033df59807b768223368f189a023d0242d837768900000000171600147c9dea14ffbcaec4b575e03f05ceb7a81cd3fcbffdffffff0200cdf5050000000017a9146803c72d9154a6a20f404bed6d3dcee07986235a8700e1f5050000000017a9144e6a4c7cb5b5562904843bdf816342f4db9f5797870247304402205e9bf6e70eb0e4b495bf483fd8e6e02da64900f290ef8aaa64bb32600d973c450220670896f5d0e5f33473e5f399ab680cc1d25c2d2afd15abd722f04978f28be887012103e4e4d9312b2261af508b367d8ba9be4f01b61d6d6e78bec499845b4f410bcf2702473044022045ac80596a6ac9c8c572f94708709adaf106677221122e08daf8b9741a04f66a022003ccd52a3b78f8fd08058fc04fc0cffa5f4c196c84eae9e37e2a85babe731b57012103e4e4d9312b2261af508b367d8ba9be4f01b61d6d6e78bec499845b4f410bcf276a000000").unwrap();
+        let raw_compact = Vec::<u8>::from_hex("000000206c750a364035aefd5f81508a08769975116d9195312ee4520dceac39e1fdc62c4dc67473b8e354358c1e610afeaff7410858bd45df43e2940f8a62bd3d5e3ac943c2975cffff7f2000000000a4df3c3744da89fa010a6979e971450100020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff04016b0101ffffffff020006062a0100000001510000000000000000266a24aa21a9ed4a3d9f3343dafcc0d6f6d4310f2ee5ce273ed34edca6c75db3a73e7f368734200120000000000000000000000000000000000000000000000000000000000000000000000000").unwrap();
+
+        let block: Block = deserialize(&raw_block).unwrap();
+        let compact = HeaderAndShortIds::from_block(&block, 18053200567810711460, 2, &[]).unwrap();
+        assert_eq!(serialize(&compact), raw_compact);
+        assert_eq!(deserialize::<HeaderAndShortIds>(&raw_compact).unwrap(), compact);
+
+        // The segwit transaction of the block is found by wtxid
+        let keys = compact.siphash_keys();
+        let tx = block.txdata[1].clone();
+        let partial = compact.reconstruct(|id| {
+            if *id == ShortId::with_siphash_keys(&tx.wtxid()[..], keys) { Some(tx.clone()) } else { None }
+        }).unwrap();
+        assert!(partial.missing().is_empty());
+        assert_eq!(partial.into_block().unwrap(), block);
+    }
+
+    #[test]
+    fn differential_encoding() {
+        let vectors: Vec<(Vec<u8>, Vec<u16>)> = vec![
+            (vec![4, 0, 5, 1, 10], vec![0, 6, 8, 19]),
+            (vec![1, 0], vec![0]),
+            (vec![5, 0, 0, 0, 0, 0], vec![0, 1, 2, 3, 4]),
+            (vec![3, 1, 1, 1], vec![1, 3, 5]),
+            (vec![3, 0, 0, 253, 0, 1], vec![0, 1, 258]),
+        ];
+        for (encoded, indexes) in vectors {
+            let mut raw = vec![0u8; 32];
+            raw.extend(encoded);
+            let request = BlockTransactionsRequest { block_hash: Default::default(), indexes: indexes };
+            assert_eq!(deserialize::<BlockTransactionsRequest>(&raw).unwrap(), request);
+            assert_eq!(serialize(&request), raw);
+        }
+
+        for encoded in &[
+            vec![1, 253, 0, 0, 1],
+            vec![2, 253, 255, 255, 0],
+            vec![1, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ] {
+            let mut raw = vec![0u8; 32];
+            raw.extend(encoded);
+            assert!(deserialize::<BlockTransactionsRequest>(&raw).is_err());
+        }
+
+        let request = BlockTransactionsRequest { block_hash: Default::default(), indexes: vec![3, 3] };
+        assert!(request.consensus_encode(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn reconstruct() {
+        let block = dummy_block(10);
+        let compact = HeaderAndShortIds::from_block(&block, 7, 1, &[5]).unwrap();
+        let compact: HeaderAndShortIds = deserialize(&serialize(&compact)).unwrap();
+
+        // A mempool with some of the transactions, and others
+        let keys = compact.siphash_keys();
+        let mut mempool = HashMap::new();
+        for tx in block.txdata.iter().skip(2).take(6).chain(dummy_block(20).txdata[12..].iter()) {
+            mempool.insert(ShortId::with_siphash_keys(&tx.txid()[..], keys), tx.clone());
+        }
+
+        let mut partial = compact.reconstruct(|id| mempool.get(id).cloned()).unwrap();
+        assert_eq!(partial.header(), &block.header);
+        assert_eq!(partial.missing(), vec![1, 8, 9]);
+        assert_eq!(partial.clone().into_block(), Err(Error::IncompleteBlock));
+
+        let request: BlockTransactionsRequest = deserialize(&serialize(&partial.request())).unwrap();
+        assert_eq!(request, BlockTransactionsRequest { block_hash: block.block_hash(), indexes: vec![1, 8, 9] });
+        let txs = BlockTransactions::from_request(&request, &block).unwrap();
+        let txs: BlockTransactions = deserialize(&serialize(&txs)).unwrap();
+
+        let mut wrong = txs.clone();
+        wrong.transactions.pop();
+        assert_eq!(partial.fill(&wrong), Err(Error::UnexpectedTransactions));
+        partial.fill(&txs).unwrap();
+        assert!(partial.missing().is_empty());
+        assert_eq!(partial.into_block().unwrap(), block);
+
+        // A colliding mempool transaction is caught by the merkle root
+        let partial = compact.reconstruct(|_| Some(dummy_tx(0))).unwrap();
+        assert_eq!(partial.into_block(), Err(Error::MerkleRootMismatch));
+
+        // Malformed compact blocks
+        let mut invalid = compact.clone();
+        invalid.short_ids[1] = invalid.short_ids[0];
+        assert_eq!(invalid.reconstruct(|_| None), Err(Error::ShortIdCollision));
+        let mut invalid = compact.clone();
+        invalid.prefilled_txs[1].index = 10;
+        assert_eq!(invalid.reconstruct(|_| None), Err(Error::InvalidCompactBlock));
+
+        let request = BlockTransactionsRequest { block_hash: block.block_hash(), indexes: vec![10] };
+        assert_eq!(BlockTransactions::from_request(&request, &block), Err(Error::IndexOutOfRange(10)));
+    }
+}
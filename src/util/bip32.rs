@@ -25,6 +25,7 @@ use hash_types::XpubIdentifier;
 use hashes::{sha512, Hash, HashEngine, Hmac, HmacEngine};
 use secp256k1::{self, Secp256k1};
 
+use network::chainparams::ChainParams;
 use network::constants::Network;
 use util::{base58, endian};
 use util::key::{self, PublicKey, PrivateKey};
@@ -425,6 +426,8 @@ pub enum Error {
     CannotDeriveFromHardenedKey,
     /// A secp256k1 error occurred
     Ecdsa(secp256k1::Error),
+    /// A secp256k1 error occurred in a schnorr signature operation
+    Schnorr(secp256k1::Error),
     /// A child number was provided that was out of range
     InvalidChildNumber(u32),
     /// Error creating a master seed --- for application use
@@ -446,6 +449,7 @@ impl fmt::Display for Error {
         match *self {
             Error::CannotDeriveFromHardenedKey => f.write_str("cannot derive hardened key from public key"),
             Error::Ecdsa(ref e) => fmt::Display::fmt(e, f),
+            Error::Schnorr(ref e) => write!(f, "schnorr signature error: {}", e),
             Error::InvalidChildNumber(ref n) => write!(f, "child number {} is invalid (not within [0, 2^31 - 1])", n),
             Error::RngError(ref s) => write!(f, "rng error {}", s),
             Error::InvalidChildNumberFormat => f.write_str("invalid child number format"),
@@ -459,10 +463,9 @@ impl fmt::Display for Error {
 
 impl error::Error for Error {
     fn cause(&self) -> Option<&dyn error::Error> {
-       if let Error::Ecdsa(ref e) = *self {
-           Some(e)
-       } else {
-           None
+       match *self {
+           Error::Ecdsa(ref e) | Error::Schnorr(ref e) => Some(e),
+           _ => None,
        }
     }
 }
@@ -472,6 +475,10 @@ impl From<key::Error> for Error {
         match err {
             key::Error::Base58(e) => Error::Base58(e),
             key::Error::Secp256k1(e) => Error::Ecdsa(e),
+            key::Error::Schnorr(e) => Error::Schnorr(e),
+            // secp256k1 reports malformed schnorr signatures the same way
+            key::Error::InvalidSchnorrSigSize(_) |
+            key::Error::InvalidSchnorrSigHashType(_) => Error::Schnorr(secp256k1::Error::InvalidSignature),
         }
     }
 }
@@ -573,7 +580,25 @@ impl ExtendedPrivKey {
             ver.copy_from_slice(&data[0..4]);
             return Err(Error::UnknownVersion(ver));
         };
+        ExtendedPrivKey::decode_unversioned(data, network)
+    }
+
+    /// Decoding extended private key from binary data with the version bytes
+    /// of the chain `params`. The key gets `params.network` as its network.
+    pub fn decode_with_params(data: &[u8], params: &ChainParams) -> Result<ExtendedPrivKey, Error> {
+        if data.len() != 78 {
+            return Err(Error::WrongExtendedKeyLength(data.len()))
+        }
+        if data[0..4] != params.xpriv_prefix {
+            let mut ver = [0u8; 4];
+            ver.copy_from_slice(&data[0..4]);
+            return Err(Error::UnknownVersion(ver));
+        }
+        ExtendedPrivKey::decode_unversioned(data, params.network)
+    }
 
+    /// Decodes all but the version bytes of an extended private key
+    fn decode_unversioned(data: &[u8], network: Network) -> Result<ExtendedPrivKey, Error> {
         Ok(ExtendedPrivKey {
             network: network,
             depth: data[4],
@@ -606,6 +631,31 @@ impl ExtendedPrivKey {
         ret
     }
 
+    /// Extended private key binary encoding with the version bytes of the
+    /// chain `params`
+    pub fn encode_with_params(&self, params: &ChainParams) -> [u8; 78] {
+        let mut ret = self.encode();
+        ret[0..4].copy_from_slice(&params.xpriv_prefix);
+        ret
+    }
+
+    /// Base58 encoding with the version bytes of the chain `params`
+    pub fn to_string_with_params(&self, params: &ChainParams) -> String {
+        base58::check_encode_slice(&self.encode_with_params(params)[..])
+    }
+
+    /// Parses a base58 extended private key with the version bytes of the
+    /// chain `params`
+    pub fn from_str_with_params(inp: &str, params: &ChainParams) -> Result<ExtendedPrivKey, Error> {
+        let data = base58::from_check(inp)?;
+
+        if data.len() != 78 {
+            return Err(base58::Error::InvalidLength(data.len()).into());
+        }
+
+        ExtendedPrivKey::decode_with_params(&data[..], params)
+    }
+
     /// Returns the HASH160 of the public key belonging to the xpriv
     pub fn identifier<C: secp256k1::Signing>(&self, secp: &Secp256k1<C>) -> XpubIdentifier {
         ExtendedPubKey::from_private(secp, self).identifier()
@@ -695,16 +745,36 @@ impl ExtendedPubKey {
             return Err(Error::WrongExtendedKeyLength(data.len()))
         }
 
+        let network = if data[0..4] == [0x04u8, 0x88, 0xB2, 0x1E] {
+            Network::Bitcoin
+        } else if data[0..4] == [0x04u8, 0x35, 0x87, 0xCF] {
+            Network::Testnet
+        } else {
+            let mut ver = [0u8; 4];
+            ver.copy_from_slice(&data[0..4]);
+            return Err(Error::UnknownVersion(ver));
+        };
+        ExtendedPubKey::decode_unversioned(data, network)
+    }
+
+    /// Decoding extended public key from binary data with the version bytes
+    /// of the chain `params`. The key gets `params.network` as its network.
+    pub fn decode_with_params(data: &[u8], params: &ChainParams) -> Result<ExtendedPubKey, Error> {
+        if data.len() != 78 {
+            return Err(Error::WrongExtendedKeyLength(data.len()))
+        }
+        if data[0..4] != params.xpub_prefix {
+            let mut ver = [0u8; 4];
+            ver.copy_from_slice(&data[0..4]);
+            return Err(Error::UnknownVersion(ver));
+        }
+        ExtendedPubKey::decode_unversioned(data, params.network)
+    }
+
+    /// Decodes all but the version bytes of an extended public key
+    fn decode_unversioned(data: &[u8], network: Network) -> Result<ExtendedPubKey, Error> {
         Ok(ExtendedPubKey {
-            network: if data[0..4] == [0x04u8, 0x88, 0xB2, 0x1E] {
-                Network::Bitcoin
-            } else if data[0..4] == [0x04u8, 0x35, 0x87, 0xCF] {
-                Network::Testnet
-            } else {
-                let mut ver = [0u8; 4];
-                ver.copy_from_slice(&data[0..4]);
-                return Err(Error::UnknownVersion(ver));
-            },
+            network: network,
             depth: data[4],
             parent_fingerprint: Fingerprint::from(&data[5..9]),
             child_number: endian::slice_to_u32_be(&data[9..13]).into(),
@@ -728,6 +798,31 @@ impl ExtendedPubKey {
         ret
     }
 
+    /// Extended public key binary encoding with the version bytes of the
+    /// chain `params`
+    pub fn encode_with_params(&self, params: &ChainParams) -> [u8; 78] {
+        let mut ret = self.encode();
+        ret[0..4].copy_from_slice(&params.xpub_prefix);
+        ret
+    }
+
+    /// Base58 encoding with the version bytes of the chain `params`
+    pub fn to_string_with_params(&self, params: &ChainParams) -> String {
+        base58::check_encode_slice(&self.encode_with_params(params)[..])
+    }
+
+    /// Parses a base58 extended public key with the version bytes of the
+    /// chain `params`
+    pub fn from_str_with_params(inp: &str, params: &ChainParams) -> Result<ExtendedPubKey, Error> {
+        let data = base58::from_check(inp)?;
+
+        if data.len() != 78 {
+            return Err(base58::Error::InvalidLength(data.len()).into());
+        }
+
+        ExtendedPubKey::decode_with_params(&data[..], params)
+    }
+
     /// Returns the HASH160 of the chaincode
     pub fn identifier(&self) -> XpubIdentifier {
         let mut engine = XpubIdentifier::engine();
@@ -1060,6 +1155,22 @@ mod tests {
         serde_round_trip!(ChildNumber::from_hardened_idx((1 << 31) - 1).unwrap());
     }
 
+    #[test]
+    fn schnorr_errors() {
+        use util::key;
+
+        let incorrect = key::Error::Schnorr(secp256k1::Error::IncorrectSignature);
+        assert_eq!(Error::from(incorrect), Error::Schnorr(secp256k1::Error::IncorrectSignature));
+        assert_eq!(
+            Error::from(key::Error::InvalidSchnorrSigSize(63)),
+            Error::Schnorr(secp256k1::Error::InvalidSignature)
+        );
+        assert_eq!(
+            Error::from(key::Error::Secp256k1(secp256k1::Error::InvalidTweak)),
+            Error::Ecdsa(secp256k1::Error::InvalidTweak)
+        );
+    }
+
     #[test]
     #[cfg(feature = "serde")]
     pub fn encode_fingerprint_chaincode() {
@@ -0,0 +1,442 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP37 bloom filters
+//!
+//! Implementation of the connection bloom filters of
+//! [BIP37](https://github.com/bitcoin/bips/blob/master/bip-0037.mediawiki),
+//! which SPV clients load into their peers with a `filterload` message so
+//! that only the transactions they are interested in are relayed to them,
+//! in `merkleblock` messages for blocks, see
+//! [`MerkleBlock::from_block_with_filter`](::util::merkleblock::MerkleBlock::from_block_with_filter).
+//!
+//! # Examples
+//!
+//! ```rust
+//! use bitcoin::util::bloom::{BloomFilter, BloomFlags};
+//!
+//! let mut filter = BloomFilter::new(10, 0.0001, 0, BloomFlags::All);
+//! filter.insert(b"an element");
+//! assert!(filter.contains(b"an element"));
+//! ```
+//!
+
+use std::{cmp, io};
+
+use blockdata::opcodes;
+use blockdata::script::{Instruction, Script};
+use blockdata::transaction::{OutPoint, Transaction};
+use consensus::encode::{self, serialize, Decodable, Encodable};
+
+/// Maximum size of a filter in bytes
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+
+/// Maximum number of hash functions of a filter
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// Maximum size of the data of a `filteradd` message
+pub const MAX_FILTER_ADD_SIZE: usize = 520;
+
+/// Multiplier of the hash function index in the murmur3 seed, chosen to give
+/// different enough seeds
+const HASH_NUM_MULTIPLIER: u32 = 0xFBA4C795;
+
+/// How a filter is updated when an output matches, so that transactions
+/// spending it match too
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum BloomFlags {
+    /// The filter is never updated
+    None,
+    /// The outpoint of any matching output is added to the filter
+    All,
+    /// The outpoint of matching pay-to-pubkey and bare multisig outputs is
+    /// added to the filter
+    PubkeyOnly,
+}
+
+impl Encodable for BloomFlags {
+    fn consensus_encode<S: io::Write>(&self, s: S) -> Result<usize, io::Error> {
+        let flags: u8 = match *self {
+            BloomFlags::None => 0,
+            BloomFlags::All => 1,
+            BloomFlags::PubkeyOnly => 2,
+        };
+        flags.consensus_encode(s)
+    }
+}
+
+/// Bits of the flags byte which select the update mode
+const BLOOM_UPDATE_MASK: u8 = 3;
+
+impl Decodable for BloomFlags {
+    /// Like Bitcoin Core, unknown bits are ignored and the unassigned update
+    /// mode 3 never updates the filter
+    fn consensus_decode<D: io::Read>(d: D) -> Result<Self, encode::Error> {
+        match u8::consensus_decode(d)? & BLOOM_UPDATE_MASK {
+            1 => Ok(BloomFlags::All),
+            2 => Ok(BloomFlags::PubkeyOnly),
+            _ => Ok(BloomFlags::None),
+        }
+    }
+}
+
+/// A BIP37 bloom filter, as loaded by a `filterload` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BloomFilter {
+    /// The bit field
+    pub data: Vec<u8>,
+    /// Number of hash functions
+    pub hash_funcs: u32,
+    /// Random value added to the seeds of the hash functions
+    pub tweak: u32,
+    /// How the filter is updated by matching transactions
+    pub flags: BloomFlags,
+}
+
+impl_consensus_encoding!(BloomFilter, data, hash_funcs, tweak, flags);
+
+impl BloomFilter {
+    /// Creates an empty filter sized to hold `elements` elements with a
+    /// false positive rate of `fp_rate`, within the size limits
+    ///
+    /// # Panics
+    /// Panics if `elements` is 0 or `fp_rate` is not between 0 and 1
+    /// exclusive.
+    pub fn new(elements: u32, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        assert!(elements > 0, "a bloom filter needs elements");
+        assert!(fp_rate > 0.0 && fp_rate < 1.0, "false positive rate must be within (0, 1)");
+        let ln2 = ::std::f64::consts::LN_2;
+        let bits = -1.0 / (ln2 * ln2) * elements as f64 * fp_rate.ln();
+        let size = cmp::min(bits as usize, MAX_BLOOM_FILTER_SIZE * 8) / 8;
+        // Integer division first, as Bitcoin Core does
+        let hash_funcs = ((size * 8) / elements as usize) as f64 * ln2;
+        BloomFilter {
+            data: vec![0; size],
+            hash_funcs: cmp::min(hash_funcs as u32, MAX_HASH_FUNCS),
+            tweak: tweak,
+            flags: flags,
+        }
+    }
+
+    /// Whether the filter respects the limits of BIP37, which peers must
+    /// enforce on filters they receive
+    pub fn is_within_size_constraints(&self) -> bool {
+        self.data.len() <= MAX_BLOOM_FILTER_SIZE && self.hash_funcs <= MAX_HASH_FUNCS
+    }
+
+    fn bit_index(&self, hash_num: u32, data: &[u8]) -> usize {
+        let seed = hash_num.wrapping_mul(HASH_NUM_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3(seed, data) as usize % (self.data.len() * 8)
+    }
+
+    /// Adds an element to the filter, as a `filteradd` message does
+    pub fn insert(&mut self, element: &[u8]) {
+        // An empty filter matches everything
+        if self.data.is_empty() {
+            return;
+        }
+        for i in 0..self.hash_funcs {
+            let index = self.bit_index(i, element);
+            self.data[index >> 3] |= 1 << (index & 7);
+        }
+    }
+
+    /// Adds an outpoint to the filter
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&serialize(outpoint));
+    }
+
+    /// Checks whether the filter matches an element. False positives are
+    /// expected, at the rate the filter was created for.
+    pub fn contains(&self, element: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|i| {
+            let index = self.bit_index(i, element);
+            self.data[index >> 3] & (1 << (index & 7)) != 0
+        })
+    }
+
+    /// Checks whether the filter matches an outpoint
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize(outpoint))
+    }
+
+    /// Checks whether a transaction matches the filter: its txid, a data push
+    /// of one of its output scripts, an outpoint it spends or a data push of
+    /// one of its input scripts. Matching outputs are added to the filter as
+    /// its flags say.
+    pub fn is_relevant_and_update(&mut self, tx: &Transaction) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+        let txid = tx.txid();
+        let mut found = self.contains(&txid[..]);
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            if !self.contains_push(&output.script_pubkey) {
+                continue;
+            }
+            found = true;
+            let update = match self.flags {
+                BloomFlags::None => false,
+                BloomFlags::All => true,
+                BloomFlags::PubkeyOnly => output.script_pubkey.is_p2pk() || is_bare_multisig(&output.script_pubkey),
+            };
+            if update {
+                self.insert_outpoint(&OutPoint::new(txid, vout as u32));
+            }
+        }
+        if found {
+            return true;
+        }
+
+        tx.input.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output) || self.contains_push(&input.script_sig)
+        })
+    }
+
+    /// Checks whether any non-empty data push of `script` matches, up to the
+    /// first parse error
+    fn contains_push(&self, script: &Script) -> bool {
+        for instruction in script.instructions() {
+            match instruction {
+                Ok(Instruction::PushBytes(data)) if !data.is_empty() && self.contains(data) => return true,
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        false
+    }
+}
+
+/// Checks for an `m <pubkey>... n OP_CHECKMULTISIG` script
+fn is_bare_multisig(script: &Script) -> bool {
+    let small_int = |op: opcodes::All| {
+        let code = op.into_u8();
+        if code >= opcodes::all::OP_PUSHNUM_1.into_u8() && code <= opcodes::all::OP_PUSHNUM_16.into_u8() {
+            Some(code - opcodes::all::OP_PUSHNUM_1.into_u8() + 1)
+        } else {
+            None
+        }
+    };
+
+    let instructions: Vec<_> = match script.instructions().collect() {
+        Ok(instructions) => instructions,
+        Err(_) => return false,
+    };
+    if instructions.len() < 4 {
+        return false;
+    }
+    let required = match instructions[0] {
+        Instruction::Op(op) => small_int(op),
+        _ => None,
+    };
+    let keys = match instructions[instructions.len() - 2] {
+        Instruction::Op(op) => small_int(op),
+        _ => None,
+    };
+    let pubkeys = &instructions[1..instructions.len() - 2];
+    match (required, keys, &instructions[instructions.len() - 1]) {
+        (Some(m), Some(n), &Instruction::Op(opcodes::all::OP_CHECKMULTISIG)) => {
+            m <= n && n as usize == pubkeys.len() && pubkeys.iter().all(|key| match *key {
+                Instruction::PushBytes(key) => match key.len() {
+                    33 => key[0] == 0x02 || key[0] == 0x03,
+                    65 => key[0] == 0x04,
+                    _ => false,
+                },
+                _ => false,
+            })
+        }
+        _ => false,
+    }
+}
+
+/// The 32 bit x86 variant of MurmurHash3
+pub fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut h1 = seed;
+    let blocks = data.chunks(4);
+    let mut tail: &[u8] = &[];
+    for block in blocks {
+        if block.len() < 4 {
+            tail = block;
+            break;
+        }
+        let mut k1 = block[0] as u32 | (block[1] as u32) << 8 | (block[2] as u32) << 16 | (block[3] as u32) << 24;
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    if !tail.is_empty() {
+        let mut k1 = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k1 ^= (*byte as u32) << (8 * i);
+        }
+        h1 ^= k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85ebca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2ae35);
+    h1 ^= h1 >> 16;
+    h1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blockdata::script::Builder;
+    use blockdata::transaction::{TxIn, TxOut};
+    use consensus::encode::deserialize;
+    use hashes::hex::{FromHex, ToHex};
+
+    #[test]
+    fn murmur3_vectors() {
+        // From Bitcoin Core's hash_tests.cpp
+        let vectors: &[(u32, u32, &str)] = &[
+            (0x00000000, 0x00000000, ""),
+            (0x6a396f08, 0xFBA4C795, ""),
+            (0x81f16f39, 0xffffffff, ""),
+            (0x514e28b7, 0x00000000, "00"),
+            (0xea3f0b17, 0xFBA4C795, "00"),
+            (0xfd6cf10d, 0x00000000, "ff"),
+            (0x16c6b7ab, 0x00000000, "0011"),
+            (0x8eb51c3d, 0x00000000, "001122"),
+            (0xb4471bf8, 0x00000000, "00112233"),
+            (0xe2301fa8, 0x00000000, "0011223344"),
+            (0xfc2e4a15, 0x00000000, "001122334455"),
+            (0xb074502c, 0x00000000, "00112233445566"),
+            (0x8034d2a0, 0x00000000, "0011223344556677"),
+            (0xb4698def, 0x00000000, "001122334455667788"),
+        ];
+        for &(expected, seed, data) in vectors {
+            assert_eq!(murmur3(seed, &Vec::from_hex(data).unwrap()), expected, "data {}", data);
+        }
+    }
+
+    #[test]
+    fn create_insert_serialize() {
+        // From Bitcoin Core's bloom_tests.cpp
+        for &(tweak, expected) in &[(0, "03614e9b050000000000000001"), (2147483649, "03ce4299050000000100008001")] {
+            let mut filter = BloomFilter::new(3, 0.01, tweak, BloomFlags::All);
+            let elements = [
+                "99108ad8ed9bb6274d3980bab5a85c048f0950c8",
+                "b5a2c786d9ef4658287ced5914b37a1b4aa32eee",
+                "b9300670b4c5366e95b2699e8b18bc75e5f729c5",
+            ];
+            filter.insert(&Vec::from_hex(elements[0]).unwrap());
+            assert!(filter.contains(&Vec::from_hex(elements[0]).unwrap()));
+            assert!(!filter.contains(&Vec::from_hex("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap()));
+            filter.insert(&Vec::from_hex(elements[1]).unwrap());
+            filter.insert(&Vec::from_hex(elements[2]).unwrap());
+            for element in &elements {
+                assert!(filter.contains(&Vec::from_hex(element).unwrap()));
+            }
+
+            assert_eq!(serialize(&filter).to_hex(), expected);
+            assert_eq!(deserialize::<BloomFilter>(&serialize(&filter)).unwrap(), filter);
+            assert!(filter.is_within_size_constraints());
+        }
+
+        // Unknown flag bits are masked off
+        for &(flags, expected) in &[("03", BloomFlags::None), ("05", BloomFlags::All), ("fe", BloomFlags::PubkeyOnly)] {
+            let filter = deserialize::<BloomFilter>(&Vec::from_hex(&format!("03614e9b0500000000000000{}", flags)).unwrap()).unwrap();
+            assert_eq!(filter.flags, expected);
+        }
+
+        // Bitcoin Core's CBloomFilter(100, 0.1, ...) has 59 bytes and, as the
+        // number of bits per element is truncated before being scaled by
+        // ln(2), two hash functions rather than three
+        let filter = BloomFilter::new(100, 0.1, 0, BloomFlags::None);
+        assert_eq!(filter.data.len(), 59);
+        assert_eq!(filter.hash_funcs, 2);
+        let filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::All);
+        assert_eq!((filter.data.len(), filter.hash_funcs), (35, 19));
+        let big = BloomFilter::new(1_000_000, 0.000_001, 0, BloomFlags::None);
+        assert_eq!(big.data.len(), MAX_BLOOM_FILTER_SIZE);
+        assert!(big.hash_funcs <= MAX_HASH_FUNCS);
+    }
+
+    fn tx(input: OutPoint, script_sig: Script, outputs: Vec<Script>) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn { previous_output: input, script_sig: script_sig, sequence: 0xFFFFFFFF, witness: vec![] }],
+            output: outputs.into_iter().map(|s| TxOut { value: 1000, script_pubkey: s }).collect(),
+        }
+    }
+
+    #[test]
+    fn relevant_and_update() {
+        let pubkey = Vec::from_hex("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
+        let p2pk = Builder::new().push_slice(&pubkey).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+        let multisig = Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_slice(&pubkey)
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let hash = [0x42; 20];
+        let p2wpkh = Builder::new().push_int(0).push_slice(&hash).into_script();
+        assert!(is_bare_multisig(&multisig));
+        assert!(!is_bare_multisig(&p2pk));
+
+        let funding = tx(OutPoint::null(), Script::new(), vec![p2wpkh.clone(), p2pk.clone(), multisig.clone()]);
+        let spend = |vout| tx(OutPoint::new(funding.txid(), vout), Script::new(), vec![Script::new()]);
+        let unrelated = tx(OutPoint::new(Default::default(), 1), Builder::new().push_slice(&[1; 72]).into_script(), vec![]);
+
+        // The txid matches
+        let mut filter = BloomFilter::new(10, 0.000_001, 5, BloomFlags::None);
+        assert!(!filter.is_relevant_and_update(&funding));
+        filter.insert(&funding.txid()[..]);
+        assert!(filter.is_relevant_and_update(&funding));
+        assert!(!filter.is_relevant_and_update(&unrelated));
+
+        // Pushes of inputs match
+        let mut filter = BloomFilter::new(10, 0.000_001, 5, BloomFlags::None);
+        filter.insert(&[1; 72]);
+        assert!(filter.is_relevant_and_update(&unrelated));
+        assert!(!filter.is_relevant_and_update(&funding));
+
+        // A matching output makes spends match depending on the flags
+        for &(flags, updated) in &[
+            (BloomFlags::None, [false, false, false]),
+            (BloomFlags::All, [true, true, true]),
+            (BloomFlags::PubkeyOnly, [false, true, true]),
+        ] {
+            let mut filter = BloomFilter::new(10, 0.000_001, 5, flags);
+            filter.insert(&hash);
+            filter.insert(&pubkey);
+            assert!(filter.is_relevant_and_update(&funding));
+            for vout in 0..3 {
+                assert_eq!(filter.contains_outpoint(&OutPoint::new(funding.txid(), vout)), updated[vout as usize]);
+                assert_eq!(filter.is_relevant_and_update(&spend(vout)), updated[vout as usize]);
+            }
+        }
+
+        // An empty filter matches everything
+        let mut filter = BloomFilter { data: vec![], hash_funcs: 0, tweak: 0, flags: BloomFlags::None };
+        assert!(filter.is_relevant_and_update(&unrelated));
+        filter.insert(&[1]);
+        assert!(filter.contains(&[2]));
+    }
+}
@@ -0,0 +1,461 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! ChaCha20-Poly1305
+//!
+//! The ChaCha20 stream cipher, the Poly1305 authenticator and their
+//! combination as an AEAD, as specified by
+//! [RFC 8439](https://tools.ietf.org/html/rfc8439). They are the primitives
+//! of the BIP324 encrypted transport.
+//!
+
+use std::{error, fmt};
+
+use util::endian;
+
+/// Key length of ChaCha20 and of the AEAD
+pub const KEY_LEN: usize = 32;
+
+/// Nonce length of ChaCha20 and of the AEAD
+pub const NONCE_LEN: usize = 12;
+
+/// Length of Poly1305 tags
+pub const TAG_LEN: usize = 16;
+
+/// Length of a ChaCha20 block
+const BLOCK_LEN: usize = 64;
+
+/// An AEAD error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The ciphertext is shorter than a tag
+    TooShort,
+    /// The tag doesn't authenticate the ciphertext
+    InvalidTag,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::TooShort => f.write_str("ciphertext shorter than a tag"),
+            Error::InvalidTag => f.write_str("invalid authentication tag"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[inline]
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]); s[d] ^= s[a]; s[d] = s[d].rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]); s[b] ^= s[c]; s[b] = s[b].rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]); s[d] ^= s[a]; s[d] = s[d].rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]); s[b] ^= s[c]; s[b] = s[b].rotate_left(7);
+}
+
+/// The ChaCha20 block function
+fn chacha20_block(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], counter: u32) -> [u8; BLOCK_LEN] {
+    let mut state = [0u32; 16];
+    state[0] = 0x61707865;
+    state[1] = 0x3320646e;
+    state[2] = 0x79622d32;
+    state[3] = 0x6b206574;
+    for i in 0..8 {
+        state[4 + i] = endian::slice_to_u32_le(&key[4 * i..4 * i + 4]);
+    }
+    state[12] = counter;
+    for i in 0..3 {
+        state[13 + i] = endian::slice_to_u32_le(&nonce[4 * i..4 * i + 4]);
+    }
+
+    let mut working = state;
+    for _ in 0..10 {
+        quarter_round(&mut working, 0, 4, 8, 12);
+        quarter_round(&mut working, 1, 5, 9, 13);
+        quarter_round(&mut working, 2, 6, 10, 14);
+        quarter_round(&mut working, 3, 7, 11, 15);
+        quarter_round(&mut working, 0, 5, 10, 15);
+        quarter_round(&mut working, 1, 6, 11, 12);
+        quarter_round(&mut working, 2, 7, 8, 13);
+        quarter_round(&mut working, 3, 4, 9, 14);
+    }
+
+    let mut block = [0u8; BLOCK_LEN];
+    for i in 0..16 {
+        block[4 * i..4 * i + 4].copy_from_slice(&endian::u32_to_array_le(working[i].wrapping_add(state[i])));
+    }
+    block
+}
+
+/// The ChaCha20 stream cipher, keeping the unused keystream of the current
+/// block between calls
+#[derive(Clone)]
+pub struct ChaCha20 {
+    key: [u8; KEY_LEN],
+    nonce: [u8; NONCE_LEN],
+    counter: u32,
+    block: [u8; BLOCK_LEN],
+    used: usize,
+}
+
+impl ChaCha20 {
+    /// Starts the keystream of `key` and `nonce` at block `counter`
+    pub fn new(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], counter: u32) -> ChaCha20 {
+        ChaCha20 {
+            key: *key,
+            nonce: *nonce,
+            counter: counter,
+            block: [0; BLOCK_LEN],
+            used: BLOCK_LEN,
+        }
+    }
+
+    /// Writes the next bytes of keystream to `out`
+    pub fn keystream(&mut self, out: &mut [u8]) {
+        for byte in out.iter_mut() {
+            if self.used == BLOCK_LEN {
+                self.block = chacha20_block(&self.key, &self.nonce, self.counter);
+                self.counter = self.counter.wrapping_add(1);
+                self.used = 0;
+            }
+            *byte = self.block[self.used];
+            self.used += 1;
+        }
+    }
+
+    /// Encrypts or decrypts `data` in place
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            if self.used == BLOCK_LEN {
+                self.block = chacha20_block(&self.key, &self.nonce, self.counter);
+                self.counter = self.counter.wrapping_add(1);
+                self.used = 0;
+            }
+            *byte ^= self.block[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+impl fmt::Debug for ChaCha20 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ChaCha20(..)")
+    }
+}
+
+/// The Poly1305 one-time authenticator of `data` with `key`
+pub fn poly1305(key: &[u8; 32], data: &[u8]) -> [u8; TAG_LEN] {
+    let mut poly = Poly1305::new(key);
+    poly.input(data);
+    poly.result()
+}
+
+/// Incremental Poly1305, on 26 bit limbs
+struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+    buffer: [u8; 16],
+    buffered: usize,
+}
+
+impl Poly1305 {
+    fn new(key: &[u8; 32]) -> Poly1305 {
+        let le = |i: usize| endian::slice_to_u32_le(&key[i..i + 4]);
+        Poly1305 {
+            r: [
+                le(0) & 0x3ffffff,
+                (le(3) >> 2) & 0x3ffff03,
+                (le(6) >> 4) & 0x3ffc0ff,
+                (le(9) >> 6) & 0x3f03fff,
+                (le(12) >> 8) & 0x00fffff,
+            ],
+            h: [0; 5],
+            pad: [le(16), le(20), le(24), le(28)],
+            buffer: [0; 16],
+            buffered: 0,
+        }
+    }
+
+    /// Processes a block, with `hibit` the 2^128 bit of full blocks
+    fn block(&mut self, m: &[u8; 16], hibit: u32) {
+        let le = |i: usize| endian::slice_to_u32_le(&m[i..i + 4]);
+        let [r0, r1, r2, r3, r4] = self.r;
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+
+        let h0 = (self.h[0] + (le(0) & 0x3ffffff)) as u64;
+        let h1 = (self.h[1] + ((le(3) >> 2) & 0x3ffffff)) as u64;
+        let h2 = (self.h[2] + ((le(6) >> 4) & 0x3ffffff)) as u64;
+        let h3 = (self.h[3] + ((le(9) >> 6) & 0x3ffffff)) as u64;
+        let h4 = (self.h[4] + ((le(12) >> 8) | hibit)) as u64;
+        let (r0, r1, r2, r3, r4) = (r0 as u64, r1 as u64, r2 as u64, r3 as u64, r4 as u64);
+        let (s1, s2, s3, s4) = (s1 as u64, s2 as u64, s3 as u64, s4 as u64);
+
+        let d0 = h0 * r0 + h1 * s4 + h2 * s3 + h3 * s2 + h4 * s1;
+        let mut d1 = h0 * r1 + h1 * r0 + h2 * s4 + h3 * s3 + h4 * s2;
+        let mut d2 = h0 * r2 + h1 * r1 + h2 * r0 + h3 * s4 + h4 * s3;
+        let mut d3 = h0 * r3 + h1 * r2 + h2 * r1 + h3 * r0 + h4 * s4;
+        let mut d4 = h0 * r4 + h1 * r3 + h2 * r2 + h3 * r1 + h4 * r0;
+
+        let mut c = d0 >> 26;
+        let mut h0 = d0 as u32 & 0x3ffffff;
+        d1 += c;
+        c = d1 >> 26;
+        let mut h1 = d1 as u32 & 0x3ffffff;
+        d2 += c;
+        c = d2 >> 26;
+        let h2 = d2 as u32 & 0x3ffffff;
+        d3 += c;
+        c = d3 >> 26;
+        let h3 = d3 as u32 & 0x3ffffff;
+        d4 += c;
+        c = d4 >> 26;
+        let h4 = d4 as u32 & 0x3ffffff;
+        h0 += c as u32 * 5;
+        let c = h0 >> 26;
+        h0 &= 0x3ffffff;
+        h1 += c;
+
+        self.h = [h0, h1, h2, h3, h4];
+    }
+
+    fn input(&mut self, mut data: &[u8]) {
+        if self.buffered > 0 {
+            let take = ::std::cmp::min(16 - self.buffered, data.len());
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+            if self.buffered < 16 {
+                return;
+            }
+            let block = self.buffer;
+            self.block(&block, 1 << 24);
+            self.buffered = 0;
+        }
+        while data.len() >= 16 {
+            let mut block = [0; 16];
+            block.copy_from_slice(&data[..16]);
+            self.block(&block, 1 << 24);
+            data = &data[16..];
+        }
+        self.buffer[..data.len()].copy_from_slice(data);
+        self.buffered = data.len();
+    }
+
+    fn result(mut self) -> [u8; TAG_LEN] {
+        if self.buffered > 0 {
+            let mut block = [0; 16];
+            block[..self.buffered].copy_from_slice(&self.buffer[..self.buffered]);
+            block[self.buffered] = 1;
+            self.block(&block, 0);
+        }
+
+        // Fully carry h
+        let [mut h0, mut h1, mut h2, mut h3, mut h4] = self.h;
+        let mut c = h1 >> 26;
+        h1 &= 0x3ffffff;
+        h2 += c;
+        c = h2 >> 26;
+        h2 &= 0x3ffffff;
+        h3 += c;
+        c = h3 >> 26;
+        h3 &= 0x3ffffff;
+        h4 += c;
+        c = h4 >> 26;
+        h4 &= 0x3ffffff;
+        h0 += c * 5;
+        c = h0 >> 26;
+        h0 &= 0x3ffffff;
+        h1 += c;
+
+        // Compute h - p and select it if it doesn't underflow
+        let mut g0 = h0.wrapping_add(5);
+        c = g0 >> 26;
+        g0 &= 0x3ffffff;
+        let mut g1 = h1.wrapping_add(c);
+        c = g1 >> 26;
+        g1 &= 0x3ffffff;
+        let mut g2 = h2.wrapping_add(c);
+        c = g2 >> 26;
+        g2 &= 0x3ffffff;
+        let mut g3 = h3.wrapping_add(c);
+        c = g3 >> 26;
+        g3 &= 0x3ffffff;
+        let g4 = h4.wrapping_add(c).wrapping_sub(1 << 26);
+
+        let mask = (g4 >> 31).wrapping_sub(1);
+        let h0 = (h0 & !mask) | (g0 & mask);
+        let h1 = (h1 & !mask) | (g1 & mask);
+        let h2 = (h2 & !mask) | (g2 & mask);
+        let h3 = (h3 & !mask) | (g3 & mask);
+        let h4 = (h4 & !mask) | (g4 & mask);
+
+        // h = (h + pad) mod 2^128
+        let words = [
+            h0 | (h1 << 26),
+            (h1 >> 6) | (h2 << 20),
+            (h2 >> 12) | (h3 << 14),
+            (h3 >> 18) | (h4 << 8),
+        ];
+        let mut tag = [0u8; TAG_LEN];
+        let mut carry = 0u64;
+        for i in 0..4 {
+            let f = words[i] as u64 + self.pad[i] as u64 + carry;
+            tag[4 * i..4 * i + 4].copy_from_slice(&endian::u32_to_array_le(f as u32));
+            carry = f >> 32;
+        }
+        tag
+    }
+}
+
+/// The ChaCha20-Poly1305 AEAD
+#[derive(Clone)]
+pub struct ChaCha20Poly1305 {
+    key: [u8; KEY_LEN],
+}
+
+impl ChaCha20Poly1305 {
+    /// Creates the AEAD of `key`
+    pub fn new(key: &[u8; KEY_LEN]) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305 { key: *key }
+    }
+
+    /// The tag of `ciphertext` and `aad`
+    fn tag(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+        let mut poly_key = [0u8; 32];
+        ChaCha20::new(&self.key, nonce, 0).keystream(&mut poly_key);
+
+        let padding = [0u8; 16];
+        let mut poly = Poly1305::new(&poly_key);
+        poly.input(aad);
+        poly.input(&padding[..(16 - aad.len() % 16) % 16]);
+        poly.input(ciphertext);
+        poly.input(&padding[..(16 - ciphertext.len() % 16) % 16]);
+        poly.input(&endian::u64_to_array_le(aad.len() as u64));
+        poly.input(&endian::u64_to_array_le(ciphertext.len() as u64));
+        poly.result()
+    }
+
+    /// Encrypts `plaintext` and authenticates it along with `aad`, returning
+    /// the ciphertext followed by the tag
+    pub fn encrypt(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut ciphertext = plaintext.to_vec();
+        ChaCha20::new(&self.key, nonce, 1).apply_keystream(&mut ciphertext);
+        let tag = self.tag(nonce, aad, &ciphertext);
+        ciphertext.extend_from_slice(&tag);
+        ciphertext
+    }
+
+    /// Authenticates and decrypts a ciphertext followed by its tag
+    pub fn decrypt(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        if ciphertext.len() < TAG_LEN {
+            return Err(Error::TooShort);
+        }
+        let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - TAG_LEN);
+        let expected = self.tag(nonce, aad, ciphertext);
+        // Constant time comparison
+        if expected.iter().zip(tag.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) != 0 {
+            return Err(Error::InvalidTag);
+        }
+        let mut plaintext = ciphertext.to_vec();
+        ChaCha20::new(&self.key, nonce, 1).apply_keystream(&mut plaintext);
+        Ok(plaintext)
+    }
+
+    /// Writes the keystream of `nonce` starting at block `counter` to `out`
+    pub fn keystream(&self, nonce: &[u8; NONCE_LEN], counter: u32, out: &mut [u8]) {
+        ChaCha20::new(&self.key, nonce, counter).keystream(out)
+    }
+}
+
+impl fmt::Debug for ChaCha20Poly1305 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ChaCha20Poly1305(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hashes::hex::{FromHex, ToHex};
+
+    const SUNSCREEN: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+    #[test]
+    fn chacha20_vector() {
+        // RFC 8439 2.4.2
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut nonce = [0u8; 12];
+        nonce[7] = 0x4a;
+
+        let mut data = SUNSCREEN.to_vec();
+        ChaCha20::new(&key, &nonce, 1).apply_keystream(&mut data);
+        assert_eq!(data.to_hex(), "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d");
+
+        // The keystream continues across calls
+        let mut cipher = ChaCha20::new(&key, &nonce, 1);
+        let mut data = SUNSCREEN.to_vec();
+        for chunk in data.chunks_mut(3) {
+            cipher.apply_keystream(chunk);
+        }
+        cipher = ChaCha20::new(&key, &nonce, 1);
+        cipher.apply_keystream(&mut data);
+        assert_eq!(data, SUNSCREEN);
+    }
+
+    #[test]
+    fn poly1305_vectors() {
+        // RFC 8439 2.5.2
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&Vec::from_hex("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b").unwrap());
+        assert_eq!(poly1305(&key, b"Cryptographic Forum Research Group").to_hex(), "a8061dc1305136c6c22b8baf0c0127a9");
+
+        // Maximal limbs, with the final reduction and a partial block
+        assert_eq!(poly1305(&[0xff; 32], &[0xff; 70]).to_hex(), "bc02a831d2c6f80703a159214977f218");
+
+        // Incremental input
+        let mut poly = Poly1305::new(&key);
+        for chunk in b"Cryptographic Forum Research Group".chunks(5) {
+            poly.input(chunk);
+        }
+        assert_eq!(poly.result().to_hex(), "a8061dc1305136c6c22b8baf0c0127a9");
+    }
+
+    #[test]
+    fn aead_vector() {
+        // RFC 8439 2.8.2
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = 0x80 + i as u8;
+        }
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&Vec::from_hex("070000004041424344454647").unwrap());
+        let aad = Vec::from_hex("50515253c0c1c2c3c4c5c6c7").unwrap();
+
+        let aead = ChaCha20Poly1305::new(&key);
+        let ciphertext = aead.encrypt(&nonce, &aad, SUNSCREEN);
+        assert_eq!(ciphertext.to_hex(), "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b61161ae10b594f09e26a7e902ecbd0600691");
+        assert_eq!(aead.decrypt(&nonce, &aad, &ciphertext).unwrap(), SUNSCREEN);
+
+        let mut tampered = ciphertext.clone();
+        tampered[3] ^= 1;
+        assert_eq!(aead.decrypt(&nonce, &aad, &tampered), Err(Error::InvalidTag));
+        assert_eq!(aead.decrypt(&nonce, &[], &ciphertext), Err(Error::InvalidTag));
+        assert_eq!(aead.decrypt(&nonce, &aad, &ciphertext[..15]), Err(Error::TooShort));
+    }
+}
@@ -0,0 +1,1296 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Output Script Descriptors
+//!
+//! Parsing, checksumming and derivation of the output script descriptors
+//! defined in BIP380 to BIP386: `pk`, `pkh`, `wpkh`, `sh`, `wsh`, `multi`,
+//! `sortedmulti`, `tr` (with `pk`, `multi_a` and `sortedmulti_a` leaves),
+//! `addr` and `raw`. Only public keys are supported.
+//!
+
+use std::{error, fmt};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use secp256k1::{self, Secp256k1};
+
+use hashes::hex::{self, FromHex, ToHex};
+use blockdata::opcodes;
+use consensus::encode::VarInt;
+use blockdata::script::{Builder, Script};
+use network::constants::Network;
+use util::address::{self, Address};
+use util::bip32::{self, ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint, KeySource};
+use util::key::{PublicKey, XOnlyPublicKey};
+use util::taproot::{self, LeafVersion, TaprootBuilder, TaprootSpendInfo, TAPROOT_CONTROL_MAX_NODE_COUNT};
+
+/// Characters allowed in a descriptor, in the order used by the checksum
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+/// Characters of the checksum itself
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Maximum number of keys of a bare `multi()`
+const MAX_BARE_MULTI_KEYS: usize = 3;
+/// Maximum number of keys of a `multi()` inside `sh()`
+const MAX_P2SH_MULTI_KEYS: usize = 15;
+/// Maximum number of keys of a `multi()` inside `wsh()`
+const MAX_MULTI_KEYS: usize = 20;
+/// Maximum number of keys of a `multi_a()`
+const MAX_MULTI_A_KEYS: usize = 999;
+
+/// Maximum size of a DER encoded ECDSA signature with its sighash byte
+const MAX_ECDSA_SIG_SIZE: usize = 73;
+/// Maximum size of a schnorr signature with a non-default sighash byte
+const MAX_SCHNORR_SIG_SIZE: usize = 65;
+
+/// A descriptor error
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// A character outside of the descriptor character set was found
+    InvalidCharacter(char),
+    /// The checksum does not match the descriptor
+    InvalidChecksum {
+        /// Checksum computed from the descriptor
+        expected: String,
+        /// Checksum found after the `#`
+        found: String,
+    },
+    /// Unbalanced parentheses or braces, or otherwise malformed expression
+    Syntax(String),
+    /// Unknown script expression
+    UnknownFunction(String),
+    /// Script expression used where it is not allowed, e.g. `wpkh()` inside `wsh()`
+    InvalidContext(String),
+    /// Wrong number of arguments to a script expression
+    WrongArgumentCount(String),
+    /// Invalid key expression
+    InvalidKey(String),
+    /// Uncompressed key used inside a segwit or taproot descriptor
+    UncompressedKey,
+    /// X-only key used outside of a taproot descriptor
+    XOnlyKey,
+    /// Multisig threshold of zero, or above the number of keys
+    InvalidThreshold {
+        /// The threshold
+        threshold: usize,
+        /// The number of keys
+        keys: usize,
+    },
+    /// Too many keys for a multisig in this context
+    TooManyKeys(usize),
+    /// The descriptor has no address form, e.g. bare `multi()`
+    NoAddress,
+    /// Hex decoding error
+    Hex(hex::Error),
+    /// BIP32 error while parsing or deriving a key
+    Bip32(bip32::Error),
+    /// Invalid `addr()` address
+    Address(address::Error),
+    /// Invalid taproot tree
+    Taproot(taproot::Error),
+    /// The descriptor has no known satisfaction, e.g. `addr()` or `raw()`
+    Unsatisfiable,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidCharacter(c) => write!(f, "invalid descriptor character: {:?}", c),
+            Error::InvalidChecksum { ref expected, ref found } => write!(f, "invalid descriptor checksum {}, expected {}", found, expected),
+            Error::Syntax(ref s) => write!(f, "invalid descriptor syntax: {}", s),
+            Error::UnknownFunction(ref s) => write!(f, "unknown script expression: {}", s),
+            Error::InvalidContext(ref s) => write!(f, "{}() is not allowed here", s),
+            Error::WrongArgumentCount(ref s) => write!(f, "wrong number of arguments for {}()", s),
+            Error::InvalidKey(ref s) => write!(f, "invalid key expression: {}", s),
+            Error::UncompressedKey => f.write_str("uncompressed keys are not allowed in segwit or taproot descriptors"),
+            Error::XOnlyKey => f.write_str("x-only keys are only allowed in taproot descriptors"),
+            Error::InvalidThreshold { threshold, keys } => write!(f, "invalid multisig threshold {} of {} keys", threshold, keys),
+            Error::TooManyKeys(n) => write!(f, "too many multisig keys: {}", n),
+            Error::NoAddress => f.write_str("descriptor has no address form"),
+            Error::Hex(ref e) => write!(f, "hex error: {}", e),
+            Error::Bip32(ref e) => write!(f, "bip32 error: {}", e),
+            Error::Address(ref e) => write!(f, "address error: {}", e),
+            Error::Taproot(ref e) => write!(f, "taproot error: {}", e),
+            Error::Unsatisfiable => f.write_str("descriptor has no known satisfaction"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[doc(hidden)]
+impl From<hex::Error> for Error {
+    fn from(e: hex::Error) -> Error {
+        Error::Hex(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<bip32::Error> for Error {
+    fn from(e: bip32::Error) -> Error {
+        Error::Bip32(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<address::Error> for Error {
+    fn from(e: address::Error) -> Error {
+        Error::Address(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<taproot::Error> for Error {
+    fn from(e: taproot::Error) -> Error {
+        Error::Taproot(e)
+    }
+}
+
+/// Computes the 8-character checksum of a descriptor, as specified in BIP380
+pub fn checksum(desc: &str) -> Result<String, Error> {
+    fn poly_mod(mut c: u64, val: u64) -> u64 {
+        let c0 = c >> 35;
+        c = ((c & 0x7ffffffff) << 5) ^ val;
+        if c0 & 1 != 0 { c ^= 0xf5dee51989 };
+        if c0 & 2 != 0 { c ^= 0xa9fdca3312 };
+        if c0 & 4 != 0 { c ^= 0x1bab10e32d };
+        if c0 & 8 != 0 { c ^= 0x3706b1677a };
+        if c0 & 16 != 0 { c ^= 0x644d626ffd };
+        c
+    }
+
+    let mut c = 1;
+    let mut cls = 0;
+    let mut clscount = 0;
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET.find(ch).ok_or(Error::InvalidCharacter(ch))? as u64;
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = poly_mod(c, cls);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+
+    let mut ret = String::with_capacity(8);
+    for j in 0..8 {
+        ret.push(CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char);
+    }
+    Ok(ret)
+}
+
+/// A single public key in a descriptor, either a full key or a taproot x-only key
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SinglePubKey {
+    /// A compressed or uncompressed public key
+    FullKey(PublicKey),
+    /// An x-only public key, only valid inside `tr()`
+    XOnly(XOnlyPublicKey),
+}
+
+/// A key expression of a descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescriptorPublicKey {
+    /// A single public key
+    Single {
+        /// Origin of the key, if given
+        origin: Option<KeySource>,
+        /// The key
+        key: SinglePubKey,
+    },
+    /// An extended public key, with a derivation path and an optional
+    /// trailing unhardened `/*` wildcard
+    XPub {
+        /// Origin of the extended key, if given
+        origin: Option<KeySource>,
+        /// The extended key
+        xkey: ExtendedPubKey,
+        /// Derivation path from the extended key, excluding the wildcard
+        derivation_path: DerivationPath,
+        /// Whether the path ends with a `/*` wildcard
+        wildcard: bool,
+    },
+}
+
+impl DescriptorPublicKey {
+    /// The origin of the key, as given in the descriptor
+    pub fn origin(&self) -> Option<&KeySource> {
+        match *self {
+            DescriptorPublicKey::Single { ref origin, .. } => origin.as_ref(),
+            DescriptorPublicKey::XPub { ref origin, .. } => origin.as_ref(),
+        }
+    }
+
+    /// Whether the key ends with a wildcard and so depends on the derivation index
+    pub fn is_ranged(&self) -> bool {
+        match *self {
+            DescriptorPublicKey::Single { .. } => false,
+            DescriptorPublicKey::XPub { wildcard, .. } => wildcard,
+        }
+    }
+
+    /// The full derivation path of the key at `index` relative to its master
+    /// fingerprint, as used in PSBT `bip32_derivation` maps.
+    ///
+    /// Extended keys without origin are their own master. Single keys without
+    /// origin have no key source.
+    pub fn key_source(&self, index: u32) -> Option<KeySource> {
+        match *self {
+            DescriptorPublicKey::Single { ref origin, .. } => origin.clone(),
+            DescriptorPublicKey::XPub { ref origin, ref xkey, ref derivation_path, wildcard } => {
+                let (fingerprint, mut path): (Fingerprint, Vec<ChildNumber>) = match *origin {
+                    Some((fingerprint, ref path)) => (fingerprint, path.clone().into()),
+                    None => (xkey.fingerprint(), vec![]),
+                };
+                path.extend(derivation_path.as_ref());
+                if wildcard {
+                    path.push(ChildNumber::Normal { index: index });
+                }
+                Some((fingerprint, path.into()))
+            }
+        }
+    }
+
+    /// Derives the public key at `index`. Fails on x-only keys.
+    pub fn derive_public_key<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<PublicKey, Error> {
+        match *self {
+            DescriptorPublicKey::Single { key: SinglePubKey::FullKey(pk), .. } => Ok(pk),
+            DescriptorPublicKey::Single { key: SinglePubKey::XOnly(_), .. } => Err(Error::XOnlyKey),
+            DescriptorPublicKey::XPub { ref xkey, ref derivation_path, wildcard, .. } => {
+                let mut xkey = xkey.derive_pub(secp, derivation_path)?;
+                if wildcard {
+                    xkey = xkey.ckd_pub(secp, ChildNumber::Normal { index: index })?;
+                }
+                Ok(xkey.public_key)
+            }
+        }
+    }
+
+    /// Derives the x-only public key at `index`
+    pub fn derive_x_only_key<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<XOnlyPublicKey, Error> {
+        match *self {
+            DescriptorPublicKey::Single { key: SinglePubKey::XOnly(pk), .. } => Ok(pk),
+            _ => Ok(self.derive_public_key(secp, index)?.to_x_only_pubkey()),
+        }
+    }
+
+    fn from_str_ctx(s: &str, ctx: Context) -> Result<DescriptorPublicKey, Error> {
+        let (origin, rest) = if s.starts_with('[') {
+            let end = s.find(']').ok_or_else(|| Error::InvalidKey(s.to_owned()))?;
+            let mut parts = s[1..end].split('/');
+            let fingerprint = parts.next().unwrap_or("");
+            if fingerprint.len() != 8 {
+                return Err(Error::InvalidKey(s.to_owned()));
+            }
+            let fingerprint = Fingerprint::from_hex(fingerprint)?;
+            let path = parts.map(parse_child).collect::<Result<DerivationPath, Error>>()?;
+            (Some((fingerprint, path)), &s[end + 1..])
+        } else {
+            (None, s)
+        };
+
+        let mut parts = rest.split('/');
+        let key = parts.next().unwrap_or("");
+        let path: Vec<&str> = parts.collect();
+
+        if key.chars().all(|c| c.is_ascii_hexdigit()) && (key.len() == 66 || key.len() == 130 || key.len() == 64) {
+            if !path.is_empty() {
+                return Err(Error::InvalidKey(s.to_owned()));
+            }
+            let key = if key.len() == 64 {
+                if ctx != Context::Tap {
+                    return Err(Error::XOnlyKey);
+                }
+                SinglePubKey::XOnly(XOnlyPublicKey::from_str(key).map_err(|_| Error::InvalidKey(s.to_owned()))?)
+            } else {
+                let pk = PublicKey::from_str(key).map_err(|_| Error::InvalidKey(s.to_owned()))?;
+                if !pk.compressed && ctx.is_witness() {
+                    return Err(Error::UncompressedKey);
+                }
+                SinglePubKey::FullKey(pk)
+            };
+            return Ok(DescriptorPublicKey::Single { origin: origin, key: key });
+        }
+
+        let xkey = ExtendedPubKey::from_str(key).map_err(|_| Error::InvalidKey(s.to_owned()))?;
+        let (path, wildcard) = match path.last() {
+            Some(&"*") => (&path[..path.len() - 1], true),
+            Some(&"*'") | Some(&"*h") | Some(&"*H") => return Err(Error::Bip32(bip32::Error::CannotDeriveFromHardenedKey)),
+            _ => (&path[..], false),
+        };
+        let derivation_path = path.iter().cloned().map(parse_child).collect::<Result<DerivationPath, Error>>()?;
+        if derivation_path.into_iter().any(|c| c.is_hardened()) {
+            return Err(Error::Bip32(bip32::Error::CannotDeriveFromHardenedKey));
+        }
+        Ok(DescriptorPublicKey::XPub {
+            origin: origin,
+            xkey: xkey,
+            derivation_path: derivation_path,
+            wildcard: wildcard,
+        })
+    }
+}
+
+impl fmt::Display for DescriptorPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(&(fingerprint, ref path)) = self.origin() {
+            write!(f, "[{}", fingerprint)?;
+            for child in path {
+                write!(f, "/{}", child)?;
+            }
+            f.write_str("]")?;
+        }
+        match *self {
+            DescriptorPublicKey::Single { key: SinglePubKey::FullKey(ref pk), .. } => write!(f, "{}", pk),
+            DescriptorPublicKey::Single { key: SinglePubKey::XOnly(ref pk), .. } => write!(f, "{}", pk),
+            DescriptorPublicKey::XPub { ref xkey, ref derivation_path, wildcard, .. } => {
+                write!(f, "{}", xkey)?;
+                for child in derivation_path {
+                    write!(f, "/{}", child)?;
+                }
+                if wildcard {
+                    f.write_str("/*")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for DescriptorPublicKey {
+    type Err = Error;
+
+    /// Parses a key expression, allowing x-only and uncompressed keys
+    fn from_str(s: &str) -> Result<DescriptorPublicKey, Error> {
+        let key = DescriptorPublicKey::from_str_ctx(s, Context::Tap);
+        match key {
+            Err(Error::UncompressedKey) => DescriptorPublicKey::from_str_ctx(s, Context::Top),
+            key => key,
+        }
+    }
+}
+
+/// A `k`-of-`n` multisig, as in `multi()`, `sortedmulti()`, `multi_a()` and `sortedmulti_a()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Multisig {
+    /// Number of required signatures
+    pub threshold: usize,
+    /// The keys
+    pub keys: Vec<DescriptorPublicKey>,
+    /// Whether the derived keys are sorted in the script
+    pub sorted: bool,
+}
+
+impl Multisig {
+    fn from_args(name: &str, args: &[&str], ctx: Context) -> Result<Multisig, Error> {
+        if args.len() < 2 {
+            return Err(Error::WrongArgumentCount(name.to_owned()));
+        }
+        let threshold = usize::from_str(args[0]).map_err(|_| Error::Syntax(args[0].to_owned()))?;
+        let keys = args[1..].iter()
+            .map(|k| DescriptorPublicKey::from_str_ctx(k, ctx))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let max_keys = match ctx {
+            Context::Top => MAX_BARE_MULTI_KEYS,
+            Context::Sh => MAX_P2SH_MULTI_KEYS,
+            Context::Wsh => MAX_MULTI_KEYS,
+            Context::Tap => MAX_MULTI_A_KEYS,
+        };
+        if keys.len() > max_keys {
+            return Err(Error::TooManyKeys(keys.len()));
+        }
+        if threshold == 0 || threshold > keys.len() {
+            return Err(Error::InvalidThreshold { threshold: threshold, keys: keys.len() });
+        }
+        Ok(Multisig {
+            threshold: threshold,
+            keys: keys,
+            sorted: name.starts_with("sorted"),
+        })
+    }
+
+    fn fmt_with_name(&self, f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+        if self.sorted {
+            f.write_str("sorted")?;
+        }
+        write!(f, "{}({}", name, self.threshold)?;
+        for key in &self.keys {
+            write!(f, ",{}", key)?;
+        }
+        f.write_str(")")
+    }
+
+    fn script<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Script, Error> {
+        let mut keys = self.keys.iter()
+            .map(|k| k.derive_public_key(secp, index))
+            .collect::<Result<Vec<_>, Error>>()?;
+        if self.sorted {
+            keys.sort_by_key(|k| k.to_bytes());
+        }
+        let mut builder = Builder::new().push_int(self.threshold as i64);
+        for key in &keys {
+            builder = builder.push_key(key);
+        }
+        Ok(builder
+            .push_int(keys.len() as i64)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script())
+    }
+
+    /// Sizes of the stack items satisfying the `OP_CHECKMULTISIG` script,
+    /// including the dummy element
+    fn satisfaction_sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0];
+        sizes.extend((0..self.threshold).map(|_| MAX_ECDSA_SIG_SIZE));
+        sizes
+    }
+
+    fn tap_script<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Script, Error> {
+        let mut keys = self.keys.iter()
+            .map(|k| k.derive_x_only_key(secp, index))
+            .collect::<Result<Vec<_>, Error>>()?;
+        if self.sorted {
+            keys.sort_by_key(|k| k.serialize());
+        }
+        let mut builder = Builder::new();
+        for (i, key) in keys.iter().enumerate() {
+            builder = builder.push_x_only_key(key);
+            builder = if i == 0 {
+                builder.push_opcode(opcodes::all::OP_CHECKSIG)
+            } else {
+                builder.push_opcode(opcodes::all::OP_CHECKSIGADD)
+            };
+        }
+        Ok(builder
+            .push_int(self.threshold as i64)
+            .push_opcode(opcodes::all::OP_NUMEQUAL)
+            .into_script())
+    }
+}
+
+/// A tapscript leaf of a `tr()` descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapScript {
+    /// `pk(KEY)`: `<KEY> OP_CHECKSIG`
+    Pk(DescriptorPublicKey),
+    /// `multi_a()` or `sortedmulti_a()`: a multisig using `OP_CHECKSIGADD`
+    MultiA(Multisig),
+}
+
+impl TapScript {
+    /// Computes the leaf script at `index`
+    pub fn script<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Script, Error> {
+        match *self {
+            TapScript::Pk(ref key) => Ok(Builder::new()
+                .push_x_only_key(&key.derive_x_only_key(secp, index)?)
+                .push_opcode(opcodes::all::OP_CHECKSIG)
+                .into_script()),
+            TapScript::MultiA(ref multi) => multi.tap_script(secp, index),
+        }
+    }
+
+    /// Sizes of the stack items satisfying the leaf script, the
+    /// unused keys of a `multi_a()` getting an empty signature
+    fn satisfaction_sizes(&self) -> Vec<usize> {
+        match *self {
+            TapScript::Pk(_) => vec![MAX_SCHNORR_SIG_SIZE],
+            TapScript::MultiA(ref multi) => (0..multi.keys.len())
+                .map(|i| if i < multi.threshold { MAX_SCHNORR_SIG_SIZE } else { 0 })
+                .collect(),
+        }
+    }
+
+    /// The key expressions of the leaf, in order
+    pub fn keys(&self) -> Vec<&DescriptorPublicKey> {
+        match *self {
+            TapScript::Pk(ref key) => vec![key],
+            TapScript::MultiA(ref multi) => multi.keys.iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for TapScript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TapScript::Pk(ref key) => write!(f, "pk({})", key),
+            TapScript::MultiA(ref multi) => multi.fmt_with_name(f, "multi_a"),
+        }
+    }
+}
+
+/// The script tree of a `tr()` descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapTree {
+    /// A leaf script
+    Leaf(TapScript),
+    /// A `{left,right}` branch
+    Branch(Box<TapTree>, Box<TapTree>),
+}
+
+impl TapTree {
+    /// The leaf scripts, in depth-first order
+    pub fn leaves(&self) -> Vec<&TapScript> {
+        match *self {
+            TapTree::Leaf(ref leaf) => vec![leaf],
+            TapTree::Branch(ref left, ref right) => {
+                let mut leaves = left.leaves();
+                leaves.extend(right.leaves());
+                leaves
+            }
+        }
+    }
+
+    fn add_to_builder<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        depth: u8,
+        builder: TaprootBuilder,
+    ) -> Result<TaprootBuilder, Error> {
+        match *self {
+            TapTree::Leaf(ref leaf) => Ok(builder.add_leaf(depth, leaf.script(secp, index)?)?),
+            TapTree::Branch(ref left, ref right) => {
+                let builder = left.add_to_builder(secp, index, depth + 1, builder)?;
+                right.add_to_builder(secp, index, depth + 1, builder)
+            }
+        }
+    }
+
+    fn from_str_depth(s: &str, depth: usize) -> Result<TapTree, Error> {
+        if depth > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            return Err(Error::Taproot(taproot::Error::InvalidMerkleTreeDepth(depth)));
+        }
+        if s.starts_with('{') && s.ends_with('}') {
+            let args = split_args(&s[1..s.len() - 1])?;
+            if args.len() != 2 {
+                return Err(Error::Syntax(s.to_owned()));
+            }
+            return Ok(TapTree::Branch(
+                Box::new(TapTree::from_str_depth(args[0], depth + 1)?),
+                Box::new(TapTree::from_str_depth(args[1], depth + 1)?),
+            ));
+        }
+
+        let (name, args) = split_call(s)?;
+        match name {
+            "pk" => {
+                if args.len() != 1 {
+                    return Err(Error::WrongArgumentCount(name.to_owned()));
+                }
+                Ok(TapTree::Leaf(TapScript::Pk(DescriptorPublicKey::from_str_ctx(args[0], Context::Tap)?)))
+            }
+            "multi_a" | "sortedmulti_a" => {
+                Ok(TapTree::Leaf(TapScript::MultiA(Multisig::from_args(name, &args, Context::Tap)?)))
+            }
+            "pkh" | "wpkh" | "multi" | "sortedmulti" | "sh" | "wsh" | "tr" | "addr" | "raw" => {
+                Err(Error::InvalidContext(name.to_owned()))
+            }
+            _ => Err(Error::UnknownFunction(name.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for TapTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TapTree::Leaf(ref leaf) => write!(f, "{}", leaf),
+            TapTree::Branch(ref left, ref right) => write!(f, "{{{},{}}}", left, right),
+        }
+    }
+}
+
+/// An output script descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Descriptor {
+    /// `pk(KEY)`: a bare pay-to-pubkey output
+    Pk(DescriptorPublicKey),
+    /// `pkh(KEY)`: pay-to-pubkey-hash
+    Pkh(DescriptorPublicKey),
+    /// `wpkh(KEY)`: pay-to-witness-pubkey-hash
+    Wpkh(DescriptorPublicKey),
+    /// `multi()` or `sortedmulti()`: a bare `OP_CHECKMULTISIG`
+    Multi(Multisig),
+    /// `sh(SCRIPT)`: pay-to-script-hash
+    Sh(Box<Descriptor>),
+    /// `wsh(SCRIPT)`: pay-to-witness-script-hash
+    Wsh(Box<Descriptor>),
+    /// `tr(KEY)` or `tr(KEY,TREE)`: pay-to-taproot
+    Tr(DescriptorPublicKey, Option<TapTree>),
+    /// `addr(ADDR)`: the output script of an address
+    Addr(Address),
+    /// `raw(HEX)`: a raw output script
+    Raw(Script),
+}
+
+impl Descriptor {
+    /// Whether any of the keys ends with a wildcard, so that the scripts
+    /// depend on the derivation index
+    pub fn is_ranged(&self) -> bool {
+        self.keys().iter().any(|k| k.is_ranged())
+    }
+
+    /// All the key expressions of the descriptor, in order
+    pub fn keys(&self) -> Vec<&DescriptorPublicKey> {
+        match *self {
+            Descriptor::Pk(ref key) | Descriptor::Pkh(ref key) | Descriptor::Wpkh(ref key) => vec![key],
+            Descriptor::Multi(ref multi) => multi.keys.iter().collect(),
+            Descriptor::Sh(ref inner) | Descriptor::Wsh(ref inner) => inner.keys(),
+            Descriptor::Tr(ref key, ref tree) => {
+                let mut keys = vec![key];
+                if let Some(ref tree) = *tree {
+                    for leaf in tree.leaves() {
+                        keys.extend(leaf.keys());
+                    }
+                }
+                keys
+            }
+            Descriptor::Addr(_) | Descriptor::Raw(_) => vec![],
+        }
+    }
+
+    /// Computes the output script at `index`
+    pub fn script_pubkey<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Script, Error> {
+        match *self {
+            Descriptor::Pk(ref key) => Ok(Script::new_p2pk(&key.derive_public_key(secp, index)?)),
+            Descriptor::Pkh(ref key) => Ok(Script::new_p2pkh(&key.derive_public_key(secp, index)?.pubkey_hash())),
+            Descriptor::Wpkh(ref key) => {
+                let wpkh = key.derive_public_key(secp, index)?.wpubkey_hash().ok_or(Error::UncompressedKey)?;
+                Ok(Script::new_v0_wpkh(&wpkh))
+            }
+            Descriptor::Multi(ref multi) => multi.script(secp, index),
+            Descriptor::Sh(ref inner) => Ok(Script::new_p2sh(&inner.script_pubkey(secp, index)?.script_hash())),
+            Descriptor::Wsh(ref inner) => Ok(Script::new_v0_wsh(&inner.script_pubkey(secp, index)?.wscript_hash())),
+            Descriptor::Tr(..) => {
+                let output_key = self.taproot_spend_info(secp, index)?.expect("tr").output_key();
+                Ok(Script::new_witness_program(::bech32::u5::try_from_u8(1).expect("1<32"), &output_key.serialize()))
+            }
+            Descriptor::Addr(ref address) => Ok(address.script_pubkey()),
+            Descriptor::Raw(ref script) => Ok(script.clone()),
+        }
+    }
+
+    /// Computes the address at `index`, failing for descriptors without an
+    /// address form such as `pk()`, bare `multi()` and most `raw()` scripts
+    pub fn address<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        network: Network,
+    ) -> Result<Address, Error> {
+        match *self {
+            Descriptor::Pk(_) | Descriptor::Multi(_) => Err(Error::NoAddress),
+            _ => Address::from_script(&self.script_pubkey(secp, index)?, network).ok_or(Error::NoAddress),
+        }
+    }
+
+    /// The redeem script at `index` of an `sh()` descriptor
+    pub fn redeem_script<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Option<Script>, Error> {
+        match *self {
+            Descriptor::Sh(ref inner) => Ok(Some(inner.script_pubkey(secp, index)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// The witness script at `index` of a `wsh()` or `sh(wsh())` descriptor
+    pub fn witness_script<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Option<Script>, Error> {
+        match *self {
+            Descriptor::Wsh(ref inner) => Ok(Some(inner.script_pubkey(secp, index)?)),
+            Descriptor::Sh(ref inner) => inner.witness_script(secp, index),
+            _ => Ok(None),
+        }
+    }
+
+    /// The taproot spending data at `index` of a `tr()` descriptor
+    pub fn taproot_spend_info<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<Option<TaprootSpendInfo>, Error> {
+        match *self {
+            Descriptor::Tr(ref key, ref tree) => {
+                let internal_key = key.derive_x_only_key(secp, index)?;
+                let mut builder = TaprootBuilder::new();
+                if let Some(ref tree) = *tree {
+                    builder = tree.add_to_builder(secp, index, 0, builder)?;
+                }
+                Ok(Some(builder.finalize(secp, internal_key)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The derived public keys at `index` along with their key source, in
+    /// the shape of the PSBT `bip32_derivation` maps. Keys without origin
+    /// information and the x-only keys of `tr()` descriptors are skipped.
+    pub fn bip32_derivations<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<BTreeMap<PublicKey, KeySource>, Error> {
+        let mut ret = BTreeMap::new();
+        if let Descriptor::Tr(..) = *self {
+            return Ok(ret);
+        }
+        for key in self.keys() {
+            if let Some(source) = key.key_source(index) {
+                ret.insert(key.derive_public_key(secp, index)?, source);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Whether the outputs of the descriptor are spent with a witness
+    pub fn is_witness(&self) -> bool {
+        match *self {
+            Descriptor::Wpkh(_) | Descriptor::Wsh(_) | Descriptor::Tr(..) => true,
+            Descriptor::Sh(ref inner) => inner.is_witness(),
+            _ => false,
+        }
+    }
+
+    /// The maximum weight of the `scriptSig` (including its length prefix)
+    /// and witness needed to spend the output at `index`, assuming the
+    /// largest possible signatures. Adding it to the 160 weight units of
+    /// the outpoint and sequence gives the maximum weight of the input.
+    ///
+    /// Fails with [Error::Unsatisfiable] for `addr()` and `raw()`.
+    pub fn max_satisfaction_weight<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<usize, Error> {
+        let (script_sig, witness) = match *self {
+            Descriptor::Sh(ref inner) => match **inner {
+                Descriptor::Wpkh(_) | Descriptor::Wsh(_) => {
+                    let redeem_script = inner.script_pubkey(secp, index)?;
+                    (vec![redeem_script.len()], inner.witness_sizes(secp, index)?)
+                }
+                _ => {
+                    let mut script_sig = inner.stack_sizes(secp, index)?;
+                    script_sig.push(inner.script_pubkey(secp, index)?.len());
+                    (script_sig, vec![])
+                }
+            },
+            Descriptor::Wpkh(_) | Descriptor::Wsh(_) | Descriptor::Tr(..) => (vec![], self.witness_sizes(secp, index)?),
+            _ => (self.stack_sizes(secp, index)?, vec![]),
+        };
+
+        let script_sig_len = script_sig.iter().map(|&size| push_size(size)).sum::<usize>();
+        let mut weight = 4 * (VarInt(script_sig_len as u64).len() + script_sig_len);
+        if !witness.is_empty() {
+            weight += VarInt(witness.len() as u64).len();
+            weight += witness.iter().map(|&size| VarInt(size as u64).len() + size).sum::<usize>();
+        }
+        Ok(weight)
+    }
+
+    /// Sizes of the stack items satisfying a non-witness script
+    fn stack_sizes<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Vec<usize>, Error> {
+        match *self {
+            Descriptor::Pk(_) => Ok(vec![MAX_ECDSA_SIG_SIZE]),
+            Descriptor::Pkh(ref key) => {
+                let key_len = key.derive_public_key(secp, index)?.to_bytes().len();
+                Ok(vec![MAX_ECDSA_SIG_SIZE, key_len])
+            }
+            Descriptor::Multi(ref multi) => Ok(multi.satisfaction_sizes()),
+            _ => Err(Error::Unsatisfiable),
+        }
+    }
+
+    /// Sizes of the witness items satisfying a witness descriptor; the
+    /// largest of the key path and script path spends for `tr()`
+    fn witness_sizes<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>, index: u32) -> Result<Vec<usize>, Error> {
+        match *self {
+            Descriptor::Wpkh(_) => Ok(vec![MAX_ECDSA_SIG_SIZE, 33]),
+            Descriptor::Wsh(ref inner) => {
+                let mut sizes = inner.stack_sizes(secp, index)?;
+                sizes.push(inner.script_pubkey(secp, index)?.len());
+                Ok(sizes)
+            }
+            Descriptor::Tr(_, ref tree) => {
+                let witness_size = |sizes: &[usize]| sizes.iter().map(|&size| VarInt(size as u64).len() + size).sum::<usize>();
+                let mut best = vec![MAX_SCHNORR_SIG_SIZE];
+                if let Some(ref tree) = *tree {
+                    let spend_info = self.taproot_spend_info(secp, index)?.expect("tr");
+                    for leaf in tree.leaves() {
+                        let script = leaf.script(secp, index)?;
+                        let control_block = spend_info.control_block(&script, LeafVersion::tapscript())
+                            .expect("leaf of the tree");
+                        let mut sizes = leaf.satisfaction_sizes();
+                        sizes.push(script.len());
+                        sizes.push(control_block.size());
+                        if witness_size(&sizes) > witness_size(&best) {
+                            best = sizes;
+                        }
+                    }
+                }
+                Ok(best)
+            }
+            _ => Err(Error::Unsatisfiable),
+        }
+    }
+
+    fn from_str_ctx(s: &str, ctx: Context) -> Result<Descriptor, Error> {
+        let (name, args) = split_call(s)?;
+        let single_key = |witness_ctx: Context| {
+            if args.len() != 1 {
+                return Err(Error::WrongArgumentCount(name.to_owned()));
+            }
+            DescriptorPublicKey::from_str_ctx(args[0], witness_ctx)
+        };
+        match (name, ctx) {
+            ("pk", _) => Ok(Descriptor::Pk(single_key(ctx)?)),
+            ("pkh", _) => Ok(Descriptor::Pkh(single_key(ctx)?)),
+            ("wpkh", Context::Top) | ("wpkh", Context::Sh) => Ok(Descriptor::Wpkh(single_key(Context::Wsh)?)),
+            ("multi", _) | ("sortedmulti", _) => Ok(Descriptor::Multi(Multisig::from_args(name, &args, ctx)?)),
+            ("sh", Context::Top) => {
+                if args.len() != 1 {
+                    return Err(Error::WrongArgumentCount(name.to_owned()));
+                }
+                Ok(Descriptor::Sh(Box::new(Descriptor::from_str_ctx(args[0], Context::Sh)?)))
+            }
+            ("wsh", Context::Top) | ("wsh", Context::Sh) => {
+                if args.len() != 1 {
+                    return Err(Error::WrongArgumentCount(name.to_owned()));
+                }
+                Ok(Descriptor::Wsh(Box::new(Descriptor::from_str_ctx(args[0], Context::Wsh)?)))
+            }
+            ("tr", Context::Top) => {
+                let tree = match args.len() {
+                    1 => None,
+                    2 => Some(TapTree::from_str_depth(args[1], 0)?),
+                    _ => return Err(Error::WrongArgumentCount(name.to_owned())),
+                };
+                Ok(Descriptor::Tr(DescriptorPublicKey::from_str_ctx(args[0], Context::Tap)?, tree))
+            }
+            ("addr", Context::Top) => {
+                if args.len() != 1 {
+                    return Err(Error::WrongArgumentCount(name.to_owned()));
+                }
+                Ok(Descriptor::Addr(Address::from_str(args[0])?))
+            }
+            ("raw", Context::Top) => {
+                if args.len() != 1 {
+                    return Err(Error::WrongArgumentCount(name.to_owned()));
+                }
+                Ok(Descriptor::Raw(Script::from(Vec::<u8>::from_hex(args[0])?)))
+            }
+            ("wpkh", _) | ("sh", _) | ("wsh", _) | ("tr", _) | ("addr", _) | ("raw", _) |
+            ("multi_a", _) | ("sortedmulti_a", _) => Err(Error::InvalidContext(name.to_owned())),
+            _ => Err(Error::UnknownFunction(name.to_owned())),
+        }
+    }
+
+    fn fmt_no_checksum(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Descriptor::Pk(ref key) => write!(f, "pk({})", key),
+            Descriptor::Pkh(ref key) => write!(f, "pkh({})", key),
+            Descriptor::Wpkh(ref key) => write!(f, "wpkh({})", key),
+            Descriptor::Multi(ref multi) => multi.fmt_with_name(f, "multi"),
+            Descriptor::Sh(ref inner) => {
+                f.write_str("sh(")?;
+                inner.fmt_no_checksum(f)?;
+                f.write_str(")")
+            }
+            Descriptor::Wsh(ref inner) => {
+                f.write_str("wsh(")?;
+                inner.fmt_no_checksum(f)?;
+                f.write_str(")")
+            }
+            Descriptor::Tr(ref key, None) => write!(f, "tr({})", key),
+            Descriptor::Tr(ref key, Some(ref tree)) => write!(f, "tr({},{})", key, tree),
+            Descriptor::Addr(ref address) => write!(f, "addr({})", address),
+            Descriptor::Raw(ref script) => write!(f, "raw({})", script[..].to_hex()),
+        }
+    }
+}
+
+/// The size of the minimal push of `size` bytes of data
+fn push_size(size: usize) -> usize {
+    match size {
+        0 => 1,
+        _ if size < opcodes::all::OP_PUSHDATA1.into_u8() as usize => 1 + size,
+        _ if size <= 0xff => 2 + size,
+        _ if size <= 0xffff => 3 + size,
+        _ => 5 + size,
+    }
+}
+
+/// Displays the descriptor followed by its checksum, or without the checksum
+/// with the alternate `{:#}` flag
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct NoChecksum<'a>(&'a Descriptor);
+        impl<'a> fmt::Display for NoChecksum<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt_no_checksum(f)
+            }
+        }
+
+        let desc = NoChecksum(self).to_string();
+        if f.alternate() {
+            return f.write_str(&desc);
+        }
+        let checksum = checksum(&desc).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", desc, checksum)
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = Error;
+
+    /// Parses a descriptor, verifying its checksum if present
+    fn from_str(s: &str) -> Result<Descriptor, Error> {
+        let desc = match s.find('#') {
+            Some(pos) => {
+                let (desc, found) = (&s[..pos], &s[pos + 1..]);
+                let expected = checksum(desc)?;
+                if found != expected {
+                    return Err(Error::InvalidChecksum { expected: expected, found: found.to_owned() });
+                }
+                desc
+            }
+            None => {
+                checksum(s)?;
+                s
+            }
+        };
+        Descriptor::from_str_ctx(desc, Context::Top)
+    }
+}
+
+serde_string_impl!(Descriptor, "an output script descriptor");
+
+/// Position of a script or key expression in a descriptor
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Context {
+    Top,
+    Sh,
+    Wsh,
+    Tap,
+}
+
+impl Context {
+    fn is_witness(self) -> bool {
+        self == Context::Wsh || self == Context::Tap
+    }
+}
+
+/// Parses a derivation step, accepting `'`, `h` and `H` as hardened markers
+fn parse_child(s: &str) -> Result<ChildNumber, Error> {
+    if s.ends_with('H') {
+        Ok(ChildNumber::from_str(&format!("{}h", &s[..s.len() - 1]))?)
+    } else {
+        Ok(ChildNumber::from_str(s)?)
+    }
+}
+
+/// Splits `name(args)` into its name and top-level arguments
+fn split_call(s: &str) -> Result<(&str, Vec<&str>), Error> {
+    let open = s.find('(').ok_or_else(|| Error::Syntax(s.to_owned()))?;
+    if !s.ends_with(')') {
+        return Err(Error::Syntax(s.to_owned()));
+    }
+    Ok((&s[..open], split_args(&s[open + 1..s.len() - 1])?))
+}
+
+/// Splits a comma-separated argument list, ignoring commas nested in
+/// parentheses, brackets or braces
+fn split_args(s: &str) -> Result<Vec<&str>, Error> {
+    let mut args = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                if depth == 0 {
+                    return Err(Error::Syntax(s.to_owned()));
+                }
+                depth -= 1;
+            }
+            ',' if depth == 0 => {
+                args.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(Error::Syntax(s.to_owned()));
+    }
+    args.push(&s[start..]);
+    if args.iter().any(|a| a.is_empty()) {
+        return Err(Error::Syntax(s.to_owned()));
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashes::hex::ToHex;
+
+    fn spk(desc: &str, index: u32) -> String {
+        let secp = Secp256k1::verification_only();
+        Descriptor::from_str(desc).unwrap().script_pubkey(&secp, index).unwrap()[..].to_hex()
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert!(Descriptor::from_str("raw(deadbeef)#89f8spxm").is_ok());
+        assert!(Descriptor::from_str("raw(deadbeef)").is_ok());
+        for bad in &["raw(deadbeef)#", "raw(deadbeef)#89f8spxmx", "raw(deadbeef)#89f8spx",
+                     "raw(dedbeef)#89f8spxm", "raw(deadbeef)##9f8spxm"] {
+            match Descriptor::from_str(bad) {
+                Err(Error::InvalidChecksum { .. }) | Err(Error::InvalidCharacter(_)) => {},
+                res => panic!("{} parsed: {:?}", bad, res),
+            }
+        }
+        assert_eq!(Descriptor::from_str("raw(Ü)#00000000"), Err(Error::InvalidCharacter('Ü')));
+
+        let desc = "sh(multi(2,[00000000/111'/222]xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB,xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH/0))";
+        let parsed = Descriptor::from_str(desc).unwrap();
+        assert_eq!(parsed.to_string(), format!("{}#{}", desc, checksum(desc).unwrap()));
+        assert_eq!(format!("{:#}", parsed), desc);
+    }
+
+    #[test]
+    fn test_single_key_scripts() {
+        assert_eq!(
+            spk("pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)", 0),
+            "210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac"
+        );
+        assert_eq!(
+            spk("pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)", 0),
+            "76a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac"
+        );
+        assert_eq!(
+            spk("wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)", 0),
+            "00147dd65592d0ab2fe0d0257d571abf032cd9db93dc"
+        );
+        assert_eq!(
+            spk("sh(wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556))", 0),
+            "a914cc6ffbc0bf31af759451068f90ba7a0272b6b33287"
+        );
+        assert_eq!(
+            spk("raw(deadbeef)", 0),
+            "deadbeef"
+        );
+        assert_eq!(
+            spk("addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)", 0),
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+    }
+
+    #[test]
+    fn test_multisig() {
+        let k1 = "022f8bde4d1a07209355b4a7250a5c5128e88b84bddc619ab7cba8d569b240efe4";
+        let k2 = "025cbdf0646e5db4eaa398f365f2ea7a0e3d419b7e0330e39ce92bddedcac4f9bc";
+        let script = format!("5121{}21{}52ae", k1, k2);
+        assert_eq!(spk(&format!("multi(1,{},{})", k1, k2), 0), script);
+        assert_eq!(spk(&format!("sortedmulti(1,{},{})", k2, k1), 0), script);
+        assert_eq!(spk(&format!("multi(1,{},{})", k2, k1), 0), format!("5121{}21{}52ae", k2, k1));
+
+        let secp = Secp256k1::verification_only();
+        let script = Script::from(Vec::from_hex(&script).unwrap());
+        let desc = Descriptor::from_str(&format!("sh(wsh(sortedmulti(1,{},{})))", k2, k1)).unwrap();
+        assert_eq!(desc.witness_script(&secp, 0).unwrap(), Some(script.clone()));
+        assert_eq!(desc.redeem_script(&secp, 0).unwrap(), Some(Script::new_v0_wsh(&script.wscript_hash())));
+        assert_eq!(desc.script_pubkey(&secp, 0).unwrap(), Script::new_p2sh(&Script::new_v0_wsh(&script.wscript_hash()).script_hash()));
+        assert_eq!(
+            desc.address(&secp, 0, Network::Bitcoin).unwrap(),
+            Address::p2shwsh(&script, Network::Bitcoin)
+        );
+        assert_eq!(Descriptor::from_str(&format!("multi(1,{},{})", k1, k2)).unwrap().address(&secp, 0, Network::Bitcoin), Err(Error::NoAddress));
+
+        assert_eq!(
+            Descriptor::from_str(&format!("multi(3,{},{})", k1, k2)),
+            Err(Error::InvalidThreshold { threshold: 3, keys: 2 })
+        );
+        assert_eq!(
+            Descriptor::from_str(&format!("multi(0,{},{})", k1, k2)),
+            Err(Error::InvalidThreshold { threshold: 0, keys: 2 })
+        );
+        assert_eq!(
+            Descriptor::from_str(&format!("multi(1,{},{},{},{})", k1, k2, k1, k2)),
+            Err(Error::TooManyKeys(4))
+        );
+        assert!(Descriptor::from_str(&format!("sh(multi(1,{},{},{},{}))", k1, k2, k1, k2)).is_ok());
+    }
+
+    #[test]
+    fn test_contexts() {
+        let key = "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af";
+        let uncompressed = "042e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af191923a2964c177f5b5923ae500fca49e99492d534aa3759d6b25a8bc971b133";
+        assert!(Descriptor::from_str(&format!("pkh({})", uncompressed)).is_ok());
+        assert!(Descriptor::from_str(&format!("sh(pk({}))", uncompressed)).is_ok());
+        assert_eq!(Descriptor::from_str(&format!("wpkh({})", uncompressed)), Err(Error::UncompressedKey));
+        assert_eq!(Descriptor::from_str(&format!("wsh(pk({}))", uncompressed)), Err(Error::UncompressedKey));
+        assert_eq!(Descriptor::from_str(&format!("tr({})", uncompressed)), Err(Error::UncompressedKey));
+
+        assert_eq!(Descriptor::from_str(&format!("wsh(wpkh({}))", key)), Err(Error::InvalidContext("wpkh".to_owned())));
+        assert_eq!(Descriptor::from_str(&format!("sh(sh(pk({})))", key)), Err(Error::InvalidContext("sh".to_owned())));
+        assert_eq!(Descriptor::from_str(&format!("wsh(wsh(pk({})))", key)), Err(Error::InvalidContext("wsh".to_owned())));
+        assert_eq!(Descriptor::from_str(&format!("sh(tr({}))", key)), Err(Error::InvalidContext("tr".to_owned())));
+        assert_eq!(Descriptor::from_str(&format!("tr({},wpkh({}))", key, key)), Err(Error::InvalidContext("wpkh".to_owned())));
+        assert_eq!(Descriptor::from_str("sh(raw(deadbeef))"), Err(Error::InvalidContext("raw".to_owned())));
+        assert_eq!(Descriptor::from_str(&format!("pk({})", &key[2..])), Err(Error::XOnlyKey));
+        assert_eq!(Descriptor::from_str(&format!("foo({})", key)), Err(Error::UnknownFunction("foo".to_owned())));
+        assert_eq!(Descriptor::from_str(&format!("pkh({},{})", key, key)), Err(Error::WrongArgumentCount("pkh".to_owned())));
+        assert!(Descriptor::from_str(&format!("pkh({}", key)).is_err());
+        assert!(Descriptor::from_str(&format!("pkh({}))", key)).is_err());
+        assert!(Descriptor::from_str(&format!("sh(pkh({})", key)).is_err());
+    }
+
+    #[test]
+    fn test_xpub_derivation() {
+        let secp = Secp256k1::verification_only();
+        let xpub = ExtendedPubKey::from_str("xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL").unwrap();
+        let origin = "[d34db33f/84'/0'/0']";
+        let desc = Descriptor::from_str(&format!("wpkh({}{}/1/*)", origin, xpub)).unwrap();
+        assert!(desc.is_ranged());
+
+        for &index in &[0, 1, 7, (1 << 31) - 1] {
+            let path = DerivationPath::from_str(&format!("m/1/{}", index)).unwrap();
+            let pk = xpub.derive_pub(&secp, &path).unwrap().public_key;
+            assert_eq!(desc.script_pubkey(&secp, index).unwrap(), Script::new_v0_wpkh(&pk.wpubkey_hash().unwrap()));
+            assert_eq!(desc.address(&secp, index, Network::Bitcoin).unwrap(), Address::p2wpkh(&pk, Network::Bitcoin).unwrap());
+
+            let derivations = desc.bip32_derivations(&secp, index).unwrap();
+            assert_eq!(derivations.len(), 1);
+            let (fingerprint, path) = derivations[&pk].clone();
+            assert_eq!(fingerprint, Fingerprint::from_hex("d34db33f").unwrap());
+            assert_eq!(path, DerivationPath::from_str(&format!("m/84'/0'/0'/1/{}", index)).unwrap());
+        }
+
+        // Without origin the xpub is its own master
+        let desc = Descriptor::from_str(&format!("pkh({}/0/*)", xpub)).unwrap();
+        let pk = xpub.derive_pub(&secp, &DerivationPath::from_str("m/0/5").unwrap()).unwrap().public_key;
+        let (fingerprint, path) = desc.bip32_derivations(&secp, 5).unwrap()[&pk].clone();
+        assert_eq!(fingerprint, xpub.fingerprint());
+        assert_eq!(path, DerivationPath::from_str("m/0/5").unwrap());
+
+        // 'h' and 'H' are accepted for hardened steps
+        let key = DescriptorPublicKey::from_str(&format!("[d34db33f/44h/0H/0']{}/0", xpub)).unwrap();
+        assert!(!key.is_ranged());
+        assert_eq!(key.to_string(), format!("[d34db33f/44'/0'/0']{}/0", xpub));
+        assert_eq!(key.key_source(9).unwrap().1, DerivationPath::from_str("m/44'/0'/0'/0").unwrap());
+
+        assert_eq!(
+            Descriptor::from_str(&format!("pkh({}/0'/*)", xpub)),
+            Err(Error::Bip32(bip32::Error::CannotDeriveFromHardenedKey))
+        );
+        assert_eq!(
+            Descriptor::from_str(&format!("pkh({}/0/*')", xpub)),
+            Err(Error::Bip32(bip32::Error::CannotDeriveFromHardenedKey))
+        );
+        assert!(Descriptor::from_str(&format!("pkh([d34db3/0]{})", xpub)).is_err());
+    }
+
+    #[test]
+    fn test_taproot() {
+        // BIP341 scriptPubKey vectors
+        let secp = Secp256k1::verification_only();
+        let desc = Descriptor::from_str("tr(d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d)").unwrap();
+        assert_eq!(
+            desc.script_pubkey(&secp, 0).unwrap()[..].to_hex(),
+            "512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"
+        );
+        let desc = Descriptor::from_str("tr(187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27,pk(d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8))").unwrap();
+        assert_eq!(
+            desc.script_pubkey(&secp, 0).unwrap()[..].to_hex(),
+            "5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3"
+        );
+        assert_eq!(
+            desc.address(&secp, 0, Network::Bitcoin).unwrap().to_string(),
+            "bc1pz37fc4cn9ah8anwm4xqqhvxygjf9rjf2resrw8h8w4tmvcs0863sa2e586"
+        );
+        assert!(desc.bip32_derivations(&secp, 0).unwrap().is_empty());
+
+        let k1 = "02d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8";
+        let k2 = "03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd";
+        let desc = Descriptor::from_str(&format!("tr({},{{pk({}),sortedmulti_a(1,{},{})}})", k1, k2, k2, &k1[2..])).unwrap();
+        assert_eq!(desc.keys().len(), 4);
+        let info = desc.taproot_spend_info(&secp, 0).unwrap().unwrap();
+        let multi_a = Script::from(Vec::from_hex(&format!("20{}ac20{}ba519c", &k2[2..], &k1[2..])).unwrap());
+        let pk = Script::from(Vec::from_hex(&format!("20{}ac", &k2[2..])).unwrap());
+        assert_eq!(info.script_map().len(), 2);
+        assert!(info.control_block(&multi_a, Default::default()).is_some());
+        assert!(info.control_block(&pk, Default::default()).is_some());
+        assert_eq!(format!("{:#}", desc), format!("tr({},{{pk({}),sortedmulti_a(1,{},{})}})", k1, k2, k2, &k1[2..]));
+
+        let k3 = "2e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af";
+        let nested = format!("tr({},{{pk({}),{{pk({}),pk({})}}}})", k1, k2, k1, k3);
+        assert_eq!(Descriptor::from_str(&nested).unwrap().taproot_spend_info(&secp, 0).unwrap().unwrap().script_map().len(), 3);
+        assert!(Descriptor::from_str(&format!("tr({},{{pk({})}})", k1, k2)).is_err());
+    }
+
+    #[test]
+    fn test_max_satisfaction_weight() {
+        let secp = Secp256k1::verification_only();
+        let weight = |desc: &str| Descriptor::from_str(desc).unwrap().max_satisfaction_weight(&secp, 0);
+        let k1 = "02d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8";
+        let k2 = "03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd";
+
+        assert_eq!(weight(&format!("wpkh({})", k1)), Ok(113));
+        assert_eq!(weight(&format!("pkh({})", k1)), Ok(436));
+        assert_eq!(weight(&format!("sh(wpkh({}))", k1)), Ok(205));
+        assert_eq!(weight(&format!("wsh(multi(1,{},{}))", k1, k2)), Ok(152));
+        assert_eq!(weight(&format!("sh(multi(1,{},{}))", k1, k2)), Ok(4 * (1 + 1 + 74 + 72)));
+        assert_eq!(weight(&format!("tr({})", &k1[2..])), Ok(71));
+        assert_eq!(weight(&format!("tr({},{{pk({}),sortedmulti_a(1,{},{})}})", k1, k2, k2, &k1[2..])), Ok(209));
+        assert_eq!(weight("raw(6a)"), Err(Error::Unsatisfiable));
+        assert_eq!(weight("addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)"), Err(Error::Unsatisfiable));
+
+        assert!(Descriptor::from_str(&format!("sh(wsh(pk({})))", k1)).unwrap().is_witness());
+        assert!(!Descriptor::from_str(&format!("sh(pk({}))", k1)).unwrap().is_witness());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let descs = [
+            "pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)",
+            "sh(wpkh([d34db33f/49'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*))",
+            "wsh(multi(1,xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB/1/0/*,xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH/0/0/*))",
+            "tr(a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd,{pk(669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0),multi_a(1,02d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8,669b8afcec803a0d323e9a17f3ea8e68e8abe5a278020a929adbec52421adbd0)})",
+            "addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)",
+            "raw(6a)",
+        ];
+        for desc in &descs {
+            let parsed = Descriptor::from_str(desc).unwrap();
+            assert_eq!(format!("{:#}", parsed), *desc);
+            assert_eq!(Descriptor::from_str(&parsed.to_string()).unwrap(), parsed);
+        }
+    }
+}
@@ -0,0 +1,484 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! ElligatorSwift
+//!
+//! The encoding of secp256k1 public keys as 64 bytes indistinguishable from
+//! random used by the BIP324 key exchange: a pair of field elements `(u, t)`
+//! which the SwiftEC map sends to the X coordinate of the key. Only X
+//! coordinates are encoded, and the key exchange is an X-only ECDH.
+//!
+
+use std::{fmt, ops};
+
+use hashes::{sha512, Hash, HashEngine};
+use secp256k1::{PublicKey, Secp256k1, SecretKey, Signing, Verification};
+
+/// An element of the field of secp256k1 coordinates, on little endian 64
+/// bit limbs, always fully reduced
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct FieldElement([u64; 4]);
+
+/// The field size
+const P: [u64; 4] = [0xFFFFFFFEFFFFFC2F, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF];
+
+/// 2^256 modulo the field size
+const R: u64 = 0x1000003D1;
+
+/// The field size minus 2, exponent of inversions
+const P_MINUS_2: [u64; 4] = [0xFFFFFFFEFFFFFC2D, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF];
+
+/// (p + 1) / 4, exponent of square roots
+const P_PLUS_1_DIV_4: [u64; 4] = [0xFFFFFFFFBFFFFF0C, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0x3FFFFFFFFFFFFFFF];
+
+impl FieldElement {
+    const ZERO: FieldElement = FieldElement([0; 4]);
+    const ONE: FieldElement = FieldElement([1, 0, 0, 0]);
+
+    fn from_u64(value: u64) -> FieldElement {
+        FieldElement([value, 0, 0, 0])
+    }
+
+    /// Reads a big endian number, modulo the field size
+    fn from_bytes(bytes: &[u8]) -> FieldElement {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            for byte in &bytes[24 - 8 * i..32 - 8 * i] {
+                *limb = (*limb << 8) | *byte as u64;
+            }
+        }
+        FieldElement(reduce_once(limbs, false))
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for i in 0..4 {
+            for j in 0..8 {
+                bytes[31 - 8 * i - j] = (self.0[i] >> (8 * j)) as u8;
+            }
+        }
+        bytes
+    }
+
+    fn is_zero(self) -> bool {
+        self == FieldElement::ZERO
+    }
+
+    fn square(self) -> FieldElement {
+        self * self
+    }
+
+    fn pow(self, exponent: &[u64; 4]) -> FieldElement {
+        let mut result = FieldElement::ONE;
+        for limb in exponent.iter().rev() {
+            for bit in (0..64).rev() {
+                result = result.square();
+                if (limb >> bit) & 1 == 1 {
+                    result = result * self;
+                }
+            }
+        }
+        result
+    }
+
+    /// The inverse, or zero for zero
+    fn inverse(self) -> FieldElement {
+        self.pow(&P_MINUS_2)
+    }
+
+    fn sqrt(self) -> Option<FieldElement> {
+        let root = self.pow(&P_PLUS_1_DIV_4);
+        if root.square() == self {
+            Some(root)
+        } else {
+            None
+        }
+    }
+
+    /// Whether this is the X coordinate of a point of the curve
+    fn is_valid_x(self) -> bool {
+        (self.square() * self + FieldElement::from_u64(7)).sqrt().is_some()
+    }
+}
+
+/// Reduces a number below 2p, with `carry` its 2^256 bit
+fn reduce_once(mut limbs: [u64; 4], carry: bool) -> [u64; 4] {
+    let at_least_p = carry || (0..4).rev()
+        .find(|&i| limbs[i] != P[i])
+        .map_or(true, |i| limbs[i] > P[i]);
+    if at_least_p {
+        // Subtracting p is adding 2^256 - p modulo 2^256
+        let mut carry = R as u128;
+        for limb in limbs.iter_mut() {
+            let sum = *limb as u128 + carry;
+            *limb = sum as u64;
+            carry = sum >> 64;
+        }
+    }
+    limbs
+}
+
+impl ops::Add for FieldElement {
+    type Output = FieldElement;
+
+    fn add(self, other: FieldElement) -> FieldElement {
+        let mut limbs = [0u64; 4];
+        let mut carry = 0u128;
+        for i in 0..4 {
+            let sum = self.0[i] as u128 + other.0[i] as u128 + carry;
+            limbs[i] = sum as u64;
+            carry = sum >> 64;
+        }
+        FieldElement(reduce_once(limbs, carry != 0))
+    }
+}
+
+impl ops::Neg for FieldElement {
+    type Output = FieldElement;
+
+    fn neg(self) -> FieldElement {
+        if self.is_zero() {
+            return self;
+        }
+        let mut limbs = [0u64; 4];
+        let mut borrow = 0u64;
+        for i in 0..4 {
+            let (diff, b1) = P[i].overflowing_sub(self.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow);
+            limbs[i] = diff;
+            borrow = (b1 || b2) as u64;
+        }
+        FieldElement(limbs)
+    }
+}
+
+impl ops::Sub for FieldElement {
+    type Output = FieldElement;
+
+    fn sub(self, other: FieldElement) -> FieldElement {
+        self + -other
+    }
+}
+
+impl ops::Mul for FieldElement {
+    type Output = FieldElement;
+
+    fn mul(self, other: FieldElement) -> FieldElement {
+        let mut wide = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let product = wide[i + j] as u128 + self.0[i] as u128 * other.0[j] as u128 + carry;
+                wide[i + j] = product as u64;
+                carry = product >> 64;
+            }
+            wide[i + 4] = carry as u64;
+        }
+
+        // 2^256 = R modulo p, fold the high half twice
+        let mut limbs = [0u64; 4];
+        let mut carry = 0u128;
+        for i in 0..4 {
+            let sum = wide[i] as u128 + wide[i + 4] as u128 * R as u128 + carry;
+            limbs[i] = sum as u64;
+            carry = sum >> 64;
+        }
+        let mut carry = carry * R as u128;
+        for limb in limbs.iter_mut() {
+            let sum = *limb as u128 + carry;
+            *limb = sum as u64;
+            carry = sum >> 64;
+        }
+        FieldElement(reduce_once(limbs, carry != 0))
+    }
+}
+
+impl ops::Div for FieldElement {
+    type Output = FieldElement;
+
+    fn div(self, other: FieldElement) -> FieldElement {
+        self * other.inverse()
+    }
+}
+
+/// A square root of -3
+fn sqrt_minus_3() -> FieldElement {
+    (-FieldElement::from_u64(3)).sqrt().expect("-3 is a square")
+}
+
+/// The SwiftEC map of `(u, t)` to an X coordinate
+fn xswiftec(mut u: FieldElement, mut t: FieldElement) -> FieldElement {
+    let two = FieldElement::from_u64(2);
+    let seven = FieldElement::from_u64(7);
+    if u.is_zero() {
+        u = FieldElement::ONE;
+    }
+    if t.is_zero() {
+        t = FieldElement::ONE;
+    }
+    if (u.square() * u + t.square() + seven).is_zero() {
+        t = t + t;
+    }
+    let x = (u.square() * u + seven - t.square()) / (two * t);
+    let y = (x + t) / (sqrt_minus_3() * u);
+
+    let candidates = [
+        u + FieldElement::from_u64(4) * y.square(),
+        (-x / y - u) / two,
+        (x / y - u) / two,
+    ];
+    *candidates.iter()
+        .find(|x| x.is_valid_x())
+        .expect("one of the candidates is on the curve")
+}
+
+/// A `t` such that `xswiftec(u, t) = x`, for one of the 8 cases of the
+/// inverse map, if it exists
+fn xswiftec_inv(x: FieldElement, u: FieldElement, case: u8) -> Option<FieldElement> {
+    let two = FieldElement::from_u64(2);
+    let g = u.square() * u + FieldElement::from_u64(7);
+    let (v, s) = if case & 2 == 0 {
+        if (-x - u).is_valid_x() {
+            return None;
+        }
+        (x, -g / (u.square() + u * x + x.square()))
+    } else {
+        let s = x - u;
+        if s.is_zero() {
+            return None;
+        }
+        let three_s_u2 = FieldElement::from_u64(3) * s * u.square();
+        let r = (-s * (FieldElement::from_u64(4) * g + three_s_u2)).sqrt()?;
+        if case & 1 == 1 && r.is_zero() {
+            return None;
+        }
+        ((-u + r / s) / two, s)
+    };
+    let w = s.sqrt()?;
+    let c = sqrt_minus_3();
+    Some(match case & 5 {
+        0 => -w * (u * (FieldElement::ONE - c) / two + v),
+        1 => w * (u * (FieldElement::ONE + c) / two + v),
+        4 => w * (u * (FieldElement::ONE - c) / two + v),
+        _ => -w * (u * (FieldElement::ONE + c) / two + v),
+    })
+}
+
+/// A public key encoded with ElligatorSwift
+#[derive(Copy, Clone)]
+pub struct ElligatorSwift([u8; 64]);
+
+impl_array_newtype!(ElligatorSwift, u8, 64);
+impl_bytes_newtype!(ElligatorSwift, 64);
+
+impl PartialEq for ElligatorSwift {
+    fn eq(&self, other: &ElligatorSwift) -> bool {
+        self.0[..] == other.0[..]
+    }
+}
+
+impl Eq for ElligatorSwift {}
+
+impl ElligatorSwift {
+    /// Wraps an encoding. Every 64 byte string encodes a key.
+    pub fn from_array(bytes: [u8; 64]) -> ElligatorSwift {
+        ElligatorSwift(bytes)
+    }
+
+    /// Encodes the X coordinate of `pubkey`, as one of its many encodings
+    /// selected by `entropy`, which should be uniformly random
+    pub fn from_pubkey(pubkey: &PublicKey, entropy: &[u8; 32]) -> ElligatorSwift {
+        let x = FieldElement::from_bytes(&pubkey.serialize()[1..33]);
+        let mut counter = 0u32;
+        loop {
+            let mut engine = sha512::Hash::engine();
+            engine.input(b"ElligatorSwift/encode");
+            engine.input(entropy);
+            engine.input(&::util::endian::u32_to_array_le(counter));
+            let hash = sha512::Hash::from_engine(engine);
+            counter += 1;
+
+            let u = FieldElement::from_bytes(&hash[0..32]);
+            if u.is_zero() {
+                continue;
+            }
+            if let Some(t) = xswiftec_inv(x, u, hash[32] & 7) {
+                // t = 0 would be mapped to 1
+                if t.is_zero() || xswiftec(u, t) != x {
+                    continue;
+                }
+                let mut bytes = [0u8; 64];
+                bytes[..32].copy_from_slice(&u.to_bytes());
+                bytes[32..].copy_from_slice(&t.to_bytes());
+                return ElligatorSwift(bytes);
+            }
+        }
+    }
+
+    /// Encodes the public key of `secret_key`, see [`ElligatorSwift::from_pubkey`]
+    pub fn from_secret_key<C: Signing>(secp: &Secp256k1<C>, secret_key: &SecretKey, entropy: &[u8; 32]) -> ElligatorSwift {
+        ElligatorSwift::from_pubkey(&PublicKey::from_secret_key(secp, secret_key), entropy)
+    }
+
+    /// The X coordinate of the encoded key
+    pub fn decode(&self) -> [u8; 32] {
+        xswiftec(FieldElement::from_bytes(&self.0[0..32]), FieldElement::from_bytes(&self.0[32..64])).to_bytes()
+    }
+
+    /// The X coordinate of the product of the encoded key and `secret_key`
+    pub fn xonly_ecdh<C: Verification>(&self, secp: &Secp256k1<C>, secret_key: &SecretKey) -> [u8; 32] {
+        let mut compressed = [2u8; 33];
+        compressed[1..].copy_from_slice(&self.decode());
+        let mut point = PublicKey::from_slice(&compressed).expect("decoded X coordinates are on the curve");
+        point.mul_assign(secp, &secret_key[..]).expect("secret keys are valid tweaks");
+        let mut x = [0u8; 32];
+        x.copy_from_slice(&point.serialize()[1..33]);
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hashes::hex::{FromHex, ToHex};
+
+    fn fe(hex: &str) -> FieldElement {
+        FieldElement::from_bytes(&Vec::from_hex(hex).unwrap())
+    }
+
+    #[test]
+    fn field_arithmetic() {
+        let p_minus_1 = fe("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2e");
+        assert_eq!(fe("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f"), FieldElement::ZERO);
+        assert_eq!(fe("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc30"), FieldElement::ONE);
+        assert_eq!(p_minus_1 + FieldElement::ONE, FieldElement::ZERO);
+        assert_eq!(-FieldElement::ONE, p_minus_1);
+        assert_eq!(p_minus_1 * p_minus_1, FieldElement::ONE);
+        assert_eq!(FieldElement::ZERO - FieldElement::ONE, p_minus_1);
+
+        let a = fe("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+        let b = fe("483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8");
+        assert_eq!(a * b / b, a);
+        assert_eq!((a + b) - b, a);
+        assert_eq!(a * (b + FieldElement::ONE), a * b + a);
+        // The generator is on the curve
+        assert_eq!(b.square(), a.square() * a + FieldElement::from_u64(7));
+        assert!(a.is_valid_x());
+        assert_eq!(b.square().sqrt().map(|r| r == b || r == -b), Some(true));
+        assert_eq!(FieldElement::from_u64(5).to_bytes().to_hex(), "0000000000000000000000000000000000000000000000000000000000000005");
+    }
+
+    #[test]
+    fn decode_vectors() {
+        // From the ElligatorSwift decoding test vectors of BIP324
+        let vectors = [
+            ("00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000", "edd1fd3e327ce90cc7a3542614289aee9682003e9cf7dcc9cf2ca9743be5aa0c"),
+            ("000000000000000000000000000000000000000000000000000000000000000001d3475bf7655b0fb2d852921035b2ef607f49069b97454e6795251062741771", "b5da00b73cd6560520e7c364086e7cd23a34bf60d0e707be9fc34d4cd5fdfa2c"),
+            ("000000000000000000000000000000000000000000000000000000000000000082277c4a71f9d22e66ece523f8fa08741a7c0912c66a69ce68514bfd3515b49f", "f482f2e241753ad0fb89150d8491dc1e34ff0b8acfbb442cfe999e2e5e6fd1d2"),
+            ("00000000000000000000000000000000000000000000000000000000000000008421cc930e77c9f514b6915c3dbe2a94c6d8f690b5b739864ba6789fb8a55dd0", "9f59c40275f5085a006f05dae77eb98c6fd0db1ab4a72ac47eae90a4fc9e57e0"),
+            ("0000000000000000000000000000000000000000000000000000000000000000bde70df51939b94c9c24979fa7dd04ebd9b3572da7802290438af2a681895441", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa9fffffd6b"),
+            // u and t are taken modulo p
+            ("0000000000000000000000000000000000000000000000000000000000000000fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f", "edd1fd3e327ce90cc7a3542614289aee9682003e9cf7dcc9cf2ca9743be5aa0c"),
+            ("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f0000000000000000000000000000000000000000000000000000000000000000", "edd1fd3e327ce90cc7a3542614289aee9682003e9cf7dcc9cf2ca9743be5aa0c"),
+            // Computed with the reference code of BIP324, not from its CSV file
+            ("941e0fd59bd4ca10937102df2f991b6009f388264579d8fac1daf6fb2bb7b171e3fb7ee680b3c830b32acb74f2aa181c1c3d42899892dff158e4980a039dbb04", "51fa901837364d681a583fc1c9ac9dd059c241a37611672792ee0c0266a40897"),
+            ("cf0ead81c0c949e631d6b44166eddbfb7a6931bb01cdf4f428951b18ebe76b61b7a4880da5106ac9582b1d9cdaa10183d4c33f9e3b203b3c45c0bde5fd9a4b5f", "50ecc0d4fb3c941ee32e4442e3a3e4600332bbbb246a0723c06cc16b30051c14"),
+            ("b8fa4d5f678f1413cd607a5d8461cfdb004bab13c2c19f7deeffa745af6900bf7b9efe09703374a4f1375805338c0e5d914536f98f418e8a29df382427c72561", "b8e5b6554aa903d4c66088f3986685bd5ad4cb9e14b25d9f73b26f35071bf954"),
+            // u^3 + t^2 + 7 = 0
+            ("d0f631ca1ddba8db3bcfcb9e057cdc98d0379f1bee00e75a545147a27dadd98213001e3a413bb175f9cc21b36a84476f4770de62ab1540b26cc9273fd2644ab2", "693bc41166fe28988509eda5aa248fa4d843fac23c5b01ccd344b6983eeec85a"),
+            ("44ba554e17977b31413d531e0aa4e02e87a7a38115d3be3b33c95f85fac2a4f70000000000000000000000000000000000000000000000000000000000000000", "f2c5d7fce2ac0bbc8db0a2cf1a7b7107bffc957f3e446f506d44339c87abe64f"),
+            ("fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc343c417b7ea567c3115deebed7319de56c4d008e6990b0d45ed5cfa53d4c5d37fa", "b26c8cb002bbbe8ea37aa6d3a9ad941bd91c3c84bf623fa7e9653732edd56b0b"),
+            ("47d1607efc92e4e3b765be65c7ec2ac063524455d36ae201aec7cccd4a6e431efffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc30", "f1b3e9b7c9cc2ddb97f0ae9000767fb8204c877af08c80f6ed2b56448b0bfdfb"),
+            // The in_ellswift_theirs of the first packet encoding test vector,
+            // with t >= p
+            ("a4a94dfce69b4a2a0a099313d10f9f7e7d649d60501c9e1d274c300e0d89aafaffffffffffffffffffffffffffffffffffffffffffffffffffffffff8faf88d5", "0c71defa3fafd74cb835102acd81490963f6b72d889495e06561375bd65f6ffc"),
+        ];
+        for &(encoded, x) in vectors.iter() {
+            let ellswift = ElligatorSwift::from(&Vec::from_hex(encoded).unwrap()[..]);
+            assert_eq!(ellswift.decode().to_hex(), x);
+        }
+    }
+
+    #[test]
+    fn xswiftec_inv_vectors() {
+        // u, x and the t of each of the 8 cases, empty when there is none,
+        // computed with the reference code of BIP324, not from its CSV file
+        let vectors = [
+            ("bb82030dbc2bcaba32a90bf2e207a84a856fc5f033b77c480836ab6f77f40f19", "ec31682fde561917952ff78a7a8adeffd0febc372dd26871916c46c630381b45",
+             ["", "", "", "", "", "", "", ""]),
+            ("011e39efe22590f4a339ad19cd180f4d855e32feba602d1ec8e154780838c99c", "844b69c4d54cc264bc2dadb6bb70f53bc123beafc0f58d81ed8cd4a07c24a5a7",
+             ["0e2746c5dc13a602d805c66d11f5fa2299c6b39a60e79f5b3d9ad615fd329131", "2743dc40a9fee40d1dea7443a8c1f97c4c5d1f0d3d491e9e52840eca90014f80", "", "",
+              "f1d8b93a23ec59fd27fa3992ee0a05dd66394c659f1860a4c26529e902cd6afe", "d8bc23bf56011bf2e2158bbc573e0683b3a2e0f2c2b6e161ad7bf1346ffeacaf", "", ""]),
+            ("5850a03e801ffb108da1160e3373979443004b9e670addf33000dca9045fa413", "29f2394eb92d0ded9247b8d7188ebddae3e13c71ebcf939302619b29604486b0",
+             ["b1ee8489bbefc835e550999f96da4e21db9804b651356ad38d51c5cbe85de3f9", "706855cd6c36d70771e3071850d981b7900489f092ca6a52675ca24ab8be5b83",
+              "1722f11a00fab6b131091166ae522a9ed8e311ac8c77bb815db500fff0d63130", "bff816b7bd8a2d623367131aa17b5966b13b3b047c598f322988806cb1fda66e",
+              "4e117b76441037ca1aaf66606925b1de2467fb49aeca952c72ae3a3317a21836", "8f97aa3293c928f88e1cf8e7af267e486ffb760f6d3595ad98a35db44741a0ac",
+              "e8dd0ee5ff05494ecef6ee9951add561271cee537388447ea24afeff0f29caff", "4007e9484275d29dcc98ece55e84a6994ec4c4fb83a670cdd6777f924e0255c1"]),
+            ("6fd1b3e081a50a236af0114c66f7affa7cf4cd0c627cc71203151d25a0c4d980", "bdb63d6b7c35d8ad1de13ba03d61c4265813c3bb2d00f47dad05e9d1ce1ea474",
+             ["", "", "", "", "", "", "", ""]),
+            ("d238f8b22b170a316512d14cbfbce16123895d3ada7a6c0f77dd3928472229fe", "2a006857f4fbd469d09a2bf11d702c452df48a1f2af5b43e5f3cad17ceec4088",
+             ["01a916e4c632e02599ea3f095d598ab966ebe38f666a73b5fda44cba11524b27", "e580928e6bc0990851225704a28b68b119225597e47af0485f112b85a4fddde6", "", "",
+              "fe56e91b39cd1fda6615c0f6a2a6754699141c7099958c4a025bb344eeadb108", "1a7f6d71943f66f7aedda8fb5d74974ee6ddaa681b850fb7a0eed4795b021e49", "", ""]),
+            // x = u
+            ("e8bc163c82eee18733288c7d4ac636db3a6deb013ef2d37b68322be20edc45cc", "e8bc163c82eee18733288c7d4ac636db3a6deb013ef2d37b68322be20edc45cc",
+             ["", "", "", "", "", "", "", ""]),
+        ];
+        for &(u, x, ref ts) in vectors.iter() {
+            let (u, x) = (fe(u), fe(x));
+            for (case, t) in ts.iter().enumerate() {
+                match xswiftec_inv(x, u, case as u8) {
+                    Some(inv) => {
+                        assert_eq!(inv.to_bytes().to_hex(), *t);
+                        assert_eq!(xswiftec(u, inv), x);
+                    }
+                    None => assert_eq!(*t, ""),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encode_and_ecdh() {
+        let secp = Secp256k1::new();
+
+        // From the first packet encoding test vector of BIP324
+        let secret_key = SecretKey::from_slice(&Vec::from_hex("61062ea5071d800bbfd59e2e8b53d47d194b095ae5a4df04936b49772ef0d4d7").unwrap()).unwrap();
+        let ellswift: ElligatorSwift = "ec0adff257bbfe500c188c80b4fdd640f6b45a482bbc15fc7cef5931deff0aa186f6eb9bba7b85dc4dcc28b28722de1e3d9108b985e2967045668f66098e475b".parse().unwrap();
+        let x = "19e965bc20fc40614e33f2f82d4eeff81b5e7516b12a5c6c0d6053527eba0923";
+        assert_eq!(ellswift.decode().to_hex(), x);
+        assert_eq!(PublicKey::from_secret_key(&secp, &secret_key).serialize()[1..].to_hex(), x);
+        let theirs = PublicKey::from_slice(&Vec::from_hex("020c71defa3fafd74cb835102acd81490963f6b72d889495e06561375bd65f6ffc").unwrap()).unwrap();
+        let theirs = ElligatorSwift::from_pubkey(&theirs, &[0; 32]);
+        assert_eq!(theirs.xonly_ecdh(&secp, &secret_key).to_hex(), "4eb2bf85bd00939468ea2abb25b63bc642e3d1eb8b967fb90caa2d89e716050e");
+
+        // Encodings of a key decode to it and depend on the entropy
+        let first = ElligatorSwift::from_secret_key(&secp, &secret_key, &[1; 32]);
+        let second = ElligatorSwift::from_secret_key(&secp, &secret_key, &[2; 32]);
+        assert_ne!(first, second);
+        assert_eq!(first.decode().to_hex(), x);
+        assert_eq!(second.decode().to_hex(), x);
+
+        // Both sides of the key exchange agree
+        let other_key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let other = ElligatorSwift::from_secret_key(&secp, &other_key, &[3; 32]);
+        assert_eq!(other.xonly_ecdh(&secp, &secret_key), first.xonly_ecdh(&secp, &other_key));
+
+        for i in 0..32u8 {
+            let key = SecretKey::from_slice(&[i + 1; 32]).unwrap();
+            let encoded = ElligatorSwift::from_secret_key(&secp, &key, &[i; 32]);
+            assert_eq!(encoded.decode()[..], PublicKey::from_secret_key(&secp, &key).serialize()[1..]);
+        }
+    }
+}
@@ -20,11 +20,14 @@ use std::fmt::{self, Write};
 use std::{io, ops, error};
 use std::str::FromStr;
 
-use secp256k1::{self, Secp256k1};
+use secp256k1::{self, schnorrsig, Secp256k1};
+use network::chainparams::ChainParams;
 use network::constants::Network;
 use hashes::{Hash, hash160};
 use hash_types::{PubkeyHash, WPubkeyHash};
 use util::base58;
+use util::bip341::SchnorrSigHashType;
+use util::taproot::{TapBranchHash, TapTweakHash};
 
 /// A key-related error.
 #[derive(Debug)]
@@ -33,6 +36,12 @@ pub enum Error {
     Base58(base58::Error),
     /// secp256k1-related error
     Secp256k1(secp256k1::Error),
+    /// secp256k1 error parsing or verifying a schnorr signature
+    Schnorr(secp256k1::Error),
+    /// A schnorr signature must be 64 or 65 bytes long
+    InvalidSchnorrSigSize(usize),
+    /// Invalid sighash byte of a 65-byte schnorr signature
+    InvalidSchnorrSigHashType(u8),
 }
 
 
@@ -41,6 +50,9 @@ impl fmt::Display for Error {
         match *self {
             Error::Base58(ref e) => write!(f, "base58 error: {}", e),
             Error::Secp256k1(ref e) => write!(f, "secp256k1 error: {}", e),
+            Error::Schnorr(ref e) => write!(f, "schnorr signature error: {}", e),
+            Error::InvalidSchnorrSigSize(sz) => write!(f, "invalid schnorr signature size: {}", sz),
+            Error::InvalidSchnorrSigHashType(ty) => write!(f, "invalid schnorr signature sighash type: {:#04x}", ty),
         }
     }
 }
@@ -49,7 +61,8 @@ impl error::Error for Error {
     fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
             Error::Base58(ref e) => Some(e),
-            Error::Secp256k1(ref e) => Some(e),
+            Error::Secp256k1(ref e) | Error::Schnorr(ref e) => Some(e),
+            Error::InvalidSchnorrSigSize(_) | Error::InvalidSchnorrSigHashType(_) => None,
         }
     }
 }
@@ -207,11 +220,16 @@ impl PrivateKey {
 
     /// Format the private key to WIF format.
     pub fn fmt_wif(&self, fmt: &mut dyn fmt::Write) -> fmt::Result {
-        let mut ret = [0; 34];
-        ret[0] = match self.network {
+        let prefix = match self.network {
             Network::Bitcoin => 128,
             Network::Testnet | Network::Signet | Network::Regtest => 239,
         };
+        self.fmt_wif_with_prefix(fmt, prefix)
+    }
+
+    fn fmt_wif_with_prefix(&self, fmt: &mut dyn fmt::Write, prefix: u8) -> fmt::Result {
+        let mut ret = [0; 34];
+        ret[0] = prefix;
         ret[1..33].copy_from_slice(&self.key[..]);
         let privkey = if self.compressed {
             ret[33] = 1;
@@ -230,17 +248,18 @@ impl PrivateKey {
         buf
     }
 
+    /// Get WIF encoding of this private key with the prefix of the chain
+    /// `params`.
+    pub fn to_wif_with_params(&self, params: &ChainParams) -> String {
+        let mut buf = String::new();
+        self.fmt_wif_with_prefix(&mut buf, params.secret_key_prefix).unwrap();
+        buf
+    }
+
     /// Parse WIF encoded private key.
     pub fn from_wif(wif: &str) -> Result<PrivateKey, Error> {
-        let data = base58::from_check(wif)?;
-
-        let compressed = match data.len() {
-            33 => false,
-            34 => true,
-            _ => { return Err(Error::Base58(base58::Error::InvalidLength(data.len()))); }
-        };
-
-        let network = match data[0] {
+        let (prefix, compressed, key) = PrivateKey::decode_wif(wif)?;
+        let network = match prefix {
             128 => Network::Bitcoin,
             239 => Network::Testnet,
             x   => { return Err(Error::Base58(base58::Error::InvalidVersion(vec![x]))); }
@@ -249,9 +268,37 @@ impl PrivateKey {
         Ok(PrivateKey {
             compressed: compressed,
             network: network,
-            key: secp256k1::SecretKey::from_slice(&data[1..33])?,
+            key: key,
         })
     }
+
+    /// Parse a private key WIF encoded with the prefix of the chain
+    /// `params`. The key gets `params.network` as its network.
+    pub fn from_wif_with_params(wif: &str, params: &ChainParams) -> Result<PrivateKey, Error> {
+        let (prefix, compressed, key) = PrivateKey::decode_wif(wif)?;
+        if prefix != params.secret_key_prefix {
+            return Err(Error::Base58(base58::Error::InvalidVersion(vec![prefix])));
+        }
+
+        Ok(PrivateKey {
+            compressed: compressed,
+            network: params.network,
+            key: key,
+        })
+    }
+
+    /// Splits a WIF into its prefix, compression flag and secret key
+    fn decode_wif(wif: &str) -> Result<(u8, bool, secp256k1::SecretKey), Error> {
+        let data = base58::from_check(wif)?;
+
+        let compressed = match data.len() {
+            33 => false,
+            34 => true,
+            _ => { return Err(Error::Base58(base58::Error::InvalidLength(data.len()))); }
+        };
+
+        Ok((data[0], compressed, secp256k1::SecretKey::from_slice(&data[1..33])?))
+    }
 }
 
 impl fmt::Display for PrivateKey {
@@ -280,6 +327,153 @@ impl ops::Index<ops::RangeFull> for PrivateKey {
     }
 }
 
+/// A BIP340 x-only public key, as used by taproot
+pub use secp256k1::schnorrsig::PublicKey as XOnlyPublicKey;
+
+impl PublicKey {
+    /// Drops the parity of the public key, giving the corresponding x-only key
+    pub fn to_x_only_pubkey(&self) -> XOnlyPublicKey {
+        XOnlyPublicKey::from_slice(&self.key.serialize()[1..]).expect("valid x coordinate")
+    }
+}
+
+/// A BIP340 key pair. BIP340 public keys implicitly have an even y
+/// coordinate, so the secret key is negated on construction if its public
+/// key has an odd one.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct KeyPair {
+    secret_key: secp256k1::SecretKey,
+    public_key: XOnlyPublicKey,
+}
+
+impl KeyPair {
+    /// Creates a key pair from a secret key, negating it if needed
+    pub fn from_secret_key<C: secp256k1::Signing>(secp: &Secp256k1<C>, mut sk: secp256k1::SecretKey) -> KeyPair {
+        let full = secp256k1::PublicKey::from_secret_key(secp, &sk).serialize();
+        if full[0] == 0x03 {
+            sk.negate_assign();
+        }
+        KeyPair {
+            secret_key: sk,
+            public_key: XOnlyPublicKey::from_slice(&full[1..]).expect("valid x coordinate"),
+        }
+    }
+
+    /// Creates a key pair from a private key, negating it if needed
+    pub fn from_private_key<C: secp256k1::Signing>(secp: &Secp256k1<C>, sk: &PrivateKey) -> KeyPair {
+        KeyPair::from_secret_key(secp, sk.key)
+    }
+
+    /// The secret key, whose public key has an even y coordinate
+    pub fn secret_key(&self) -> secp256k1::SecretKey {
+        self.secret_key
+    }
+
+    /// The x-only public key
+    pub fn public_key(&self) -> XOnlyPublicKey {
+        self.public_key
+    }
+
+    /// Adds `tweak` times the generator to the key pair, negating the
+    /// result if needed.
+    pub fn tweak_add_assign<C: secp256k1::Signing>(&mut self, secp: &Secp256k1<C>, tweak: &[u8]) -> Result<(), Error> {
+        let mut sk = self.secret_key;
+        sk.add_assign(tweak)?;
+        *self = KeyPair::from_secret_key(secp, sk);
+        Ok(())
+    }
+
+    /// Tweaks the key pair with the BIP341 tweak of its public key and the
+    /// given merkle root, giving the key pair of the taproot output key.
+    pub fn tap_tweak<C: secp256k1::Signing>(&self, secp: &Secp256k1<C>, merkle_root: Option<TapBranchHash>) -> Result<KeyPair, Error> {
+        let tweak = TapTweakHash::from_key_and_tweak(&self.public_key, merkle_root);
+        let mut tweaked = *self;
+        tweaked.tweak_add_assign(secp, &tweak[..])?;
+        Ok(tweaked)
+    }
+
+    /// Creates a BIP340 signature of `msg`, without auxiliary randomness
+    pub fn sign_schnorr<C: secp256k1::Signing>(&self, secp: &Secp256k1<C>, msg: &secp256k1::Message) -> schnorrsig::Signature {
+        secp.schnorrsig_sign_no_aux_rand(msg, &self.secp_keypair(secp))
+    }
+
+    /// Creates a BIP340 signature of `msg`, mixing in the given auxiliary
+    /// randomness as recommended by BIP340
+    pub fn sign_schnorr_with_aux_rand<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        msg: &secp256k1::Message,
+        aux_rand: &[u8; 32],
+    ) -> schnorrsig::Signature {
+        secp.schnorrsig_sign_with_aux_rand(msg, &self.secp_keypair(secp), aux_rand)
+    }
+
+    fn secp_keypair<C: secp256k1::Signing>(&self, secp: &Secp256k1<C>) -> schnorrsig::KeyPair {
+        schnorrsig::KeyPair::from_secret_key(secp, self.secret_key)
+    }
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "KeyPair {{ public_key: {}, [private key data] }}", self.public_key)
+    }
+}
+
+/// A BIP340 signature along with the sighash type it commits to, as found in
+/// taproot witnesses: 64 bytes for `SIGHASH_DEFAULT`, 65 bytes otherwise.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SchnorrSig {
+    /// The BIP340 signature
+    pub sig: schnorrsig::Signature,
+    /// The sighash type, serialized as a trailing byte unless it is `SIGHASH_DEFAULT`
+    pub hash_ty: SchnorrSigHashType,
+}
+
+impl SchnorrSig {
+    /// Deserializes a 64 or 65-byte signature
+    pub fn from_slice(sl: &[u8]) -> Result<SchnorrSig, Error> {
+        match sl.len() {
+            64 => Ok(SchnorrSig {
+                sig: schnorrsig::Signature::from_slice(sl).map_err(Error::Schnorr)?,
+                hash_ty: SchnorrSigHashType::Default,
+            }),
+            65 => {
+                // An explicit SIGHASH_DEFAULT byte is invalid, the 64-byte form must be used
+                let hash_ty = match SchnorrSigHashType::from_u8(sl[64]) {
+                    Ok(SchnorrSigHashType::Default) | Err(_) => return Err(Error::InvalidSchnorrSigHashType(sl[64])),
+                    Ok(hash_ty) => hash_ty,
+                };
+                Ok(SchnorrSig {
+                    sig: schnorrsig::Signature::from_slice(&sl[..64]).map_err(Error::Schnorr)?,
+                    hash_ty: hash_ty,
+                })
+            }
+            len => Err(Error::InvalidSchnorrSigSize(len)),
+        }
+    }
+
+    /// Serializes the signature, appending the sighash byte unless it is `SIGHASH_DEFAULT`
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ser = self.sig[..].to_vec();
+        if self.hash_ty != SchnorrSigHashType::Default {
+            ser.push(self.hash_ty.as_u8());
+        }
+        ser
+    }
+
+    /// Verifies the signature of `msg` by `pk`
+    // secp256k1 0.20 only exposes schnorr verification on signing contexts
+    pub fn verify<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        msg: &secp256k1::Message,
+        pk: &XOnlyPublicKey,
+    ) -> Result<(), Error> {
+        secp.schnorrsig_verify(&self.sig, msg, pk).map_err(Error::Schnorr)
+    }
+}
+
 #[cfg(feature = "serde")]
 impl ::serde::Serialize for PrivateKey {
     fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
@@ -394,8 +588,8 @@ impl<'de> ::serde::Deserialize<'de> for PublicKey {
 
 #[cfg(test)]
 mod tests {
-    use super::{PrivateKey, PublicKey};
-    use secp256k1::Secp256k1;
+    use super::{Error, KeyPair, PrivateKey, PublicKey, SchnorrSig, XOnlyPublicKey};
+    use secp256k1::{self, Secp256k1};
     use std::io;
     use std::str::FromStr;
     use hashes::hex::ToHex;
@@ -549,4 +743,141 @@ mod tests {
         assert!(PublicKey::read_from(io::Cursor::new(&[0; 65][..])).is_err());
         assert!(PublicKey::read_from(io::Cursor::new(&[4; 64][..])).is_err());
     }
+
+    #[test]
+    fn test_x_only_pubkey() {
+        let pk = PublicKey::from_str("032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        let xonly = pk.to_x_only_pubkey();
+        assert_eq!(xonly, XOnlyPublicKey::from_str("2e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap());
+        let pk = PublicKey::from_str("022e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af").unwrap();
+        assert_eq!(pk.to_x_only_pubkey(), xonly);
+    }
+
+    #[test]
+    fn test_keypair_negation() {
+        let secp = Secp256k1::new();
+        // This key has an odd y coordinate
+        let sk = PrivateKey::from_wif("L1HKVVLHXiUhecWnwFYF6L3shkf1E12HUmuZTESvBXUdx3yqVP1D").unwrap();
+        let full = sk.public_key(&secp);
+        assert_eq!(full.key.serialize()[0], 0x03);
+
+        let keypair = KeyPair::from_private_key(&secp, &sk);
+        assert_eq!(keypair.public_key(), full.to_x_only_pubkey());
+        let mut negated = sk.key;
+        negated.negate_assign();
+        assert_eq!(keypair.secret_key(), negated);
+        let even = secp256k1::PublicKey::from_secret_key(&secp, &keypair.secret_key());
+        assert_eq!(even.serialize()[0], 0x02);
+    }
+
+    #[test]
+    fn bip341_key_spend_signatures() {
+        use hashes::Hash;
+        use hashes::hex::FromHex;
+        use util::bip341::SchnorrSigHashType;
+        use util::taproot::TapBranchHash;
+
+        // (internalPrivkey, merkleRoot, hashType, tweakedPrivkey, sigHash, witness)
+        let vectors = [
+            ("6b973d88838f27366ed61c9ad6367663045cb456e28335c109e30717ae0c6baa", "", 3,
+             "2405b971772ad26915c8dcdf10f238753a9b837e5f8e6a86fd7c0cce5b7296d9",
+             "2514a6272f85cfa0f45eb907fcb0d121b808ed37c6ea160a5a9046ed5526d555",
+             "ed7c1647cb97379e76892be0cacff57ec4a7102aa24296ca39af7541246d8ff14d38958d4cc1e2e478e4d4a764bbfd835b16d4e314b72937b29833060b87276c03"),
+            ("1e4da49f6aaf4e5cd175fe08a32bb5cb4863d963921255f33d3bc31e1343907f",
+             "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21", 131,
+             "ea260c3b10e60f6de018455cd0278f2f5b7e454be1999572789e6a9565d26080",
+             "325a644af47e8a5a2591cda0ab0723978537318f10e6a63d4eed783b96a71a4d",
+             "052aedffc554b41f52b521071793a6b88d6dbca9dba94cf34c83696de0c1ec35ca9c5ed4ab28059bd606a4f3a657eec0bb96661d42921b5f50a95ad33675b54f83"),
+            ("d3c7af07da2d54f7a7735d3d0fc4f0a73164db638b2f2f7c43f711f6d4aa7e64",
+             "c525714a7f49c28aedbbba78c005931a81c234b2f6c99a73e4d06082adc8bf2b", 1,
+             "97323385e57015b75b0339a549c56a948eb961555973f0951f555ae6039ef00d",
+             "bf013ea93474aa67815b1b6cc441d23b64fa310911d991e713cd34c7f5d46669",
+             "ff45f742a876139946a149ab4d9185574b98dc919d2eb6754f8abaa59d18b025637a3aa043b91817739554f4ed2026cf8022dbd83e351ce1fabc272841d2510a01"),
+            ("f36bb07a11e469ce941d16b63b11b9b9120a84d9d87cff2c84a8d4affb438f4e",
+             "ccbd66c6f7e8fdab47b3a486f59d28262be857f30d4773f2d5ea47f7761ce0e2", 0,
+             "a8e7aa924f0d58854185a490e6c41f6efb7b675c0f3331b7f14b549400b4d501",
+             "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef",
+             "b4010dd48a617db09926f729e79c33ae0b4e94b79f04a1ae93ede6315eb3669de185a17d2b0ac9ee09fd4c64b678a0b61a0a86fa888a273c8511be83bfd6810f"),
+            ("415cfe9c15d9cea27d8104d5517c06e9de48e2f986b695e4f5ffebf230e725d8",
+             "2f6b2c5397b6d68ca18e09a3f05161668ffe93a988582d55c6f07bd5b3329def", 2,
+             "241c14f2639d0d7139282aa6abde28dd8a067baa9d633e4e7230287ec2d02901",
+             "15f25c298eb5cdc7eb1d638dd2d45c97c4c59dcaec6679cfc16ad84f30876b85",
+             "a3785919a2ce3c4ce26f298c3d51619bc474ae24014bcdd31328cd8cfbab2eff3395fa0a16fe5f486d12f22a9cedded5ae74feb4bbe5351346508c5405bcfee002"),
+            ("c7b0e81f0a9a0b0499e112279d718cca98e79a12e2f137c72ae5b213aad0d103",
+             "6c2dc106ab816b73f9d07e3cd1ef2c8c1256f519748e0813e4edd2405d277bef", 130,
+             "65b6000cd2bfa6b7cf736767a8955760e62b6649058cbc970b7c0871d786346b",
+             "cd292de50313804dabe4685e83f923d2969577191a3e1d2882220dca88cbeb10",
+             "ea0c6ba90763c2d3a296ad82ba45881abb4f426b3f87af162dd24d5109edc1cdd11915095ba47c3a9963dc1e6c432939872bc49212fe34c632cd3ab9fed429c482"),
+            ("77863416be0d0665e517e1c375fd6f75839544eca553675ef7fdf4949518ebaa",
+             "ab179431c28d3b68fb798957faf5497d69c883c6fb1e1cd9f81483d87bac90cc", 129,
+             "ec18ce6af99f43815db543f47b8af5ff5df3b2cb7315c955aa4a86e8143d2bf5",
+             "cccb739eca6c13a8a89e6e5cd317ffe55669bbda23f2fd37b0f18755e008edd2",
+             "bbc9584a11074e83bc8c6759ec55401f0ae7b03ef290c3139814f545b58a9f8127258000874f44bc46db7646322107d4d86aec8e73b8719a61fff761d75b5dd981"),
+        ];
+
+        let secp = Secp256k1::new();
+        for &(internal_priv, merkle_root, hash_ty, tweaked_priv, sighash, witness) in vectors.iter() {
+            let sk = secp256k1::SecretKey::from_str(internal_priv).unwrap();
+            let merkle_root = if merkle_root.is_empty() {
+                None
+            } else {
+                Some(TapBranchHash::from_slice(&Vec::<u8>::from_hex(merkle_root).unwrap()).unwrap())
+            };
+            let hash_ty = SchnorrSigHashType::from_u8(hash_ty).unwrap();
+
+            let tweaked = KeyPair::from_secret_key(&secp, sk).tap_tweak(&secp, merkle_root).unwrap();
+            // BIP341 lists the tweaked key before normalizing its parity
+            let expected = secp256k1::SecretKey::from_str(tweaked_priv).unwrap();
+            assert_eq!(tweaked, KeyPair::from_secret_key(&secp, expected));
+
+            let msg = secp256k1::Message::from_slice(&Vec::<u8>::from_hex(sighash).unwrap()).unwrap();
+            let sig = SchnorrSig {
+                sig: tweaked.sign_schnorr_with_aux_rand(&secp, &msg, &[0u8; 32]),
+                hash_ty: hash_ty,
+            };
+            assert_eq!(sig.to_vec().to_hex(), witness);
+            assert_eq!(SchnorrSig::from_slice(&sig.to_vec()).unwrap(), sig);
+            sig.verify(&secp, &msg, &tweaked.public_key()).unwrap();
+
+            let other = KeyPair::from_secret_key(&secp, sk);
+            match sig.verify(&secp, &msg, &other.public_key()) {
+                Err(Error::Schnorr(_)) => {},
+                _ => panic!("signature of another key accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_schnorr_sig_serialization() {
+        let secp = Secp256k1::new();
+        let sk = PrivateKey::from_wif("L1HKVVLHXiUhecWnwFYF6L3shkf1E12HUmuZTESvBXUdx3yqVP1D").unwrap();
+        let keypair = KeyPair::from_private_key(&secp, &sk);
+        let msg = secp256k1::Message::from_slice(&[1u8; 32]).unwrap();
+        let sig = keypair.sign_schnorr(&secp, &msg);
+
+        let mut ser = sig[..].to_vec();
+        let parsed = SchnorrSig::from_slice(&ser).unwrap();
+        assert_eq!(parsed.to_vec(), ser);
+        parsed.verify(&secp, &msg, &keypair.public_key()).unwrap();
+
+        // An explicit SIGHASH_DEFAULT byte is not allowed
+        ser.push(0x00);
+        match SchnorrSig::from_slice(&ser) {
+            Err(Error::InvalidSchnorrSigHashType(0x00)) => {},
+            _ => panic!("explicit SIGHASH_DEFAULT accepted"),
+        }
+        ser[64] = 0x04;
+        match SchnorrSig::from_slice(&ser) {
+            Err(Error::InvalidSchnorrSigHashType(0x04)) => {},
+            _ => panic!("invalid sighash type accepted"),
+        }
+        ser[64] = 0x81;
+        assert_eq!(SchnorrSig::from_slice(&ser).unwrap().to_vec(), ser);
+
+        ser.push(0x00);
+        match SchnorrSig::from_slice(&ser) {
+            Err(Error::InvalidSchnorrSigSize(66)) => {},
+            _ => panic!("invalid size accepted"),
+        }
+    }
 }
@@ -61,6 +61,7 @@ use hash_types::{Txid, TxMerkleNode};
 use blockdata::transaction::Transaction;
 use blockdata::constants::{MAX_BLOCK_WEIGHT, MIN_TRANSACTION_WEIGHT};
 use consensus::encode::{self, Decodable, Encodable};
+use util::bloom::BloomFilter;
 use util::merkleblock::MerkleBlockError::*;
 use {Block, BlockHeader};
 
@@ -435,6 +436,24 @@ impl MerkleBlock {
         Self::from_header_txids(&block.header, &block_txids, match_txids)
     }
 
+    /// Create a MerkleBlock from a block, that should contain proofs for the transactions matching
+    /// the BIP37 `filter`, as a peer does to answer a `getdata` for a filtered block.
+    ///
+    /// Transactions are matched in order with [`BloomFilter::is_relevant_and_update`], so that
+    /// the filter is updated by outputs spent later in the same block.
+    pub fn from_block_with_filter(block: &Block, filter: &mut BloomFilter) -> Self {
+        let block_txids: Vec<_> = block.txdata.iter().map(Transaction::txid).collect();
+        let matches: Vec<bool> = block.txdata
+            .iter()
+            .map(|tx| filter.is_relevant_and_update(tx))
+            .collect();
+
+        MerkleBlock {
+            header: block.header,
+            txn: PartialMerkleTree::from_txids(&block_txids, &matches),
+        }
+    }
+
     /// Create a MerkleBlock from the block's header and txids, that should contain proofs for match_txids.
     ///
     /// The `header` is the block header, `block_txids` is the full list of txids included in the block and
@@ -505,6 +524,9 @@ mod tests {
 
     use consensus::encode::{deserialize, serialize};
     use util::hash::bitcoin_merkle_root;
+    use blockdata::script::Instruction;
+    use blockdata::transaction::OutPoint;
+    use util::bloom::{BloomFilter, BloomFlags};
     use util::merkleblock::{MerkleBlock, PartialMerkleTree};
     use Block;
 
@@ -696,6 +718,45 @@ mod tests {
         assert_eq!(index.len(), 0);
     }
 
+    #[test]
+    fn merkleblock_construct_from_filter() {
+        let block = get_block_13b8a();
+        let txid = Txid::from_hex("74d681e0e03bafa802c8aa084379aa98d9fcd632ddc2ed9782b586ec87451f20").unwrap();
+
+        // Matching the txid of a transaction
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::All);
+        filter.insert(&txid[..]);
+        let merkle_block = MerkleBlock::from_block_with_filter(&block, &mut filter);
+        assert_eq!(merkle_block.header.block_hash(), block.block_hash());
+
+        let mut matches: Vec<Txid> = vec![];
+        let mut index: Vec<u32> = vec![];
+        assert!(merkle_block.extract_matches(&mut matches, &mut index).is_ok());
+        assert_eq!(matches, vec![txid]);
+        assert_eq!(index, vec![8]);
+
+        // Matching an output, which adds its outpoint to the filter
+        let tx = &block.txdata[8];
+        let pubkey_hash = tx.output[0].script_pubkey.instructions().filter_map(|ins| match ins {
+            Ok(Instruction::PushBytes(data)) => Some(data.to_vec()),
+            _ => None,
+        }).next().unwrap();
+        let mut filter = BloomFilter::new(10, 0.000_001, 0, BloomFlags::All);
+        filter.insert(&pubkey_hash);
+        let merkle_block = MerkleBlock::from_block_with_filter(&block, &mut filter);
+        let mut matches: Vec<Txid> = vec![];
+        assert!(merkle_block.extract_matches(&mut matches, &mut vec![]).is_ok());
+        assert!(matches.contains(&txid));
+        assert!(filter.contains_outpoint(&OutPoint::new(txid, 0)));
+
+        // An empty filter matches the whole block
+        let mut filter = BloomFilter { data: vec![], hash_funcs: 0, tweak: 0, flags: BloomFlags::None };
+        let merkle_block = MerkleBlock::from_block_with_filter(&block, &mut filter);
+        let mut matches: Vec<Txid> = vec![];
+        assert!(merkle_block.extract_matches(&mut matches, &mut vec![]).is_ok());
+        assert_eq!(matches.len(), block.txdata.len());
+    }
+
     impl PartialMerkleTree {
         /// Flip one bit in one of the hashes - this should break the authentication
         fn damage(&mut self, rng: &mut ThreadRng) {
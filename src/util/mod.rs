@@ -22,14 +22,22 @@ pub mod amount;
 pub mod base58;
 pub mod bip32;
 pub mod bip143;
+pub mod bip341;
+pub mod sighash;
 pub mod contracthash;
+pub mod descriptor;
 pub mod hash;
 pub mod merkleblock;
 pub mod misc;
 pub mod psbt;
 pub mod taproot;
+pub mod txbuilder;
 pub mod uint;
+pub mod bip152;
 pub mod bip158;
+pub mod bloom;
+pub mod chacha20_poly1305;
+pub mod ellswift;
 
 pub(crate) mod endian;
 
@@ -0,0 +1,469 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Version 2
+//!
+//! The Constructor role of BIP370, which builds up the unsigned transaction of
+//! a version 2 PSBT input by input and output by output, together with the
+//! lock time determination and the conversions between PSBT versions.
+//!
+
+use std::cmp;
+
+use blockdata::locktime::LOCKTIME_THRESHOLD;
+use blockdata::transaction::{SigHashType, Transaction, TxIn, TxOut};
+use util::bip341::SchnorrSigHashType;
+use util::key::SchnorrSig;
+use util::psbt::{Error, Global, Input, Output, PartiallySignedTransaction, TxModifiable};
+
+impl PartiallySignedTransaction {
+    /// Create an empty version 2 PSBT whose inputs and outputs may be
+    /// modified, ready for a Constructor to add them.
+    pub fn new_v2(tx_version: i32, fallback_locktime: Option<u32>) -> Self {
+        PartiallySignedTransaction {
+            global: Global {
+                unsigned_tx: Transaction {
+                    version: tx_version,
+                    lock_time: fallback_locktime.unwrap_or(0),
+                    input: vec![],
+                    output: vec![],
+                },
+                version: 2,
+                fallback_locktime: fallback_locktime,
+                tx_modifiable: Some(TxModifiable::INPUTS | TxModifiable::OUTPUTS),
+                xpub: Default::default(),
+                proprietary: Default::default(),
+                unknown: Default::default(),
+            },
+            inputs: vec![],
+            outputs: vec![],
+        }
+    }
+
+    /// Add an input spending `txin.previous_output` to a version 2 PSBT.
+    ///
+    /// The input must be unsigned, spend an outpoint no other input spends
+    /// and have required lock times compatible with the other inputs. If any
+    /// input is already signed, the lock time of the transaction must not
+    /// change.
+    ///
+    /// As inputs and outputs are only ever appended, an input signed with
+    /// SIGHASH_SINGLE keeps the output it is paired with, which must hence
+    /// have been added at the same index before the input is.
+    pub fn add_input(&mut self, txin: TxIn, input: Input) -> Result<(), Error> {
+        if self.global.version < 2 {
+            return Err(Error::UnsupportedVersion(self.global.version));
+        }
+        if !self.global.tx_modifiable.unwrap_or_default().has(TxModifiable::INPUTS) {
+            return Err(Error::InputsNotModifiable);
+        }
+        if !txin.script_sig.is_empty() {
+            return Err(Error::UnsignedTxHasScriptSigs);
+        }
+        if !txin.witness.is_empty() {
+            return Err(Error::UnsignedTxHasScriptWitnesses);
+        }
+        if self.global.unsigned_tx.input.iter().any(|i| i.previous_output == txin.previous_output) {
+            return Err(Error::DuplicateInput(txin.previous_output));
+        }
+        let index = self.inputs.len();
+        if has_sighash_single(&input) && index >= self.outputs.len() {
+            return Err(Error::UnpairedSigHashSingle(index));
+        }
+
+        let lock_time = {
+            let inputs = self.inputs.iter().chain(Some(&input));
+            determine_lock_time(inputs, self.global.fallback_locktime)?
+        };
+        if lock_time != self.global.unsigned_tx.lock_time && self.has_signatures() {
+            return Err(Error::LockTimeConflict);
+        }
+
+        self.global.unsigned_tx.input.push(txin);
+        self.global.unsigned_tx.lock_time = lock_time;
+        self.inputs.push(input);
+        Ok(())
+    }
+
+    /// Add an output paying `txout.value` to `txout.script_pubkey` to a
+    /// version 2 PSBT.
+    ///
+    /// The output may not take the index of an input signed with
+    /// SIGHASH_SINGLE, as that signature was made without a paired output.
+    pub fn add_output(&mut self, txout: TxOut, output: Output) -> Result<(), Error> {
+        if self.global.version < 2 {
+            return Err(Error::UnsupportedVersion(self.global.version));
+        }
+        let flags = self.global.tx_modifiable.unwrap_or_default();
+        if !flags.has(TxModifiable::OUTPUTS) {
+            return Err(Error::OutputsNotModifiable);
+        }
+        let index = self.outputs.len();
+        if flags.has(TxModifiable::SIGHASH_SINGLE) && self.inputs.get(index).map_or(false, has_sighash_single) {
+            return Err(Error::UnpairedSigHashSingle(index));
+        }
+
+        self.global.unsigned_tx.output.push(txout);
+        self.outputs.push(output);
+        Ok(())
+    }
+
+    /// Determine the lock time of the transaction from the lock times its
+    /// inputs require, as specified by BIP370.
+    ///
+    /// If no input requires a lock time, the fallback lock time is used. A
+    /// height-based lock time is chosen when every input that requires a
+    /// lock time accepts one, otherwise a time-based one is.
+    pub fn compute_lock_time(&self) -> Result<u32, Error> {
+        determine_lock_time(self.inputs.iter(), self.global.fallback_locktime)
+    }
+
+    /// Convert a version 0 PSBT into a version 2 PSBT describing the same
+    /// transaction. The result does not allow inputs or outputs to be
+    /// modified.
+    pub fn into_v2(mut self) -> Self {
+        if self.global.version < 2 {
+            let lock_time = self.global.unsigned_tx.lock_time;
+            self.global.version = 2;
+            self.global.fallback_locktime = if lock_time != 0 { Some(lock_time) } else { None };
+            self.global.tx_modifiable = None;
+        }
+        self
+    }
+
+    /// Convert a version 2 PSBT into a version 0 PSBT, fixing the lock time
+    /// of the unsigned transaction. Fails if the inputs require incompatible
+    /// lock times.
+    pub fn into_v0(mut self) -> Result<Self, Error> {
+        if self.global.version >= 2 {
+            self.global.unsigned_tx.lock_time = self.compute_lock_time()?;
+            self.global.version = 0;
+            self.global.fallback_locktime = None;
+            self.global.tx_modifiable = None;
+            for input in &mut self.inputs {
+                input.required_time_locktime = None;
+                input.required_height_locktime = None;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Restrict the modifiable flags of a version 2 PSBT after a signature
+    /// of type `sighash_type` was added, as the Signer role of BIP370 does.
+    pub(crate) fn update_tx_modifiable(&mut self, sighash_type: SigHashType) {
+        if self.global.version < 2 {
+            return;
+        }
+        let (sighash, anyone_can_pay) = sighash_type.split_anyonecanpay_flag();
+        let mut flags = self.global.tx_modifiable.unwrap_or_default();
+        if !anyone_can_pay {
+            flags.remove(TxModifiable::INPUTS);
+        }
+        if sighash != SigHashType::None {
+            flags.remove(TxModifiable::OUTPUTS);
+        }
+        if sighash == SigHashType::Single {
+            flags.add(TxModifiable::SIGHASH_SINGLE);
+        }
+        self.global.tx_modifiable = Some(flags);
+    }
+
+    /// Whether any input carries a partial or final signature.
+    fn has_signatures(&self) -> bool {
+        self.inputs.iter().any(|i| {
+            !i.partial_sigs.is_empty() || i.final_script_sig.is_some() || i.final_script_witness.is_some()
+        })
+    }
+}
+
+/// Whether any signature of `input` uses SIGHASH_SINGLE.
+fn has_sighash_single(input: &Input) -> bool {
+    let ecdsa_single = input.partial_sigs.values().any(|sig| {
+        let hash_ty = sig.last().map_or(0, |b| *b as u32);
+        SigHashType::from_u32(hash_ty).split_anyonecanpay_flag().0 == SigHashType::Single
+    });
+    let schnorr_single = |sig: &SchnorrSig| {
+        sig.hash_ty == SchnorrSigHashType::Single || sig.hash_ty == SchnorrSigHashType::SinglePlusAnyoneCanPay
+    };
+    ecdsa_single || input.tap_key_sig.as_ref().map_or(false, &schnorr_single)
+        || input.tap_script_sigs.values().any(&schnorr_single)
+}
+
+fn determine_lock_time<'a, I>(inputs: I, fallback_locktime: Option<u32>) -> Result<u32, Error>
+where
+    I: Iterator<Item = &'a Input>,
+{
+    let mut constrained = false;
+    let mut height: Option<u32> = Some(0);
+    let mut time: Option<u32> = Some(LOCKTIME_THRESHOLD);
+
+    for input in inputs {
+        if input.required_height_locktime.is_none() && input.required_time_locktime.is_none() {
+            continue;
+        }
+        constrained = true;
+        height = match (height, input.required_height_locktime) {
+            (Some(h), Some(req)) => Some(cmp::max(h, req)),
+            _ => None,
+        };
+        time = match (time, input.required_time_locktime) {
+            (Some(t), Some(req)) => Some(cmp::max(t, req)),
+            _ => None,
+        };
+    }
+
+    if !constrained {
+        return Ok(fallback_locktime.unwrap_or(0));
+    }
+    height.or(time).ok_or(Error::LockTimeConflict)
+}
+
+#[cfg(test)]
+mod tests {
+    use hashes::hex::FromHex;
+    use hash_types::Txid;
+
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, SigHashType, TxIn, TxOut};
+    use std::str::FromStr;
+
+    use consensus::Encodable;
+    use consensus::encode::{deserialize, serialize, serialize_hex};
+    use util::key::PublicKey;
+    use util::psbt::{Error, Input, Output, PartiallySignedTransaction, TxModifiable};
+    use util::psbt::raw;
+
+    fn txin(vout: u32) -> TxIn {
+        TxIn {
+            previous_output: OutPoint {
+                txid: Txid::from_hex("f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126").unwrap(),
+                vout: vout,
+            },
+            script_sig: Script::new(),
+            sequence: 0xfffffffd,
+            witness: vec![],
+        }
+    }
+
+    fn txout(value: u64) -> TxOut {
+        TxOut {
+            value: value,
+            script_pubkey: hex_script!("0014d0c59903c5bac2868760e90fd521a4665aa76520"),
+        }
+    }
+
+    fn height_input(height: u32) -> Input {
+        Input { required_height_locktime: Some(height), ..Default::default() }
+    }
+
+    fn time_input(time: u32) -> Input {
+        Input { required_time_locktime: Some(time), ..Default::default() }
+    }
+
+    #[test]
+    fn construct_and_roundtrip() {
+        let mut psbt = PartiallySignedTransaction::new_v2(2, Some(650_000));
+        psbt.add_input(txin(0), Default::default()).unwrap();
+        psbt.add_input(txin(1), height_input(700_000)).unwrap();
+        psbt.add_output(txout(50_000), Default::default()).unwrap();
+        psbt.inputs[0].unknown.insert(raw::Key { type_value: 0xf0, key: vec![1, 2] }, vec![3]);
+        psbt.outputs[0].unknown.insert(raw::Key { type_value: 0xf1, key: vec![] }, vec![4, 5]);
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 700_000);
+
+        let encoded = serialize(&psbt);
+        let decoded: PartiallySignedTransaction = deserialize(&encoded).unwrap();
+        assert_eq!(decoded, psbt);
+        assert_eq!(serialize(&decoded), encoded);
+
+        assert_eq!(
+            psbt.add_input(txin(1), Default::default()),
+            Err(Error::DuplicateInput(txin(1).previous_output))
+        );
+    }
+
+    #[test]
+    fn convert_versions() {
+        let mut psbt = PartiallySignedTransaction::new_v2(2, None);
+        psbt.add_input(txin(0), time_input(1_600_000_000)).unwrap();
+        psbt.add_output(txout(50_000), Output::default()).unwrap();
+
+        let v0 = psbt.clone().into_v0().unwrap();
+        assert_eq!(v0.global.version, 0);
+        assert_eq!(v0.global.unsigned_tx.lock_time, 1_600_000_000);
+        assert_eq!(v0.inputs[0].required_time_locktime, None);
+        let decoded: PartiallySignedTransaction = deserialize(&serialize(&v0)).unwrap();
+        assert_eq!(decoded, v0);
+
+        let v2 = v0.into_v2();
+        assert_eq!(v2.global.fallback_locktime, Some(1_600_000_000));
+        assert_eq!(v2.compute_lock_time(), Ok(1_600_000_000));
+        assert_eq!(v2.extract_tx(), psbt.extract_tx());
+    }
+
+    #[test]
+    fn lock_time_determination() {
+        let mut psbt = PartiallySignedTransaction::new_v2(2, Some(10));
+        psbt.add_input(txin(0), Default::default()).unwrap();
+        assert_eq!(psbt.compute_lock_time(), Ok(10));
+
+        // Both types possible, height is preferred
+        let both = Input {
+            required_height_locktime: Some(100),
+            required_time_locktime: Some(1_600_000_000),
+            ..Default::default()
+        };
+        psbt.add_input(txin(1), both).unwrap();
+        assert_eq!(psbt.compute_lock_time(), Ok(100));
+
+        // Only time remains possible
+        psbt.add_input(txin(2), time_input(1_700_000_000)).unwrap();
+        assert_eq!(psbt.compute_lock_time(), Ok(1_700_000_000));
+
+        // No type satisfies every input
+        assert_eq!(psbt.add_input(txin(3), height_input(200)), Err(Error::LockTimeConflict));
+        assert_eq!(psbt.inputs.len(), 3);
+    }
+
+    #[test]
+    fn modifiable_flags() {
+        let mut psbt = PartiallySignedTransaction::new_v2(2, None);
+        psbt.add_input(txin(0), Default::default()).unwrap();
+        psbt.update_tx_modifiable(SigHashType::SinglePlusAnyoneCanPay);
+        assert_eq!(psbt.global.tx_modifiable, Some(TxModifiable::INPUTS | TxModifiable::SIGHASH_SINGLE));
+        assert_eq!(psbt.add_output(txout(1), Default::default()), Err(Error::OutputsNotModifiable));
+        psbt.add_input(txin(1), Default::default()).unwrap();
+
+        psbt.update_tx_modifiable(SigHashType::All);
+        assert_eq!(psbt.add_input(txin(2), Default::default()), Err(Error::InputsNotModifiable));
+
+        let v0 = PartiallySignedTransaction::from_unsigned_tx(psbt.global.unsigned_tx.clone()).unwrap();
+        assert_eq!(v0.clone().add_output(txout(1), Default::default()), Err(Error::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn sighash_single_pairing() {
+        let pk = PublicKey::from_str("02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5").unwrap();
+        let mut signed = Input::default();
+        signed.partial_sigs.insert(pk, vec![0x30, 0x83]);
+
+        // The paired output has to come first
+        let mut psbt = PartiallySignedTransaction::new_v2(2, None);
+        assert_eq!(psbt.add_input(txin(0), signed.clone()), Err(Error::UnpairedSigHashSingle(0)));
+        psbt.add_output(txout(1), Default::default()).unwrap();
+        psbt.add_input(txin(0), signed.clone()).unwrap();
+        psbt.add_input(txin(1), Default::default()).unwrap();
+
+        // An output may not become the pair of a SIGHASH_SINGLE input signed
+        // without one
+        psbt.inputs[1] = signed;
+        psbt.global.tx_modifiable = Some(TxModifiable::INPUTS | TxModifiable::OUTPUTS | TxModifiable::SIGHASH_SINGLE);
+        assert_eq!(psbt.add_output(txout(2), Default::default()), Err(Error::UnpairedSigHashSingle(1)));
+        assert_eq!(psbt.outputs.len(), 1);
+        psbt.inputs[1] = Default::default();
+        psbt.add_output(txout(2), Default::default()).unwrap();
+    }
+
+    /// Splices `insert` into `hex` in place of `remove`, which must occur
+    /// exactly once
+    fn edit(hex: &str, remove: &str, insert: &str) -> Vec<u8> {
+        assert_eq!(hex.matches(remove).count(), 1, "{}", remove);
+        Vec::from_hex(&hex.replacen(remove, insert, 1)).unwrap()
+    }
+
+    #[test]
+    fn bip370_vectors() {
+        // "1 input, 2 output PSBTv2, required fields only" from BIP370
+        let valid = "70736274ff01020402000000010401010105010201fb040200000000010e200b0ad921419c1c8719735d72dc739f9ea9e0638d1fe4c1eef0f9944084815fc8010f04000000000001030808af0800000000000104160014c430f64c4756da310dbd1a085572ef299926272c000103088bbdeb0b0000000001041600144dd193ac964a56ac1b9e1cca8454fe2f474f851300";
+        let psbt: PartiallySignedTransaction = deserialize(&Vec::from_hex(valid).unwrap()).unwrap();
+        assert_eq!(serialize_hex(&psbt), valid);
+        assert_eq!(psbt.global.version, 2);
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 1);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 569_096);
+
+        // The invalid cases BIP370 lists, applied to the vector above
+        let txid_pair = "010e200b0ad921419c1c8719735d72dc739f9ea9e0638d1fe4c1eef0f9944084815fc8";
+        let index_pair = "010f0400000000";
+        let invalid = [
+            // PSBTv2 missing PSBT_GLOBAL_TX_VERSION
+            edit(valid, "0102040200000001", "01"),
+            // PSBTv2 missing PSBT_GLOBAL_INPUT_COUNT
+            edit(valid, "01040101", ""),
+            // PSBTv2 missing PSBT_GLOBAL_OUTPUT_COUNT
+            edit(valid, "01050102", ""),
+            // PSBTv2 missing PSBT_IN_PREVIOUS_TXID
+            edit(valid, txid_pair, ""),
+            // PSBTv2 missing PSBT_IN_OUTPUT_INDEX
+            edit(valid, index_pair, ""),
+            // PSBTv2 missing PSBT_OUT_AMOUNT
+            edit(valid, "01030808af080000000000", ""),
+            // PSBTv2 missing PSBT_OUT_SCRIPT
+            edit(valid, "0104160014c430f64c4756da310dbd1a085572ef299926272c", ""),
+            // PSBTv2 with PSBT_IN_REQUIRED_TIME_LOCKTIME less than 500000000
+            edit(valid, index_pair, &format!("{}011104ff64cd1d", index_pair)),
+            // PSBTv2 with PSBT_IN_REQUIRED_HEIGHT_LOCKTIME greater than or equal to 500000000
+            edit(valid, index_pair, &format!("{}0112040065cd1d", index_pair)),
+            // PSBTv2 with PSBT_GLOBAL_UNSIGNED_TX
+            edit(valid, "01fb04", "01000a0200000000000000000001fb04"),
+        ];
+        for (i, encoded) in invalid.iter().enumerate() {
+            assert!(deserialize::<PartiallySignedTransaction>(encoded).is_err(), "case {}", i);
+        }
+
+        // PSBTv0 carrying the fields of version 2
+        let v0 = serialize_hex(&psbt.clone().into_v0().unwrap());
+        let v0_invalid = [
+            edit(&v0, "70736274ff", "70736274ff01fb0402000000"),
+            edit(&v0, "70736274ff", "70736274ff01020402000000"),
+            edit(&v0, "70736274ff", "70736274ff010304ffffffff"),
+            edit(&v0, "70736274ff", "70736274ff01040101"),
+            edit(&v0, "70736274ff", "70736274ff01050102"),
+            edit(&v0, "70736274ff", "70736274ff01060100"),
+        ];
+        for (i, encoded) in v0_invalid.iter().enumerate() {
+            assert!(deserialize::<PartiallySignedTransaction>(encoded).is_err(), "v0 case {}", i);
+        }
+    }
+
+    #[test]
+    fn invalid_v2_encodings() {
+        let mut psbt = PartiallySignedTransaction::new_v2(2, None);
+        psbt.add_input(txin(0), Default::default()).unwrap();
+        psbt.add_output(txout(1), Default::default()).unwrap();
+        let encoded = serialize(&psbt);
+
+        // The version 1 PSBT is rejected
+        let mut v1 = psbt.clone();
+        v1.global.version = 1;
+        assert!(deserialize::<PartiallySignedTransaction>(&serialize(&v1)).is_err());
+
+        // A version 0 PSBT with a version 2 global field is rejected
+        let mut v0_encoded = serialize(&psbt.clone().into_v0().unwrap());
+        let tx_version_pair = Vec::from_hex("01020402000000").unwrap();
+        v0_encoded.splice(5..5, tx_version_pair);
+        assert!(deserialize::<PartiallySignedTransaction>(&v0_encoded).is_err());
+
+        // An input without its previous txid is rejected
+        let txid_pair = Vec::from_hex("010e20").unwrap();
+        let start = encoded.windows(3).position(|w| w == &txid_pair[..]).unwrap();
+        let mut missing_txid = encoded.clone();
+        missing_txid.drain(start..start + 3 + 32);
+        assert!(deserialize::<PartiallySignedTransaction>(&missing_txid).is_err());
+
+        // Maps which don't match the transaction are not serialized
+        let mut missing_output = psbt.clone();
+        missing_output.outputs.clear();
+        assert!(missing_output.consensus_encode(&mut Vec::new()).is_err());
+    }
+}
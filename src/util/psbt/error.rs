@@ -15,7 +15,7 @@
 use std::error;
 use std::fmt;
 
-use blockdata::transaction::Transaction;
+use blockdata::transaction::{OutPoint, Transaction};
 use consensus::encode;
 use util::psbt::raw;
 
@@ -76,6 +76,23 @@ pub enum Error {
     MergeConflict(String),
     /// Serialization error in bitcoin consensus-encoded structures
     ConsensusEncoding,
+    /// The operation is not supported by this version of PSBT.
+    UnsupportedVersion(u32),
+    /// The PSBT does not allow inputs to be added or removed.
+    InputsNotModifiable,
+    /// The PSBT does not allow outputs to be added or removed.
+    OutputsNotModifiable,
+    /// The PSBT already has an input spending this outpoint.
+    DuplicateInput(OutPoint),
+    /// The inputs require lock times of different types, or adding an input
+    /// would change the lock time of an already signed transaction.
+    LockTimeConflict,
+    /// The PSBT does not have exactly one input map per transaction input
+    /// and one output map per transaction output.
+    MapCountMismatch,
+    /// An input signed with SIGHASH_SINGLE is not paired with the output at
+    /// its own index.
+    UnpairedSigHashSingle(usize),
 }
 
 impl fmt::Display for Error {
@@ -101,6 +118,13 @@ impl fmt::Display for Error {
             }
             Error::MergeConflict(ref s) => { write!(f, "Merge conflict: {}", s) }
             Error::ConsensusEncoding => f.write_str("bitcoin consensus encoding error"),
+            Error::UnsupportedVersion(v) => write!(f, "operation not supported by PSBT version {}", v),
+            Error::InputsNotModifiable => f.write_str("the PSBT inputs are not modifiable"),
+            Error::OutputsNotModifiable => f.write_str("the PSBT outputs are not modifiable"),
+            Error::DuplicateInput(ref outpoint) => write!(f, "duplicate input spending {}", outpoint),
+            Error::LockTimeConflict => f.write_str("the required lock times of the inputs are incompatible"),
+            Error::MapCountMismatch => f.write_str("the number of input or output maps does not match the unsigned transaction"),
+            Error::UnpairedSigHashSingle(i) => write!(f, "SIGHASH_SINGLE input {} is not paired with output {}", i, i),
         }
     }
 }
@@ -0,0 +1,575 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Finalizer
+//!
+//! Implementation of the BIP174 finalizer role for standard scripts: P2PK,
+//! P2PKH, P2WPKH, P2SH-P2WPKH, bare, P2SH, P2WSH and P2SH-P2WSH multisig,
+//! and scripts guarded by hash preimages followed by one of these templates.
+
+use std::{error, fmt};
+use std::collections::BTreeMap;
+
+use blockdata::opcodes;
+use blockdata::script::{Builder, Instruction, Script};
+use blockdata::transaction::TxOut;
+use hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use util::key::PublicKey;
+use util::psbt::{Input, PartiallySignedTransaction};
+
+/// Ways that finalizing a PSBT input might fail
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum FinalizeError {
+    /// The input has neither a witness nor a non-witness UTXO
+    MissingUtxo,
+    /// The non-witness UTXO is not the transaction spent by the input
+    UtxoMismatch,
+    /// The output being spent is P2SH but the input has no redeem script
+    MissingRedeemScript,
+    /// The redeem script does not hash to the P2SH output
+    RedeemScriptMismatch,
+    /// The output being spent is P2WSH but the input has no witness script
+    MissingWitnessScript,
+    /// The witness script does not hash to the P2WSH program
+    WitnessScriptMismatch,
+    /// No signature for the given key
+    MissingSignature(PublicKey),
+    /// No signature for a key hashing to the given hash
+    MissingPubkeyHashSignature(hash160::Hash),
+    /// Not enough signatures to satisfy a multisig
+    NotEnoughSignatures {
+        /// Number of signatures required
+        required: usize,
+        /// Number of signatures available
+        found: usize,
+    },
+    /// No preimage for the given hash, which is given as a byte vector
+    MissingPreimage(Vec<u8>),
+    /// The script is not one of the supported templates
+    UnsupportedScript(Script),
+    /// The key of a P2WPKH output is uncompressed
+    UncompressedKey(PublicKey),
+}
+
+impl fmt::Display for FinalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FinalizeError::MissingUtxo => f.write_str("missing witness and non-witness utxo"),
+            FinalizeError::UtxoMismatch => f.write_str("non-witness utxo does not match the spent outpoint"),
+            FinalizeError::MissingRedeemScript => f.write_str("missing redeem script"),
+            FinalizeError::RedeemScriptMismatch => f.write_str("redeem script does not match the script pubkey"),
+            FinalizeError::MissingWitnessScript => f.write_str("missing witness script"),
+            FinalizeError::WitnessScriptMismatch => f.write_str("witness script does not match the witness program"),
+            FinalizeError::MissingSignature(ref pk) => write!(f, "missing signature for key {}", pk),
+            FinalizeError::MissingPubkeyHashSignature(ref hash) => write!(f, "missing signature for key hash {}", hash),
+            FinalizeError::NotEnoughSignatures { required, found } => {
+                write!(f, "not enough signatures: {} required, {} found", required, found)
+            }
+            FinalizeError::MissingPreimage(ref hash) => write!(f, "missing preimage for hash {:?}", hash),
+            FinalizeError::UnsupportedScript(ref script) => write!(f, "unsupported script: {}", script),
+            FinalizeError::UncompressedKey(ref pk) => write!(f, "uncompressed key {} in a P2WPKH output", pk),
+        }
+    }
+}
+
+impl error::Error for FinalizeError {}
+
+impl PartiallySignedTransaction {
+    /// Finalizes all the inputs which are not finalized yet, as the BIP174
+    /// finalizer role. All inputs are attempted, and the errors of those
+    /// which could not be finalized are returned along with their index.
+    pub fn finalize(&mut self) -> Result<(), Vec<(usize, FinalizeError)>> {
+        let errors: Vec<_> = (0..self.inputs.len())
+            .filter_map(|index| self.finalize_input(index).err().map(|e| (index, e)))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Finalizes the input at `index` from its partial signatures, scripts
+    /// and preimages. On success, the final scriptSig and witness are set
+    /// and all other fields but the UTXOs and unknown pairs are cleared.
+    /// Already finalized inputs are left untouched.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn finalize_input(&mut self, index: usize) -> Result<(), FinalizeError> {
+        let (script_sig, witness) = {
+            let input = &self.inputs[index];
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                return Ok(());
+            }
+
+            let prevout = self.global.unsigned_tx.input[index].previous_output;
+            let script_pubkey = match (&input.witness_utxo, &input.non_witness_utxo) {
+                (&Some(ref txout), _) => txout.script_pubkey.clone(),
+                (&None, &Some(ref tx)) => {
+                    if tx.txid() != prevout.txid {
+                        return Err(FinalizeError::UtxoMismatch);
+                    }
+                    tx.output.get(prevout.vout as usize)
+                        .map(|txout: &TxOut| txout.script_pubkey.clone())
+                        .ok_or(FinalizeError::UtxoMismatch)?
+                }
+                (&None, &None) => return Err(FinalizeError::MissingUtxo),
+            };
+
+            if script_pubkey.is_p2sh() {
+                let redeem_script = input.redeem_script.as_ref().ok_or(FinalizeError::MissingRedeemScript)?;
+                if Script::new_p2sh(&redeem_script.script_hash()) != script_pubkey {
+                    return Err(FinalizeError::RedeemScriptMismatch);
+                }
+                let push_redeem_script = Builder::new().push_slice(&redeem_script[..]).into_script();
+                if redeem_script.is_witness_program() {
+                    (push_redeem_script, finalize_witness_program(input, redeem_script)?)
+                } else {
+                    let mut stack = satisfy(input, redeem_script)?;
+                    stack.push(redeem_script.to_bytes());
+                    (script_sig_from_stack(&stack), vec![])
+                }
+            } else if script_pubkey.is_witness_program() {
+                (Script::new(), finalize_witness_program(input, &script_pubkey)?)
+            } else {
+                (script_sig_from_stack(&satisfy(input, &script_pubkey)?), vec![])
+            }
+        };
+
+        let input = &mut self.inputs[index];
+        *input = Input {
+            non_witness_utxo: input.non_witness_utxo.take(),
+            witness_utxo: input.witness_utxo.take(),
+            final_script_sig: if script_sig.is_empty() { None } else { Some(script_sig) },
+            final_script_witness: if witness.is_empty() { None } else { Some(witness) },
+            // Kept as they determine the lock time of version 2 PSBTs
+            required_time_locktime: input.required_time_locktime,
+            required_height_locktime: input.required_height_locktime,
+            proprietary: ::std::mem::replace(&mut input.proprietary, BTreeMap::new()),
+            unknown: ::std::mem::replace(&mut input.unknown, BTreeMap::new()),
+            ..Default::default()
+        };
+        Ok(())
+    }
+}
+
+/// Computes the witness spending a P2WPKH or P2WSH program
+fn finalize_witness_program(input: &Input, program: &Script) -> Result<Vec<Vec<u8>>, FinalizeError> {
+    if program.is_v0_p2wpkh() {
+        let (pk, sig) = pubkey_hash_signature(input, &program[2..])?;
+        // Uncompressed keys are not standard in segwit scripts
+        if !pk.compressed {
+            return Err(FinalizeError::UncompressedKey(*pk));
+        }
+        Ok(vec![sig.clone(), pk.to_bytes()])
+    } else if program.is_v0_p2wsh() {
+        let witness_script = input.witness_script.as_ref().ok_or(FinalizeError::MissingWitnessScript)?;
+        if Script::new_v0_wsh(&witness_script.wscript_hash()) != *program {
+            return Err(FinalizeError::WitnessScriptMismatch);
+        }
+        let mut stack = satisfy(input, witness_script)?;
+        stack.push(witness_script.to_bytes());
+        Ok(stack)
+    } else {
+        Err(FinalizeError::UnsupportedScript(program.clone()))
+    }
+}
+
+/// Finds the signature of a key hashing to `hash`
+fn pubkey_hash_signature<'a>(input: &'a Input, hash: &[u8]) -> Result<(&'a PublicKey, &'a Vec<u8>), FinalizeError> {
+    input.partial_sigs.iter()
+        .find(|&(pk, _)| pk.pubkey_hash()[..] == *hash)
+        .ok_or_else(|| FinalizeError::MissingPubkeyHashSignature(
+            hash160::Hash::from_slice(hash).expect("20-byte hash")
+        ))
+}
+
+/// Computes the stack satisfying `script`, as pushed by a scriptSig or witness
+fn satisfy(input: &Input, script: &Script) -> Result<Vec<Vec<u8>>, FinalizeError> {
+    let unsupported = || FinalizeError::UnsupportedScript(script.clone());
+    let instructions = script.instructions()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| unsupported())?;
+
+    // Hash locks, which are executed first and so consume the top of the stack
+    let mut preimages = vec![];
+    let mut ins = &instructions[..];
+    loop {
+        let size_check = ins.len() >= 3
+            && ins[0] == Instruction::Op(opcodes::all::OP_SIZE)
+            && ins[1] == Instruction::PushBytes(&[32])
+            && ins[2] == Instruction::Op(opcodes::all::OP_EQUALVERIFY);
+        let hash_lock = if size_check { &ins[3..] } else { ins };
+        if hash_lock.len() < 3 {
+            break;
+        }
+        let hash = match (&hash_lock[0], &hash_lock[1]) {
+            (&Instruction::Op(op), &Instruction::PushBytes(hash)) if is_hash_op(op) => (op, hash),
+            _ => break,
+        };
+        let last = match hash_lock[2] {
+            Instruction::Op(opcodes::all::OP_EQUAL) => true,
+            Instruction::Op(opcodes::all::OP_EQUALVERIFY) => false,
+            _ => break,
+        };
+        preimages.push(find_preimage(input, hash.0, hash.1)?);
+        ins = &hash_lock[3..];
+        if last {
+            if !ins.is_empty() {
+                return Err(unsupported());
+            }
+            preimages.reverse();
+            return Ok(preimages);
+        }
+    }
+
+    let mut stack = satisfy_keys(input, ins).ok_or_else(unsupported)??;
+    preimages.reverse();
+    stack.extend(preimages);
+    Ok(stack)
+}
+
+/// Satisfies a P2PK, P2PKH or multisig template, returning `None` if the
+/// instructions match none of them
+fn satisfy_keys(input: &Input, ins: &[Instruction]) -> Option<Result<Vec<Vec<u8>>, FinalizeError>> {
+    // <pk> OP_CHECKSIG
+    if ins.len() == 2 && ins[1] == Instruction::Op(opcodes::all::OP_CHECKSIG) {
+        let pk = match ins[0] {
+            Instruction::PushBytes(pk) => PublicKey::from_slice(pk).ok()?,
+            _ => return None,
+        };
+        return Some(match input.partial_sigs.get(&pk) {
+            Some(sig) => Ok(vec![sig.clone()]),
+            None => Err(FinalizeError::MissingSignature(pk)),
+        });
+    }
+
+    // OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+    if ins.len() == 5
+        && ins[0] == Instruction::Op(opcodes::all::OP_DUP)
+        && ins[1] == Instruction::Op(opcodes::all::OP_HASH160)
+        && ins[3] == Instruction::Op(opcodes::all::OP_EQUALVERIFY)
+        && ins[4] == Instruction::Op(opcodes::all::OP_CHECKSIG)
+    {
+        let hash = match ins[2] {
+            Instruction::PushBytes(hash) if hash.len() == 20 => hash,
+            _ => return None,
+        };
+        return Some(pubkey_hash_signature(input, hash).map(|(pk, sig)| vec![sig.clone(), pk.to_bytes()]));
+    }
+
+    // <k> <pk>... <n> OP_CHECKMULTISIG
+    if ins.len() >= 4 && ins[ins.len() - 1] == Instruction::Op(opcodes::all::OP_CHECKMULTISIG) {
+        let k = push_num(&ins[0])?;
+        let n = push_num(&ins[ins.len() - 2])?;
+        let keys = ins[1..ins.len() - 2].iter()
+            .map(|i| match *i {
+                Instruction::PushBytes(pk) => PublicKey::from_slice(pk).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if k == 0 || k > n || n != keys.len() {
+            return None;
+        }
+        // Signatures must be in the same order as their keys
+        let sigs: Vec<Vec<u8>> = keys.iter()
+            .filter_map(|pk| input.partial_sigs.get(pk).cloned())
+            .take(k)
+            .collect();
+        if sigs.len() < k {
+            return Some(Err(FinalizeError::NotEnoughSignatures { required: k, found: sigs.len() }));
+        }
+        // Dummy element consumed by the off-by-one bug of OP_CHECKMULTISIG
+        let mut stack = vec![vec![]];
+        stack.extend(sigs);
+        return Some(Ok(stack));
+    }
+
+    None
+}
+
+fn push_num(ins: &Instruction) -> Option<usize> {
+    match *ins {
+        Instruction::Op(op) => match op.classify() {
+            opcodes::Class::PushNum(n) if n > 0 => Some(n as usize),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_hash_op(op: opcodes::All) -> bool {
+    op == opcodes::all::OP_SHA256 || op == opcodes::all::OP_HASH256
+        || op == opcodes::all::OP_RIPEMD160 || op == opcodes::all::OP_HASH160
+}
+
+fn find_preimage(input: &Input, op: opcodes::All, hash: &[u8]) -> Result<Vec<u8>, FinalizeError> {
+    let preimage = if op == opcodes::all::OP_SHA256 {
+        sha256::Hash::from_slice(hash).ok().and_then(|h| input.sha256_preimages.get(&h))
+    } else if op == opcodes::all::OP_HASH256 {
+        sha256d::Hash::from_slice(hash).ok().and_then(|h| input.hash256_preimages.get(&h))
+    } else if op == opcodes::all::OP_RIPEMD160 {
+        ripemd160::Hash::from_slice(hash).ok().and_then(|h| input.ripemd160_preimages.get(&h))
+    } else {
+        hash160::Hash::from_slice(hash).ok().and_then(|h| input.hash160_preimages.get(&h))
+    };
+    preimage.cloned().ok_or_else(|| FinalizeError::MissingPreimage(hash.to_vec()))
+}
+
+/// Pushes `stack` with the minimal push of every element, as `MINIMALDATA`
+/// requires: single bytes 1 to 16 and 0x81 have their own opcodes
+fn script_sig_from_stack(stack: &[Vec<u8>]) -> Script {
+    stack.iter()
+        .fold(Builder::new(), |builder, elem| match elem[..] {
+            [n @ 1..=16] => builder.push_int(n as i64),
+            [0x81] => builder.push_int(-1),
+            _ => builder.push_slice(elem),
+        })
+        .into_script()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use blockdata::transaction::{OutPoint, Transaction, TxIn};
+    use hash_types::WPubkeyHash;
+    use util::psbt::PartiallySignedTransaction;
+
+    const KEYS: [&str; 3] = [
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+        "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+    ];
+
+    fn key(i: usize) -> PublicKey {
+        PublicKey::from_str(KEYS[i]).unwrap()
+    }
+
+    fn sig(i: usize) -> Vec<u8> {
+        vec![0x30, i as u8, 0x01]
+    }
+
+    /// A PSBT with a single input spending `script_pubkey` through its witness utxo
+    fn psbt_spending(script_pubkey: Script) -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: 10_000, script_pubkey: script_pubkey });
+        psbt
+    }
+
+    fn multisig(k: i64, keys: &[PublicKey]) -> Script {
+        let mut builder = Builder::new().push_int(k);
+        for key in keys {
+            builder = builder.push_key(key);
+        }
+        builder.push_int(keys.len() as i64).push_opcode(opcodes::all::OP_CHECKMULTISIG).into_script()
+    }
+
+    #[test]
+    fn finalize_p2pkh() {
+        let spk = Script::new_p2pkh(&key(0).pubkey_hash());
+        let mut psbt = psbt_spending(spk.clone());
+        psbt.inputs[0].witness_utxo = None;
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::MissingUtxo));
+
+        let prev = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut { value: 10_000, script_pubkey: spk }],
+        };
+        psbt.inputs[0].non_witness_utxo = Some(prev.clone());
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::UtxoMismatch));
+
+        psbt.global.unsigned_tx.input[0].previous_output = OutPoint::new(prev.txid(), 0);
+        psbt.inputs[0].partial_sigs.insert(key(1), sig(1));
+        assert_eq!(
+            psbt.finalize_input(0),
+            Err(FinalizeError::MissingPubkeyHashSignature(hash160::Hash::from_inner(key(0).pubkey_hash().into_inner())))
+        );
+
+        psbt.inputs[0].partial_sigs.insert(key(0), sig(0));
+        psbt.inputs[0].bip32_derivation.insert(key(0), Default::default());
+        psbt.finalize_input(0).unwrap();
+        let input = &psbt.inputs[0];
+        assert_eq!(input.final_script_sig, Some(Builder::new().push_slice(&sig(0)).push_key(&key(0)).into_script()));
+        assert_eq!(input.final_script_witness, None);
+        assert!(input.partial_sigs.is_empty());
+        assert!(input.bip32_derivation.is_empty());
+        assert_eq!(input.non_witness_utxo, Some(prev));
+
+        let tx = psbt.extract_tx();
+        assert_eq!(tx.input[0].script_sig, Builder::new().push_slice(&sig(0)).push_key(&key(0)).into_script());
+    }
+
+    #[test]
+    fn finalize_p2wpkh_and_p2sh_p2wpkh() {
+        let program = Script::new_v0_wpkh(&key(0).wpubkey_hash().unwrap());
+        let mut psbt = psbt_spending(program.clone());
+        psbt.inputs[0].partial_sigs.insert(key(0), sig(0));
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(psbt.inputs[0].final_script_sig, None);
+        assert_eq!(psbt.inputs[0].final_script_witness, Some(vec![sig(0), key(0).to_bytes()]));
+
+        let mut psbt = psbt_spending(Script::new_p2sh(&program.script_hash()));
+        psbt.inputs[0].partial_sigs.insert(key(0), sig(0));
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::MissingRedeemScript));
+        psbt.inputs[0].redeem_script = Some(Script::new_v0_wpkh(&key(1).wpubkey_hash().unwrap()));
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::RedeemScriptMismatch));
+        psbt.inputs[0].redeem_script = Some(program.clone());
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(psbt.inputs[0].final_script_sig, Some(Builder::new().push_slice(&program[..]).into_script()));
+        assert_eq!(psbt.inputs[0].final_script_witness, Some(vec![sig(0), key(0).to_bytes()]));
+        assert_eq!(psbt.inputs[0].redeem_script, None);
+
+        let uncompressed = PublicKey { compressed: false, key: key(0).key };
+        let hash = WPubkeyHash::from_slice(&uncompressed.pubkey_hash()[..]).unwrap();
+        let mut psbt = psbt_spending(Script::new_v0_wpkh(&hash));
+        psbt.inputs[0].partial_sigs.insert(uncompressed, sig(0));
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::UncompressedKey(uncompressed)));
+    }
+
+    #[test]
+    fn finalize_multisig() {
+        let keys = [key(0), key(1), key(2)];
+        let script = multisig(2, &keys);
+
+        // Bare
+        let mut psbt = psbt_spending(script.clone());
+        psbt.inputs[0].partial_sigs.insert(key(2), sig(2));
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::NotEnoughSignatures { required: 2, found: 1 }));
+        psbt.inputs[0].partial_sigs.insert(key(0), sig(0));
+        psbt.inputs[0].partial_sigs.insert(key(1), sig(1));
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(
+            psbt.inputs[0].final_script_sig,
+            Some(Builder::new().push_slice(&[]).push_slice(&sig(0)).push_slice(&sig(1)).into_script())
+        );
+
+        // P2SH
+        let mut psbt = psbt_spending(Script::new_p2sh(&script.script_hash()));
+        psbt.inputs[0].redeem_script = Some(script.clone());
+        psbt.inputs[0].partial_sigs.insert(key(2), sig(2));
+        psbt.inputs[0].partial_sigs.insert(key(1), sig(1));
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(
+            psbt.inputs[0].final_script_sig,
+            Some(Builder::new().push_slice(&[]).push_slice(&sig(1)).push_slice(&sig(2)).push_slice(&script[..]).into_script())
+        );
+
+        // P2WSH
+        let program = Script::new_v0_wsh(&script.wscript_hash());
+        let mut psbt = psbt_spending(program.clone());
+        psbt.inputs[0].partial_sigs.insert(key(2), sig(2));
+        psbt.inputs[0].partial_sigs.insert(key(0), sig(0));
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::MissingWitnessScript));
+        psbt.inputs[0].witness_script = Some(multisig(1, &keys));
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::WitnessScriptMismatch));
+        psbt.inputs[0].witness_script = Some(script.clone());
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(psbt.inputs[0].final_script_witness, Some(vec![vec![], sig(0), sig(2), script.to_bytes()]));
+
+        // P2SH-P2WSH
+        let mut psbt = psbt_spending(Script::new_p2sh(&program.script_hash()));
+        psbt.inputs[0].redeem_script = Some(program.clone());
+        psbt.inputs[0].witness_script = Some(script.clone());
+        psbt.inputs[0].partial_sigs.insert(key(1), sig(1));
+        psbt.inputs[0].partial_sigs.insert(key(0), sig(0));
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(psbt.inputs[0].final_script_sig, Some(Builder::new().push_slice(&program[..]).into_script()));
+        assert_eq!(psbt.inputs[0].final_script_witness, Some(vec![vec![], sig(0), sig(1), script.to_bytes()]));
+    }
+
+    #[test]
+    fn minimal_pushes() {
+        let stack = [vec![], vec![0x01], vec![0x10], vec![0x11], vec![0x81], vec![0x00], vec![0x01, 0x02]];
+        assert_eq!(script_sig_from_stack(&stack), hex_script!("00516001114f0100020102"));
+    }
+
+    #[test]
+    fn finalize_hash_locks() {
+        let preimage = vec![0x42; 32];
+        let sha = sha256::Hash::hash(&preimage);
+        let ripemd = ripemd160::Hash::hash(&preimage);
+
+        // OP_SIZE 32 OP_EQUALVERIFY OP_SHA256 <h> OP_EQUALVERIFY OP_RIPEMD160 <h> OP_EQUALVERIFY <pk> OP_CHECKSIG
+        let script = Builder::new()
+            .push_opcode(opcodes::all::OP_SIZE).push_int(32).push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_SHA256).push_slice(&sha[..]).push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_opcode(opcodes::all::OP_RIPEMD160).push_slice(&ripemd[..]).push_opcode(opcodes::all::OP_EQUALVERIFY)
+            .push_key(&key(0)).push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        let mut psbt = psbt_spending(Script::new_v0_wsh(&script.wscript_hash()));
+        psbt.inputs[0].witness_script = Some(script.clone());
+        psbt.inputs[0].partial_sigs.insert(key(0), sig(0));
+        psbt.inputs[0].sha256_preimages.insert(sha, preimage.clone());
+        assert_eq!(psbt.finalize_input(0), Err(FinalizeError::MissingPreimage(ripemd[..].to_vec())));
+        psbt.inputs[0].ripemd160_preimages.insert(ripemd, preimage.clone());
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(
+            psbt.inputs[0].final_script_witness,
+            Some(vec![sig(0), preimage.clone(), preimage.clone(), script.to_bytes()])
+        );
+        assert!(psbt.inputs[0].sha256_preimages.is_empty());
+
+        // A bare hash lock needs no signature
+        let script = Builder::new()
+            .push_opcode(opcodes::all::OP_HASH160).push_slice(&hash160::Hash::hash(&preimage)[..])
+            .push_opcode(opcodes::all::OP_EQUAL)
+            .into_script();
+        let mut psbt = psbt_spending(Script::new_p2sh(&script.script_hash()));
+        psbt.inputs[0].redeem_script = Some(script.clone());
+        psbt.inputs[0].hash160_preimages.insert(hash160::Hash::hash(&preimage), preimage.clone());
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(
+            psbt.inputs[0].final_script_sig,
+            Some(Builder::new().push_slice(&preimage).push_slice(&script[..]).into_script())
+        );
+    }
+
+    #[test]
+    fn finalize_reports_errors() {
+        let op_return = Script::new_op_return(&[]);
+        let mut psbt = psbt_spending(op_return.clone());
+        let mut input = psbt.global.unsigned_tx.input[0].clone();
+        input.previous_output.vout = 1;
+        psbt.global.unsigned_tx.input.push(input);
+        psbt.inputs.push(Input {
+            witness_utxo: Some(TxOut { value: 1, script_pubkey: Script::new_p2pk(&key(1)) }),
+            ..Default::default()
+        });
+        psbt.inputs[1].partial_sigs.insert(key(1), sig(1));
+
+        assert_eq!(psbt.finalize(), Err(vec![(0, FinalizeError::UnsupportedScript(op_return))]));
+        assert_eq!(psbt.inputs[1].final_script_sig, Some(Builder::new().push_slice(&sig(1)).into_script()));
+        // Finalized inputs are left alone
+        psbt.inputs[0].final_script_sig = Some(Script::new());
+        assert_eq!(psbt.finalize(), Ok(()));
+    }
+}
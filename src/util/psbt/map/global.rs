@@ -15,10 +15,11 @@
 use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
 use std::io::{self, Cursor, Read};
-use std::cmp;
+use std::{cmp, fmt, mem, ops};
 
-use blockdata::transaction::Transaction;
+use blockdata::transaction::{Transaction, TxIn, TxOut};
 use consensus::{encode, Encodable, Decodable};
+use consensus::encode::VarInt;
 use util::psbt::map::Map;
 use util::psbt::raw;
 use util::psbt;
@@ -30,20 +31,107 @@ use util::bip32::{ExtendedPubKey, KeySource, Fingerprint, DerivationPath, ChildN
 const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
 /// Type: Extended Public Key PSBT_GLOBAL_XPUB = 0x01
 const PSBT_GLOBAL_XPUB: u8 = 0x01;
+/// Type: Transaction Version PSBT_GLOBAL_TX_VERSION = 0x02
+const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+/// Type: Fallback Locktime PSBT_GLOBAL_FALLBACK_LOCKTIME = 0x03
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+/// Type: Input Count PSBT_GLOBAL_INPUT_COUNT = 0x04
+const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+/// Type: Output Count PSBT_GLOBAL_OUTPUT_COUNT = 0x05
+const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+/// Type: Transaction Modifiable Flags PSBT_GLOBAL_TX_MODIFIABLE = 0x06
+const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
 /// Type: Version Number PSBT_GLOBAL_VERSION = 0xFB
 const PSBT_GLOBAL_VERSION: u8 = 0xFB;
 /// Type: Proprietary Use Type PSBT_GLOBAL_PROPRIETARY = 0xFC
 const PSBT_GLOBAL_PROPRIETARY: u8 = 0xFC;
 
+/// The flags of a version 2 PSBT indicating which parts of its unsigned
+/// transaction may still be modified, as defined by BIP 370.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TxModifiable(u8);
+
+impl TxModifiable {
+    /// Nothing may be modified.
+    pub const NONE: TxModifiable = TxModifiable(0);
+    /// Inputs may be added or removed.
+    pub const INPUTS: TxModifiable = TxModifiable(1 << 0);
+    /// Outputs may be added or removed.
+    pub const OUTPUTS: TxModifiable = TxModifiable(1 << 1);
+    /// Some input has a SIGHASH_SINGLE signature, so inputs and outputs must
+    /// be added or removed in pairs to keep their indices matched.
+    pub const SIGHASH_SINGLE: TxModifiable = TxModifiable(1 << 2);
+
+    /// Add [TxModifiable] flags to this.
+    ///
+    /// Returns itself.
+    pub fn add(&mut self, other: TxModifiable) -> TxModifiable {
+        self.0 |= other.0;
+        *self
+    }
+
+    /// Remove [TxModifiable] flags from this.
+    ///
+    /// Returns itself.
+    pub fn remove(&mut self, other: TxModifiable) -> TxModifiable {
+        self.0 &= !other.0;
+        *self
+    }
+
+    /// Check whether [TxModifiable] flags are included in this one.
+    pub fn has(self, flags: TxModifiable) -> bool {
+        (self.0 | flags.0) == self.0
+    }
+
+    /// Get the integer representation of these [TxModifiable] flags.
+    pub fn as_u8(self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::LowerHex for TxModifiable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl From<u8> for TxModifiable {
+    fn from(f: u8) -> Self {
+        TxModifiable(f)
+    }
+}
+
+impl ops::BitOr for TxModifiable {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: Self) -> Self {
+        self.add(rhs)
+    }
+}
+
+impl ops::BitOrAssign for TxModifiable {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.add(rhs);
+    }
+}
+
 /// A key-value map for global data.
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Global {
     /// The unsigned transaction, scriptSigs and witnesses for each input must be
-    /// empty.
+    /// empty. For version 2 PSBTs this is assembled from the per-input and
+    /// per-output fields, and its lock time is the one determined from them.
     pub unsigned_tx: Transaction,
     /// The version number of this PSBT. If omitted, the version number is 0.
     pub version: u32,
+    /// The lock time to use if no input requires a particular one (version 2
+    /// only). If omitted, the fallback is 0.
+    pub fallback_locktime: Option<u32>,
+    /// Which parts of the unsigned transaction may be modified (version 2
+    /// only). If omitted, nothing may be modified.
+    pub tx_modifiable: Option<TxModifiable>,
     /// A global map from extended public keys to the used key fingerprint and
     /// derivation path as defined by BIP 32
     pub xpub: BTreeMap<ExtendedPubKey, KeySource>,
@@ -72,6 +160,8 @@ impl Global {
             unsigned_tx: tx,
             xpub: Default::default(),
             version: 0,
+            fallback_locktime: None,
+            tx_modifiable: None,
             proprietary: Default::default(),
             unknown: Default::default(),
         })
@@ -86,7 +176,12 @@ impl Map for Global {
         } = pair;
 
         match raw_key.type_value {
-            PSBT_GLOBAL_UNSIGNED_TX => return Err(Error::DuplicateKey(raw_key).into()),
+            PSBT_GLOBAL_UNSIGNED_TX
+            | PSBT_GLOBAL_TX_VERSION
+            | PSBT_GLOBAL_FALLBACK_LOCKTIME
+            | PSBT_GLOBAL_INPUT_COUNT
+            | PSBT_GLOBAL_OUTPUT_COUNT
+            | PSBT_GLOBAL_TX_MODIFIABLE => return Err(Error::DuplicateKey(raw_key).into()),
             PSBT_GLOBAL_PROPRIETARY => match self.proprietary.entry(raw::ProprietaryKey::from_key(raw_key.clone())?) {
                 Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
                 Entry::Occupied(_) => return Err(Error::DuplicateKey(raw_key).into()),
@@ -103,22 +198,25 @@ impl Map for Global {
     fn get_pairs(&self) -> Result<Vec<raw::Pair>, io::Error> {
         let mut rv: Vec<raw::Pair> = Default::default();
 
-        rv.push(raw::Pair {
-            key: raw::Key {
-                type_value: PSBT_GLOBAL_UNSIGNED_TX,
-                key: vec![],
-            },
-            value: {
-                // Manually serialized to ensure 0-input txs are serialized
-                // without witnesses.
-                let mut ret = Vec::new();
-                self.unsigned_tx.version.consensus_encode(&mut ret)?;
-                self.unsigned_tx.input.consensus_encode(&mut ret)?;
-                self.unsigned_tx.output.consensus_encode(&mut ret)?;
-                self.unsigned_tx.lock_time.consensus_encode(&mut ret)?;
-                ret
-            },
-        });
+        // Version 2 PSBTs carry the unsigned transaction in separate fields
+        if self.version < 2 {
+            rv.push(raw::Pair {
+                key: raw::Key {
+                    type_value: PSBT_GLOBAL_UNSIGNED_TX,
+                    key: vec![],
+                },
+                value: {
+                    // Manually serialized to ensure 0-input txs are serialized
+                    // without witnesses.
+                    let mut ret = Vec::new();
+                    self.unsigned_tx.version.consensus_encode(&mut ret)?;
+                    self.unsigned_tx.input.consensus_encode(&mut ret)?;
+                    self.unsigned_tx.output.consensus_encode(&mut ret)?;
+                    self.unsigned_tx.lock_time.consensus_encode(&mut ret)?;
+                    ret
+                },
+            });
+        }
 
         for (xpub, (fingerprint, derivation)) in &self.xpub {
             rv.push(raw::Pair {
@@ -135,6 +233,18 @@ impl Map for Global {
             });
         }
 
+        if self.version >= 2 {
+            rv.push(v2_pair(PSBT_GLOBAL_TX_VERSION, &self.unsigned_tx.version)?);
+            if let Some(fallback_locktime) = self.fallback_locktime {
+                rv.push(v2_pair(PSBT_GLOBAL_FALLBACK_LOCKTIME, &fallback_locktime)?);
+            }
+            rv.push(v2_pair(PSBT_GLOBAL_INPUT_COUNT, &VarInt(self.unsigned_tx.input.len() as u64))?);
+            rv.push(v2_pair(PSBT_GLOBAL_OUTPUT_COUNT, &VarInt(self.unsigned_tx.output.len() as u64))?);
+            if let Some(tx_modifiable) = self.tx_modifiable {
+                rv.push(v2_pair(PSBT_GLOBAL_TX_MODIFIABLE, &tx_modifiable.as_u8())?);
+            }
+        }
+
         // Serializing version only for non-default value; otherwise test vectors fail
         if self.version > 0 {
             rv.push(raw::Pair {
@@ -179,6 +289,20 @@ impl Map for Global {
         // Keeping the highest version
         self.version = cmp::max(self.version, other.version);
 
+        merge!(fallback_locktime, self, other);
+
+        // Something may only be modified if both sides allow it, while a
+        // SIGHASH_SINGLE signature on either side restricts both
+        if self.tx_modifiable.is_some() || other.tx_modifiable.is_some() {
+            let ours = self.tx_modifiable.unwrap_or_default();
+            let theirs = other.tx_modifiable.unwrap_or_default();
+            let mut merged = TxModifiable(ours.0 & theirs.0);
+            if ours.has(TxModifiable::SIGHASH_SINGLE) || theirs.has(TxModifiable::SIGHASH_SINGLE) {
+                merged.add(TxModifiable::SIGHASH_SINGLE);
+            }
+            self.tx_modifiable = Some(merged);
+        }
+
         // Merging xpubs
         for (xpub, (fingerprint1, derivation1)) in other.xpub {
             match self.xpub.entry(xpub) {
@@ -232,6 +356,11 @@ impl Decodable for Global {
 
         let mut tx: Option<Transaction> = None;
         let mut version: Option<u32> = None;
+        let mut tx_version: Option<i32> = None;
+        let mut fallback_locktime: Option<u32> = None;
+        let mut input_count: Option<VarInt> = None;
+        let mut output_count: Option<VarInt> = None;
+        let mut tx_modifiable: Option<u8> = None;
         let mut unknowns: BTreeMap<raw::Key, Vec<u8>> = Default::default();
         let mut xpub_map: BTreeMap<ExtendedPubKey, (Fingerprint, DerivationPath)> = Default::default();
         let mut proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>> = Default::default();
@@ -307,11 +436,6 @@ impl Decodable for Global {
                                         return Err(encode::Error::ParseFailed("Wrong global version value length (must be 4 bytes)"))
                                     }
                                     version = Some(Decodable::consensus_decode(&mut decoder)?);
-                                    // We only understand version 0 PSBTs. According to BIP-174 we
-                                    // should throw an error if we see anything other than version 0.
-                                    if version != Some(0) {
-                                        return Err(encode::Error::ParseFailed("PSBT versions greater than 0 are not supported"))
-                                    }
                                 } else {
                                     return Err(Error::DuplicateKey(pair.key).into())
                                 }
@@ -319,6 +443,11 @@ impl Decodable for Global {
                                 return Err(Error::InvalidKey(pair.key).into())
                             }
                         }
+                        PSBT_GLOBAL_TX_VERSION => insert_v2_value(&mut tx_version, pair)?,
+                        PSBT_GLOBAL_FALLBACK_LOCKTIME => insert_v2_value(&mut fallback_locktime, pair)?,
+                        PSBT_GLOBAL_INPUT_COUNT => insert_v2_value(&mut input_count, pair)?,
+                        PSBT_GLOBAL_OUTPUT_COUNT => insert_v2_value(&mut output_count, pair)?,
+                        PSBT_GLOBAL_TX_MODIFIABLE => insert_v2_value(&mut tx_modifiable, pair)?,
                         PSBT_GLOBAL_PROPRIETARY => match proprietary.entry(raw::ProprietaryKey::from_key(pair.key.clone())?) {
                             Entry::Vacant(empty_key) => {empty_key.insert(pair.value);},
                             Entry::Occupied(_) => return Err(Error::DuplicateKey(pair.key).into()),
@@ -334,14 +463,95 @@ impl Decodable for Global {
             }
         }
 
-        if let Some(tx) = tx {
-            let mut rv: Global = Global::from_unsigned_tx(tx)?;
-            rv.version = version.unwrap_or(0);
-            rv.xpub = xpub_map;
-            rv.unknown = unknowns;
-            Ok(rv)
-        } else {
-            Err(Error::MustHaveUnsignedTx.into())
-        }
+        let version = version.unwrap_or(0);
+        let has_v2_fields = tx_version.is_some() || fallback_locktime.is_some()
+            || input_count.is_some() || output_count.is_some() || tx_modifiable.is_some();
+
+        // We only understand version 0 and version 2 PSBTs. According to BIP-174
+        // we should throw an error if we see any other version.
+        let mut rv = match version {
+            0 => {
+                if has_v2_fields {
+                    return Err(encode::Error::ParseFailed("PSBTv0 must not have PSBTv2 global fields"))
+                }
+                match tx {
+                    Some(tx) => Global::from_unsigned_tx(tx)?,
+                    None => return Err(Error::MustHaveUnsignedTx.into()),
+                }
+            }
+            2 => {
+                if tx.is_some() {
+                    return Err(encode::Error::ParseFailed("PSBTv2 must not have an unsigned transaction"))
+                }
+                let tx_version = tx_version
+                    .ok_or(encode::Error::ParseFailed("PSBTv2 must have a transaction version"))?;
+                let input_count = input_count
+                    .ok_or(encode::Error::ParseFailed("PSBTv2 must have an input count"))?;
+                let output_count = output_count
+                    .ok_or(encode::Error::ParseFailed("PSBTv2 must have an output count"))?;
+
+                // The inputs and outputs are filled in from their own maps,
+                // and the lock time once those are known.
+                Global {
+                    unsigned_tx: Transaction {
+                        version: tx_version,
+                        lock_time: fallback_locktime.unwrap_or(0),
+                        input: vec![TxIn::default(); checked_count::<TxIn>(input_count)?],
+                        output: vec![TxOut::default(); checked_count::<TxOut>(output_count)?],
+                    },
+                    version: version,
+                    fallback_locktime: fallback_locktime,
+                    tx_modifiable: tx_modifiable.map(TxModifiable),
+                    xpub: Default::default(),
+                    proprietary: Default::default(),
+                    unknown: Default::default(),
+                }
+            }
+            _ => return Err(encode::Error::ParseFailed("PSBT versions other than 0 and 2 are not supported")),
+        };
+
+        rv.version = version;
+        rv.xpub = xpub_map;
+        rv.proprietary = proprietary;
+        rv.unknown = unknowns;
+        Ok(rv)
+    }
+}
+
+/// Insert the value of a unkeyed PSBTv2 global field, which may only appear
+/// once.
+fn insert_v2_value<T: Decodable>(slot: &mut Option<T>, pair: raw::Pair) -> Result<(), encode::Error> {
+    if !pair.key.key.is_empty() {
+        return Err(Error::InvalidKey(pair.key).into())
+    }
+    if slot.is_some() {
+        return Err(Error::DuplicateKey(pair.key).into())
+    }
+    *slot = Some(encode::deserialize(&pair.value)?);
+    Ok(())
+}
+
+/// Build the key-value pair of a unkeyed PSBTv2 global field.
+fn v2_pair<T: Encodable>(type_value: u8, value: &T) -> Result<raw::Pair, io::Error> {
+    let mut ret = Vec::new();
+    value.consensus_encode(&mut ret)?;
+    Ok(raw::Pair {
+        key: raw::Key {
+            type_value: type_value,
+            key: vec![],
+        },
+        value: ret,
+    })
+}
+
+/// Convert a PSBTv2 input or output count, refusing ones whose placeholder
+/// vector would exceed the allocation limit used for consensus decoding.
+fn checked_count<T>(count: VarInt) -> Result<usize, encode::Error> {
+    let byte_size = (count.0 as usize)
+        .checked_mul(mem::size_of::<T>())
+        .ok_or(encode::Error::ParseFailed("PSBTv2 count is too large"))?;
+    if byte_size > encode::MAX_VEC_SIZE {
+        return Err(encode::Error::OversizedVectorAllocation { requested: byte_size, max: encode::MAX_VEC_SIZE })
     }
+    Ok(count.0 as usize)
 }
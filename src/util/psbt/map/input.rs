@@ -12,20 +12,23 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
-use std::io;
+use std::{fmt, io, str};
 use std::collections::btree_map::{BTreeMap, Entry};
 
 use blockdata::script::Script;
-use blockdata::transaction::{SigHashType, Transaction, TxOut};
-use consensus::encode;
+use blockdata::transaction::{SigHashType, Transaction, TxIn, TxOut};
+use consensus::{encode, Decodable};
+use hash_types::Txid;
 use util::bip32::KeySource;
+use util::bip341::{self, SchnorrSigHashType};
 use hashes::{self, hash160, ripemd160, sha256, sha256d};
-use util::key::PublicKey;
+use util::key::{PublicKey, SchnorrSig, XOnlyPublicKey};
 use util::psbt;
 use util::psbt::map::Map;
 use util::psbt::raw;
 use util::psbt::serialize::Deserialize;
 use util::psbt::{Error, error};
+use util::taproot::{ControlBlock, LeafVersion, TapBranchHash, TapLeafHash};
 
 /// Type: Non-Witness UTXO PSBT_IN_NON_WITNESS_UTXO = 0x00
 const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
@@ -53,6 +56,28 @@ const PSBT_IN_SHA256: u8 = 0x0b;
 const PSBT_IN_HASH160: u8 = 0x0c;
 /// Type: HASH256 preimage PSBT_IN_HASH256 = 0x0d
 const PSBT_IN_HASH256: u8 = 0x0d;
+/// Type: Previous TXID PSBT_IN_PREVIOUS_TXID = 0x0e
+const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+/// Type: Spent Output Index PSBT_IN_OUTPUT_INDEX = 0x0f
+const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+/// Type: Sequence Number PSBT_IN_SEQUENCE = 0x10
+const PSBT_IN_SEQUENCE: u8 = 0x10;
+/// Type: Required Time-based Locktime PSBT_IN_REQUIRED_TIME_LOCKTIME = 0x11
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+/// Type: Required Height-based Locktime PSBT_IN_REQUIRED_HEIGHT_LOCKTIME = 0x12
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+/// Type: Taproot Key Spend Signature PSBT_IN_TAP_KEY_SIG = 0x13
+const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+/// Type: Taproot Script Spend Signature PSBT_IN_TAP_SCRIPT_SIG = 0x14
+const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+/// Type: Taproot Leaf Script PSBT_IN_TAP_LEAF_SCRIPT = 0x15
+const PSBT_IN_TAP_LEAF_SCRIPT: u8 = 0x15;
+/// Type: Taproot Key BIP 32 Derivation Path PSBT_IN_TAP_BIP32_DERIVATION = 0x16
+const PSBT_IN_TAP_BIP32_DERIVATION: u8 = 0x16;
+/// Type: Taproot Internal Key PSBT_IN_TAP_INTERNAL_KEY = 0x17
+const PSBT_IN_TAP_INTERNAL_KEY: u8 = 0x17;
+/// Type: Taproot Merkle Root PSBT_IN_TAP_MERKLE_ROOT = 0x18
+const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
 /// Type: Proprietary Use Type PSBT_IN_PROPRIETARY = 0xFC
 const PSBT_IN_PROPRIETARY: u8 = 0xFC;
 
@@ -75,7 +100,7 @@ pub struct Input {
     pub partial_sigs: BTreeMap<PublicKey, Vec<u8>>,
     /// The sighash type to be used for this input. Signatures for this input
     /// must use the sighash type.
-    pub sighash_type: Option<SigHashType>,
+    pub sighash_type: Option<PsbtSigHashType>,
     /// The redeem script for this input.
     pub redeem_script: Option<Script>,
     /// The witness script for this input.
@@ -103,6 +128,31 @@ pub struct Input {
     /// HAS256 hash to preimage map
     #[cfg_attr(feature = "serde", serde(with = "::serde_utils::btreemap_byte_values"))]
     pub hash256_preimages: BTreeMap<sha256d::Hash, Vec<u8>>,
+    /// The minimum Unix timestamp lock time this input requires, if any
+    /// (PSBT version 2 only).
+    pub required_time_locktime: Option<u32>,
+    /// The minimum block height lock time this input requires, if any
+    /// (PSBT version 2 only).
+    pub required_height_locktime: Option<u32>,
+    /// The signature for a taproot key path spend of this input.
+    pub tap_key_sig: Option<SchnorrSig>,
+    /// A map from x-only public keys and the hashes of the leaves they sign
+    /// for to the corresponding taproot script path signatures.
+    #[cfg_attr(feature = "serde", serde(with = "::serde_utils::btreemap_as_seq"))]
+    pub tap_script_sigs: BTreeMap<(XOnlyPublicKey, TapLeafHash), SchnorrSig>,
+    /// A map from control blocks to the leaf scripts and leaf versions they
+    /// prove the commitment of, for spending this input by script path.
+    #[cfg_attr(feature = "serde", serde(with = "::serde_utils::btreemap_as_seq"))]
+    pub tap_scripts: BTreeMap<ControlBlock, (Script, LeafVersion)>,
+    /// A map from x-only public keys needed to sign this input to the hashes
+    /// of the leaves they are used in and their corresponding master key
+    /// fingerprints and derivation paths.
+    #[cfg_attr(feature = "serde", serde(with = "::serde_utils::btreemap_as_seq"))]
+    pub tap_bip32_derivation: BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+    /// The taproot internal key of the output this input spends.
+    pub tap_internal_key: Option<XOnlyPublicKey>,
+    /// The taproot merkle root of the output this input spends.
+    pub tap_merkle_root: Option<TapBranchHash>,
     /// Proprietary key-value pairs for this input.
     #[cfg_attr(feature = "serde", serde(with = "::serde_utils::btreemap_as_seq_byte_values"))]
     pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
@@ -111,6 +161,96 @@ pub struct Input {
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 }
 
+/// The sighash type of a PSBT input. Unlike [`SigHashType`], it can hold
+/// `SIGHASH_DEFAULT` for taproot inputs as well as any non-standard value,
+/// which is only rejected when the input is signed.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PsbtSigHashType(u32);
+
+impl PsbtSigHashType {
+    /// Creates a [PsbtSigHashType] from any raw value.
+    pub fn from_u32(n: u32) -> PsbtSigHashType {
+        PsbtSigHashType(n)
+    }
+
+    /// Get the raw value of this [PsbtSigHashType].
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the [`SigHashType`] to sign legacy and segwit v0 inputs with,
+    /// if this is a standard ECDSA sighash type.
+    pub fn ecdsa_hash_ty(self) -> Result<SigHashType, Error> {
+        let sighash_type = SigHashType::from_u32(self.0);
+        if sighash_type.as_u32() == self.0 {
+            Ok(sighash_type)
+        } else {
+            Err(Error::NonStandardSigHashType(self.0))
+        }
+    }
+
+    /// Returns the [`SchnorrSigHashType`] to sign taproot inputs with, if this
+    /// is a valid one.
+    pub fn schnorr_hash_ty(self) -> Result<SchnorrSigHashType, bip341::Error> {
+        if self.0 > 0xff {
+            return Err(bip341::Error::InvalidSigHashType(self.0));
+        }
+        SchnorrSigHashType::from_u8(self.0 as u8)
+    }
+}
+
+impl fmt::Display for PsbtSigHashType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.schnorr_hash_ty() {
+            Ok(sighash_type) => fmt::Display::fmt(&sighash_type, f),
+            Err(_) => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+impl str::FromStr for PsbtSigHashType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(sighash_type) = s.parse::<SchnorrSigHashType>() {
+            return Ok(sighash_type.into());
+        }
+        if s.starts_with("0x") {
+            if let Ok(n) = u32::from_str_radix(&s[2..], 16) {
+                return Ok(PsbtSigHashType(n));
+            }
+        }
+        Err("can't recognize SIGHASH string".to_string())
+    }
+}
+
+impl From<SigHashType> for PsbtSigHashType {
+    fn from(sighash_type: SigHashType) -> Self {
+        PsbtSigHashType(sighash_type.as_u32())
+    }
+}
+
+impl From<SchnorrSigHashType> for PsbtSigHashType {
+    fn from(sighash_type: SchnorrSigHashType) -> Self {
+        PsbtSigHashType(sighash_type.as_u8() as u32)
+    }
+}
+
+impl Input {
+    /// The sighash type to sign this legacy or segwit v0 input with,
+    /// `SIGHASH_ALL` if none is specified.
+    pub fn ecdsa_hash_ty(&self) -> Result<SigHashType, Error> {
+        self.sighash_type.map_or(Ok(SigHashType::All), PsbtSigHashType::ecdsa_hash_ty)
+    }
+
+    /// The sighash type to sign this taproot input with, `SIGHASH_DEFAULT` if
+    /// none is specified.
+    pub fn schnorr_hash_ty(&self) -> Result<SchnorrSigHashType, bip341::Error> {
+        self.sighash_type.map_or(Ok(SchnorrSigHashType::Default), PsbtSigHashType::schnorr_hash_ty)
+    }
+}
+
 impl Map for Input {
     fn insert_pair(&mut self, pair: raw::Pair) -> Result<(), encode::Error> {
         let raw::Pair {
@@ -136,7 +276,7 @@ impl Map for Input {
             }
             PSBT_IN_SIGHASH_TYPE => {
                 impl_psbt_insert_pair! {
-                    self.sighash_type <= <raw_key: _>|<raw_value: SigHashType>
+                    self.sighash_type <= <raw_key: _>|<raw_value: PsbtSigHashType>
                 }
             }
             PSBT_IN_REDEEM_SCRIPT => {
@@ -176,6 +316,58 @@ impl Map for Input {
             PSBT_IN_HASH256 => {
                 psbt_insert_hash_pair(&mut self.hash256_preimages, raw_key, raw_value, error::PsbtHash::Hash256)?;
             }
+            PSBT_IN_REQUIRED_TIME_LOCKTIME => {
+                impl_psbt_insert_pair! {
+                    self.required_time_locktime <= <raw_key: _>|<raw_value: u32>
+                }
+                match self.required_time_locktime {
+                    Some(t) if t < 500_000_000 => {
+                        return Err(encode::Error::ParseFailed("required time-based lock time out of range"))
+                    }
+                    _ => {}
+                }
+            }
+            PSBT_IN_REQUIRED_HEIGHT_LOCKTIME => {
+                impl_psbt_insert_pair! {
+                    self.required_height_locktime <= <raw_key: _>|<raw_value: u32>
+                }
+                match self.required_height_locktime {
+                    Some(h) if h == 0 || h >= 500_000_000 => {
+                        return Err(encode::Error::ParseFailed("required height-based lock time out of range"))
+                    }
+                    _ => {}
+                }
+            }
+            PSBT_IN_TAP_KEY_SIG => {
+                impl_psbt_insert_pair! {
+                    self.tap_key_sig <= <raw_key: _>|<raw_value: SchnorrSig>
+                }
+            }
+            PSBT_IN_TAP_SCRIPT_SIG => {
+                impl_psbt_insert_pair! {
+                    self.tap_script_sigs <= <raw_key: (XOnlyPublicKey, TapLeafHash)>|<raw_value: SchnorrSig>
+                }
+            }
+            PSBT_IN_TAP_LEAF_SCRIPT => {
+                impl_psbt_insert_pair! {
+                    self.tap_scripts <= <raw_key: ControlBlock>|<raw_value: (Script, LeafVersion)>
+                }
+            }
+            PSBT_IN_TAP_BIP32_DERIVATION => {
+                impl_psbt_insert_pair! {
+                    self.tap_bip32_derivation <= <raw_key: XOnlyPublicKey>|<raw_value: (Vec<TapLeafHash>, KeySource)>
+                }
+            }
+            PSBT_IN_TAP_INTERNAL_KEY => {
+                impl_psbt_insert_pair! {
+                    self.tap_internal_key <= <raw_key: _>|<raw_value: XOnlyPublicKey>
+                }
+            }
+            PSBT_IN_TAP_MERKLE_ROOT => {
+                impl_psbt_insert_pair! {
+                    self.tap_merkle_root <= <raw_key: _>|<raw_value: TapBranchHash>
+                }
+            }
             PSBT_IN_PROPRIETARY => match self.proprietary.entry(raw::ProprietaryKey::from_key(raw_key.clone())?) {
                 ::std::collections::btree_map::Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
                 ::std::collections::btree_map::Entry::Occupied(_) => return Err(Error::DuplicateKey(raw_key).into()),
@@ -209,7 +401,7 @@ impl Map for Input {
         }
 
         impl_psbt_get_pair! {
-            rv.push(self.sighash_type as <PSBT_IN_SIGHASH_TYPE, _>|<SigHashType>)
+            rv.push(self.sighash_type as <PSBT_IN_SIGHASH_TYPE, _>|<PsbtSigHashType>)
         }
 
         impl_psbt_get_pair! {
@@ -248,6 +440,38 @@ impl Map for Input {
             rv.push(self.hash256_preimages as <PSBT_IN_HASH256, sha256d::Hash>|<Vec<u8>>)
         }
 
+        impl_psbt_get_pair! {
+            rv.push(self.required_time_locktime as <PSBT_IN_REQUIRED_TIME_LOCKTIME, _>|<u32>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.required_height_locktime as <PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, _>|<u32>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.tap_key_sig as <PSBT_IN_TAP_KEY_SIG, _>|<SchnorrSig>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.tap_script_sigs as <PSBT_IN_TAP_SCRIPT_SIG, (XOnlyPublicKey, TapLeafHash)>|<SchnorrSig>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.tap_scripts as <PSBT_IN_TAP_LEAF_SCRIPT, ControlBlock>|<(Script, LeafVersion)>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.tap_bip32_derivation as <PSBT_IN_TAP_BIP32_DERIVATION, XOnlyPublicKey>|<(Vec<TapLeafHash>, KeySource)>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.tap_internal_key as <PSBT_IN_TAP_INTERNAL_KEY, _>|<XOnlyPublicKey>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.tap_merkle_root as <PSBT_IN_TAP_MERKLE_ROOT, _>|<TapBranchHash>)
+        }
+
         for (key, value) in self.proprietary.iter() {
             rv.push(raw::Pair {
                 key: key.to_key(),
@@ -279,6 +503,9 @@ impl Map for Input {
         self.sha256_preimages.extend(other.sha256_preimages);
         self.hash160_preimages.extend(other.hash160_preimages);
         self.hash256_preimages.extend(other.hash256_preimages);
+        self.tap_script_sigs.extend(other.tap_script_sigs);
+        self.tap_scripts.extend(other.tap_scripts);
+        self.tap_bip32_derivation.extend(other.tap_bip32_derivation);
         self.proprietary.extend(other.proprietary);
         self.unknown.extend(other.unknown);
 
@@ -286,6 +513,11 @@ impl Map for Input {
         merge!(witness_script, self, other);
         merge!(final_script_sig, self, other);
         merge!(final_script_witness, self, other);
+        merge!(required_time_locktime, self, other);
+        merge!(required_height_locktime, self, other);
+        merge!(tap_key_sig, self, other);
+        merge!(tap_internal_key, self, other);
+        merge!(tap_merkle_root, self, other);
 
         Ok(())
     }
@@ -293,6 +525,87 @@ impl Map for Input {
 
 impl_psbtmap_consensus_enc_dec_oding!(Input);
 
+/// The fields of a version 2 input map which describe the unsigned
+/// transaction rather than the input's signing data.
+#[derive(Default)]
+struct TxInFields {
+    txid: Option<Txid>,
+    vout: Option<u32>,
+    sequence: Option<u32>,
+}
+
+impl Input {
+    /// Decode a version 2 input map, writing the previous output and sequence
+    /// number it carries into `txin`.
+    pub(crate) fn decode_v2<D: io::Read>(mut d: D, txin: &mut TxIn) -> Result<Input, encode::Error> {
+        let mut rv = Input::default();
+        let mut fields = TxInFields::default();
+
+        loop {
+            match raw::Pair::consensus_decode(&mut d) {
+                Ok(pair) => match pair.key.type_value {
+                    PSBT_IN_PREVIOUS_TXID => {
+                        let raw::Pair { key: raw_key, value: raw_value } = pair;
+                        impl_psbt_insert_pair! {
+                            fields.txid <= <raw_key: _>|<raw_value: Txid>
+                        }
+                    }
+                    PSBT_IN_OUTPUT_INDEX => {
+                        let raw::Pair { key: raw_key, value: raw_value } = pair;
+                        impl_psbt_insert_pair! {
+                            fields.vout <= <raw_key: _>|<raw_value: u32>
+                        }
+                    }
+                    PSBT_IN_SEQUENCE => {
+                        let raw::Pair { key: raw_key, value: raw_value } = pair;
+                        impl_psbt_insert_pair! {
+                            fields.sequence <= <raw_key: _>|<raw_value: u32>
+                        }
+                    }
+                    _ => rv.insert_pair(pair)?,
+                },
+                Err(encode::Error::Psbt(Error::NoMorePairs)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        txin.previous_output.txid = fields.txid
+            .ok_or(encode::Error::ParseFailed("PSBTv2 input must have a previous txid"))?;
+        txin.previous_output.vout = fields.vout
+            .ok_or(encode::Error::ParseFailed("PSBTv2 input must have an output index"))?;
+        txin.sequence = fields.sequence.unwrap_or(0xffffffff);
+        Ok(rv)
+    }
+
+    /// Get all key-value pairs of a version 2 input map, including the
+    /// previous output and sequence number taken from `txin`.
+    pub(crate) fn get_pairs_v2(&self, txin: &TxIn) -> Result<Vec<raw::Pair>, io::Error> {
+        let fields = TxInFields {
+            txid: Some(txin.previous_output.txid),
+            vout: Some(txin.previous_output.vout),
+            // The sequence number defaults to final when omitted
+            sequence: if txin.sequence != 0xffffffff { Some(txin.sequence) } else { None },
+        };
+
+        let mut rv: Vec<raw::Pair> = Default::default();
+
+        impl_psbt_get_pair! {
+            rv.push(fields.txid as <PSBT_IN_PREVIOUS_TXID, _>|<Txid>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(fields.vout as <PSBT_IN_OUTPUT_INDEX, _>|<u32>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(fields.sequence as <PSBT_IN_SEQUENCE, _>|<u32>)
+        }
+
+        rv.extend(self.get_pairs()?);
+        Ok(rv)
+    }
+}
+
 fn psbt_insert_hash_pair<H>(
     map: &mut BTreeMap<H, Vec<u8>>,
     raw_key: raw::Key,
@@ -35,6 +35,6 @@ mod global;
 mod input;
 mod output;
 
-pub use self::global::Global;
-pub use self::input::Input;
+pub use self::global::{Global, TxModifiable};
+pub use self::input::{Input, PsbtSigHashType};
 pub use self::output::Output;
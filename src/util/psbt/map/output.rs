@@ -17,13 +17,15 @@ use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
 
 use blockdata::script::Script;
-use consensus::encode;
+use blockdata::transaction::TxOut;
+use consensus::{encode, Decodable};
 use util::bip32::KeySource;
-use util::key::PublicKey;
+use util::key::{PublicKey, XOnlyPublicKey};
 use util::psbt;
 use util::psbt::map::Map;
 use util::psbt::raw;
 use util::psbt::Error;
+use util::taproot::TapLeafHash;
 
 /// Type: Redeem Script PSBT_OUT_REDEEM_SCRIPT = 0x00
 const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
@@ -31,6 +33,14 @@ const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
 const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
 /// Type: BIP 32 Derivation Path PSBT_OUT_BIP32_DERIVATION = 0x02
 const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+/// Type: Output Amount PSBT_OUT_AMOUNT = 0x03
+const PSBT_OUT_AMOUNT: u8 = 0x03;
+/// Type: Output Script PSBT_OUT_SCRIPT = 0x04
+const PSBT_OUT_SCRIPT: u8 = 0x04;
+/// Type: Taproot Internal Key PSBT_OUT_TAP_INTERNAL_KEY = 0x05
+const PSBT_OUT_TAP_INTERNAL_KEY: u8 = 0x05;
+/// Type: Taproot Key BIP 32 Derivation Path PSBT_OUT_TAP_BIP32_DERIVATION = 0x07
+const PSBT_OUT_TAP_BIP32_DERIVATION: u8 = 0x07;
 /// Type: Proprietary Use Type PSBT_IN_PROPRIETARY = 0xFC
 const PSBT_OUT_PROPRIETARY: u8 = 0xFC;
 
@@ -47,6 +57,13 @@ pub struct Output {
     /// corresponding master key fingerprints and derivation paths.
     #[cfg_attr(feature = "serde", serde(with = "::serde_utils::btreemap_as_seq"))]
     pub bip32_derivation: BTreeMap<PublicKey, KeySource>,
+    /// The taproot internal key of this output.
+    pub tap_internal_key: Option<XOnlyPublicKey>,
+    /// A map from x-only public keys needed to spend this output to the
+    /// hashes of the leaves they are used in and their corresponding master
+    /// key fingerprints and derivation paths.
+    #[cfg_attr(feature = "serde", serde(with = "::serde_utils::btreemap_as_seq"))]
+    pub tap_bip32_derivation: BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
     /// Proprietary key-value pairs for this output.
     #[cfg_attr(feature = "serde", serde(with = "::serde_utils::btreemap_as_seq_byte_values"))]
     pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
@@ -78,6 +95,16 @@ impl Map for Output {
                     self.bip32_derivation <= <raw_key: PublicKey>|<raw_value: KeySource>
                 }
             }
+            PSBT_OUT_TAP_INTERNAL_KEY => {
+                impl_psbt_insert_pair! {
+                    self.tap_internal_key <= <raw_key: _>|<raw_value: XOnlyPublicKey>
+                }
+            }
+            PSBT_OUT_TAP_BIP32_DERIVATION => {
+                impl_psbt_insert_pair! {
+                    self.tap_bip32_derivation <= <raw_key: XOnlyPublicKey>|<raw_value: (Vec<TapLeafHash>, KeySource)>
+                }
+            }
             PSBT_OUT_PROPRIETARY => match self.proprietary.entry(raw::ProprietaryKey::from_key(raw_key.clone())?) {
                 Entry::Vacant(empty_key) => {empty_key.insert(raw_value);},
                 Entry::Occupied(_) => return Err(Error::DuplicateKey(raw_key.clone()).into()),
@@ -106,6 +133,14 @@ impl Map for Output {
             rv.push(self.bip32_derivation as <PSBT_OUT_BIP32_DERIVATION, PublicKey>|<KeySource>)
         }
 
+        impl_psbt_get_pair! {
+            rv.push(self.tap_internal_key as <PSBT_OUT_TAP_INTERNAL_KEY, _>|<XOnlyPublicKey>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.tap_bip32_derivation as <PSBT_OUT_TAP_BIP32_DERIVATION, XOnlyPublicKey>|<(Vec<TapLeafHash>, KeySource)>)
+        }
+
         for (key, value) in self.proprietary.iter() {
             rv.push(raw::Pair {
                 key: key.to_key(),
@@ -125,14 +160,83 @@ impl Map for Output {
 
     fn merge(&mut self, other: Self) -> Result<(), psbt::Error> {
         self.bip32_derivation.extend(other.bip32_derivation);
+        self.tap_bip32_derivation.extend(other.tap_bip32_derivation);
         self.proprietary.extend(other.proprietary);
         self.unknown.extend(other.unknown);
 
         merge!(redeem_script, self, other);
         merge!(witness_script, self, other);
+        merge!(tap_internal_key, self, other);
 
         Ok(())
     }
 }
 
 impl_psbtmap_consensus_enc_dec_oding!(Output);
+
+/// The fields of a version 2 output map which describe the unsigned
+/// transaction rather than the output's metadata.
+#[derive(Default)]
+struct TxOutFields {
+    amount: Option<u64>,
+    script: Option<Script>,
+}
+
+impl Output {
+    /// Decode a version 2 output map, writing the amount and script it
+    /// carries into `txout`.
+    pub(crate) fn decode_v2<D: io::Read>(mut d: D, txout: &mut TxOut) -> Result<Output, encode::Error> {
+        let mut rv = Output::default();
+        let mut fields = TxOutFields::default();
+
+        loop {
+            match raw::Pair::consensus_decode(&mut d) {
+                Ok(pair) => match pair.key.type_value {
+                    PSBT_OUT_AMOUNT => {
+                        let raw::Pair { key: raw_key, value: raw_value } = pair;
+                        impl_psbt_insert_pair! {
+                            fields.amount <= <raw_key: _>|<raw_value: u64>
+                        }
+                    }
+                    PSBT_OUT_SCRIPT => {
+                        let raw::Pair { key: raw_key, value: raw_value } = pair;
+                        impl_psbt_insert_pair! {
+                            fields.script <= <raw_key: _>|<raw_value: Script>
+                        }
+                    }
+                    _ => rv.insert_pair(pair)?,
+                },
+                Err(encode::Error::Psbt(Error::NoMorePairs)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        txout.value = fields.amount
+            .ok_or(encode::Error::ParseFailed("PSBTv2 output must have an amount"))?;
+        txout.script_pubkey = fields.script
+            .ok_or(encode::Error::ParseFailed("PSBTv2 output must have a script"))?;
+        Ok(rv)
+    }
+
+    /// Get all key-value pairs of a version 2 output map, including the
+    /// amount and script taken from `txout`.
+    pub(crate) fn get_pairs_v2(&self, txout: &TxOut) -> Result<Vec<raw::Pair>, io::Error> {
+        let fields = TxOutFields {
+            amount: Some(txout.value),
+            script: Some(txout.script_pubkey.clone()),
+        };
+
+        let mut rv: Vec<raw::Pair> = Default::default();
+
+        impl_psbt_get_pair! {
+            rv.push(fields.amount as <PSBT_OUT_AMOUNT, _>|<u64>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(fields.script as <PSBT_OUT_SCRIPT, _>|<Script>)
+        }
+
+        rv.extend(self.get_pairs()?);
+        Ok(rv)
+    }
+}
@@ -17,6 +17,7 @@
 //! Implementation of BIP174 Partially Signed Bitcoin Transaction Format as
 //! defined at https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
 //! except we define PSBTs containing non-standard SigHash types as invalid.
+//! Version 2 PSBTs, as defined by BIP370, are supported as well.
 
 use blockdata::script::Script;
 use blockdata::transaction::Transaction;
@@ -35,7 +36,15 @@ mod macros;
 pub mod serialize;
 
 mod map;
-pub use self::map::{Map, Global, Input, Output};
+pub use self::map::{Map, Global, Input, Output, PsbtSigHashType, TxModifiable};
+
+mod constructor;
+
+mod finalizer;
+pub use self::finalizer::FinalizeError;
+
+mod signer;
+pub use self::signer::{GetKey, SignError, SigningKeys, SigningErrors};
 
 /// A Partially Signed Transaction.
 #[derive(Debug, Clone, PartialEq)]
@@ -103,6 +112,29 @@ impl Encodable for PartiallySignedTransaction {
 
         len += self.global.consensus_encode(&mut s)?;
 
+        if self.global.version >= 2 {
+            let tx = &self.global.unsigned_tx;
+            if self.inputs.len() != tx.input.len() || self.outputs.len() != tx.output.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, Error::MapCountMismatch));
+            }
+
+            for (i, txin) in self.inputs.iter().zip(tx.input.iter()) {
+                for pair in i.get_pairs_v2(txin)? {
+                    len += pair.consensus_encode(&mut s)?;
+                }
+                len += 0x00_u8.consensus_encode(&mut s)?;
+            }
+
+            for (o, txout) in self.outputs.iter().zip(tx.output.iter()) {
+                for pair in o.get_pairs_v2(txout)? {
+                    len += pair.consensus_encode(&mut s)?;
+                }
+                len += 0x00_u8.consensus_encode(&mut s)?;
+            }
+
+            return Ok(len);
+        }
+
         for i in &self.inputs {
             len += i.consensus_encode(&mut s)?;
         }
@@ -127,7 +159,27 @@ impl Decodable for PartiallySignedTransaction {
             return Err(Error::InvalidSeparator.into());
         }
 
-        let global: Global = Decodable::consensus_decode(&mut d)?;
+        let mut global: Global = Decodable::consensus_decode(&mut d)?;
+
+        if global.version >= 2 {
+            let mut inputs: Vec<Input> = Vec::with_capacity(global.unsigned_tx.input.len());
+            for txin in global.unsigned_tx.input.iter_mut() {
+                inputs.push(Input::decode_v2(&mut d, txin)?);
+            }
+
+            let mut outputs: Vec<Output> = Vec::with_capacity(global.unsigned_tx.output.len());
+            for txout in global.unsigned_tx.output.iter_mut() {
+                outputs.push(Output::decode_v2(&mut d, txout)?);
+            }
+
+            let mut psbt = PartiallySignedTransaction {
+                global: global,
+                inputs: inputs,
+                outputs: outputs,
+            };
+            psbt.global.unsigned_tx.lock_time = psbt.compute_lock_time()?;
+            return Ok(psbt);
+        }
 
         let inputs: Vec<Input> = {
             let inputs_len: usize = (&global.unsigned_tx.input).len();
@@ -135,7 +187,11 @@ impl Decodable for PartiallySignedTransaction {
             let mut inputs: Vec<Input> = Vec::with_capacity(inputs_len);
 
             for _ in 0..inputs_len {
-                inputs.push(Decodable::consensus_decode(&mut d)?);
+                let input: Input = Decodable::consensus_decode(&mut d)?;
+                if input.required_time_locktime.is_some() || input.required_height_locktime.is_some() {
+                    return Err(encode::Error::ParseFailed("PSBTv0 inputs must not have required lock times"));
+                }
+                inputs.push(input);
             }
 
             inputs
@@ -194,6 +250,8 @@ mod tests {
                 },
                 xpub: Default::default(),
                 version: 0,
+                fallback_locktime: None,
+                tx_modifiable: None,
                 proprietary: BTreeMap::new(),
                 unknown: BTreeMap::new(),
             },
@@ -284,6 +342,8 @@ mod tests {
             },
             xpub: Default::default(),
             version: 0,
+            fallback_locktime: None,
+            tx_modifiable: None,
             proprietary: Default::default(),
             unknown: Default::default(),
         };
@@ -308,6 +368,46 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn psbt_sighash_type() {
+        use blockdata::transaction::SigHashType;
+        use util::bip341::SchnorrSigHashType;
+        use util::psbt::PsbtSigHashType;
+        use util::psbt::serialize::{Deserialize, Serialize};
+
+        for &ecdsa in &[
+            SigHashType::All, SigHashType::None, SigHashType::Single,
+            SigHashType::AllPlusAnyoneCanPay, SigHashType::NonePlusAnyoneCanPay, SigHashType::SinglePlusAnyoneCanPay,
+        ] {
+            let sighash_type = PsbtSigHashType::from(ecdsa);
+            assert_eq!(sighash_type.to_string().parse::<PsbtSigHashType>(), Ok(sighash_type));
+            assert_eq!(sighash_type.ecdsa_hash_ty(), Ok(ecdsa));
+            assert_eq!(sighash_type.schnorr_hash_ty(), Ok(SchnorrSigHashType::from(ecdsa)));
+        }
+
+        // SIGHASH_DEFAULT is only valid for taproot inputs
+        let default: PsbtSigHashType = Deserialize::deserialize(&[0, 0, 0, 0]).unwrap();
+        assert_eq!(default.to_string(), "SIGHASH_DEFAULT");
+        assert_eq!(default.schnorr_hash_ty(), Ok(SchnorrSigHashType::Default));
+        assert_eq!(default.ecdsa_hash_ty(), Err(super::Error::NonStandardSigHashType(0)));
+
+        // Non-standard values are kept as they are
+        let non_standard: PsbtSigHashType = Deserialize::deserialize(&[0x84, 0, 0, 0]).unwrap();
+        assert_eq!(non_standard.serialize(), vec![0x84, 0, 0, 0]);
+        assert_eq!(non_standard.to_string(), "0x84");
+        assert_eq!("0x84".parse::<PsbtSigHashType>(), Ok(non_standard));
+        assert!(non_standard.schnorr_hash_ty().is_err());
+        assert_eq!(non_standard.ecdsa_hash_ty(), Err(super::Error::NonStandardSigHashType(0x84)));
+        assert!("SIGHASH_ANYONECANPAY".parse::<PsbtSigHashType>().is_err());
+
+        let mut input = Input::default();
+        assert_eq!(input.ecdsa_hash_ty(), Ok(SigHashType::All));
+        assert_eq!(input.schnorr_hash_ty(), Ok(SchnorrSigHashType::Default));
+        input.sighash_type = Some(SigHashType::NonePlusAnyoneCanPay.into());
+        assert_eq!(input.ecdsa_hash_ty(), Ok(SigHashType::NonePlusAnyoneCanPay));
+        assert_eq!(input.schnorr_hash_ty(), Ok(SchnorrSigHashType::NonePlusAnyoneCanPay));
+    }
+
     #[test]
     fn deserialize_and_serialize_psbt_with_two_partial_sigs() {
         let hex = "70736274ff0100890200000001207ae985d787dfe6143d5c58fad79cc7105e0e799fcf033b7f2ba17e62d7b3200000000000ffffffff02563d03000000000022002019899534b9a011043c0dd57c3ff9a381c3522c5f27c6a42319085b56ca543a1d6adc020000000000220020618b47a07ebecca4e156edb1b9ea7c24bdee0139fc049237965ffdaf56d5ee73000000000001012b801a0600000000002200201148e93e9315e37dbed2121be5239257af35adc03ffdfc5d914b083afa44dab82202025fe7371376d53cf8a2783917c28bf30bd690b0a4d4a207690093ca2b920ee076473044022007e06b362e89912abd4661f47945430739b006a85d1b2a16c01dc1a4bd07acab022061576d7aa834988b7ab94ef21d8eebd996ea59ea20529a19b15f0c9cebe3d8ac01220202b3fe93530020a8294f0e527e33fbdff184f047eb6b5a1558a352f62c29972f8a473044022002787f926d6817504431ee281183b8119b6845bfaa6befae45e13b6d430c9d2f02202859f149a6cd26ae2f03a107e7f33c7d91730dade305fe077bae677b5d44952a01010547522102b3fe93530020a8294f0e527e33fbdff184f047eb6b5a1558a352f62c29972f8a21025fe7371376d53cf8a2783917c28bf30bd690b0a4d4a207690093ca2b920ee07652ae0001014752210283ef76537f2d58ae3aa3a4bd8ae41c3f230ccadffb1a0bd3ca504d871cff05e7210353d79cc0cb1396f4ce278d005f16d948e02a6aec9ed1109f13747ecb1507b37b52ae00010147522102b3937241777b6665e0d694e52f9c1b188433641df852da6fc42187b5d8a368a321034cdd474f01cc5aa7ff834ad8bcc882a87e854affc775486bc2a9f62e8f49bd7852ae00";
@@ -320,7 +420,9 @@ mod tests {
     fn test_serde_psbt() {
         //! Create a full PSBT value with various fields filled and make sure it can be JSONized.
         use hashes::sha256d;
+        use util::key::{SchnorrSig, XOnlyPublicKey};
         use util::psbt::map::Input;
+        use util::taproot::{ControlBlock, LeafVersion, TapBranchHash, TapLeafHash};
 
         // create some values to use in the PSBT
         let tx = Transaction {
@@ -361,9 +463,24 @@ mod tests {
             vec![5, 6, 7],
         )].into_iter().collect();
 
+        let tap_key: XOnlyPublicKey =
+            "fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232".parse().unwrap();
+        let leaf_script = Script::from(vec![0x51]);
+        let leaf_hash = TapLeafHash::from_script(&leaf_script, LeafVersion::tapscript());
+        let control_block = {
+            let mut bytes = vec![0xc0];
+            bytes.extend(&tap_key.serialize()[..]);
+            ControlBlock::from_slice(&bytes).unwrap()
+        };
+        let schnorr_sig = SchnorrSig::from_slice(&[7u8; 65][..64]).unwrap();
+        let tap_keypaths: BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)> =
+            vec![(tap_key, (vec![leaf_hash], key_source.clone()))].into_iter().collect();
+
         let psbt = PartiallySignedTransaction {
             global: Global {
                 version: 0,
+                fallback_locktime: None,
+                tx_modifiable: None,
                 xpub: {
                     let xpub: ExtendedPubKey =
                         "xpub661MyMwAqRbcGoRVtwfvzZsq2VBJR1LAHfQstHUoxqDorV89vRoMxUZ27kLrraAj6MPi\
@@ -398,12 +515,20 @@ mod tests {
                 sha256_preimages: vec![(sha256::Hash::hash(&[]), vec![1, 2])].into_iter().collect(),
                 hash160_preimages: vec![(hash160::Hash::hash(&[]), vec![1, 2])].into_iter().collect(),
                 hash256_preimages: vec![(sha256d::Hash::hash(&[]), vec![1, 2])].into_iter().collect(),
+                tap_key_sig: Some(schnorr_sig),
+                tap_script_sigs: vec![((tap_key, leaf_hash), schnorr_sig)].into_iter().collect(),
+                tap_scripts: vec![(control_block, (leaf_script, LeafVersion::tapscript()))].into_iter().collect(),
+                tap_bip32_derivation: tap_keypaths.clone(),
+                tap_internal_key: Some(tap_key),
+                tap_merkle_root: Some(TapBranchHash::hash(&[1, 2, 3])),
                 proprietary: proprietary.clone(),
                 unknown: unknown.clone(),
                 ..Default::default()
             }],
             outputs: vec![Output {
                 bip32_derivation: keypaths.clone(),
+                tap_internal_key: Some(tap_key),
+                tap_bip32_derivation: tap_keypaths,
                 proprietary: proprietary.clone(),
                 unknown: unknown.clone(),
                 ..Default::default()
@@ -489,6 +614,8 @@ mod tests {
                     },
                     xpub: Default::default(),
                     version: 0,
+                    fallback_locktime: None,
+                    tx_modifiable: None,
                     proprietary: BTreeMap::new(),
                     unknown: BTreeMap::new(),
                 },
@@ -595,7 +722,7 @@ mod tests {
             );
             assert_eq!(
                 (&psbt.inputs[0].sighash_type).as_ref().unwrap(),
-                &SigHashType::All
+                &SigHashType::All.into()
             );
         }
 
@@ -673,6 +800,92 @@ mod tests {
         }
     }
 
+    mod bip_371_vectors {
+        use consensus::encode::{deserialize, serialize};
+        use util::psbt::PartiallySignedTransaction;
+
+        fn rtt_psbt(psbt: PartiallySignedTransaction) {
+            let psbt2: PartiallySignedTransaction = deserialize(&serialize(&psbt)).unwrap();
+            assert_eq!(psbt, psbt2);
+        }
+
+        #[test]
+        fn invalid_vectors() {
+            let vectors = [
+                // input internal key is not x-only
+                "70736274ff010071020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02787c01000000000016001483a7e34bd99ff03a4962ef8a1a101bb295461ece606b042a010000001600147ac369df1b20e033d6116623957b0ac49f3c52e8000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a075701172102fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232000000",
+                // input key spend signature has the wrong length
+                "70736274ff010071020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02787c01000000000016001483a7e34bd99ff03a4962ef8a1a101bb295461ece606b042a010000001600147ac369df1b20e033d6116623957b0ac49f3c52e8000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757011342173bb3d36c074afb716fec6307a069a2e450b995f3c82785945ab8df0e24260dcd703b0cbf34de399184a9481ac2b3586db6601f026a77f7e4938481bc34751701aa000000",
+                // input tap bip32 derivation key is not x-only
+                "70736274ff010071020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02787c01000000000016001483a7e34bd99ff03a4962ef8a1a101bb295461ece606b042a010000001600147ac369df1b20e033d6116623957b0ac49f3c52e8000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757221602fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000000000",
+                // output internal key is not x-only
+                "70736274ff01007d020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02887b0100000000001600142382871c7e8421a00093f754d91281e675874b9f606b042a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757000001052102fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa23200",
+                // output tap bip32 derivation key is not x-only
+                "70736274ff01007d020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02887b0100000000001600142382871c7e8421a00093f754d91281e675874b9f606b042a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a07570000220702fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da7560000800100008000000080010000000000000000",
+                // input script spend signature key has the wrong length
+                "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b6924214022cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b094089756aa3739ccc689ec0fcf3a360be32cc0b59b16e93a1e8bb4605726b2ca7a3ff706c4176649632b2cc68e1f912b8a578e3719ce7710885c7a966f49bcd43cb0000",
+                // input script spend signature has the wrong length
+                "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b69241142cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b094289756aa3739ccc689ec0fcf3a360be32cc0b59b16e93a1e8bb4605726b2ca7a3ff706c4176649632b2cc68e1f912b8a578e3719ce7710885c7a966f49bcd43cb01010000",
+                // input script spend signature has the wrong length
+                "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b69241142cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b093989756aa3739ccc689ec0fcf3a360be32cc0b59b16e93a1e8bb4605726b2ca7a3ff706c4176649632b2cc68e1f912b8a578e3719ce7710885c7a966f49bcd43cb0000",
+                // input control block has the wrong length
+                "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b6926315c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac06f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f80023202cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2acc00000",
+                // input control block has the wrong length
+                "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b6926115c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac06f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e123202cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2acc00000",
+            ];
+            for vector in vectors.iter() {
+                let psbt: Result<PartiallySignedTransaction, _> = hex_psbt!(vector);
+                assert!(psbt.is_err());
+            }
+        }
+
+        #[test]
+        fn valid_vectors() {
+            // key spend with the internal key's origin
+            let psbt: PartiallySignedTransaction = hex_psbt!("70736274ff010052020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff0148e6052a01000000160014768e1eeb4cf420866033f80aceff0f9720744969000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a07572116fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000011720fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232002202036b772a6db74d8753c98a827958de6c78ab3312109f37d3e0304484242ece73d818772b2da7540000800100008000000080000000000000000000").unwrap();
+            let internal_key = psbt.inputs[0].tap_internal_key.unwrap();
+            assert!(psbt.inputs[0].tap_bip32_derivation.contains_key(&internal_key));
+            rtt_psbt(psbt);
+
+            // key spend with a signature
+            let psbt: PartiallySignedTransaction = hex_psbt!("70736274ff010052020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff0148e6052a01000000160014768e1eeb4cf420866033f80aceff0f9720744969000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757011340bb53ec917bad9d906af1ba87181c48b86ace5aae2b53605a725ca74625631476fc6f5baedaf4f2ee0f477f36f58f3970d5b8273b7e497b97af2e3f125c97af342116fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000011720fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232002202036b772a6db74d8753c98a827958de6c78ab3312109f37d3e0304484242ece73d818772b2da7540000800100008000000080000000000000000000").unwrap();
+            let internal_key = psbt.inputs[0].tap_internal_key.unwrap();
+            assert!(psbt.inputs[0].tap_bip32_derivation.contains_key(&internal_key));
+            assert!(psbt.inputs[0].tap_key_sig.is_some());
+            rtt_psbt(psbt);
+
+            // taproot output with its internal key's origin
+            let psbt: PartiallySignedTransaction = hex_psbt!("70736274ff01005e020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff0148e6052a0100000022512083698e458c6664e1595d75da2597de1e22ee97d798e706c4c0a4b5a9823cd743000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a07572116fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000011720fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232000105201124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e67121071124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e6711900772b2da7560000800100008000000080000000000500000000").unwrap();
+            let internal_key = psbt.outputs[0].tap_internal_key.unwrap();
+            assert!(psbt.outputs[0].tap_bip32_derivation.contains_key(&internal_key));
+            rtt_psbt(psbt);
+
+            // script spend with leaf scripts and a merkle root
+            let psbt: PartiallySignedTransaction = hex_psbt!("70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a0100000022512083698e458c6664e1595d75da2597de1e22ee97d798e706c4c0a4b5a9823cd743000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b6926215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac06f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f823202cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2acc04215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac097c6e6fea5ff714ff5724499990810e406e98aa10f5bf7e5f6784bc1d0a9a6ce23204320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b2acc06215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b09115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f82320fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca9acc021162cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d23901cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b09772b2da7560000800100008002000080000000000000000021164320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b23901115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f8772b2da75600008001000080010000800000000000000000211650929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac005007c461e5d2116fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca939016f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970772b2da7560000800100008003000080000000000000000001172050929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0011820f0362e2f75a6f420a5bde3eb221d96ae6720cf25f81890c95b1d775acb515e65000105201124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e67121071124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e6711900772b2da7560000800100008000000080000000000500000000").unwrap();
+            assert!(psbt.inputs[0].tap_internal_key.is_some());
+            assert!(psbt.inputs[0].tap_merkle_root.is_some());
+            assert_eq!(psbt.inputs[0].tap_bip32_derivation.len(), 4);
+            assert_eq!(psbt.inputs[0].tap_scripts.len(), 3);
+            rtt_psbt(psbt);
+
+            // taproot output with a script tree, kept as an unknown pair
+            let psbt: PartiallySignedTransaction = hex_psbt!("70736274ff01005e020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff0148e6052a010000002251200a8cbdc86de1ce1c0f9caeb22d6df7ced3683fe423e05d1e402a879341d6f6f5000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a07572116fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000011720fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2320001052050929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac001066f02c02220736e572900fe1252589a2143c8f3c79f71a0412d2353af755e9701c782694a02ac02c02220631c5f3b5832b8fbdebfb19704ceeb323c21f40f7a24f43d68ef0cc26b125969ac01c0222044faa49a0338de488c8dfffecdfb6f329f380bd566ef20c8df6d813eab1c4273ac210744faa49a0338de488c8dfffecdfb6f329f380bd566ef20c8df6d813eab1c42733901f06b798b92a10ed9a9d0bbfd3af173a53b1617da3a4159ca008216cd856b2e0e772b2da75600008001000080010000800000000003000000210750929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac005007c461e5d2107631c5f3b5832b8fbdebfb19704ceeb323c21f40f7a24f43d68ef0cc26b125969390118ace409889785e0ea70ceebb8e1ca892a7a78eaede0f2e296cf435961a8f4ca772b2da756000080010000800200008000000000030000002107736e572900fe1252589a2143c8f3c79f71a0412d2353af755e9701c782694a02390129a5b4915090162d759afd3fe0f93fa3326056d0b4088cb933cae7826cb8d82c772b2da7560000800100008003000080000000000300000000").unwrap();
+            assert!(psbt.outputs[0].tap_internal_key.is_some());
+            assert_eq!(psbt.outputs[0].tap_bip32_derivation.len(), 4);
+            assert!(psbt.outputs[0].unknown.keys().any(|k| k.type_value == 0x06));
+            rtt_psbt(psbt);
+
+            // script spend with signatures
+            let psbt: PartiallySignedTransaction = hex_psbt!("70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a0100000022512083698e458c6664e1595d75da2597de1e22ee97d798e706c4c0a4b5a9823cd743000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b69241142cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b0940bf818d9757d6ffeb538ba057fb4c1fc4e0f5ef186e765beb564791e02af5fd3d5e2551d4e34e33d86f276b82c99c79aed3f0395a081efcd2cc2c65dd7e693d7941144320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b2115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f840e1f1ab6fabfa26b236f21833719dc1d428ab768d80f91f9988d8abef47bfb863bb1f2a529f768c15f00ce34ec283cdc07e88f8428be28f6ef64043c32911811a4114fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca96f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae97040ec1f0379206461c83342285423326708ab031f0da4a253ee45aafa5b8c92034d8b605490f8cd13e00f989989b97e215faa36f12dee3693d2daccf3781c1757f66215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac06f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f823202cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2acc04215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac097c6e6fea5ff714ff5724499990810e406e98aa10f5bf7e5f6784bc1d0a9a6ce23204320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b2acc06215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b09115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f82320fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca9acc021162cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d23901cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b09772b2da7560000800100008002000080000000000000000021164320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b23901115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f8772b2da75600008001000080010000800000000000000000211650929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac005007c461e5d2116fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca939016f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970772b2da7560000800100008003000080000000000000000001172050929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0011820f0362e2f75a6f420a5bde3eb221d96ae6720cf25f81890c95b1d775acb515e65000105201124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e67121071124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e6711900772b2da7560000800100008000000080000000000500000000").unwrap();
+            assert!(psbt.inputs[0].tap_internal_key.is_some());
+            assert!(psbt.inputs[0].tap_merkle_root.is_some());
+            assert_eq!(psbt.inputs[0].tap_scripts.len(), 3);
+            assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 3);
+            assert!(!psbt.inputs[0].tap_bip32_derivation.is_empty());
+            rtt_psbt(psbt);
+        }
+    }
+
     #[test]
     fn serialize_and_deserialize_preimage_psbt(){
         // create a sha preimage map
@@ -714,6 +927,8 @@ mod tests {
                     ],
                 },
                 version: 0,
+                fallback_locktime: None,
+                tx_modifiable: None,
                 xpub: Default::default(),
                 proprietary: Default::default(),
                 unknown: BTreeMap::new(),
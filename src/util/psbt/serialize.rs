@@ -20,12 +20,14 @@
 use std::io;
 
 use blockdata::script::Script;
-use blockdata::transaction::{SigHashType, Transaction, TxOut};
-use consensus::encode::{self, serialize, Decodable};
+use blockdata::transaction::{Transaction, TxOut};
+use consensus::encode::{self, serialize, Decodable, VarInt};
 use util::bip32::{ChildNumber, Fingerprint, KeySource};
 use hashes::{hash160, ripemd160, sha256, sha256d, Hash};
-use util::key::PublicKey;
-use util::psbt;
+use util::key::{PublicKey, SchnorrSig, XOnlyPublicKey};
+use util::psbt::PsbtSigHashType;
+use util::taproot::{ControlBlock, LeafVersion, TapBranchHash, TapLeafHash};
+use hash_types::Txid;
 
 /// A trait for serializing a value as raw data for insertion into PSBT
 /// key-value pairs.
@@ -43,10 +45,15 @@ pub trait Deserialize: Sized {
 impl_psbt_de_serialize!(Transaction);
 impl_psbt_de_serialize!(TxOut);
 impl_psbt_de_serialize!(Vec<Vec<u8>>); // scriptWitness
+impl_psbt_de_serialize!(u32);
+impl_psbt_de_serialize!(u64);
 impl_psbt_hash_de_serialize!(ripemd160::Hash);
 impl_psbt_hash_de_serialize!(sha256::Hash);
 impl_psbt_hash_de_serialize!(hash160::Hash);
 impl_psbt_hash_de_serialize!(sha256d::Hash);
+impl_psbt_hash_de_serialize!(Txid);
+impl_psbt_hash_de_serialize!(TapLeafHash);
+impl_psbt_hash_de_serialize!(TapBranchHash);
 
 impl Serialize for Script {
     fn serialize(&self) -> Vec<u8> {
@@ -75,6 +82,113 @@ impl Deserialize for PublicKey {
     }
 }
 
+impl Serialize for XOnlyPublicKey {
+    fn serialize(&self) -> Vec<u8> {
+        XOnlyPublicKey::serialize(self).to_vec()
+    }
+}
+
+impl Deserialize for XOnlyPublicKey {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        XOnlyPublicKey::from_slice(bytes)
+            .map_err(|_| encode::Error::ParseFailed("invalid x-only public key"))
+    }
+}
+
+impl Serialize for SchnorrSig {
+    fn serialize(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl Deserialize for SchnorrSig {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        SchnorrSig::from_slice(bytes)
+            .map_err(|_| encode::Error::ParseFailed("invalid schnorr signature"))
+    }
+}
+
+// tap script sigs
+impl Serialize for (XOnlyPublicKey, TapLeafHash) {
+    fn serialize(&self) -> Vec<u8> {
+        let mut rv = self.0.serialize().to_vec();
+        rv.extend(&self.1[..]);
+        rv
+    }
+}
+
+impl Deserialize for (XOnlyPublicKey, TapLeafHash) {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        if bytes.len() != 64 {
+            return Err(encode::Error::ParseFailed("invalid tap script signature key"))
+        }
+        let key: XOnlyPublicKey = Deserialize::deserialize(&bytes[..32])?;
+        let leaf_hash: TapLeafHash = Deserialize::deserialize(&bytes[32..])?;
+        Ok((key, leaf_hash))
+    }
+}
+
+impl Serialize for ControlBlock {
+    fn serialize(&self) -> Vec<u8> {
+        ControlBlock::serialize(self)
+    }
+}
+
+impl Deserialize for ControlBlock {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        ControlBlock::from_slice(bytes)
+            .map_err(|_| encode::Error::ParseFailed("invalid control block"))
+    }
+}
+
+// tap leaf scripts
+impl Serialize for (Script, LeafVersion) {
+    fn serialize(&self) -> Vec<u8> {
+        let mut rv = self.0.to_bytes();
+        rv.push(self.1.as_u8());
+        rv
+    }
+}
+
+impl Deserialize for (Script, LeafVersion) {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        if bytes.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+        }
+        let version = LeafVersion::from_u8(bytes[bytes.len() - 1])
+            .map_err(|_| encode::Error::ParseFailed("invalid leaf version"))?;
+        Ok((Script::from(bytes[..bytes.len() - 1].to_vec()), version))
+    }
+}
+
+// tap bip32 derivations
+impl Serialize for (Vec<TapLeafHash>, KeySource) {
+    fn serialize(&self) -> Vec<u8> {
+        let mut rv = serialize(&VarInt(self.0.len() as u64));
+        for leaf_hash in &self.0 {
+            rv.extend(&leaf_hash[..]);
+        }
+        rv.extend(self.1.serialize());
+        rv
+    }
+}
+
+impl Deserialize for (Vec<TapLeafHash>, KeySource) {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        let mut d = bytes;
+        let count = VarInt::consensus_decode(&mut d)?.0;
+        if count > (d.len() / 32) as u64 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+        }
+        let mut leaf_hashes = Vec::with_capacity(count as usize);
+        for chunk in d.chunks(32).take(count as usize) {
+            leaf_hashes.push(Deserialize::deserialize(chunk)?);
+        }
+        let key_source = Deserialize::deserialize(&d[32 * count as usize..])?;
+        Ok((leaf_hashes, key_source))
+    }
+}
+
 impl Serialize for KeySource {
     fn serialize(&self) -> Vec<u8> {
         let mut rv: Vec<u8> = Vec::with_capacity(4 + 4 * (self.1).as_ref().len());
@@ -123,21 +237,15 @@ impl Deserialize for Vec<u8> {
     }
 }
 
-impl Serialize for SigHashType {
+impl Serialize for PsbtSigHashType {
     fn serialize(&self) -> Vec<u8> {
         serialize(&self.as_u32())
     }
 }
 
-impl Deserialize for SigHashType {
+impl Deserialize for PsbtSigHashType {
     fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
         let raw: u32 = encode::deserialize(bytes)?;
-        let rv: SigHashType = SigHashType::from_u32(raw);
-
-        if rv.as_u32() == raw {
-            Ok(rv)
-        } else {
-            Err(psbt::Error::NonStandardSigHashType(raw).into())
-        }
+        Ok(PsbtSigHashType::from_u32(raw))
     }
 }
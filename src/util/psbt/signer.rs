@@ -0,0 +1,593 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Signer
+//!
+//! Implementation of the BIP174 signer role for legacy and segwit v0 inputs.
+//! Keys are looked up through the `bip32_derivation` map of each input.
+//!
+//! The signature hash of any input, taproot ones included, can be computed
+//! from the PSBT with [`PartiallySignedTransaction::sighash`].
+
+use std::{error, fmt};
+use std::collections::BTreeMap;
+
+use secp256k1::{self, Secp256k1};
+
+use blockdata::script::Script;
+use blockdata::transaction::{SigHashType, Transaction, TxOut};
+use hash_types::PubkeyHash;
+use hashes::Hash;
+use util::bip32::{ExtendedPrivKey, KeySource};
+use util::bip341::{Prevouts, NO_CODESEPARATOR};
+use util::key::{PrivateKey, PublicKey};
+use util::psbt::{Input, PartiallySignedTransaction, PsbtSigHashType};
+use util::sighash::{self, SigHashCache, SignatureHash};
+use util::taproot::TapLeafHash;
+
+/// Provides the private keys needed to sign a PSBT
+pub trait GetKey {
+    /// Returns the private key for `pubkey`, which the PSBT says is derived
+    /// at `source`, if it is known
+    fn get_key<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        pubkey: &PublicKey,
+        source: &KeySource,
+    ) -> Option<PrivateKey>;
+}
+
+/// Master keys provide the keys derived from them, when the master
+/// fingerprint of the key source matches
+impl GetKey for ExtendedPrivKey {
+    fn get_key<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        _: &PublicKey,
+        source: &KeySource,
+    ) -> Option<PrivateKey> {
+        if source.0 != self.fingerprint(secp) {
+            return None;
+        }
+        self.derive_priv(secp, &source.1).ok().map(|xpriv| xpriv.private_key)
+    }
+}
+
+/// A map of single keys provides the keys it contains, regardless of the key source
+impl GetKey for BTreeMap<PublicKey, PrivateKey> {
+    fn get_key<C: secp256k1::Signing>(
+        &self,
+        _: &Secp256k1<C>,
+        pubkey: &PublicKey,
+        _: &KeySource,
+    ) -> Option<PrivateKey> {
+        self.get(pubkey).cloned()
+    }
+}
+
+/// Ways that signing a PSBT input might fail
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SignError {
+    /// The input has neither a witness nor a non-witness UTXO
+    MissingUtxo,
+    /// Legacy inputs must have a non-witness UTXO to be signed safely
+    MissingNonWitnessUtxo,
+    /// The UTXOs of the input do not match the outpoint spent by the unsigned
+    /// transaction, or each other
+    UtxoMismatch,
+    /// The output being spent is P2SH but the input has no redeem script
+    MissingRedeemScript,
+    /// The redeem script does not hash to the P2SH output
+    RedeemScriptMismatch,
+    /// The output being spent is P2WSH but the input has no witness script
+    MissingWitnessScript,
+    /// The witness script does not hash to the P2WSH program
+    WitnessScriptMismatch,
+    /// The output being spent is a witness program of an unsupported version
+    UnsupportedScript(Script),
+    /// The sighash type of the input is not valid for the output it spends
+    InvalidSigHashType(u32),
+    /// Computing the signature hash failed
+    SigHash(sighash::Error),
+}
+
+impl fmt::Display for SignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SignError::MissingUtxo => f.write_str("missing witness and non-witness utxo"),
+            SignError::MissingNonWitnessUtxo => f.write_str("legacy input without non-witness utxo"),
+            SignError::UtxoMismatch => f.write_str("utxo does not match the spent outpoint"),
+            SignError::MissingRedeemScript => f.write_str("missing redeem script"),
+            SignError::RedeemScriptMismatch => f.write_str("redeem script does not match the script pubkey"),
+            SignError::MissingWitnessScript => f.write_str("missing witness script"),
+            SignError::WitnessScriptMismatch => f.write_str("witness script does not match the witness program"),
+            SignError::UnsupportedScript(ref script) => write!(f, "unsupported script: {}", script),
+            SignError::InvalidSigHashType(sighash_type) => write!(f, "invalid sighash type: {:#x}", sighash_type),
+            SignError::SigHash(ref e) => write!(f, "sighash error: {}", e),
+        }
+    }
+}
+
+impl error::Error for SignError {}
+
+#[doc(hidden)]
+impl From<sighash::Error> for SignError {
+    fn from(e: sighash::Error) -> SignError {
+        SignError::SigHash(e)
+    }
+}
+
+/// The keys that signed each input, by input index
+pub type SigningKeys = BTreeMap<usize, Vec<PublicKey>>;
+
+/// The reasons inputs could not be signed, by input index
+pub type SigningErrors = BTreeMap<usize, SignError>;
+
+impl PartiallySignedTransaction {
+    /// Signs all inputs which are not finalized yet with the keys of their
+    /// `bip32_derivation` maps that `key` provides, as the BIP174 signer role.
+    ///
+    /// Returns the keys that signed each input. If any input could not be
+    /// signed, the errors are returned along with the keys that signed the
+    /// other inputs.
+    pub fn sign<C: secp256k1::Signing, K: GetKey>(
+        &mut self,
+        key: &K,
+        secp: &Secp256k1<C>,
+    ) -> Result<SigningKeys, (SigningKeys, SigningErrors)> {
+        let mut used = BTreeMap::new();
+        let mut errors = BTreeMap::new();
+        for index in 0..self.inputs.len() {
+            match self.sign_input(index, key, secp) {
+                Ok(keys) => {
+                    if !keys.is_empty() {
+                        used.insert(index, keys);
+                    }
+                }
+                Err(e) => {
+                    errors.insert(index, e);
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(used)
+        } else {
+            Err((used, errors))
+        }
+    }
+
+    /// Signs the input at `index` with the keys `key` provides, returning
+    /// the keys that signed it.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn sign_input<C: secp256k1::Signing, K: GetKey>(
+        &mut self,
+        index: usize,
+        key: &K,
+        secp: &Secp256k1<C>,
+    ) -> Result<Vec<PublicKey>, SignError> {
+        let (sigs, sighash_type) = {
+            let input = &self.inputs[index];
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                return Ok(vec![]);
+            }
+
+            let (utxo, _) = self.spent_utxo(index)?;
+            if utxo.script_pubkey.is_v1_p2tr() {
+                return Err(SignError::UnsupportedScript(utxo.script_pubkey.clone()));
+            }
+            let mut cache = SigHashCache::new(&self.global.unsigned_tx);
+            let msg = self.sighash(&mut cache, index, None)?.to_message();
+            let sighash_type = ecdsa_hash_ty(input)?;
+
+            let mut sigs = vec![];
+            for (pk, source) in &input.bip32_derivation {
+                let sk = match key.get_key(secp, pk, source) {
+                    Some(sk) => sk,
+                    None => continue,
+                };
+                // Never trust the key provider to return the requested key
+                if sk.public_key(secp).key != pk.key {
+                    continue;
+                }
+                let mut sig = secp.sign(&msg, &sk.key).serialize_der().to_vec();
+                sig.push(sighash_type.as_u32() as u8);
+                sigs.push((*pk, sig));
+            }
+            (sigs, sighash_type)
+        };
+
+        let used: Vec<PublicKey> = sigs.iter().map(|&(pk, _)| pk).collect();
+        self.inputs[index].partial_sigs.extend(sigs);
+        if !used.is_empty() {
+            self.update_tx_modifiable(sighash_type);
+        }
+        Ok(used)
+    }
+
+    /// Computes the signature hash of the input at `index`, as a legacy,
+    /// BIP143 or BIP341 sighash depending on the output it spends, after
+    /// checking its UTXOs against the unsigned transaction.
+    ///
+    /// The sighash type is the one of the input, defaulting to `SIGHASH_ALL`
+    /// for ECDSA and to `SIGHASH_DEFAULT` for taproot. `leaf_hash` selects
+    /// the script path spend of a taproot input through that leaf, and the
+    /// key path spend if `None`; it is ignored for other inputs. Taproot
+    /// inputs need the UTXOs of all inputs, unless `SIGHASH_ANYONECANPAY`
+    /// is used.
+    ///
+    /// `cache` must have been created for the unsigned transaction of this
+    /// PSBT, and may be reused for all its inputs.
+    pub fn sighash(
+        &self,
+        cache: &mut SigHashCache<&Transaction>,
+        index: usize,
+        leaf_hash: Option<TapLeafHash>,
+    ) -> Result<SignatureHash, SignError> {
+        let tx = &self.global.unsigned_tx;
+        if index >= self.inputs.len() || index >= tx.input.len() {
+            return Err(SignError::SigHash(sighash::Error::IndexOutOfInputsBounds {
+                index: index,
+                inputs_size: tx.input.len(),
+            }));
+        }
+        let input = &self.inputs[index];
+        let (utxo, has_non_witness_utxo) = self.spent_utxo(index)?;
+
+        if utxo.script_pubkey.is_v1_p2tr() {
+            let sighash_type = input.schnorr_hash_ty().map_err(|_| invalid_sighash_type(input))?;
+            let (_, anyone_can_pay) = sighash_type.split_anyonecanpay_flag();
+
+            let all_utxos;
+            let prevouts = if anyone_can_pay {
+                Prevouts::One(index, utxo)
+            } else {
+                all_utxos = (0..tx.input.len())
+                    .map(|i| self.spent_utxo(i).map(|(utxo, _)| utxo.clone()))
+                    .collect::<Result<Vec<TxOut>, SignError>>()?;
+                Prevouts::All(&all_utxos)
+            };
+            let leaf_hash_code_separator = leaf_hash.map(|leaf_hash| (leaf_hash, NO_CODESEPARATOR));
+            let sighash = cache.taproot_signature_hash(index, &prevouts, None, leaf_hash_code_separator, sighash_type)?;
+            return Ok(SignatureHash::Taproot(sighash));
+        }
+
+        let sighash_type = ecdsa_hash_ty(input)?;
+
+        let mut script = &utxo.script_pubkey;
+        if script.is_p2sh() {
+            let redeem_script = input.redeem_script.as_ref().ok_or(SignError::MissingRedeemScript)?;
+            if Script::new_p2sh(&redeem_script.script_hash()) != *script {
+                return Err(SignError::RedeemScriptMismatch);
+            }
+            script = redeem_script;
+        }
+
+        if script.is_v0_p2wpkh() {
+            let script_code = Script::new_p2pkh(&PubkeyHash::from_slice(&script[2..]).expect("20 bytes"));
+            let sighash = cache.segwit_signature_hash(index, &script_code, utxo.value, sighash_type)?;
+            Ok(SignatureHash::SegwitV0(sighash))
+        } else if script.is_v0_p2wsh() {
+            let witness_script = input.witness_script.as_ref().ok_or(SignError::MissingWitnessScript)?;
+            if Script::new_v0_wsh(&witness_script.wscript_hash()) != *script {
+                return Err(SignError::WitnessScriptMismatch);
+            }
+            let sighash = cache.segwit_signature_hash(index, witness_script, utxo.value, sighash_type)?;
+            Ok(SignatureHash::SegwitV0(sighash))
+        } else if script.is_witness_program() {
+            Err(SignError::UnsupportedScript(script.clone()))
+        } else {
+            if !has_non_witness_utxo {
+                return Err(SignError::MissingNonWitnessUtxo);
+            }
+            let sighash = cache.legacy_signature_hash(index, script, sighash_type)?;
+            Ok(SignatureHash::Legacy(sighash))
+        }
+    }
+
+    /// Returns the output spent by the input at `index`, checked against the
+    /// unsigned transaction, and whether it was given as a non-witness UTXO
+    fn spent_utxo(&self, index: usize) -> Result<(&TxOut, bool), SignError> {
+        let input = &self.inputs[index];
+        let prevout = self.global.unsigned_tx.input[index].previous_output;
+
+        let non_witness_output = match input.non_witness_utxo {
+            Some(ref prev_tx) => {
+                if prev_tx.txid() != prevout.txid {
+                    return Err(SignError::UtxoMismatch);
+                }
+                Some(prev_tx.output.get(prevout.vout as usize).ok_or(SignError::UtxoMismatch)?)
+            }
+            None => None,
+        };
+        match (input.witness_utxo.as_ref(), non_witness_output) {
+            (Some(witness), Some(non_witness)) if witness != non_witness => Err(SignError::UtxoMismatch),
+            (Some(utxo), non_witness) => Ok((utxo, non_witness.is_some())),
+            (None, Some(utxo)) => Ok((utxo, true)),
+            (None, None) => Err(SignError::MissingUtxo),
+        }
+    }
+}
+
+fn ecdsa_hash_ty(input: &Input) -> Result<SigHashType, SignError> {
+    input.ecdsa_hash_ty().map_err(|_| invalid_sighash_type(input))
+}
+
+fn invalid_sighash_type(input: &Input) -> SignError {
+    SignError::InvalidSigHashType(input.sighash_type.map_or(0, PsbtSigHashType::as_u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use blockdata::interpreter::VerifyFlags;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn};
+    use hashes::hex::FromHex;
+    use network::constants::Network;
+    use util::bip32::{ChildNumber, DerivationPath};
+    use util::bip341::SchnorrSigHashType;
+    use util::psbt::PartiallySignedTransaction;
+
+    fn prev_tx(script_pubkey: Script) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![
+                TxOut { value: 1_000, script_pubkey: Script::new() },
+                TxOut { value: 50_000, script_pubkey: script_pubkey },
+            ],
+        }
+    }
+
+    fn psbt_spending(prev: &Transaction) -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev.txid(), 1),
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value: 49_000, script_pubkey: Script::new() }],
+        };
+        PartiallySignedTransaction::from_unsigned_tx(tx).unwrap()
+    }
+
+    fn master() -> ExtendedPrivKey {
+        let seed = Vec::<u8>::from_hex("000102030405060708090a0b0c0d0e0f").unwrap();
+        ExtendedPrivKey::new_master(Network::Bitcoin, &seed).unwrap()
+    }
+
+    #[test]
+    fn sign_p2wpkh_and_p2pkh() {
+        let secp = Secp256k1::new();
+        let master = master();
+        let path = DerivationPath::from_str("m/84'/0'/0'/0/3").unwrap();
+        let sk = master.derive_priv(&secp, &path).unwrap().private_key;
+        let pk = sk.public_key(&secp);
+        let source = (master.fingerprint(&secp), path);
+
+        for &segwit in &[true, false] {
+            let spk = if segwit {
+                Script::new_v0_wpkh(&pk.wpubkey_hash().unwrap())
+            } else {
+                Script::new_p2pkh(&pk.pubkey_hash())
+            };
+            let prev = prev_tx(spk.clone());
+            let mut psbt = psbt_spending(&prev);
+            psbt.inputs[0].bip32_derivation.insert(pk, source.clone());
+            assert_eq!(psbt.sign(&master, &secp), Err((BTreeMap::new(), vec![(0, SignError::MissingUtxo)].into_iter().collect())));
+
+            if segwit {
+                psbt.inputs[0].witness_utxo = Some(prev.output[1].clone());
+            } else {
+                psbt.inputs[0].witness_utxo = Some(prev.output[1].clone());
+                assert_eq!(psbt.sign_input(0, &master, &secp), Err(SignError::MissingNonWitnessUtxo));
+                psbt.inputs[0].witness_utxo = None;
+                psbt.inputs[0].non_witness_utxo = Some(prev.clone());
+            }
+            let signed = psbt.sign(&master, &secp).unwrap();
+            assert_eq!(signed, vec![(0, vec![pk])].into_iter().collect());
+
+            let sig = &psbt.inputs[0].partial_sigs[&pk];
+            assert_eq!(*sig.last().unwrap(), 0x01);
+            let sighash = if segwit {
+                ::util::bip143::SigHashCache::new(&psbt.global.unsigned_tx)
+                    .signature_hash(0, &Script::new_p2pkh(&pk.pubkey_hash()), 50_000, SigHashType::All)
+            } else {
+                psbt.global.unsigned_tx.signature_hash(0, &spk, 1)
+            };
+            let msg = secp256k1::Message::from_slice(&sighash[..]).unwrap();
+            let sig = secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap();
+            secp.verify(&msg, &sig, &pk.key).unwrap();
+
+            // The signature passes the script interpreter once finalized
+            psbt.finalize().unwrap();
+            let tx = psbt.extract_tx();
+            let mut spent = |outpoint: &OutPoint| {
+                if *outpoint == tx.input[0].previous_output { Some(prev.output[1].clone()) } else { None }
+            };
+            tx.verify_scripts(&mut spent, VerifyFlags::STANDARD).unwrap();
+        }
+    }
+
+    #[test]
+    fn sign_p2sh_p2wsh_multisig() {
+        let secp = Secp256k1::new();
+        let master = master();
+        let fingerprint = master.fingerprint(&secp);
+        let paths: Vec<DerivationPath> = (0..2)
+            .map(|i| DerivationPath::from(vec![ChildNumber::from_hardened_idx(48).unwrap(), ChildNumber::from_normal_idx(i).unwrap()]))
+            .collect();
+        let pks: Vec<PublicKey> = paths.iter()
+            .map(|p| master.derive_priv(&secp, p).unwrap().private_key.public_key(&secp))
+            .collect();
+        let other = PublicKey::from_str("02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5").unwrap();
+
+        let witness_script = ::blockdata::script::Builder::new()
+            .push_int(2).push_key(&pks[0]).push_key(&other).push_key(&pks[1]).push_int(3)
+            .push_opcode(::blockdata::opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let redeem_script = Script::new_v0_wsh(&witness_script.wscript_hash());
+        let prev = prev_tx(Script::new_p2sh(&redeem_script.script_hash()));
+        let mut psbt = psbt_spending(&prev);
+        psbt.inputs[0].witness_utxo = Some(prev.output[1].clone());
+        psbt.inputs[0].sighash_type = Some(SigHashType::AllPlusAnyoneCanPay.into());
+        psbt.inputs[0].bip32_derivation.insert(pks[0], (fingerprint, paths[0].clone()));
+        psbt.inputs[0].bip32_derivation.insert(pks[1], (fingerprint, paths[1].clone()));
+        psbt.inputs[0].bip32_derivation.insert(other, (Default::default(), DerivationPath::default()));
+
+        assert_eq!(psbt.sign_input(0, &master, &secp), Err(SignError::MissingRedeemScript));
+        psbt.inputs[0].redeem_script = Some(witness_script.clone());
+        assert_eq!(psbt.sign_input(0, &master, &secp), Err(SignError::RedeemScriptMismatch));
+        psbt.inputs[0].redeem_script = Some(redeem_script.clone());
+        assert_eq!(psbt.sign_input(0, &master, &secp), Err(SignError::MissingWitnessScript));
+        psbt.inputs[0].witness_script = Some(witness_script.clone());
+
+        // A non-witness utxo disagreeing with the witness utxo is refused
+        let mut bad_prev = prev.clone();
+        bad_prev.output[1].value += 1;
+        psbt.inputs[0].non_witness_utxo = Some(bad_prev);
+        assert_eq!(psbt.sign_input(0, &master, &secp), Err(SignError::UtxoMismatch));
+        psbt.inputs[0].non_witness_utxo = Some(prev.clone());
+
+        let mut signed = psbt.sign_input(0, &master, &secp).unwrap();
+        signed.sort();
+        let mut expected = pks.clone();
+        expected.sort();
+        assert_eq!(signed, expected);
+        assert_eq!(psbt.inputs[0].partial_sigs.len(), 2);
+        assert!(psbt.inputs[0].partial_sigs.values().all(|sig| *sig.last().unwrap() == 0x81));
+
+        psbt.finalize().unwrap();
+        let tx = psbt.extract_tx();
+        let mut spent = |_: &OutPoint| Some(prev.output[1].clone());
+        tx.verify_scripts(&mut spent, VerifyFlags::STANDARD).unwrap();
+    }
+
+    #[test]
+    fn sign_with_key_map() {
+        let secp = Secp256k1::new();
+        let sk = PrivateKey::from_wif("L1HKVVLHXiUhecWnwFYF6L3shkf1E12HUmuZTESvBXUdx3yqVP1D").unwrap();
+        let pk = sk.public_key(&secp);
+        let prev = prev_tx(Script::new_v0_wpkh(&pk.wpubkey_hash().unwrap()));
+        let mut psbt = psbt_spending(&prev);
+        psbt.inputs[0].witness_utxo = Some(prev.output[1].clone());
+        psbt.inputs[0].bip32_derivation.insert(pk, (Default::default(), DerivationPath::default()));
+
+        // Keys that are not provided are skipped without error
+        assert_eq!(psbt.sign(&master(), &secp), Ok(BTreeMap::new()));
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+
+        let mut keys = BTreeMap::new();
+        keys.insert(pk, sk);
+        assert_eq!(psbt.sign(&keys, &secp), Ok(vec![(0, vec![pk])].into_iter().collect()));
+
+        // BIP143 defines the sighash of SIGHASH_SINGLE without corresponding
+        // output
+        psbt.global.unsigned_tx.output.clear();
+        psbt.inputs[0].partial_sigs.clear();
+        psbt.inputs[0].sighash_type = Some(SigHashType::Single.into());
+        assert_eq!(psbt.sign_input(0, &keys, &secp), Ok(vec![pk]));
+
+        // The utxo must be the output spent by the unsigned transaction
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx(Script::new()));
+        assert_eq!(psbt.sign_input(0, &keys, &secp), Err(SignError::UtxoMismatch));
+    }
+
+    #[test]
+    fn sighash_taproot() {
+        use util::bip341;
+        use util::taproot::LeafVersion;
+
+        let spk = hex_script!("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343");
+        let prev = prev_tx(spk.clone());
+        let other_prev = prev_tx(Script::new_v0_wpkh(&Default::default()));
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                TxIn { previous_output: OutPoint::new(prev.txid(), 1), ..Default::default() },
+                TxIn { previous_output: OutPoint::new(other_prev.txid(), 1), ..Default::default() },
+            ],
+            output: vec![TxOut { value: 90_000, script_pubkey: Script::new() }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(prev.output[1].clone());
+        let utxos = [prev.output[1].clone(), other_prev.output[1].clone()];
+        let leaf_hash = TapLeafHash::from_script(&Script::new(), LeafVersion::default());
+
+        let unsigned_tx = psbt.global.unsigned_tx.clone();
+        let mut cache = SigHashCache::new(&unsigned_tx);
+        let mut expected = bip341::SigHashCache::new(&unsigned_tx);
+
+        // Without SIGHASH_ANYONECANPAY, the UTXOs of all inputs are needed
+        assert_eq!(psbt.sighash(&mut cache, 0, None), Err(SignError::MissingUtxo));
+        psbt.inputs[0].sighash_type = Some(SchnorrSigHashType::AllPlusAnyoneCanPay.into());
+        assert_eq!(
+            psbt.sighash(&mut cache, 0, None),
+            Ok(SignatureHash::Taproot(expected.key_spend_signature_hash(
+                0, &Prevouts::One(0, &utxos[0]), SchnorrSigHashType::AllPlusAnyoneCanPay,
+            ).unwrap()))
+        );
+
+        psbt.inputs[0].sighash_type = None;
+        psbt.inputs[1].witness_utxo = Some(utxos[1].clone());
+        assert_eq!(
+            psbt.sighash(&mut cache, 0, None),
+            Ok(SignatureHash::Taproot(expected.key_spend_signature_hash(
+                0, &Prevouts::All(&utxos), SchnorrSigHashType::Default,
+            ).unwrap()))
+        );
+        assert_eq!(
+            psbt.sighash(&mut cache, 0, Some(leaf_hash)),
+            Ok(SignatureHash::Taproot(expected.script_spend_signature_hash(
+                0, &Prevouts::All(&utxos), leaf_hash, SchnorrSigHashType::Default,
+            ).unwrap()))
+        );
+
+        // ECDSA-only and taproot-only sighash types are told apart
+        psbt.inputs[0].sighash_type = Some(PsbtSigHashType::from_u32(0x84));
+        assert_eq!(psbt.sighash(&mut cache, 0, None), Err(SignError::InvalidSigHashType(0x84)));
+        psbt.inputs[1].sighash_type = Some(SchnorrSigHashType::Default.into());
+        assert_eq!(psbt.sighash(&mut cache, 1, None), Err(SignError::InvalidSigHashType(0)));
+
+        psbt.inputs[0].sighash_type = Some(SchnorrSigHashType::Single.into());
+        assert!(psbt.sighash(&mut cache, 0, None).is_ok());
+        psbt.inputs[1].sighash_type = Some(SigHashType::Single.into());
+        assert!(psbt.sighash(&mut cache, 1, None).is_ok());
+        assert_eq!(
+            psbt.sighash(&mut cache, 2, None),
+            Err(SignError::SigHash(sighash::Error::IndexOutOfInputsBounds { index: 2, inputs_size: 2 }))
+        );
+
+        // SIGHASH_SINGLE without corresponding output is invalid for taproot
+        psbt.global.unsigned_tx.output.clear();
+        let no_outputs = psbt.global.unsigned_tx.clone();
+        assert_eq!(
+            psbt.sighash(&mut SigHashCache::new(&no_outputs), 0, None),
+            Err(SignError::SigHash(sighash::Error::SingleWithoutCorrespondingOutput { index: 0, outputs_size: 0 }))
+        );
+
+        // The signer does not produce schnorr signatures
+        assert_eq!(psbt.sign_input(0, &master(), &Secp256k1::new()), Err(SignError::UnsupportedScript(spk)));
+    }
+}
@@ -0,0 +1,312 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Signature hashes
+//!
+//! A single cache computing the signature hash of any input of a transaction,
+//! whether it spends a legacy, segwit v0 (BIP143) or taproot (BIP341) output.
+//!
+//! Unlike [`Transaction::signature_hash`], which follows consensus and returns
+//! the hash `0000...0001` when `SIGHASH_SINGLE` is used on an input without a
+//! corresponding output, the legacy sighash here reports that case as an
+//! error: signing such a hash would authorize spending the input in any
+//! transaction. BIP341 makes it invalid for taproot inputs, while BIP143
+//! defines a proper sighash for it, which is returned.
+//!
+
+use std::ops::Deref;
+
+use secp256k1::Message;
+
+use blockdata::script::Script;
+use blockdata::transaction::{SigHashType, Transaction};
+use hash_types::SigHash;
+use hashes::Hash;
+use util::bip143;
+use util::bip341::{self, Annex, Prevouts, SchnorrSigHashType};
+use util::taproot::{TapLeafHash, TapSighashHash};
+
+pub use util::bip341::Error;
+
+/// The signature hash of an input, tagged with the rules it was computed with
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SignatureHash {
+    /// Pre-segwit signature hash
+    Legacy(SigHash),
+    /// BIP143 signature hash of a segwit v0 input
+    SegwitV0(SigHash),
+    /// BIP341 signature hash of a taproot input
+    Taproot(TapSighashHash),
+}
+
+impl SignatureHash {
+    /// Returns the 32 bytes of the signature hash
+    pub fn into_inner(self) -> [u8; 32] {
+        match self {
+            SignatureHash::Legacy(hash) | SignatureHash::SegwitV0(hash) => hash.into_inner(),
+            SignatureHash::Taproot(hash) => hash.into_inner(),
+        }
+    }
+
+    /// Returns the message to be signed with ECDSA or schnorr, depending on
+    /// the kind of the signature hash
+    pub fn to_message(self) -> Message {
+        Message::from_slice(&self.into_inner()).expect("32 bytes")
+    }
+
+    /// Whether the hash must be signed with a schnorr signature
+    pub fn is_taproot(&self) -> bool {
+        match *self {
+            SignatureHash::Taproot(_) => true,
+            SignatureHash::Legacy(_) | SignatureHash::SegwitV0(_) => false,
+        }
+    }
+}
+
+/// Computes the signature hashes of the inputs of a transaction, reusing the
+/// intermediate BIP143 and BIP341 hashes across inputs
+pub struct SigHashCache<R: Deref<Target=Transaction> + Copy> {
+    tx: R,
+    segwit: bip143::SigHashCache<R>,
+    taproot: bip341::SigHashCache<R>,
+}
+
+impl<R: Deref<Target=Transaction> + Copy> SigHashCache<R> {
+    /// Compute the signature hashes of `tx`
+    pub fn new(tx: R) -> Self {
+        SigHashCache {
+            tx: tx,
+            segwit: bip143::SigHashCache::new(tx),
+            taproot: bip341::SigHashCache::new(tx),
+        }
+    }
+
+    /// Compute the legacy sighash of input `input_index`, spending an output
+    /// locked by `script_pubkey` (the redeem script for P2SH)
+    pub fn legacy_signature_hash<U: Into<u32>>(
+        &self,
+        input_index: usize,
+        script_pubkey: &Script,
+        sighash_type: U,
+    ) -> Result<SigHash, Error> {
+        let sighash_type = sighash_type.into();
+        let (sighash, _) = SigHashType::from_u32(sighash_type).split_anyonecanpay_flag();
+        self.check_input(input_index, sighash == SigHashType::Single)?;
+        Ok(self.tx.signature_hash(input_index, script_pubkey, sighash_type))
+    }
+
+    /// Compute the BIP143 sighash of input `input_index`, spending an output of
+    /// `value` satoshis. `script_code` is the P2PKH script of the key hash for
+    /// P2WPKH and the witness script, starting after the last executed
+    /// `OP_CODESEPARATOR`, for P2WSH.
+    pub fn segwit_signature_hash(
+        &mut self,
+        input_index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: SigHashType,
+    ) -> Result<SigHash, Error> {
+        self.check_input(input_index, false)?;
+        Ok(self.segwit.signature_hash(input_index, script_code, value, sighash_type))
+    }
+
+    /// Compute the BIP341 sighash of input `input_index`. See
+    /// [`bip341::SigHashCache::signature_hash`] for the meaning of the
+    /// arguments.
+    pub fn taproot_signature_hash(
+        &mut self,
+        input_index: usize,
+        prevouts: &Prevouts,
+        annex: Option<Annex>,
+        leaf_hash_code_separator: Option<(TapLeafHash, u32)>,
+        sighash_type: SchnorrSigHashType,
+    ) -> Result<TapSighashHash, Error> {
+        self.taproot.signature_hash(input_index, prevouts, annex, leaf_hash_code_separator, sighash_type)
+    }
+
+    fn check_input(&self, input_index: usize, single: bool) -> Result<(), Error> {
+        if input_index >= self.tx.input.len() {
+            return Err(Error::IndexOutOfInputsBounds {
+                index: input_index,
+                inputs_size: self.tx.input.len(),
+            });
+        }
+        if single && input_index >= self.tx.output.len() {
+            return Err(Error::SingleWithoutCorrespondingOutput {
+                index: input_index,
+                outputs_size: self.tx.output.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blockdata::transaction::{OutPoint, TxIn, TxOut};
+    use consensus::encode::deserialize;
+    use hashes::hex::FromHex;
+
+    fn tx_from_hex(hex: &str) -> Transaction {
+        deserialize(&Vec::<u8>::from_hex(hex).unwrap()).unwrap()
+    }
+
+    fn run_test_segwit(tx: &Transaction, input_index: usize, script_code: &str, value: u64, sighash_type: SigHashType, expected: &str) {
+        let script_code = Script::from(Vec::<u8>::from_hex(script_code).unwrap());
+        let mut cache = SigHashCache::new(tx);
+        assert_eq!(
+            cache.segwit_signature_hash(input_index, &script_code, value, sighash_type).unwrap(),
+            hex_hash!(SigHash, expected)
+        );
+    }
+
+    #[test]
+    fn bip143_vectors() {
+        // Native P2WPKH
+        let tx = tx_from_hex(
+            "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f000000\
+            0000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a01000000\
+            00ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093\
+            510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000"
+        );
+        run_test_segwit(
+            &tx, 1, "76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac", 600_000_000, SigHashType::All,
+            "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670",
+        );
+
+        // P2SH-P2WPKH
+        let tx = tx_from_hex(
+            "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a5477010000\
+            0000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac00\
+            08af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000"
+        );
+        run_test_segwit(
+            &tx, 0, "76a91479091972186c449eb1ded22b78e40d009bdf008988ac", 1_000_000_000, SigHashType::All,
+            "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6",
+        );
+
+        // Native P2WSH with OP_CODESEPARATOR, signing with SIGHASH_SINGLE an
+        // input without corresponding output, before and after the separator
+        let tx = tx_from_hex(
+            "0100000002fe3dc9208094f3ffd12645477b3dc56f60ec4fa8e6f5d67c565d1c6b9216b36e000000\
+            0000ffffffff0815cf020f013ed6cf91d29f4202e8a58726b1ac6c79da47c23d1bee0a6925f800000000\
+            00ffffffff0100f2052a010000001976a914a30741f8145e5acadf23f751864167f32e0963f788ac0000\
+            0000"
+        );
+        run_test_segwit(
+            &tx, 1,
+            "21026dccc749adc2a9d0d89497ac511f760f45c47dc5ed9cf352a58ac706453880aeadab210255a9626aeb\
+            f5e29c0e6538428ba0d1dcf6ca98ffdf086aa8ced5e0d0215ea465ac",
+            4_900_000_000, SigHashType::Single,
+            "82dde6e4f1e94d02c2b7ad03d2115d691f48d064e9d52f58194a6637e4194391",
+        );
+        run_test_segwit(
+            &tx, 1, "210255a9626aebf5e29c0e6538428ba0d1dcf6ca98ffdf086aa8ced5e0d0215ea465ac",
+            4_900_000_000, SigHashType::Single,
+            "fef7bd749cce710c5c052bd796df1af0d935e59cea63736268bcbe2d2134fc47",
+        );
+
+        // Native P2WSH with an unexecuted and an executed OP_CODESEPARATOR,
+        // signing with SIGHASH_SINGLE|SIGHASH_ANYONECANPAY
+        let tx = tx_from_hex(
+            "0100000002e9b542c5176808107ff1df906f46bb1f2583b16112b95ee5380665ba7fcfc001000000\
+            0000ffffffff80e68831516392fcd100d186b3c2c7b95c80b53c77e77c35ba03a66b429a2a1b00000000\
+            00ffffffff0280969800000000001976a914de4b231626ef508c9a74a8517e6783c0546d6b2888ac8096\
+            9800000000001976a9146648a8cd4531e1ec47f35916de8e259237294d1e88ac00000000"
+        );
+        run_test_segwit(
+            &tx, 0, "0063ab68210392972e2eb617b2388771abe27235fd5ac44af8e61693261550447a4c3e39da98ac",
+            16_777_215, SigHashType::SinglePlusAnyoneCanPay,
+            "e9071e75e25b8a1e298a72f0d2e9f4f95a0f5cdf86a533cda597eb402ed13b3a",
+        );
+        run_test_segwit(
+            &tx, 1, "68210392972e2eb617b2388771abe27235fd5ac44af8e61693261550447a4c3e39da98ac",
+            16_777_215, SigHashType::SinglePlusAnyoneCanPay,
+            "cd72f1f1a433ee9df816857fad88d8ebd97e09a75cd481583eb841c330275e54",
+        );
+
+        // P2SH-P2WSH 6-of-6 multisig, with every sighash type
+        let tx = tx_from_hex(
+            "010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000\
+            ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f\
+            05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000"
+        );
+        let witness_script =
+            "56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28\
+            bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b\
+            9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58\
+            c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b1486\
+            2c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b\
+            56ae";
+        for &(sighash_type, expected) in &[
+            (SigHashType::All, "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c"),
+            (SigHashType::None, "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36"),
+            (SigHashType::Single, "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea"),
+            (SigHashType::AllPlusAnyoneCanPay, "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e"),
+            (SigHashType::NonePlusAnyoneCanPay, "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a"),
+            (SigHashType::SinglePlusAnyoneCanPay, "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b"),
+        ] {
+            run_test_segwit(&tx, 0, witness_script, 987_654_321, sighash_type, expected);
+        }
+
+        // No FindAndDelete: the signature in the script code is committed to
+        let tx = tx_from_hex(
+            "010000000169c12106097dc2e0526493ef67f21269fe888ef05c7a3a5dacab38e1ac8387f14c1d00\
+            0000ffffffff0101000000000000000000000000"
+        );
+        run_test_segwit(
+            &tx, 0,
+            "ad4830450220487fb382c4974de3f7d834c1b617fe15860828c7f96454490edd6d891556dcc9022100\
+            baf95feb48f845d5bfc9882eb6aeefa1bc3790e39f59eaa46ff7f15ae626c53e01",
+            200_000, SigHashType::All,
+            "71c9cd9b2869b9c70b01b1f0360c148f42dee72297db312638df136f43311f23",
+        );
+    }
+
+    #[test]
+    fn sighash_single_without_output() {
+        let txout = TxOut {
+            value: 10_000,
+            script_pubkey: hex_script!("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn { previous_output: OutPoint::null(), ..Default::default() }; 2],
+            output: vec![txout.clone()],
+        };
+        let script = hex_script!("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac");
+        let prevouts = [txout.clone(), txout];
+        let mut cache = SigHashCache::new(&tx);
+
+        // Consensus signs "one" for legacy inputs, which we refuse to return
+        assert_eq!(tx.signature_hash(1, &script, 3)[..], hex_hash!(SigHash, "0100000000000000000000000000000000000000000000000000000000000000")[..]);
+        let single_error = Error::SingleWithoutCorrespondingOutput { index: 1, outputs_size: 1 };
+        assert_eq!(cache.legacy_signature_hash(1, &script, SigHashType::Single), Err(single_error.clone()));
+        assert_eq!(cache.legacy_signature_hash(1, &script, 0x83u32), Err(single_error.clone()));
+        assert_eq!(
+            cache.taproot_signature_hash(1, &Prevouts::All(&prevouts), None, None, SchnorrSigHashType::Single),
+            Err(single_error)
+        );
+        assert!(cache.legacy_signature_hash(0, &script, SigHashType::Single).is_ok());
+        assert!(cache.legacy_signature_hash(1, &script, SigHashType::All).is_ok());
+        assert!(cache.segwit_signature_hash(1, &script, 10_000, SigHashType::Single).is_ok());
+
+        let index_error = Error::IndexOutOfInputsBounds { index: 2, inputs_size: 2 };
+        assert_eq!(cache.legacy_signature_hash(2, &script, SigHashType::All), Err(index_error.clone()));
+        assert_eq!(cache.segwit_signature_hash(2, &script, 10_000, SigHashType::All), Err(index_error));
+    }
+}
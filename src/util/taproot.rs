@@ -13,8 +13,17 @@
 
 //! Taproot
 //!
+//! BIP341 tagged hashes, script trees and control blocks.
+//!
+
+use std::{error, fmt, io};
+use std::collections::{BTreeMap, BTreeSet};
+
+use secp256k1::{self, schnorrsig, Secp256k1};
 
-use hashes::{sha256, sha256t, Hash};
+use hashes::{sha256, sha256t, Hash, HashEngine};
+use blockdata::script::Script;
+use consensus::Encodable;
 
 /// The SHA-256 midstate value for the TapLeaf hash.
 const MIDSTATE_TAPLEAF: [u8; 32] = [
@@ -76,12 +85,540 @@ sha256t_hash_newtype!(TapSighashHash, TapSighashTag, MIDSTATE_TAPSIGHASH, 64,
     doc="Taproot-tagged hash for the taproot signature hash", true
 );
 
+impl TapTweakHash {
+    /// Create the BIP341 tweak `H_taptweak(P||R)` of an internal key `P` and an
+    /// optional merkle root `R` (omitted when there is no script path).
+    pub fn from_key_and_tweak(internal_key: &schnorrsig::PublicKey, merkle_root: Option<TapBranchHash>) -> TapTweakHash {
+        let mut eng = TapTweakHash::engine();
+        eng.input(&internal_key.serialize());
+        if let Some(root) = merkle_root {
+            eng.input(&root[..]);
+        }
+        TapTweakHash::from_engine(eng)
+    }
+}
+
+impl TapLeafHash {
+    /// Compute the leaf hash of a script with the given leaf version
+    pub fn from_script(script: &Script, version: LeafVersion) -> TapLeafHash {
+        let mut eng = TapLeafHash::engine();
+        version.as_u8().consensus_encode(&mut eng).expect("engines don't error");
+        script.consensus_encode(&mut eng).expect("engines don't error");
+        TapLeafHash::from_engine(eng)
+    }
+}
+
+impl TapBranchHash {
+    /// Compute the hash of a tree branch from the hashes of its two children,
+    /// which are hashed in lexicographic order
+    pub fn from_node_hashes(a: sha256::Hash, b: sha256::Hash) -> TapBranchHash {
+        let mut eng = TapBranchHash::engine();
+        if a < b {
+            eng.input(&a[..]);
+            eng.input(&b[..]);
+        } else {
+            eng.input(&b[..]);
+            eng.input(&a[..]);
+        }
+        TapBranchHash::from_engine(eng)
+    }
+}
+
+/// Maximum depth of a taproot script tree, and so maximum number of hashes in
+/// a control block.
+pub const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+/// Size of a hash in a control block.
+pub const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+/// Size of a control block without any merkle branch: the leaf version and
+/// parity byte followed by the internal key.
+pub const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+/// Maximum size of a control block.
+pub const TAPROOT_CONTROL_MAX_SIZE: usize =
+    TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE * TAPROOT_CONTROL_MAX_NODE_COUNT;
+/// Mask extracting the leaf version from the first byte of a control block.
+pub const TAPROOT_LEAF_MASK: u8 = 0xfe;
+/// Leaf version of BIP342 tapscript.
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+
+/// Taproot-related errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Leaves and hidden nodes must be added in depth-first order
+    NodeNotInDfsOrder,
+    /// A second node was added at depth 0
+    OverCompleteTree,
+    /// The builder was finalized while some branch is missing its sibling
+    IncompleteTree,
+    /// The script tree or merkle branch is deeper than 128 levels
+    InvalidMerkleTreeDepth(usize),
+    /// The serialized merkle branch is not a multiple of 32 bytes
+    InvalidMerkleBranchSize(usize),
+    /// The control block is not 33 + 32m bytes long, for some 0 <= m <= 128
+    InvalidControlBlockSize(usize),
+    /// The leaf version is odd, or is the annex tag `0x50`
+    InvalidLeafVersion(u8),
+    /// The internal key is not a valid x-only public key
+    InvalidInternalKey(secp256k1::Error),
+    /// Tweaking the internal key gave an invalid output key
+    InvalidTweak(secp256k1::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NodeNotInDfsOrder => f.write_str("tree nodes must be added in depth-first order"),
+            Error::OverCompleteTree => f.write_str("more than one node at depth 0"),
+            Error::IncompleteTree => f.write_str("finalized an incomplete script tree"),
+            Error::InvalidMerkleTreeDepth(d) => write!(f, "merkle tree depth ({}) exceeds {}", d, TAPROOT_CONTROL_MAX_NODE_COUNT),
+            Error::InvalidMerkleBranchSize(s) => write!(f, "merkle branch size ({}) is not a multiple of {}", s, TAPROOT_CONTROL_NODE_SIZE),
+            Error::InvalidControlBlockSize(s) => write!(f, "invalid control block size: {}", s),
+            Error::InvalidLeafVersion(v) => write!(f, "invalid leaf version: {:#04x}", v),
+            Error::InvalidInternalKey(ref e) => write!(f, "invalid internal key: {}", e),
+            Error::InvalidTweak(ref e) => write!(f, "invalid tweak: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The version of a tapleaf. Leaf versions are even and never `0x50`, so
+/// that they can share the first byte of a control block with the output key
+/// parity and can't be confused with an annex.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct LeafVersion(u8);
+
+impl LeafVersion {
+    /// BIP342 tapscript, the only leaf version with defined semantics
+    pub fn tapscript() -> LeafVersion {
+        LeafVersion(TAPROOT_LEAF_TAPSCRIPT)
+    }
+
+    /// Reads a leaf version, checking it is even and not `0x50`
+    pub fn from_u8(version: u8) -> Result<LeafVersion, Error> {
+        if version & TAPROOT_LEAF_MASK != version || version == 0x50 {
+            Err(Error::InvalidLeafVersion(version))
+        } else {
+            Ok(LeafVersion(version))
+        }
+    }
+
+    /// Converts to a u8
+    pub fn as_u8(self) -> u8 { self.0 }
+}
+
+impl Default for LeafVersion {
+    fn default() -> LeafVersion {
+        LeafVersion::tapscript()
+    }
+}
+
+impl fmt::Display for LeafVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#04x}", self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for LeafVersion {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u8(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for LeafVersion {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<LeafVersion, D::Error> {
+        let version: u8 = ::serde::Deserialize::deserialize(d)?;
+        LeafVersion::from_u8(version).map_err(::serde::de::Error::custom)
+    }
+}
+
+/// The merkle path from a tapleaf to the root of the script tree: the hashes
+/// of the siblings met on the way up, deepest first.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct TaprootMerkleBranch(Vec<sha256::Hash>);
+
+impl TaprootMerkleBranch {
+    /// Creates a merkle branch from a list of hashes
+    pub fn from_inner(inner: Vec<sha256::Hash>) -> Result<TaprootMerkleBranch, Error> {
+        if inner.len() > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            Err(Error::InvalidMerkleTreeDepth(inner.len()))
+        } else {
+            Ok(TaprootMerkleBranch(inner))
+        }
+    }
+
+    /// Parses a merkle branch from concatenated 32-byte hashes
+    pub fn from_slice(sl: &[u8]) -> Result<TaprootMerkleBranch, Error> {
+        if sl.len() % TAPROOT_CONTROL_NODE_SIZE != 0 {
+            return Err(Error::InvalidMerkleBranchSize(sl.len()));
+        }
+        TaprootMerkleBranch::from_inner(
+            sl.chunks(TAPROOT_CONTROL_NODE_SIZE)
+                .map(|chunk| sha256::Hash::from_slice(chunk).expect("chunks are 32 bytes"))
+                .collect()
+        )
+    }
+
+    /// Returns the hashes of the branch
+    pub fn as_inner(&self) -> &[sha256::Hash] {
+        &self.0
+    }
+
+    /// Returns the hashes of the branch
+    pub fn into_inner(self) -> Vec<sha256::Hash> {
+        self.0
+    }
+
+    /// Serializes the branch into a writer, returning the number of bytes written
+    pub fn encode<W: io::Write>(&self, mut writer: W) -> Result<usize, io::Error> {
+        for hash in &self.0 {
+            writer.write_all(&hash[..])?;
+        }
+        Ok(self.0.len() * TAPROOT_CONTROL_NODE_SIZE)
+    }
+
+    /// Serializes the branch as concatenated hashes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.0.len() * TAPROOT_CONTROL_NODE_SIZE);
+        self.encode(&mut buf).expect("vecs don't error");
+        buf
+    }
+
+    fn push(&mut self, hash: sha256::Hash) -> Result<(), Error> {
+        if self.0.len() >= TAPROOT_CONTROL_MAX_NODE_COUNT {
+            Err(Error::InvalidMerkleTreeDepth(self.0.len() + 1))
+        } else {
+            self.0.push(hash);
+            Ok(())
+        }
+    }
+}
+
+/// The control block of a script path spend, the last witness element. It
+/// proves that the spent script is committed to by the output key.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ControlBlock {
+    /// The leaf version of the spent script
+    pub leaf_version: LeafVersion,
+    /// Whether the y coordinate of the output key is odd
+    pub output_key_parity: bool,
+    /// The untweaked internal key
+    pub internal_key: schnorrsig::PublicKey,
+    /// The merkle path from the spent script to the merkle root
+    pub merkle_branch: TaprootMerkleBranch,
+}
+
+impl ControlBlock {
+    /// Parses a control block
+    pub fn from_slice(sl: &[u8]) -> Result<ControlBlock, Error> {
+        if sl.len() < TAPROOT_CONTROL_BASE_SIZE
+            || (sl.len() - TAPROOT_CONTROL_BASE_SIZE) % TAPROOT_CONTROL_NODE_SIZE != 0
+        {
+            return Err(Error::InvalidControlBlockSize(sl.len()));
+        }
+        let leaf_version = LeafVersion::from_u8(sl[0] & TAPROOT_LEAF_MASK)?;
+        let internal_key = schnorrsig::PublicKey::from_slice(&sl[1..TAPROOT_CONTROL_BASE_SIZE])
+            .map_err(Error::InvalidInternalKey)?;
+        let merkle_branch = TaprootMerkleBranch::from_slice(&sl[TAPROOT_CONTROL_BASE_SIZE..])?;
+        Ok(ControlBlock {
+            leaf_version: leaf_version,
+            output_key_parity: sl[0] & 1 == 1,
+            internal_key: internal_key,
+            merkle_branch: merkle_branch,
+        })
+    }
+
+    /// The size of the serialized control block
+    pub fn size(&self) -> usize {
+        TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE * self.merkle_branch.0.len()
+    }
+
+    /// Serializes the control block into a writer, returning the number of bytes written
+    pub fn encode<W: io::Write>(&self, mut writer: W) -> Result<usize, io::Error> {
+        let first_byte = self.leaf_version.as_u8() | self.output_key_parity as u8;
+        writer.write_all(&[first_byte])?;
+        writer.write_all(&self.internal_key.serialize())?;
+        self.merkle_branch.encode(&mut writer)?;
+        Ok(self.size())
+    }
+
+    /// Serializes the control block, as pushed in the witness
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.size());
+        self.encode(&mut buf).expect("vecs don't error");
+        buf
+    }
+
+    /// Checks that the control block proves the commitment of `script` in
+    /// `output_key`. This does not execute the script.
+    pub fn verify_taproot_commitment<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        output_key: &schnorrsig::PublicKey,
+        script: &Script,
+    ) -> bool {
+        let leaf_hash = TapLeafHash::from_script(script, self.leaf_version);
+        let mut curr_hash = sha256::Hash::from_inner(leaf_hash.into_inner());
+        for elem in &self.merkle_branch.0 {
+            let branch = TapBranchHash::from_node_hashes(curr_hash, *elem);
+            curr_hash = sha256::Hash::from_inner(branch.into_inner());
+        }
+        let merkle_root = TapBranchHash::from_inner(curr_hash.into_inner());
+        let tweak = TapTweakHash::from_key_and_tweak(&self.internal_key, Some(merkle_root));
+        self.internal_key.tweak_add_check(secp, output_key, self.output_key_parity, tweak.into_inner())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for ControlBlock {
+    fn serialize<S: ::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        ::serde_utils::hex_bytes::serialize(&self.serialize(), s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for ControlBlock {
+    fn deserialize<D: ::serde::Deserializer<'de>>(d: D) -> Result<ControlBlock, D::Error> {
+        let bytes: Vec<u8> = ::serde_utils::hex_bytes::deserialize(d)?;
+        ControlBlock::from_slice(&bytes).map_err(::serde::de::Error::custom)
+    }
+}
+
+/// A tapleaf along with its merkle branch in a tree under construction
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct LeafInfo {
+    script: Script,
+    version: LeafVersion,
+    merkle_branch: TaprootMerkleBranch,
+}
+
+/// A subtree under construction: its hash and the leaves it contains
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct NodeInfo {
+    hash: sha256::Hash,
+    leaves: Vec<LeafInfo>,
+}
+
+impl NodeInfo {
+    fn combine(a: NodeInfo, b: NodeInfo) -> Result<NodeInfo, Error> {
+        let mut leaves = Vec::with_capacity(a.leaves.len() + b.leaves.len());
+        for mut leaf in a.leaves {
+            leaf.merkle_branch.push(b.hash)?;
+            leaves.push(leaf);
+        }
+        for mut leaf in b.leaves {
+            leaf.merkle_branch.push(a.hash)?;
+            leaves.push(leaf);
+        }
+        let hash = TapBranchHash::from_node_hashes(a.hash, b.hash);
+        Ok(NodeInfo {
+            hash: sha256::Hash::from_inner(hash.into_inner()),
+            leaves: leaves,
+        })
+    }
+}
+
+/// Builds a taproot script tree from leaves given in depth-first order, each
+/// with its depth in the tree (the root being at depth 0).
+///
+/// ```
+/// use bitcoin::blockdata::script::Script;
+/// use bitcoin::util::taproot::TaprootBuilder;
+///
+/// // A tree with one leaf at depth 1 and two leaves at depth 2
+/// let builder = TaprootBuilder::new()
+///     .add_leaf(1, Script::from(vec![0x51])).unwrap()
+///     .add_leaf(2, Script::from(vec![0x52])).unwrap()
+///     .add_leaf(2, Script::from(vec![0x53])).unwrap();
+/// assert!(builder.is_complete());
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct TaprootBuilder {
+    // For each depth, the subtree waiting for its right sibling, if any. The
+    // last entry is never `None`, and once the tree is complete only the root
+    // is left at index 0.
+    branch: Vec<Option<NodeInfo>>,
+}
+
+impl TaprootBuilder {
+    /// Creates an empty builder
+    pub fn new() -> TaprootBuilder {
+        TaprootBuilder { branch: vec![] }
+    }
+
+    /// Adds a tapscript leaf at the given depth
+    pub fn add_leaf(self, depth: u8, script: Script) -> Result<TaprootBuilder, Error> {
+        self.add_leaf_with_ver(depth, script, LeafVersion::tapscript())
+    }
+
+    /// Adds a leaf at the given depth, with the given leaf version
+    pub fn add_leaf_with_ver(self, depth: u8, script: Script, version: LeafVersion) -> Result<TaprootBuilder, Error> {
+        let hash = sha256::Hash::from_inner(TapLeafHash::from_script(&script, version).into_inner());
+        let leaf = LeafInfo {
+            script: script,
+            version: version,
+            merkle_branch: TaprootMerkleBranch::default(),
+        };
+        self.insert(NodeInfo { hash: hash, leaves: vec![leaf] }, depth)
+    }
+
+    /// Adds a subtree which is only known by its hash, at the given depth
+    pub fn add_hidden_node(self, depth: u8, hash: sha256::Hash) -> Result<TaprootBuilder, Error> {
+        self.insert(NodeInfo { hash: hash, leaves: vec![] }, depth)
+    }
+
+    /// Whether the leaves added so far form a complete tree
+    pub fn is_complete(&self) -> bool {
+        self.branch.len() == 1 && self.branch[0].is_some()
+    }
+
+    /// Computes the output key and the control block of every leaf from the
+    /// internal key. An empty builder gives a key-path-only output.
+    pub fn finalize<C: secp256k1::Verification>(
+        mut self,
+        secp: &Secp256k1<C>,
+        internal_key: schnorrsig::PublicKey,
+    ) -> Result<TaprootSpendInfo, Error> {
+        if self.branch.is_empty() {
+            return TaprootSpendInfo::new_key_spend(secp, internal_key, None);
+        }
+        if !self.is_complete() {
+            return Err(Error::IncompleteTree);
+        }
+        let node = self.branch.pop().expect("complete tree").expect("complete tree");
+        let merkle_root = TapBranchHash::from_inner(node.hash.into_inner());
+        let mut info = TaprootSpendInfo::new_key_spend(secp, internal_key, Some(merkle_root))?;
+        for leaf in node.leaves {
+            info.script_map
+                .entry((leaf.script, leaf.version))
+                .or_insert_with(BTreeSet::new)
+                .insert(leaf.merkle_branch);
+        }
+        Ok(info)
+    }
+
+    fn insert(mut self, mut node: NodeInfo, mut depth: u8) -> Result<TaprootBuilder, Error> {
+        if depth as usize > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            return Err(Error::InvalidMerkleTreeDepth(depth as usize));
+        }
+        // A node shallower than a pending one would not be in DFS order
+        if (depth as usize) + 1 < self.branch.len() {
+            return Err(Error::NodeNotInDfsOrder);
+        }
+        // Combine with pending left siblings as long as possible
+        while self.branch.len() == depth as usize + 1 {
+            let sibling = match self.branch.pop() {
+                Some(Some(sibling)) => sibling,
+                _ => {
+                    self.branch.push(None);
+                    break;
+                }
+            };
+            if depth == 0 {
+                return Err(Error::OverCompleteTree);
+            }
+            node = NodeInfo::combine(node, sibling)?;
+            depth -= 1;
+        }
+        while self.branch.len() < depth as usize + 1 {
+            self.branch.push(None);
+        }
+        self.branch[depth as usize] = Some(node);
+        Ok(self)
+    }
+}
+
+/// Everything needed to spend a taproot output: the internal and tweaked
+/// keys, and a merkle branch for each script of the tree.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TaprootSpendInfo {
+    internal_key: schnorrsig::PublicKey,
+    merkle_root: Option<TapBranchHash>,
+    output_key: schnorrsig::PublicKey,
+    output_key_parity: bool,
+    // A script may appear several times in a tree, keep all of its branches
+    script_map: BTreeMap<(Script, LeafVersion), BTreeSet<TaprootMerkleBranch>>,
+}
+
+impl TaprootSpendInfo {
+    /// Computes the output key of an internal key committing to the given
+    /// merkle root. Without a merkle root, the output key is still tweaked
+    /// with `H_taptweak(P)` as recommended by BIP341.
+    pub fn new_key_spend<C: secp256k1::Verification>(
+        secp: &Secp256k1<C>,
+        internal_key: schnorrsig::PublicKey,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Result<TaprootSpendInfo, Error> {
+        let tweak = TapTweakHash::from_key_and_tweak(&internal_key, merkle_root);
+        let mut output_key = internal_key;
+        let output_key_parity = output_key.tweak_add_assign(secp, &tweak[..])
+            .map_err(Error::InvalidTweak)?;
+        Ok(TaprootSpendInfo {
+            internal_key: internal_key,
+            merkle_root: merkle_root,
+            output_key: output_key,
+            output_key_parity: output_key_parity,
+            script_map: BTreeMap::new(),
+        })
+    }
+
+    /// The tweak applied to the internal key
+    pub fn tap_tweak(&self) -> TapTweakHash {
+        TapTweakHash::from_key_and_tweak(&self.internal_key, self.merkle_root)
+    }
+
+    /// The untweaked internal key
+    pub fn internal_key(&self) -> schnorrsig::PublicKey {
+        self.internal_key
+    }
+
+    /// The merkle root of the script tree, if any
+    pub fn merkle_root(&self) -> Option<TapBranchHash> {
+        self.merkle_root
+    }
+
+    /// The tweaked output key, as found in the scriptPubKey
+    pub fn output_key(&self) -> schnorrsig::PublicKey {
+        self.output_key
+    }
+
+    /// Whether the y coordinate of the output key is odd
+    pub fn output_key_parity(&self) -> bool {
+        self.output_key_parity
+    }
+
+    /// The scripts of the tree with their merkle branches
+    pub fn script_map(&self) -> &BTreeMap<(Script, LeafVersion), BTreeSet<TaprootMerkleBranch>> {
+        &self.script_map
+    }
+
+    /// The control block to spend the given script, using its shortest merkle
+    /// branch. Returns `None` if the script is not in the tree.
+    pub fn control_block(&self, script: &Script, version: LeafVersion) -> Option<ControlBlock> {
+        let branches = self.script_map.get(&(script.clone(), version))?;
+        let branch = branches.iter().min_by_key(|branch| branch.0.len())?;
+        Some(ControlBlock {
+            leaf_version: version,
+            output_key_parity: self.output_key_parity,
+            internal_key: self.internal_key,
+            merkle_branch: branch.clone(),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use hashes::hex::ToHex;
     use hashes::sha256t::Tag;
     use hashes::{sha256, Hash, HashEngine};
+    use hashes::hex::FromHex;
+    use network::constants::Network;
+    use util::address::Address;
+
+    extern crate serde_json;
+    use self::serde_json::Value;
 
     fn tag_engine(tag_name: &str) -> sha256::HashEngine {
         let mut engine = sha256::Hash::engine();
@@ -163,4 +700,379 @@ mod test {
             "cd10c023c300fb9a507dff136370fba1d8a0566667cfafc4099a8803e00dfdc2"
         );
     }
+
+    fn leaf(n: u8) -> Script {
+        Script::from(vec![0x50 + n])
+    }
+
+    #[test]
+    fn test_leaf_version() {
+        assert_eq!(LeafVersion::default().as_u8(), 0xc0);
+        assert_eq!(LeafVersion::from_u8(0xfa).unwrap().as_u8(), 0xfa);
+        assert_eq!(LeafVersion::from_u8(0xc1), Err(Error::InvalidLeafVersion(0xc1)));
+        assert_eq!(LeafVersion::from_u8(0x50), Err(Error::InvalidLeafVersion(0x50)));
+        assert_eq!(LeafVersion::tapscript().to_string(), "0xc0");
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        // Two nodes at depth 0
+        let builder = TaprootBuilder::new().add_leaf(0, leaf(1)).unwrap();
+        assert_eq!(builder.add_leaf(0, leaf(2)), Err(Error::OverCompleteTree));
+
+        // A leaf at depth 1 while a depth 2 branch is pending
+        let builder = TaprootBuilder::new()
+            .add_leaf(1, leaf(1)).unwrap()
+            .add_leaf(3, leaf(2)).unwrap();
+        assert_eq!(builder.clone().add_leaf(1, leaf(3)), Err(Error::NodeNotInDfsOrder));
+
+        // Missing siblings
+        let secp = Secp256k1::verification_only();
+        let key = schnorrsig::PublicKey::from_slice(
+            &Vec::from_hex("93478e9488f956df2396be2ce6c5cced75f900dfa18e7dabd2428aae78451820").unwrap()
+        ).unwrap();
+        assert!(!builder.is_complete());
+        assert_eq!(builder.finalize(&secp, key), Err(Error::IncompleteTree));
+
+        assert_eq!(TaprootBuilder::new().add_leaf(129, leaf(1)), Err(Error::InvalidMerkleTreeDepth(129)));
+    }
+
+    #[test]
+    fn test_builder_and_control_blocks() {
+        let secp = Secp256k1::verification_only();
+        let internal_key = schnorrsig::PublicKey::from_slice(
+            &Vec::from_hex("93478e9488f956df2396be2ce6c5cced75f900dfa18e7dabd2428aae78451820").unwrap()
+        ).unwrap();
+
+        //      root
+        //     /    \
+        //    1     /\
+        //         2  /\
+        //           3  hidden
+        let hidden = sha256::Hash::hash(&[0xff]);
+        let builder = TaprootBuilder::new()
+            .add_leaf(1, leaf(1)).unwrap()
+            .add_leaf(2, leaf(2)).unwrap()
+            .add_leaf(3, leaf(3)).unwrap()
+            .add_hidden_node(3, hidden).unwrap();
+        assert!(builder.is_complete());
+        let info = builder.finalize(&secp, internal_key).unwrap();
+
+        // The merkle root is independent of the order of siblings
+        let h = |n: u8| sha256::Hash::from_inner(TapLeafHash::from_script(&leaf(n), LeafVersion::default()).into_inner());
+        let b = |x: sha256::Hash, y: sha256::Hash| sha256::Hash::from_inner(TapBranchHash::from_node_hashes(x, y).into_inner());
+        let root = b(b(b(hidden, h(3)), h(2)), h(1));
+        assert_eq!(info.merkle_root(), Some(TapBranchHash::from_inner(root.into_inner())));
+        assert_eq!(info.script_map().len(), 3);
+
+        for n in 1..4 {
+            let cb = info.control_block(&leaf(n), LeafVersion::default()).unwrap();
+            assert_eq!(cb.merkle_branch.as_inner().len(), n as usize);
+            assert_eq!(cb.size(), 33 + 32 * n as usize);
+            assert!(cb.verify_taproot_commitment(&secp, &info.output_key(), &leaf(n)));
+            assert!(!cb.verify_taproot_commitment(&secp, &info.output_key(), &leaf(4)));
+            assert!(!cb.verify_taproot_commitment(&secp, &internal_key, &leaf(n)));
+
+            let ser = cb.serialize();
+            assert_eq!(ControlBlock::from_slice(&ser), Ok(cb));
+            assert_eq!(ControlBlock::from_slice(&ser[..ser.len() - 1]), Err(Error::InvalidControlBlockSize(ser.len() - 1)));
+        }
+        assert_eq!(info.control_block(&leaf(4), LeafVersion::default()), None);
+
+        // Bad leaf version in the first byte
+        let mut ser = info.control_block(&leaf(1), LeafVersion::default()).unwrap().serialize();
+        ser[0] = 0x51;
+        assert_eq!(ControlBlock::from_slice(&ser), Err(Error::InvalidLeafVersion(0x50)));
+
+        // A key-path-only output still commits to the tweak
+        let info = TaprootBuilder::new().finalize(&secp, internal_key).unwrap();
+        assert_eq!(info.merkle_root(), None);
+        assert_ne!(info.output_key(), internal_key);
+        assert_eq!(info.tap_tweak(), TapTweakHash::from_key_and_tweak(&internal_key, None));
+    }
+
+    #[test]
+    fn bip_341_script_tree_tests() {
+        // Flattens a BIP341 JSON script tree to (depth, leaf) in DFS order
+        fn process_tree<'a>(tree: &'a Value, depth: u8, leaves: &mut Vec<(u8, &'a Value)>) {
+            if let Some(children) = tree.as_array() {
+                for child in children {
+                    process_tree(child, depth + 1, leaves);
+                }
+            } else {
+                leaves.push((depth, tree));
+            }
+        }
+
+        let secp = Secp256k1::verification_only();
+        let data: Value = serde_json::from_str(BIP341_SCRIPT_PUBKEY).unwrap();
+        for test in data.as_array().unwrap() {
+            let internal_key = schnorrsig::PublicKey::from_slice(
+                &Vec::from_hex(test["given"]["internalPubkey"].as_str().unwrap()).unwrap()
+            ).unwrap();
+
+            let mut leaves = vec![];
+            if !test["given"]["scriptTree"].is_null() {
+                process_tree(&test["given"]["scriptTree"], 0, &mut leaves);
+            }
+            let mut builder = TaprootBuilder::new();
+            let mut scripts = vec![];
+            for &(depth, leaf) in &leaves {
+                let script = Script::from(Vec::from_hex(leaf["script"].as_str().unwrap()).unwrap());
+                let version = LeafVersion::from_u8(leaf["leafVersion"].as_u64().unwrap() as u8).unwrap();
+                let id = leaf["id"].as_u64().unwrap() as usize;
+                assert_eq!(
+                    TapLeafHash::from_script(&script, version).into_inner().to_hex(),
+                    test["intermediary"]["leafHashes"][id].as_str().unwrap()
+                );
+                builder = builder.add_leaf_with_ver(depth, script.clone(), version).unwrap();
+                scripts.push((id, script, version));
+            }
+            let info = builder.finalize(&secp, internal_key).unwrap();
+
+            let intermediary = &test["intermediary"];
+            match info.merkle_root() {
+                Some(root) => assert_eq!(root.into_inner().to_hex(), intermediary["merkleRoot"].as_str().unwrap()),
+                None => assert!(intermediary["merkleRoot"].is_null()),
+            }
+            assert_eq!(info.tap_tweak().into_inner().to_hex(), intermediary["tweak"].as_str().unwrap());
+            assert_eq!(info.output_key().serialize().to_hex(), intermediary["tweakedPubkey"].as_str().unwrap());
+
+            let addr = Address::p2tr(&secp, internal_key, info.merkle_root(), Network::Bitcoin);
+            assert_eq!(addr.to_string(), test["expected"]["bip350Address"].as_str().unwrap());
+            assert_eq!(addr.script_pubkey().as_bytes().to_hex(), test["expected"]["scriptPubKey"].as_str().unwrap());
+
+            for (id, script, version) in scripts {
+                let expected = test["expected"]["scriptPathControlBlocks"][id].as_str().unwrap();
+                let cb = info.control_block(&script, version).unwrap();
+                assert_eq!(cb.serialize().to_hex(), expected);
+                assert_eq!(ControlBlock::from_slice(&Vec::from_hex(expected).unwrap()).unwrap(), cb);
+                assert!(cb.verify_taproot_commitment(&secp, &info.output_key(), &script));
+            }
+        }
+    }
+
+    const BIP341_SCRIPT_PUBKEY: &str = r#"
+    [
+        {
+            "given": {
+                "internalPubkey": "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d",
+                "scriptTree": null
+            },
+            "intermediary": {
+                "merkleRoot": null,
+                "tweak": "b86e7be8f39bab32a6f2c0443abbc210f0edac0e2c53d501b36b64437d9c6c70",
+                "tweakedPubkey": "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"
+            },
+            "expected": {
+                "scriptPubKey": "512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343",
+                "bip350Address": "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5"
+            }
+        },
+        {
+            "given": {
+                "internalPubkey": "187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27",
+                "scriptTree": {
+                    "id": 0,
+                    "script": "20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac",
+                    "leafVersion": 192
+                }
+            },
+            "intermediary": {
+                "leafHashes": [
+                    "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21"
+                ],
+                "merkleRoot": "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21",
+                "tweak": "cbd8679ba636c1110ea247542cfbd964131a6be84f873f7f3b62a777528ed001",
+                "tweakedPubkey": "147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3"
+            },
+            "expected": {
+                "scriptPubKey": "5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3",
+                "bip350Address": "bc1pz37fc4cn9ah8anwm4xqqhvxygjf9rjf2resrw8h8w4tmvcs0863sa2e586",
+                "scriptPathControlBlocks": [
+                    "c1187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27"
+                ]
+            }
+        },
+        {
+            "given": {
+                "internalPubkey": "93478e9488f956df2396be2ce6c5cced75f900dfa18e7dabd2428aae78451820",
+                "scriptTree": {
+                    "id": 0,
+                    "script": "20b617298552a72ade070667e86ca63b8f5789a9fe8731ef91202a91c9f3459007ac",
+                    "leafVersion": 192
+                }
+            },
+            "intermediary": {
+                "leafHashes": [
+                    "c525714a7f49c28aedbbba78c005931a81c234b2f6c99a73e4d06082adc8bf2b"
+                ],
+                "merkleRoot": "c525714a7f49c28aedbbba78c005931a81c234b2f6c99a73e4d06082adc8bf2b",
+                "tweak": "6af9e28dbf9d6aaf027696e2598a5b3d056f5fd2355a7fd5a37a0e5008132d30",
+                "tweakedPubkey": "e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e"
+            },
+            "expected": {
+                "scriptPubKey": "5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e",
+                "bip350Address": "bc1punvppl2stp38f7kwv2u2spltjuvuaayuqsthe34hd2dyy5w4g58qqfuag5",
+                "scriptPathControlBlocks": [
+                    "c093478e9488f956df2396be2ce6c5cced75f900dfa18e7dabd2428aae78451820"
+                ]
+            }
+        },
+        {
+            "given": {
+                "internalPubkey": "ee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf3786592",
+                "scriptTree": [
+                    {
+                        "id": 0,
+                        "script": "20387671353e273264c495656e27e39ba899ea8fee3bb69fb2a680e22093447d48ac",
+                        "leafVersion": 192
+                    },
+                    {
+                        "id": 1,
+                        "script": "06424950333431",
+                        "leafVersion": 250
+                    }
+                ]
+            },
+            "intermediary": {
+                "leafHashes": [
+                    "8ad69ec7cf41c2a4001fd1f738bf1e505ce2277acdcaa63fe4765192497f47a7",
+                    "f224a923cd0021ab202ab139cc56802ddb92dcfc172b9212261a539df79a112a"
+                ],
+                "merkleRoot": "6c2dc106ab816b73f9d07e3cd1ef2c8c1256f519748e0813e4edd2405d277bef",
+                "tweak": "9e0517edc8259bb3359255400b23ca9507f2a91cd1e4250ba068b4eafceba4a9",
+                "tweakedPubkey": "712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5"
+            },
+            "expected": {
+                "scriptPubKey": "5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5",
+                "bip350Address": "bc1pwyjywgrd0ffr3tx8laflh6228dj98xkjj8rum0zfpd6h0e930h6saqxrrm",
+                "scriptPathControlBlocks": [
+                    "c0ee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf3786592f224a923cd0021ab202ab139cc56802ddb92dcfc172b9212261a539df79a112a",
+                    "faee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf37865928ad69ec7cf41c2a4001fd1f738bf1e505ce2277acdcaa63fe4765192497f47a7"
+                ]
+            }
+        },
+        {
+            "given": {
+                "internalPubkey": "f9f400803e683727b14f463836e1e78e1c64417638aa066919291a225f0e8dd8",
+                "scriptTree": [
+                    {
+                        "id": 0,
+                        "script": "2044b178d64c32c4a05cc4f4d1407268f764c940d20ce97abfd44db5c3592b72fdac",
+                        "leafVersion": 192
+                    },
+                    {
+                        "id": 1,
+                        "script": "07546170726f6f74",
+                        "leafVersion": 192
+                    }
+                ]
+            },
+            "intermediary": {
+                "leafHashes": [
+                    "64512fecdb5afa04f98839b50e6f0cb7b1e539bf6f205f67934083cdcc3c8d89",
+                    "2cb2b90daa543b544161530c925f285b06196940d6085ca9474d41dc3822c5cb"
+                ],
+                "merkleRoot": "ab179431c28d3b68fb798957faf5497d69c883c6fb1e1cd9f81483d87bac90cc",
+                "tweak": "639f0281b7ac49e742cd25b7f188657626da1ad169209078e2761cefd91fd65e",
+                "tweakedPubkey": "77e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220"
+            },
+            "expected": {
+                "scriptPubKey": "512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220",
+                "bip350Address": "bc1pwl3s54fzmk0cjnpl3w9af39je7pv5ldg504x5guk2hpecpg2kgsqaqstjq",
+                "scriptPathControlBlocks": [
+                    "c1f9f400803e683727b14f463836e1e78e1c64417638aa066919291a225f0e8dd82cb2b90daa543b544161530c925f285b06196940d6085ca9474d41dc3822c5cb",
+                    "c1f9f400803e683727b14f463836e1e78e1c64417638aa066919291a225f0e8dd864512fecdb5afa04f98839b50e6f0cb7b1e539bf6f205f67934083cdcc3c8d89"
+                ]
+            }
+        },
+        {
+            "given": {
+                "internalPubkey": "e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6f",
+                "scriptTree": [
+                    {
+                        "id": 0,
+                        "script": "2072ea6adcf1d371dea8fba1035a09f3d24ed5a059799bae114084130ee5898e69ac",
+                        "leafVersion": 192
+                    },
+                    [
+                        {
+                            "id": 1,
+                            "script": "202352d137f2f3ab38d1eaa976758873377fa5ebb817372c71e2c542313d4abda8ac",
+                            "leafVersion": 192
+                        },
+                        {
+                            "id": 2,
+                            "script": "207337c0dd4253cb86f2c43a2351aadd82cccb12a172cd120452b9bb8324f2186aac",
+                            "leafVersion": 192
+                        }
+                    ]
+                ]
+            },
+            "intermediary": {
+                "leafHashes": [
+                    "2645a02e0aac1fe69d69755733a9b7621b694bb5b5cde2bbfc94066ed62b9817",
+                    "ba982a91d4fc552163cb1c0da03676102d5b7a014304c01f0c77b2b8e888de1c",
+                    "9e31407bffa15fefbf5090b149d53959ecdf3f62b1246780238c24501d5ceaf6"
+                ],
+                "merkleRoot": "ccbd66c6f7e8fdab47b3a486f59d28262be857f30d4773f2d5ea47f7761ce0e2",
+                "tweak": "b57bfa183d28eeb6ad688ddaabb265b4a41fbf68e5fed2c72c74de70d5a786f4",
+                "tweakedPubkey": "91b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605"
+            },
+            "expected": {
+                "scriptPubKey": "512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605",
+                "bip350Address": "bc1pjxmy65eywgafs5tsunw95ruycpqcqnev6ynxp7jaasylcgtcxczs6n332e",
+                "scriptPathControlBlocks": [
+                    "c0e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6fffe578e9ea769027e4f5a3de40732f75a88a6353a09d767ddeb66accef85e553",
+                    "c0e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6f9e31407bffa15fefbf5090b149d53959ecdf3f62b1246780238c24501d5ceaf62645a02e0aac1fe69d69755733a9b7621b694bb5b5cde2bbfc94066ed62b9817",
+                    "c0e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6fba982a91d4fc552163cb1c0da03676102d5b7a014304c01f0c77b2b8e888de1c2645a02e0aac1fe69d69755733a9b7621b694bb5b5cde2bbfc94066ed62b9817"
+                ]
+            }
+        },
+        {
+            "given": {
+                "internalPubkey": "55adf4e8967fbd2e29f20ac896e60c3b0f1d5b0efa9d34941b5958c7b0a0312d",
+                "scriptTree": [
+                    {
+                        "id": 0,
+                        "script": "2071981521ad9fc9036687364118fb6ccd2035b96a423c59c5430e98310a11abe2ac",
+                        "leafVersion": 192
+                    },
+                    [
+                        {
+                            "id": 1,
+                            "script": "20d5094d2dbe9b76e2c245a2b89b6006888952e2faa6a149ae318d69e520617748ac",
+                            "leafVersion": 192
+                        },
+                        {
+                            "id": 2,
+                            "script": "20c440b462ad48c7a77f94cd4532d8f2119dcebbd7c9764557e62726419b08ad4cac",
+                            "leafVersion": 192
+                        }
+                    ]
+                ]
+            },
+            "intermediary": {
+                "leafHashes": [
+                    "f154e8e8e17c31d3462d7132589ed29353c6fafdb884c5a6e04ea938834f0d9d",
+                    "737ed1fe30bc42b8022d717b44f0d93516617af64a64753b7a06bf16b26cd711",
+                    "d7485025fceb78b9ed667db36ed8b8dc7b1f0b307ac167fa516fe4352b9f4ef7"
+                ],
+                "merkleRoot": "2f6b2c5397b6d68ca18e09a3f05161668ffe93a988582d55c6f07bd5b3329def",
+                "tweak": "6579138e7976dc13b6a92f7bfd5a2fc7684f5ea42419d43368301470f3b74ed9",
+                "tweakedPubkey": "75169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831"
+            },
+            "expected": {
+                "scriptPubKey": "512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831",
+                "bip350Address": "bc1pw5tf7sqp4f50zka7629jrr036znzew70zxyvvej3zrpf8jg8hqcssyuewe",
+                "scriptPathControlBlocks": [
+                    "c155adf4e8967fbd2e29f20ac896e60c3b0f1d5b0efa9d34941b5958c7b0a0312d3cd369a528b326bc9d2133cbd2ac21451acb31681a410434672c8e34fe757e91",
+                    "c155adf4e8967fbd2e29f20ac896e60c3b0f1d5b0efa9d34941b5958c7b0a0312dd7485025fceb78b9ed667db36ed8b8dc7b1f0b307ac167fa516fe4352b9f4ef7f154e8e8e17c31d3462d7132589ed29353c6fafdb884c5a6e04ea938834f0d9d",
+                    "c155adf4e8967fbd2e29f20ac896e60c3b0f1d5b0efa9d34941b5958c7b0a0312d737ed1fe30bc42b8022d717b44f0d93516617af64a64753b7a06bf16b26cd711f154e8e8e17c31d3462d7132589ed29353c6fafdb884c5a6e04ea938834f0d9d"
+                ]
+            }
+        }
+    ]
+    "#;
 }
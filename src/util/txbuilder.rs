@@ -0,0 +1,687 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Transaction builder
+//!
+//! Funds payments to a set of addresses from candidate UTXOs at a target
+//! fee rate. Coins are selected with branch-and-bound, looking for an input
+//! set which needs no change, falling back to the knapsack solver of
+//! Bitcoin Core. Change is only added when it is above the dust threshold.
+//!
+
+use std::{error, fmt};
+use std::collections::BTreeMap;
+
+use secp256k1::{self, Secp256k1};
+
+use blockdata::locktime::{LockTime, Sequence};
+use blockdata::script::Script;
+use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+use consensus::encode::{self, VarInt};
+use util::address::Address;
+use util::amount::{Amount, FeeRate, Weight};
+use util::descriptor::{self, Descriptor};
+use util::psbt::{self, PartiallySignedTransaction};
+use util::taproot::{LeafVersion, TapLeafHash};
+
+/// Weight of an input without its `scriptSig` and witness
+const BASE_INPUT_WEIGHT: usize = 4 * (32 + 4 + 4);
+/// Maximum satisfaction weight of a p2wpkh output, assumed for change
+const P2WPKH_SATISFACTION_WEIGHT: usize = 113;
+/// Number of branches branch-and-bound explores before giving up
+const BNB_TOTAL_TRIES: usize = 100_000;
+/// Number of random subsets the knapsack solver tries
+const KNAPSACK_ITERATIONS: usize = 1000;
+
+/// A transaction building error
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// No recipient was added
+    NoRecipients,
+    /// The payment to the recipient at this index is below the dust threshold
+    DustOutput(usize),
+    /// The candidates can not pay for the recipients and the fee
+    InsufficientFunds {
+        /// Value of the outputs plus the fee of the transaction without inputs
+        needed: Amount,
+        /// Value of the candidates net of the fee to spend them
+        available: Amount,
+    },
+    /// Error creating the PSBT
+    Psbt(psbt::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoRecipients => f.write_str("no recipients"),
+            Error::DustOutput(i) => write!(f, "output {} is below the dust threshold", i),
+            Error::InsufficientFunds { needed, available } => write!(f, "insufficient funds: {} available, {} needed", available, needed),
+            Error::Psbt(ref e) => write!(f, "psbt error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[doc(hidden)]
+impl From<psbt::Error> for Error {
+    fn from(e: psbt::Error) -> Error {
+        Error::Psbt(e)
+    }
+}
+
+/// A UTXO which can be spent by the transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// The outpoint of the UTXO
+    pub outpoint: OutPoint,
+    /// The UTXO
+    pub txout: TxOut,
+    /// The maximum weight of the `scriptSig` (including its length prefix)
+    /// and witness spending the UTXO
+    pub satisfaction_weight: usize,
+    /// Whether the UTXO is spent with a witness
+    pub is_witness: bool,
+    /// The PSBT input data to spend the UTXO. Legacy inputs need their
+    /// `non_witness_utxo` to be set for signing.
+    pub psbt_input: psbt::Input,
+}
+
+impl Candidate {
+    /// Creates a candidate spent with at most `satisfaction_weight`, as
+    /// returned by [Descriptor::max_satisfaction_weight]. Witness outputs
+    /// are detected from their script, so p2sh-wrapped segwit outputs
+    /// should set `is_witness`.
+    pub fn new(outpoint: OutPoint, txout: TxOut, satisfaction_weight: usize) -> Candidate {
+        Candidate {
+            outpoint: outpoint,
+            is_witness: txout.script_pubkey.is_witness_program(),
+            txout: txout,
+            satisfaction_weight: satisfaction_weight,
+            psbt_input: Default::default(),
+        }
+    }
+
+    /// Creates a candidate for the output at `index` of a descriptor,
+    /// filling in the scripts and key derivations of its PSBT input
+    pub fn from_descriptor<C: secp256k1::Verification>(
+        secp: &Secp256k1<C>,
+        outpoint: OutPoint,
+        value: Amount,
+        descriptor: &Descriptor,
+        index: u32,
+    ) -> Result<Candidate, descriptor::Error> {
+        let txout = TxOut {
+            value: value.as_sat(),
+            script_pubkey: descriptor.script_pubkey(secp, index)?,
+        };
+        let mut input = psbt::Input {
+            redeem_script: descriptor.redeem_script(secp, index)?,
+            witness_script: descriptor.witness_script(secp, index)?,
+            bip32_derivation: descriptor.bip32_derivations(secp, index)?,
+            ..Default::default()
+        };
+        if descriptor.is_witness() {
+            input.witness_utxo = Some(txout.clone());
+        }
+        if let Descriptor::Tr(ref key, ref tree) = *descriptor {
+            let spend_info = descriptor.taproot_spend_info(secp, index)?.expect("tr");
+            input.tap_internal_key = Some(spend_info.internal_key());
+            input.tap_merkle_root = spend_info.merkle_root();
+
+            let mut derivations = BTreeMap::new();
+            if let Some(source) = key.key_source(index) {
+                derivations.insert(key.derive_x_only_key(secp, index)?, (vec![], source));
+            }
+            if let Some(ref tree) = *tree {
+                for leaf in tree.leaves() {
+                    let script = leaf.script(secp, index)?;
+                    let version = LeafVersion::tapscript();
+                    let control_block = spend_info.control_block(&script, version).expect("leaf of the tree");
+                    let leaf_hash = TapLeafHash::from_script(&script, version);
+                    input.tap_scripts.insert(control_block, (script, version));
+                    for key in leaf.keys() {
+                        if let Some(source) = key.key_source(index) {
+                            derivations.entry(key.derive_x_only_key(secp, index)?)
+                                .or_insert_with(|| (vec![], source))
+                                .0.push(leaf_hash);
+                        }
+                    }
+                }
+            }
+            input.tap_bip32_derivation = derivations;
+        }
+        Ok(Candidate {
+            outpoint: outpoint,
+            is_witness: descriptor.is_witness(),
+            txout: txout,
+            satisfaction_weight: descriptor.max_satisfaction_weight(secp, index)?,
+            psbt_input: input,
+        })
+    }
+
+    /// The maximum weight of the input spending the candidate
+    fn weight(&self) -> usize {
+        BASE_INPUT_WEIGHT + self.satisfaction_weight
+    }
+}
+
+/// The result of coin selection
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoinSelection {
+    /// The selected candidates, in the order they were added to the builder
+    pub selected: Vec<Candidate>,
+    /// The change output, if any
+    pub change: Option<TxOut>,
+    /// The fee paid by the transaction
+    pub fee: Amount,
+}
+
+/// Builds a transaction paying a set of recipients from candidate UTXOs
+#[derive(Debug, Clone)]
+pub struct TxBuilder {
+    candidates: Vec<Candidate>,
+    recipients: Vec<TxOut>,
+    fee_rate: FeeRate,
+    change_script: Script,
+    change_satisfaction_weight: usize,
+    version: i32,
+    lock_time: LockTime,
+    sequence: Sequence,
+}
+
+impl TxBuilder {
+    /// Creates a builder paying `fee_rate` and sending change to `change_script`, which is assumed to be spent
+    /// like a p2wpkh output.
+    pub fn new(fee_rate: FeeRate, change_script: Script) -> TxBuilder {
+        TxBuilder {
+            candidates: vec![],
+            recipients: vec![],
+            fee_rate: fee_rate,
+            change_script: change_script,
+            change_satisfaction_weight: P2WPKH_SATISFACTION_WEIGHT,
+            version: 2,
+            lock_time: LockTime::ZERO,
+            sequence: Sequence::MAX,
+        }
+    }
+
+    /// Adds a UTXO which may be spent
+    pub fn add_candidate(mut self, candidate: Candidate) -> TxBuilder {
+        self.candidates.push(candidate);
+        self
+    }
+
+    /// Adds a payment of `amount` to `address`
+    pub fn add_recipient(mut self, address: &Address, amount: Amount) -> TxBuilder {
+        self.recipients.push(TxOut {
+            value: amount.as_sat(),
+            script_pubkey: address.script_pubkey(),
+        });
+        self
+    }
+
+    /// Sets the maximum weight of the `scriptSig` and witness spending the
+    /// change output, which decides whether creating change is worth it
+    pub fn change_satisfaction_weight(mut self, weight: usize) -> TxBuilder {
+        self.change_satisfaction_weight = weight;
+        self
+    }
+
+    /// Sets the transaction version, 2 by default
+    pub fn version(mut self, version: i32) -> TxBuilder {
+        self.version = version;
+        self
+    }
+
+    /// Sets the transaction lock time, zero by default
+    pub fn lock_time(mut self, lock_time: LockTime) -> TxBuilder {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Sets the sequence number of the inputs. The default of
+    /// [Sequence::MAX] disables the lock time.
+    pub fn sequence(mut self, sequence: Sequence) -> TxBuilder {
+        self.sequence = sequence;
+        self
+    }
+
+    /// The fee for `weight` weight units at the builder's fee rate, rounded up
+    fn fee(&self, weight: usize) -> u64 {
+        self.fee_rate.fee_wu(Weight::from_wu(weight as u64)).map_or(u64::max_value(), |fee| fee.as_sat())
+    }
+
+    /// Selects the candidates to spend and computes the change and fee
+    pub fn select_coins(&self) -> Result<CoinSelection, Error> {
+        if self.recipients.is_empty() {
+            return Err(Error::NoRecipients);
+        }
+        for (i, output) in self.recipients.iter().enumerate() {
+            if output.value < output.script_pubkey.dust_value() {
+                return Err(Error::DustOutput(i));
+            }
+        }
+
+        // Everything but the inputs and the change output, counting the
+        // input and output counts as if all candidates and change were used
+        let outputs_value = self.recipients.iter().map(|o| o.value).sum::<u64>();
+        let mut base_weight = 4 * (4 + 4
+            + VarInt(self.candidates.len() as u64).len()
+            + VarInt(self.recipients.len() as u64 + 1).len()
+            + self.recipients.iter().map(|o| encode::serialize(o).len()).sum::<usize>());
+        if self.candidates.iter().any(|c| c.is_witness) {
+            // Segwit marker and flag
+            base_weight += 2;
+        }
+        let target = outputs_value.saturating_add(self.fee(base_weight));
+
+        let change_weight = 4 * encode::serialize(&TxOut {
+            value: 0,
+            script_pubkey: self.change_script.clone(),
+        }).len();
+        let change_fee = self.fee(change_weight);
+        let cost_of_change = change_fee.saturating_add(self.fee(BASE_INPUT_WEIGHT + self.change_satisfaction_weight));
+
+        // Candidates worth more than what spending them costs
+        let (indices, values): (Vec<usize>, Vec<u64>) = self.candidates.iter().enumerate()
+            .filter(|&(_, c)| c.txout.value > self.fee(c.weight()))
+            .map(|(i, c)| (i, c.txout.value - self.fee(c.weight())))
+            .unzip();
+        let available = values.iter().sum::<u64>();
+        if available < target {
+            return Err(Error::InsufficientFunds {
+                needed: Amount::from_sat(target),
+                available: Amount::from_sat(available),
+            });
+        }
+
+        let change_dust = self.change_script.dust_value();
+        let mut selected = match select_bnb(&values, target, cost_of_change) {
+            Some(selected) => selected,
+            None => select_knapsack(&values, target.saturating_add(change_fee), change_dust)
+                .or_else(|| select_knapsack(&values, target, 0))
+                .expect("available value is above target"),
+        };
+        selected.sort();
+
+        let selected_value = selected.iter().map(|&i| values[i]).sum::<u64>();
+        let change = if selected_value >= target.saturating_add(change_fee).saturating_add(change_dust) {
+            Some(TxOut {
+                value: selected_value - target - change_fee,
+                script_pubkey: self.change_script.clone(),
+            })
+        } else {
+            None
+        };
+        let selected = selected.iter().map(|&i| self.candidates[indices[i]].clone()).collect::<Vec<_>>();
+        let inputs_value = selected.iter().map(|c| c.txout.value).sum::<u64>();
+        let change_value = change.as_ref().map_or(0, |c| c.value);
+        Ok(CoinSelection {
+            selected: selected,
+            change: change,
+            fee: Amount::from_sat(inputs_value - outputs_value - change_value),
+        })
+    }
+
+    /// Selects coins and builds the unsigned transaction, the change
+    /// output coming after the recipients
+    pub fn build_tx(&self) -> Result<Transaction, Error> {
+        let selection = self.select_coins()?;
+        Ok(self.unsigned_tx(&selection))
+    }
+
+    /// Selects coins and builds a PSBT, adding the witness UTXO of segwit
+    /// inputs to their candidate PSBT input data
+    pub fn build_psbt(&self) -> Result<PartiallySignedTransaction, Error> {
+        let selection = self.select_coins()?;
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(self.unsigned_tx(&selection))?;
+        for (input, candidate) in psbt.inputs.iter_mut().zip(selection.selected) {
+            *input = candidate.psbt_input;
+            if candidate.is_witness && input.witness_utxo.is_none() {
+                input.witness_utxo = Some(candidate.txout);
+            }
+        }
+        Ok(psbt)
+    }
+
+    fn unsigned_tx(&self, selection: &CoinSelection) -> Transaction {
+        let mut output = self.recipients.clone();
+        output.extend(selection.change.clone());
+        Transaction {
+            version: self.version,
+            lock_time: self.lock_time.to_consensus_u32(),
+            input: selection.selected.iter().map(|c| TxIn {
+                previous_output: c.outpoint,
+                script_sig: Script::new(),
+                sequence: self.sequence.to_consensus_u32(),
+                witness: vec![],
+            }).collect(),
+            output: output,
+        }
+    }
+}
+
+/// Branch-and-bound search for the set of values summing to between
+/// `target` and `target + cost_of_change`, with the smallest excess
+fn select_bnb(values: &[u64], target: u64, cost_of_change: u64) -> Option<Vec<usize>> {
+    let mut pool = (0..values.len()).collect::<Vec<_>>();
+    pool.sort_by(|&a, &b| values[b].cmp(&values[a]));
+
+    let mut available = values.iter().sum::<u64>();
+    let mut value = 0;
+    // Positions in the pool of the values in the current branch
+    let mut selection: Vec<usize> = vec![];
+    let mut best: Option<(Vec<usize>, u64)> = None;
+
+    let mut pos = 0;
+    for _ in 0..BNB_TOTAL_TRIES {
+        let mut backtrack = false;
+        if value + available < target || value > target + cost_of_change {
+            backtrack = true;
+        } else if value >= target {
+            let excess = value - target;
+            if best.as_ref().map_or(true, |&(_, best_excess)| excess <= best_excess) {
+                best = Some((selection.clone(), excess));
+                if excess == 0 {
+                    break;
+                }
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            let last = match selection.last() {
+                Some(&last) => last,
+                None => break,
+            };
+            // Put back the values skipped after the last selected one and
+            // explore the branch omitting it
+            pos -= 1;
+            while pos > last {
+                available += values[pool[pos]];
+                pos -= 1;
+            }
+            value -= values[pool[pos]];
+            selection.pop();
+        } else {
+            available -= values[pool[pos]];
+            // Omitting a value identical to the previous omitted one gives
+            // the same branches as before
+            let skip = pos > 0
+                && selection.last() != Some(&(pos - 1))
+                && values[pool[pos]] == values[pool[pos - 1]];
+            if !skip {
+                selection.push(pos);
+                value += values[pool[pos]];
+            }
+        }
+        pos += 1;
+    }
+
+    best.map(|(selection, _)| selection.iter().map(|&pos| pool[pos]).collect())
+}
+
+/// Bitcoin Core's knapsack solver: an exact match, else the smallest subset
+/// of values reaching `target + min_change` found by random search, else
+/// the smallest value above it
+fn select_knapsack(values: &[u64], target: u64, min_change: u64) -> Option<Vec<usize>> {
+    if let Some(i) = values.iter().position(|&v| v == target) {
+        return Some(vec![i]);
+    }
+
+    let mut lowest_larger: Option<usize> = None;
+    let mut applicable = vec![];
+    let mut total_lower = 0;
+    for (i, &v) in values.iter().enumerate() {
+        if v < target + min_change {
+            applicable.push(i);
+            total_lower += v;
+        } else if lowest_larger.map_or(true, |l| v < values[l]) {
+            lowest_larger = Some(i);
+        }
+    }
+
+    if total_lower == target {
+        return Some(applicable);
+    }
+    if total_lower < target {
+        return lowest_larger.map(|l| vec![l]);
+    }
+
+    applicable.sort_by(|&a, &b| values[b].cmp(&values[a]));
+    let applicable_values = applicable.iter().map(|&i| values[i]).collect::<Vec<_>>();
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let (mut best, mut best_value) = approximate_best_subset(&mut rng, &applicable_values, total_lower, target);
+    if best_value != target && total_lower >= target + min_change {
+        let (subset, subset_value) = approximate_best_subset(&mut rng, &applicable_values, total_lower, target + min_change);
+        best = subset;
+        best_value = subset_value;
+    }
+
+    if let Some(l) = lowest_larger {
+        if (best_value != target && best_value < target + min_change) || values[l] <= best_value {
+            return Some(vec![l]);
+        }
+    }
+    Some(applicable.iter().zip(best).filter(|&(_, included)| included).map(|(&i, _)| i).collect())
+}
+
+/// Randomly includes values, then the others in order, looking for the
+/// subset with the smallest sum reaching `target`
+fn approximate_best_subset(rng: &mut XorShift, values: &[u64], total: u64, target: u64) -> (Vec<bool>, u64) {
+    let mut best = vec![true; values.len()];
+    let mut best_value = total;
+
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_value == target {
+            break;
+        }
+        let mut included = vec![false; values.len()];
+        let mut value = 0;
+        let mut reached_target = false;
+        for pass in 0..2 {
+            if reached_target {
+                break;
+            }
+            for i in 0..values.len() {
+                let include = if pass == 0 { rng.next_bool() } else { !included[i] };
+                if include {
+                    value += values[i];
+                    included[i] = true;
+                    if value >= target {
+                        reached_target = true;
+                        if value < best_value {
+                            best_value = value;
+                            best = included.clone();
+                        }
+                        value -= values[i];
+                        included[i] = false;
+                    }
+                }
+            }
+        }
+    }
+    (best, best_value)
+}
+
+/// A fixed seed xorshift generator, keeping coin selection deterministic
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_bool(&mut self) -> bool {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 & 1 == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    use hashes::hex::FromHex;
+    use hash_types::Txid;
+
+    fn candidate(value: u64, vout: u32) -> Candidate {
+        let txout = TxOut {
+            value: value,
+            script_pubkey: Script::from(Vec::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()),
+        };
+        Candidate::new(OutPoint::new(Txid::default(), vout), txout, P2WPKH_SATISFACTION_WEIGHT)
+    }
+
+    fn builder() -> TxBuilder {
+        let change = Script::from(Vec::from_hex("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1").unwrap());
+        TxBuilder::new(FeeRate::MIN_RELAY, change)
+    }
+
+    fn recipient() -> Address {
+        Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap()
+    }
+
+    /// Checks the fee pays for the transaction once every input is signed
+    fn assert_fee_covers(tx: &Transaction, selection: &CoinSelection, fee_rate: FeeRate) {
+        let satisfaction_weight = selection.selected.iter().map(|c| c.satisfaction_weight as u64).sum::<u64>();
+        let weight = tx.weight() + Weight::from_wu(2 + satisfaction_weight);
+        assert!(selection.fee >= fee_rate * weight);
+    }
+
+    #[test]
+    fn test_select_bnb() {
+        let values = [100_000, 200_000, 300_000, 400_000, 500_000];
+        let mut selected = select_bnb(&values, 600_000, 0).unwrap();
+        selected.sort();
+        assert_eq!(selected.iter().map(|&i| values[i]).sum::<u64>(), 600_000);
+        assert_eq!(select_bnb(&values, 650_000, 10_000), None);
+        let selected = select_bnb(&values, 650_000, 50_000).unwrap();
+        assert_eq!(selected.iter().map(|&i| values[i]).sum::<u64>(), 700_000);
+        assert_eq!(select_bnb(&values, 1_600_000, 0), None);
+    }
+
+    #[test]
+    fn test_select_knapsack() {
+        let values = [100, 200, 600, 700];
+        assert_eq!(select_knapsack(&values, 600, 0), Some(vec![2]));
+        assert_eq!(select_knapsack(&values, 300, 0), Some(vec![0, 1]));
+        assert_eq!(select_knapsack(&values, 250, 100), Some(vec![2]));
+        let mut selected = select_knapsack(&values, 1250, 0).unwrap();
+        selected.sort();
+        assert_eq!(selected, vec![2, 3]);
+        assert_eq!(select_knapsack(&values, 1700, 0), None);
+    }
+
+    #[test]
+    fn test_changeless() {
+        let builder = builder()
+            .add_candidate(candidate(50_000, 0))
+            .add_candidate(candidate(100_000, 1))
+            .add_recipient(&recipient(), Amount::from_sat(99_850));
+        let selection = builder.select_coins().unwrap();
+        assert_eq!(selection.selected.len(), 1);
+        assert_eq!(selection.selected[0].outpoint.vout, 1);
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, Amount::from_sat(150));
+
+        let tx = builder.build_tx().unwrap();
+        assert_eq!(tx.output.len(), 1);
+        assert_fee_covers(&tx, &selection, FeeRate::MIN_RELAY);
+    }
+
+    #[test]
+    fn test_change() {
+        let builder = builder()
+            .add_candidate(candidate(60_000, 0))
+            .add_candidate(candidate(70_000, 1))
+            .add_candidate(candidate(500_000, 2))
+            .add_recipient(&recipient(), Amount::from_sat(100_000))
+            .lock_time(LockTime::Height(700_000))
+            .sequence(Sequence::ENABLE_LOCKTIME_NO_RBF);
+        let selection = builder.select_coins().unwrap();
+        let change = selection.change.clone().unwrap();
+        assert!(change.value > 294);
+
+        let tx = builder.build_tx().unwrap();
+        assert_eq!(tx.lock_time, 700_000);
+        assert!(tx.input.iter().all(|i| i.sequence == 0xFFFFFFFE));
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1], change);
+        let inputs_value = selection.selected.iter().map(|c| c.txout.value).sum::<u64>();
+        assert_eq!(inputs_value, 100_000 + change.value + selection.fee.as_sat());
+        assert_fee_covers(&tx, &selection, FeeRate::MIN_RELAY);
+        // The change pays for its own output
+        assert!(selection.fee.as_sat() < 200 * selection.selected.len() as u64);
+    }
+
+    #[test]
+    fn test_dust_change() {
+        // About 200 satoshis would be left, not enough for change
+        let selection = builder()
+            .add_candidate(candidate(100_000, 0))
+            .add_recipient(&recipient(), Amount::from_sat(99_650))
+            .select_coins()
+            .unwrap();
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, Amount::from_sat(350));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(builder().add_candidate(candidate(100_000, 0)).select_coins(), Err(Error::NoRecipients));
+        assert_eq!(
+            builder().add_candidate(candidate(100_000, 0)).add_recipient(&recipient(), Amount::from_sat(293)).select_coins(),
+            Err(Error::DustOutput(0))
+        );
+        // The second candidate is not worth spending at this fee rate
+        let result = TxBuilder::new(FeeRate::from_sat_per_vb(10).unwrap(), Script::new())
+            .add_candidate(candidate(10_000, 0))
+            .add_candidate(candidate(500, 1))
+            .add_recipient(&recipient(), Amount::from_sat(10_000))
+            .select_coins();
+        match result {
+            Err(Error::InsufficientFunds { available, .. }) => assert_eq!(available, Amount::from_sat(10_000 - 683)),
+            _ => panic!("expected insufficient funds, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_psbt() {
+        let secp = Secp256k1::verification_only();
+        let desc = Descriptor::from_str("wpkh([d34db33f/84'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*)").unwrap();
+        let candidate = Candidate::from_descriptor(&secp, OutPoint::new(Txid::default(), 0), Amount::from_sat(100_000), &desc, 7).unwrap();
+        assert_eq!(candidate.satisfaction_weight, desc.max_satisfaction_weight(&secp, 7).unwrap());
+        assert!(candidate.is_witness);
+        assert_eq!(candidate.psbt_input.bip32_derivation.len(), 1);
+
+        let psbt = builder()
+            .add_candidate(candidate.clone())
+            .add_recipient(&recipient(), Amount::from_sat(50_000))
+            .build_psbt()
+            .unwrap();
+        assert_eq!(psbt.inputs.len(), 1);
+        assert_eq!(psbt.inputs[0].witness_utxo, Some(candidate.txout));
+        assert_eq!(psbt.inputs[0].bip32_derivation, candidate.psbt_input.bip32_derivation);
+        assert_eq!(psbt.outputs.len(), 2);
+
+        let desc = Descriptor::from_str("tr([d34db33f/86'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*,pk(d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8))").unwrap();
+        let candidate = Candidate::from_descriptor(&secp, OutPoint::new(Txid::default(), 1), Amount::from_sat(100_000), &desc, 0).unwrap();
+        assert!(candidate.psbt_input.tap_internal_key.is_some());
+        assert!(candidate.psbt_input.tap_merkle_root.is_some());
+        assert_eq!(candidate.psbt_input.tap_scripts.len(), 1);
+        assert_eq!(candidate.psbt_input.tap_bip32_derivation.len(), 1);
+    }
+}